@@ -0,0 +1,78 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use payments::toy_payments::{ChunkedTransactionReader, FastTransactionReader, TransactionReader};
+
+const ROWS: u64 = 100_000;
+
+// Mix of rows roughly shaped like a real feed, written once per run
+fn input_file() -> PathBuf {
+    let path = std::env::temp_dir().join("payments-bench-parse.csv");
+    let mut csv = String::from("type, client, tx, amount\n");
+    for tx in 0..ROWS {
+        let client = tx % 1000;
+        match tx % 10 {
+            0 => writeln!(csv, "dispute, {}, {},", client, tx - 10),
+            1..=6 => writeln!(
+                csv,
+                "deposit, {}, {}, {}.{:04}",
+                client,
+                tx,
+                tx % 500,
+                tx % 10000
+            ),
+            _ => writeln!(csv, "withdrawal, {}, {}, {}.5", client, tx, tx % 50),
+        }
+        .unwrap();
+    }
+    std::fs::write(&path, csv).unwrap();
+    path
+}
+
+fn parse(c: &mut Criterion) {
+    let path = input_file();
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(ROWS));
+
+    group.bench_function("serde", |b| {
+        b.iter(|| {
+            let mut reader = TransactionReader::from_path(path.clone()).unwrap();
+            reader.iter().filter(|result| result.is_ok()).count()
+        })
+    });
+    group.bench_function("fast", |b| {
+        b.iter(|| {
+            FastTransactionReader::from_path(path.clone())
+                .unwrap()
+                .filter(|result| result.is_ok())
+                .count()
+        })
+    });
+    // Small chunks so there's a lot of per-chunk work, compare with and
+    // without --features arena
+    for fast_parse in [false, true] {
+        let name = if fast_parse {
+            "chunked_fast"
+        } else {
+            "chunked_serde"
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                ChunkedTransactionReader::from_path(path.clone())
+                    .unwrap()
+                    .with_chunk_size(64 * 1024)
+                    .with_fast_parse(fast_parse)
+                    .unwrap()
+                    .flatten()
+                    .filter(|result| result.is_ok())
+                    .count()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,111 @@
+use criterion::measurement::WallTime;
+use criterion::{
+    BatchSize, BenchmarkGroup, Criterion, Throughput, criterion_group, criterion_main,
+};
+use payments::toy_payments::{Amount, ColumnarBatch, PaymentProcessor, Transaction};
+
+const CLIENTS: u16 = 5_000;
+// Enough that the accounts don't all stay in cache
+const MANY_CLIENTS: u16 = 60_000;
+const ROWS: u32 = 1_000_000;
+
+fn transactions(clients: u16) -> Vec<Transaction> {
+    (0..ROWS)
+        .map(|transaction_id| {
+            let client_id = (transaction_id % clients as u32) as u16;
+            if transaction_id % 4 == 3 {
+                Transaction::Withdrawal {
+                    client_id,
+                    transaction_id,
+                    amount: Amount::from(1),
+                }
+            } else {
+                Transaction::Deposit {
+                    client_id,
+                    transaction_id,
+                    amount: Amount::from(2),
+                }
+            }
+        })
+        .collect()
+}
+
+fn process(c: &mut Criterion) {
+    let transactions = transactions(CLIENTS);
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.sample_size(20);
+
+    group.bench_function("default", |b| {
+        b.iter_batched(
+            PaymentProcessor::new,
+            |mut processor| {
+                for transaction in &transactions {
+                    processor.process(transaction);
+                }
+                processor
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("with_capacity", |b| {
+        b.iter_batched(
+            || PaymentProcessor::with_capacity(CLIENTS as usize, ROWS as usize),
+            |mut processor| {
+                for transaction in &transactions {
+                    processor.process(transaction);
+                }
+                processor
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    bench_columnar(&mut group, &transactions);
+
+    group.finish();
+}
+
+// --engine columnar against the default row by row on the same rows
+fn columnar(c: &mut Criterion) {
+    let transactions = transactions(MANY_CLIENTS);
+    let mut group = c.benchmark_group("process_many_clients");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.sample_size(20);
+
+    group.bench_function("default", |b| {
+        b.iter_batched(
+            PaymentProcessor::new,
+            |mut processor| {
+                for transaction in &transactions {
+                    processor.process(transaction);
+                }
+                processor
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    bench_columnar(&mut group, &transactions);
+
+    group.finish();
+}
+
+fn bench_columnar(group: &mut BenchmarkGroup<'_, WallTime>, transactions: &[Transaction]) {
+    // Built up front, like the Vec the other ones go through
+    let mut batch = ColumnarBatch::with_capacity(transactions.len());
+    for transaction in transactions {
+        batch.push(transaction.clone());
+    }
+    group.bench_function("columnar", |b| {
+        b.iter_batched(
+            PaymentProcessor::new,
+            |mut processor| {
+                processor.process_columnar(&batch);
+                processor
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, process, columnar);
+criterion_main!(benches);
@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use clap::Args;
+use serde::Serialize;
+
+use super::{load_state, open_audit_log, save_state, state_key};
+use crate::config::Config;
+use payments::toy_payments::{
+    ClientId, Correction, CorrectionReader, PaymentProcessor, TransactionId,
+};
+
+#[derive(Args, Debug)]
+pub struct BackfillArgs {
+    /// Snapshot to apply the corrections to (path or URL)
+    #[arg(long)]
+    state: String,
+
+    /// CSV file of corrections (type, client, tx, amount, reference)
+    #[arg(long)]
+    corrections: PathBuf,
+
+    /// Where to save the corrected snapshot (path or URL)
+    #[arg(long)]
+    state_out: String,
+
+    /// Write an audit log of every applied/rejected correction to this path
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+}
+
+/// One line of the report printed to stdout
+#[derive(Serialize)]
+struct ReportRow<'a> {
+    #[serde(rename = "type")]
+    ty: &'static str,
+    client: ClientId,
+    tx: Option<TransactionId>,
+    reference: &'a str,
+    result: String,
+}
+
+impl<'a> ReportRow<'a> {
+    fn new(correction: &'a Correction, result: String) -> Self {
+        Self {
+            ty: correction.type_name(),
+            client: correction.client_id(),
+            tx: correction.transaction_id(),
+            reference: correction.reference(),
+            result,
+        }
+    }
+}
+
+pub fn run(args: BackfillArgs, config: &Config) {
+    if let Err(err) = backfill(args, config) {
+        eprintln!("Error applying corrections: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn backfill(args: BackfillArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let key = state_key(config).map_err(|err| format!("getting the state key: {}", err))?;
+    let mut processor = PaymentProcessor::new();
+    load_state(&mut processor, &args.state, key.as_ref())
+        .map_err(|err| format!("loading state: {}", err))?;
+
+    if let Some(path) = &args.audit_log {
+        let audit_log =
+            open_audit_log(path).map_err(|err| format!("opening audit log: {}", err))?;
+        processor.add_listener(Arc::new(Mutex::new(audit_log)));
+    }
+
+    let mut reader = CorrectionReader::from_path(args.corrections.clone())
+        .map_err(|err| format!("opening {}: {}", args.corrections.display(), err))?
+        .with_rounding(config.rounding);
+
+    let mut report = csv::Writer::from_writer(io::stdout());
+    for result in reader.iter() {
+        let correction = match result {
+            Ok(correction) => correction,
+            Err(err) => {
+                eprintln!("Error reading correction: {}", err);
+                continue;
+            }
+        };
+
+        let result = match processor.apply_correction(&correction) {
+            Ok(()) => String::from("applied"),
+            Err(reason) => reason.to_string(),
+        };
+        if let Err(err) = report.serialize(ReportRow::new(&correction, result)) {
+            eprintln!("Error writing report: {}", err);
+        }
+    }
+    if let Err(err) = report.flush() {
+        eprintln!("Error writing report: {}", err);
+    }
+
+    save_state(&processor, &args.state_out, key.as_ref())
+        .map_err(|err| format!("saving state: {}", err))?;
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::io::BufReader;
+
+use clap::Args;
+
+use super::{load_state, state_key};
+use crate::config::Config;
+use payments::toy_payments::{
+    ChargebackLayout, PaymentProcessor, create_output, open_input, read_dispute_timelines,
+};
+
+#[derive(Args, Debug)]
+pub struct ExportChargebacksArgs {
+    /// Snapshot to export the charged-back transactions from (path or URL)
+    #[arg(long)]
+    state: String,
+
+    /// Audit log(s) the dispute timeline comes from, oldest first. Can be
+    /// repeated, e.g. once per daily run.
+    #[arg(long)]
+    audit_log: Vec<String>,
+
+    /// Where to write the export (path or URL), defaults to stdout
+    #[arg(long)]
+    output: Option<String>,
+}
+
+pub fn run(args: ExportChargebacksArgs, config: &Config) {
+    let key = match state_key(config) {
+        Ok(key) => key,
+        Err(err) => {
+            eprintln!("Error getting the state key: {}", err);
+            return;
+        }
+    };
+
+    let mut processor = PaymentProcessor::new();
+    if let Err(err) = load_state(&mut processor, &args.state, key.as_ref()) {
+        eprintln!("Error loading state: {}", err);
+        return;
+    }
+
+    let mut timelines = HashMap::new();
+    for location in &args.audit_log {
+        let result = open_input(location).and_then(|input| {
+            read_dispute_timelines(BufReader::new(input), &mut timelines)?;
+            Ok(())
+        });
+        if let Err(err) = result {
+            eprintln!("Error reading audit log {}: {}", location, err);
+            return;
+        }
+    }
+
+    let default_layout = ChargebackLayout::default();
+    let layout = config.chargeback_export.as_ref().unwrap_or(&default_layout);
+    let result = create_output(args.output.as_deref()).and_then(|mut output| {
+        let written = processor.write_chargebacks(&mut output, layout, &timelines)?;
+        output.finish()?;
+        Ok(written)
+    });
+    match result {
+        Ok(written) => eprintln!("Exported {} chargebacks", written),
+        Err(err) => eprintln!("Error writing chargebacks: {}", err),
+    }
+}
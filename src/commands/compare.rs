@@ -0,0 +1,151 @@
+use std::error::Error;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+
+use clap::Args;
+
+use crate::config::Config;
+use payments::toy_payments::{
+    CsvDialect, EnrichmentTable, PaymentProcessor, ProcessorConfig, ShardedProcessor, Tiers,
+    Transaction, TransactionIdCollision, TransactionReader, Unrounded, compare_accounts,
+    create_output, open_input, sniff_delimiter, write_differences,
+};
+
+// Same as the chunked reader hands over at a time, roughly
+const BATCH_ROWS: usize = 10_000;
+
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// Input file (path or URL), run through both sides
+    input: String,
+
+    /// Config for the left side, instead of --config
+    #[arg(long)]
+    left_config: Option<PathBuf>,
+
+    /// Config for the right side, instead of --config
+    #[arg(long)]
+    right_config: Option<PathBuf>,
+
+    /// Shards on the left side, 1 is the plain single processor
+    #[arg(long, default_value_t = 1)]
+    left_threads: u16,
+
+    #[arg(long, default_value_t = 1)]
+    right_threads: u16,
+
+    /// Client metadata CSV (client,tier), used by both sides
+    #[arg(long)]
+    client_metadata: Option<String>,
+
+    /// Enrichment CSV (tx,category,...), used by both sides
+    #[arg(long)]
+    enrichment: Option<String>,
+
+    /// Write the differing accounts here instead of stdout
+    #[arg(long)]
+    output: Option<String>,
+}
+
+pub fn run(args: CompareArgs, config: &Config) {
+    if args.left_threads == 0 || args.right_threads == 0 {
+        eprintln!("threads have to be at least 1");
+        return;
+    }
+    let result = compare(&args, config);
+    match result {
+        // Non-zero when they differ, so it can gate a rollout in CI
+        Ok(0) => {}
+        Ok(_) => process::exit(1),
+        Err(err) => {
+            eprintln!("Error comparing: {}", err);
+            process::exit(2);
+        }
+    }
+}
+
+fn compare(args: &CompareArgs, config: &Config) -> Result<usize, Box<dyn Error>> {
+    let transactions = read_transactions(&args.input)?;
+    let enrichment = match &args.enrichment {
+        Some(location) => Some(Arc::new(EnrichmentTable::read(open_input(location)?)?)),
+        None => None,
+    };
+
+    let side = |path: &Option<PathBuf>, threads| -> Result<PaymentProcessor, Box<dyn Error>> {
+        let config = match path {
+            Some(path) => &Config::from_path(path)?,
+            None => config,
+        };
+        let processor_config = processor_config(args, config, enrichment.as_ref())?;
+        // Each side rounds the amounts the way its own config says
+        let transactions: Vec<Transaction> = transactions
+            .iter()
+            .map(|transaction| transaction.clone().round(config.rounding))
+            .collect();
+        Ok(process(processor_config, threads, &transactions)?)
+    };
+    let left = side(&args.left_config, args.left_threads)?;
+    let right = side(&args.right_config, args.right_threads)?;
+
+    let differences = compare_accounts(&left, &right);
+    let mut output = create_output(args.output.as_deref())?;
+    write_differences(&mut output, &differences)?;
+    output.finish()?;
+    eprintln!(
+        "{} client(s) differ after {} transactions",
+        differences.len(),
+        transactions.len()
+    );
+    Ok(differences.len())
+}
+
+fn read_transactions(location: &str) -> Result<Vec<Unrounded<Transaction>>, Box<dyn Error>> {
+    let mut input = BufReader::new(open_input(location)?);
+    let dialect = CsvDialect {
+        delimiter: sniff_delimiter(&mut input, None)?,
+        ..CsvDialect::default()
+    };
+    let mut reader = TransactionReader::with_dialect(input, &dialect);
+    let mut transactions = Vec::new();
+    for result in reader.iter_unrounded() {
+        match result {
+            Ok(transaction) => transactions.push(transaction),
+            Err(err) => eprintln!("Error reading transaction: {}", err),
+        }
+    }
+    Ok(transactions)
+}
+
+fn processor_config(
+    args: &CompareArgs,
+    config: &Config,
+    enrichment: Option<&Arc<EnrichmentTable>>,
+) -> Result<ProcessorConfig, Box<dyn Error>> {
+    let mut tiers = Tiers::new(config.tier.clone());
+    if let Some(location) = &args.client_metadata {
+        tiers.read_metadata(open_input(location)?)?;
+    }
+    Ok(config.processor_config(Arc::new(tiers), enrichment, None))
+}
+
+fn process(
+    config: ProcessorConfig,
+    threads: u16,
+    transactions: &[Transaction],
+) -> Result<PaymentProcessor, TransactionIdCollision> {
+    let processor = PaymentProcessor::with_config(config);
+    if threads == 1 {
+        let mut processor = processor;
+        for transaction in transactions {
+            processor.process(transaction);
+        }
+        return Ok(processor);
+    }
+    let sharded = ShardedProcessor::new(processor.into_shards(threads as usize));
+    for batch in transactions.chunks(BATCH_ROWS) {
+        sharded.process_batch(batch.to_vec());
+    }
+    sharded.finish()
+}
@@ -0,0 +1,549 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use clap::Args;
+use tiny_http::{Method, Request, Server};
+
+use super::serve::spawn_workers;
+use super::{load_state, save_state, state_key};
+use crate::config::{Config, DaemonConfig};
+use payments::toy_payments::{
+    ApiResponse, DedupWindow, Handoff, OutputSchema, PaymentProcessor, ReadOnlyApi, ReportRotation,
+    RoundingMode, Schedule, SnapshotKey, SubmitApi, Tiers, TransactionReader, acknowledge,
+    create_output, input_exists, request_handoff, send_handoff,
+};
+
+/// How often the schedule (and submissions) get checked while a file is
+/// being ingested
+const CHECK_EVERY_ROWS: u64 = 10_000;
+
+/// How many idempotency keys of submitted transactions are remembered
+const IDEMPOTENCY_KEYS: usize = 100_000;
+
+/// How long a handoff waits on the other process before giving up
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a daemon that took over keeps trying for the `--listen`
+/// address, while the old one lets go of it
+const LISTEN_RETRY: Duration = Duration::from_secs(10);
+
+#[derive(Args, Debug)]
+pub struct DaemonArgs {
+    /// Directory to pick up input files from. Every `*.csv` dropped in here
+    /// is processed in name order and then moved to `<inbox>/done`. Write
+    /// files elsewhere and move them in, so half-written ones aren't picked up.
+    #[arg(long)]
+    inbox: PathBuf,
+
+    /// Directory the balance reports get written to
+    #[arg(long)]
+    reports: PathBuf,
+
+    /// Snapshot (path or URL) to start from if it exists, saved again after
+    /// every ingested file so a restart picks up where it left off
+    #[arg(long)]
+    state: Option<String>,
+
+    /// Also answer queries on this address (same API as `serve`, against
+    /// the state as of the last ingested file), plus `POST /transactions`
+    /// for submitting single transactions
+    #[arg(long)]
+    listen: Option<String>,
+
+    /// How often to look for new files in the inbox
+    #[arg(long, default_value_t = 1000)]
+    poll_ms: u64,
+
+    /// Unix socket for handing the state over between deploys. If a daemon
+    /// is listening on it, this one takes over its state once it's done
+    /// with the file it's on (instead of loading --state), and then listens
+    /// on it for the next one.
+    #[arg(long)]
+    handoff: Option<PathBuf>,
+
+    /// Reject transactions whose tx ID the client already used in its last
+    /// N of them (or `all`), for inbox files that overlap
+    #[arg(long)]
+    dedup_window: Option<DedupWindow>,
+}
+
+// A rendered report, waiting to be written out
+struct Report {
+    time: SystemTime,
+    csv: Vec<u8>,
+}
+
+pub fn run(args: DaemonArgs, config: &Config) {
+    let Some(daemon) = &config.daemon else {
+        eprintln!("The daemon needs a [daemon] section with a schedule in --config");
+        process::exit(1);
+    };
+    let key = match args.state.as_ref().map(|_| state_key(config)).transpose() {
+        Ok(key) => key.flatten(),
+        Err(err) => {
+            eprintln!("Error getting the state key: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let mut processor = PaymentProcessor::with_config(config.processor_config(
+        Arc::new(Tiers::new(config.tier.clone())),
+        None,
+        args.dedup_window,
+    ));
+    let mut submit = SubmitApi::new(IDEMPOTENCY_KEYS).with_rounding(config.rounding);
+    let taken_over = match &args.handoff {
+        Some(path) => match take_over(path, &mut processor, &mut submit) {
+            Ok(taken_over) => taken_over,
+            Err(err) => {
+                eprintln!("Error taking over from the running daemon: {}", err);
+                process::exit(1);
+            }
+        },
+        None => false,
+    };
+    if let Some(location) = args.state.as_ref().filter(|_| !taken_over) {
+        let result = input_exists(location).and_then(|exists| match exists {
+            true => load_state(&mut processor, location, key.as_ref()),
+            false => Ok(()),
+        });
+        if let Err(err) = result {
+            eprintln!("Error loading state: {}", err);
+            process::exit(1);
+        }
+    }
+    let done = args.inbox.join("done");
+    for dir in [&done, &args.reports] {
+        if let Err(err) = fs::create_dir_all(dir) {
+            eprintln!("Error creating {}: {}", dir.display(), err);
+            process::exit(1);
+        }
+    }
+
+    let (submitter, submissions) = mpsc::channel();
+    let api = match &args.listen {
+        Some(listen) => match bind(listen, taken_over) {
+            Ok(server) => {
+                let api = Arc::new(RwLock::new(Arc::new(read_only(&processor))));
+                let current = api.clone();
+                let handle = move |request: &mut Request| {
+                    handle(request, &current.read().unwrap(), &submitter)
+                };
+                spawn_workers(Arc::new(server), 4, handle);
+                eprintln!("Serving on http://{}", listen);
+                Some(api)
+            }
+            Err(err) => {
+                eprintln!("Error listening on {}: {}", listen, err);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+    // Only listened on once the API is up, so the next daemon can't take
+    // over before this one has fully started
+    let handoff = match args.handoff.as_deref().map(listen_for_handoff).transpose() {
+        Ok(handoff) => handoff,
+        Err(err) => {
+            eprintln!("Error listening for a handoff: {}", err);
+            process::exit(1);
+        }
+    };
+
+    // Writing and uploading happen on their own thread, ingestion only
+    // stops long enough to render the balances
+    let (reports, pending) = mpsc::channel();
+    let rotation = ReportRotation {
+        prefix: String::from("balances"),
+        keep: daemon.keep,
+    };
+    let publisher = {
+        let (dir, upload_to) = (args.reports.clone(), daemon.upload_to.clone());
+        thread::spawn(move || publish_all(pending, &dir, &rotation, upload_to.as_deref()))
+    };
+
+    let mut next = Publisher::new(daemon);
+    let mut failed = false;
+    'ingest: loop {
+        let files = match inbox_files(&args.inbox) {
+            Ok(files) => files,
+            Err(err) => {
+                eprintln!("Error reading the inbox: {}", err);
+                failed = true;
+                break 'ingest;
+            }
+        };
+        for path in &files {
+            let rounding = config.rounding;
+            let result = ingest(
+                &mut processor,
+                path,
+                rounding,
+                &mut next,
+                &reports,
+                |processor| {
+                    submit_pending(processor, &mut submit, &submissions);
+                },
+            );
+            if let Err(err) = result {
+                eprintln!("Error processing {}: {}", path.display(), err);
+            }
+            // Moved even when it failed part way, rows before the error were
+            // applied. Stuck in the inbox it'd get applied again, so that's fatal.
+            if let Err(err) = fs::rename(path, done.join(path.file_name().unwrap())) {
+                eprintln!("Error moving {} out of the inbox: {}", path.display(), err);
+                failed = true;
+                break 'ingest;
+            }
+            changed(&processor, &args, key.as_ref(), api.as_deref());
+            // Whatever's left in the inbox is for the new process
+            if let Some(listener) = &handoff
+                && hand_over(listener, &mut processor, &mut submit, &submissions)
+            {
+                break 'ingest;
+            }
+        }
+        if files.is_empty() {
+            // Submissions get answered straight away while there's nothing else to do
+            let poll = Duration::from_millis(args.poll_ms);
+            if let Ok(submission) = submissions.recv_timeout(poll) {
+                submission.answer(&mut processor, &mut submit);
+                submit_pending(&mut processor, &mut submit, &submissions);
+                changed(&processor, &args, key.as_ref(), api.as_deref());
+            }
+        }
+        next.publish_if_due(&processor, &reports);
+        if let Some(listener) = &handoff
+            && hand_over(listener, &mut processor, &mut submit, &submissions)
+        {
+            break 'ingest;
+        }
+    }
+
+    // Reports already rendered still get written out before giving up
+    drop(reports);
+    let _ = publisher.join();
+    if failed {
+        process::exit(1);
+    }
+}
+
+// Connects to the running daemon's handoff socket, if there is one, and
+// takes over its state. False if there's nobody to take over from.
+fn take_over(
+    path: &Path,
+    processor: &mut PaymentProcessor,
+    submit: &mut SubmitApi,
+) -> Result<bool, Box<dyn Error>> {
+    let mut stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        // Nothing there, or left behind by a daemon that's gone
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(false);
+        }
+        Err(err) => return Err(err.into()),
+    };
+    eprintln!("Waiting for the running daemon to hand over its state");
+    let handoff = request_handoff(&mut stream)?;
+    let result = handoff.restore(processor, submit);
+    acknowledge(
+        &mut stream,
+        result.as_ref().map(|_| ()).map_err(|err| err.to_string()),
+    )?;
+    result?;
+    eprintln!(
+        "Took over {} account(s) from the running daemon",
+        processor.accounts().len()
+    );
+    Ok(true)
+}
+
+fn listen_for_handoff(path: &Path) -> io::Result<UnixListener> {
+    // A leftover socket, or the old daemon's. That one only needs it until
+    // it's handed over, which already happened by now.
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+// Hands the state over if a new daemon is waiting for it. True when it's
+// been taken over and this one has to stop.
+fn hand_over(
+    listener: &UnixListener,
+    processor: &mut PaymentProcessor,
+    submit: &mut SubmitApi,
+    submissions: &Receiver<Submission>,
+) -> bool {
+    let mut stream = match listener.accept() {
+        Ok((stream, _)) => stream,
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => return false,
+        Err(err) => {
+            eprintln!("Error accepting a handoff: {}", err);
+            return false;
+        }
+    };
+    // Submissions already queued go into the handed over state. Any that
+    // come in after it get a 503 once this one stops, for retrying against
+    // the new one.
+    submit_pending(processor, submit, submissions);
+    let result = stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(HANDOFF_TIMEOUT)))
+        .and_then(|_| send_handoff(&mut stream, || Handoff::new(processor, submit)));
+    match result {
+        Ok(()) => {
+            eprintln!("Handed the state over to the new daemon, stopping");
+            true
+        }
+        Err(err) => {
+            eprintln!("Error handing over, carrying on: {}", err);
+            false
+        }
+    }
+}
+
+// After a handoff the old daemon still has the address until it exits
+fn bind(listen: &str, taken_over: bool) -> Result<Server, Box<dyn Error + Send + Sync>> {
+    let started = SystemTime::now();
+    loop {
+        match Server::http(listen) {
+            Err(_) if taken_over && started.elapsed().unwrap_or_default() < LISTEN_RETRY => {
+                thread::sleep(Duration::from_millis(50));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Keeps track of when the next report is due
+struct Publisher {
+    schedule: Schedule,
+    schema: OutputSchema,
+    due: Option<SystemTime>,
+}
+
+impl Publisher {
+    fn new(config: &DaemonConfig) -> Self {
+        let due = config.schedule.next_after(SystemTime::now());
+        if due.is_none() {
+            eprintln!("The daemon schedule never fires, no reports will be published");
+        }
+        Self {
+            schedule: config.schedule,
+            schema: config.output_schema,
+            due,
+        }
+    }
+
+    fn publish_if_due(&mut self, processor: &PaymentProcessor, reports: &mpsc::Sender<Report>) {
+        let now = SystemTime::now();
+        if self.due.is_none_or(|due| now < due) {
+            return;
+        }
+        let mut csv = Vec::new();
+        if let Err(err) = processor.write_csv_with_schema(&mut csv, self.schema, |_, _| true) {
+            eprintln!("Error rendering balances: {}", err);
+        } else {
+            let _ = reports.send(Report { time: now, csv });
+        }
+        // Missed slots (a long pause, a slow file) get skipped, not caught up on
+        self.due = self.schedule.next_after(now);
+    }
+}
+
+fn inbox_files(inbox: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(inbox)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|extension| extension == "csv") {
+            files.push(path);
+        }
+    }
+    files.sort_unstable();
+    Ok(files)
+}
+
+fn ingest(
+    processor: &mut PaymentProcessor,
+    path: &Path,
+    rounding: RoundingMode,
+    next: &mut Publisher,
+    reports: &mpsc::Sender<Report>,
+    mut between: impl FnMut(&mut PaymentProcessor),
+) -> Result<(), Box<dyn Error>> {
+    let mut reader =
+        TransactionReader::from_reader(BufReader::new(File::open(path)?)).with_rounding(rounding);
+    let (mut rows, mut unreadable) = (0u64, 0u64);
+    for result in reader.iter() {
+        match result {
+            Ok(transaction) => processor.process(&transaction),
+            Err(_) => unreadable += 1,
+        }
+        rows += 1;
+        if rows.is_multiple_of(CHECK_EVERY_ROWS) {
+            next.publish_if_due(processor, reports);
+            between(processor);
+        }
+    }
+    eprintln!(
+        "Ingested {} ({} rows, {} unreadable)",
+        path.display(),
+        rows,
+        unreadable
+    );
+    Ok(())
+}
+
+/// A `POST /transactions` waiting for the ingestion loop to get to it
+struct Submission {
+    idempotency_key: Option<String>,
+    body: String,
+    reply: mpsc::Sender<ApiResponse>,
+}
+
+impl Submission {
+    fn answer(self, processor: &mut PaymentProcessor, submit: &mut SubmitApi) {
+        let response = submit.submit(processor, self.idempotency_key.as_deref(), &self.body);
+        // The worker only goes away if the client hung up
+        let _ = self.reply.send(response);
+    }
+}
+
+fn submit_pending(
+    processor: &mut PaymentProcessor,
+    submit: &mut SubmitApi,
+    submissions: &Receiver<Submission>,
+) {
+    for submission in submissions.try_iter() {
+        submission.answer(processor, submit);
+    }
+}
+
+// Runs on the HTTP workers. Writes go through the ingestion loop, which
+// owns the processor, so they're applied in between rows of the files.
+fn handle(
+    request: &mut Request,
+    api: &ReadOnlyApi,
+    submitter: &mpsc::Sender<Submission>,
+) -> ApiResponse {
+    let path = request.url().split('?').next().unwrap_or_default();
+    if request.method() != &Method::Post || path.trim_end_matches('/') != "/transactions" {
+        return api.handle(request.method().as_str(), request.url());
+    }
+    let idempotency_key = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Idempotency-Key"))
+        .map(|header| header.value.to_string());
+    let mut body = String::new();
+    if let Err(err) = request.as_reader().read_to_string(&mut body) {
+        return ApiResponse {
+            status: 400,
+            body: serde_json::json!({ "error": err.to_string() }).to_string(),
+        };
+    }
+    let (reply, response) = mpsc::channel();
+    let submission = Submission {
+        idempotency_key,
+        body,
+        reply,
+    };
+    match submitter
+        .send(submission)
+        .ok()
+        .and_then(|_| response.recv().ok())
+    {
+        Some(response) => response,
+        None => ApiResponse {
+            status: 503,
+            body: serde_json::json!({ "error": "shutting down" }).to_string(),
+        },
+    }
+}
+
+/// Saves the state and refreshes what the API serves after something got processed
+fn changed(
+    processor: &PaymentProcessor,
+    args: &DaemonArgs,
+    key: Option<&SnapshotKey>,
+    api: Option<&RwLock<Arc<ReadOnlyApi>>>,
+) {
+    if let Some(location) = &args.state
+        && let Err(err) = save_state(processor, location, key)
+    {
+        eprintln!("Error saving state: {}", err);
+    }
+    if let Some(api) = api {
+        *api.write().unwrap() = Arc::new(read_only(processor));
+    }
+}
+
+fn read_only(processor: &PaymentProcessor) -> ReadOnlyApi {
+    // Round trip through a snapshot to get a copy of the state
+    let mut snapshot = Vec::new();
+    let mut copy = PaymentProcessor::new();
+    processor
+        .save_snapshot(&mut snapshot)
+        .and_then(|_| copy.load_snapshot(snapshot.as_slice()))
+        .expect("snapshots round trip in memory");
+    ReadOnlyApi::new(copy)
+}
+
+fn publish_all(
+    pending: Receiver<Report>,
+    dir: &Path,
+    rotation: &ReportRotation,
+    upload_to: Option<&str>,
+) {
+    for report in pending {
+        let name = rotation.file_name(report.time);
+        match publish(&report, dir, &name, rotation, upload_to) {
+            Ok(()) => eprintln!("Published {}", name),
+            Err(err) => eprintln!("Error publishing {}: {}", name, err),
+        }
+    }
+}
+
+fn publish(
+    report: &Report,
+    dir: &Path,
+    name: &str,
+    rotation: &ReportRotation,
+    upload_to: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    // Renamed into place, so nothing reading the directory sees half a report
+    let partial = dir.join(format!(".{}.partial", name));
+    fs::write(&partial, &report.csv)?;
+    fs::rename(&partial, dir.join(name))?;
+
+    if let Some(prefix) = upload_to {
+        let mut output = create_output(Some(&format!("{}{}", prefix, name)))?;
+        output.write_all(&report.csv)?;
+        output.finish()?;
+    }
+
+    let existing: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+    for expired in rotation.expired(existing.iter().map(String::as_str)) {
+        fs::remove_file(dir.join(expired))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::Args;
+use clap_complete::Shell;
+
+#[derive(Args, Debug)]
+pub struct CompletionsArgs {
+    /// Shell to generate the completion script for
+    shell: Shell,
+}
+
+#[derive(Args, Debug)]
+pub struct ManpageArgs {
+    /// Write a page per subcommand (payments.1, payments-backfill.1, ...)
+    /// into this directory instead of printing the main page
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+}
+
+/// Prints the completion script, e.g.
+/// `payments completions bash > /etc/bash_completion.d/payments`
+pub fn completions(args: CompletionsArgs, mut command: clap::Command) {
+    let name = command.get_name().to_string();
+    clap_complete::generate(args.shell, &mut command, name, &mut io::stdout());
+}
+
+/// Prints the man page (roff), e.g.
+/// `payments manpage > /usr/local/share/man/man1/payments.1`
+pub fn manpage(args: ManpageArgs, command: clap::Command) {
+    let command = command.disable_help_subcommand(true);
+    let result = match &args.out_dir {
+        Some(dir) => clap_mangen::generate_to(command, dir),
+        None => clap_mangen::Man::new(command).render(&mut io::stdout().lock()),
+    };
+    if let Err(err) = result {
+        eprintln!("Error writing man page: {}", err);
+    }
+}
@@ -0,0 +1,98 @@
+use std::error::Error;
+use std::io::Read;
+
+use clap::Args;
+
+use payments::toy_payments::{GeneratorParams, Manifest, create_output, generate, open_input};
+
+#[derive(Args, Debug)]
+pub struct GenerateArgs {
+    /// Same seed and parameters, same file
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    #[arg(long, default_value_t = 1000)]
+    clients: u16,
+
+    #[arg(long, default_value_t = 100_000)]
+    rows: u32,
+
+    /// Chance of a row opening a dispute (and of closing one)
+    #[arg(long, default_value_t = 0.01)]
+    dispute_rate: f64,
+
+    /// Fraction of closed disputes that end in a chargeback
+    #[arg(long, default_value_t = 0.2)]
+    chargeback_rate: f64,
+
+    /// Regenerate the file described by a manifest from an earlier run
+    /// (path or URL), instead of using the flags above
+    #[arg(long, conflicts_with_all = ["seed", "clients", "rows", "dispute_rate", "chargeback_rate"])]
+    replay: Option<String>,
+
+    /// Where to write the CSV (path or URL), defaults to stdout
+    #[arg(long)]
+    output: Option<String>,
+
+    /// Where to write the manifest (path or URL). Pass it to a run with
+    /// --manifest to check the record count, checksum and final totals.
+    #[arg(long)]
+    manifest_out: Option<String>,
+}
+
+pub fn run(args: GenerateArgs) {
+    if let Err(err) = try_run(args) {
+        eprintln!("Error generating: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn try_run(args: GenerateArgs) -> Result<(), Box<dyn Error>> {
+    let (params, replayed) = match &args.replay {
+        Some(location) => {
+            let mut contents = String::new();
+            open_input(location)?.read_to_string(&mut contents)?;
+            let manifest: Manifest = serde_json::from_str(&contents)?;
+            let params = manifest
+                .generator
+                .clone()
+                .ok_or("the manifest wasn't written by generate")?;
+            (params, Some(manifest))
+        }
+        None => (
+            GeneratorParams {
+                seed: args.seed,
+                clients: args.clients,
+                rows: args.rows,
+                dispute_rate: args.dispute_rate,
+                chargeback_rate: args.chargeback_rate,
+            },
+            None,
+        ),
+    };
+
+    let mut output = create_output(args.output.as_deref())?;
+    let manifest = generate(&params, &mut output)?;
+    output.finish()?;
+
+    // A different file from the same parameters means the generator itself
+    // changed since the manifest was written
+    if let Some(replayed) = replayed
+        && replayed.checksum.is_some()
+        && replayed.checksum != manifest.checksum
+    {
+        return Err(format!(
+            "replayed file doesn't match the manifest (expected {}, got {})",
+            replayed.checksum.unwrap_or_default(),
+            manifest.checksum.unwrap_or_default()
+        )
+        .into());
+    }
+
+    if let Some(location) = &args.manifest_out {
+        let mut output = create_output(Some(location))?;
+        serde_json::to_writer_pretty(&mut output, &manifest)?;
+        output.finish()?;
+    }
+    Ok(())
+}
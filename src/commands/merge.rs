@@ -0,0 +1,71 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use clap::Args;
+
+use super::{load_state, open_audit_log, save_state, state_key};
+use crate::config::Config;
+use payments::toy_payments::{ClientId, OperatorAction, PaymentProcessor};
+
+#[derive(Args, Debug)]
+pub struct MergeClientsArgs {
+    /// Snapshot to merge the clients in (path or URL)
+    #[arg(long)]
+    state: String,
+
+    /// Client ID that goes away
+    #[arg(long)]
+    from: ClientId,
+
+    /// Client ID it gets merged into
+    #[arg(long)]
+    into: ClientId,
+
+    /// Why, e.g. the ticket for the upstream consolidation
+    #[arg(long)]
+    reference: String,
+
+    /// Where to save the merged snapshot (path or URL)
+    #[arg(long)]
+    state_out: String,
+
+    /// Write an audit log of the merge to this path
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+}
+
+pub fn run(args: MergeClientsArgs, config: &Config) {
+    let (from, into) = (args.from, args.into);
+    // The processor's gone by the time this returns, so the audit log's flushed
+    if let Err(err) = merge_clients(args, config) {
+        eprintln!("Error merging client {} into {}: {}", from, into, err);
+        std::process::exit(1);
+    }
+}
+
+fn merge_clients(args: MergeClientsArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let key = state_key(config).map_err(|err| format!("getting the state key: {}", err))?;
+    let mut processor = PaymentProcessor::new();
+    load_state(&mut processor, &args.state, key.as_ref())
+        .map_err(|err| format!("loading state: {}", err))?;
+
+    if let Some(path) = &args.audit_log {
+        let audit_log =
+            open_audit_log(path).map_err(|err| format!("opening audit log: {}", err))?;
+        processor.add_listener(Arc::new(Mutex::new(audit_log)));
+    }
+
+    let merge = OperatorAction::MergeClient {
+        client_id: args.from,
+        into: args.into,
+        reference: args.reference,
+    };
+    processor
+        .apply_action(&merge)
+        .map_err(|reason| reason.to_string())?;
+
+    save_state(&processor, &args.state_out, key.as_ref())
+        .map_err(|err| format!("saving state: {}", err))?;
+    Ok(())
+}
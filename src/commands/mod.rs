@@ -0,0 +1,103 @@
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use crate::config::Config;
+use payments::toy_payments::{
+    AuditLog, PaymentProcessor, ReviewQueue, SnapshotKey, SystemClock, create_output, input_exists,
+    open_input,
+};
+
+pub mod backfill;
+pub mod chargebacks;
+pub mod compare;
+pub mod daemon;
+pub mod docs;
+pub mod generate;
+pub mod merge;
+pub mod preview;
+pub mod privacy;
+pub mod report;
+pub mod review;
+pub mod run;
+pub mod selftest;
+pub mod serve;
+
+// Bits of plumbing shared between the subcommands
+
+/// Env var holding the snapshot encryption key (64 hex characters)
+const STATE_KEY_VAR: &str = "PAYMENTS_STATE_KEY";
+
+/// The key snapshots get encrypted with, if one is set up. The env var wins
+/// over the config's `key_command` (e.g. a KMS decrypt call that prints the key).
+pub fn state_key(config: &Config) -> Result<Option<SnapshotKey>, Box<dyn Error>> {
+    if let Ok(hex) = env::var(STATE_KEY_VAR) {
+        return Ok(Some(SnapshotKey::from_hex(&hex)?));
+    }
+    let Some(encryption) = &config.encryption else {
+        return Ok(None);
+    };
+
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&encryption.key_command)
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("key_command failed ({})", output.status).into());
+    }
+    Ok(Some(SnapshotKey::from_hex(&String::from_utf8(
+        output.stdout,
+    )?)?))
+}
+
+// Snapshots go through open_input/create_output so they can live in object stores too
+pub fn load_state(
+    processor: &mut PaymentProcessor,
+    location: &str,
+    key: Option<&SnapshotKey>,
+) -> Result<(), Box<dyn Error>> {
+    processor.load_snapshot_with_key(BufReader::new(open_input(location)?), key)?;
+    Ok(())
+}
+
+/// Encrypted whenever there's a key
+pub fn save_state(
+    processor: &PaymentProcessor,
+    location: &str,
+    key: Option<&SnapshotKey>,
+) -> Result<(), Box<dyn Error>> {
+    let mut output = create_output(Some(location))?;
+    match key {
+        Some(key) => processor.save_snapshot_encrypted(&mut output, key)?,
+        None => processor.save_snapshot(&mut output)?,
+    }
+    output.finish()?;
+    Ok(())
+}
+
+pub fn open_audit_log(path: &Path) -> io::Result<AuditLog<BufWriter<File>>> {
+    Ok(AuditLog::with_clock(
+        BufWriter::new(File::create(path)?),
+        Arc::new(SystemClock),
+    ))
+}
+
+/// An empty queue if there's nothing at `location` yet
+pub fn load_review_queue(location: &str) -> Result<ReviewQueue, Box<dyn Error>> {
+    if !input_exists(location)? {
+        return Ok(ReviewQueue::new());
+    }
+    ReviewQueue::read(open_input(location)?)
+}
+
+pub fn save_review_queue(queue: &ReviewQueue, location: &str) -> Result<(), Box<dyn Error>> {
+    let mut output = create_output(Some(location))?;
+    queue.write(&mut output)?;
+    output.finish()?;
+    Ok(())
+}
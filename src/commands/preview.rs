@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use clap::Args;
+use serde_json::json;
+
+use super::{load_state, state_key};
+use crate::config::Config;
+use payments::toy_payments::{
+    Account, ClientId, DedupWindow, PaymentProcessor, Tiers, Transaction, TransactionId, Unrounded,
+    open_input,
+};
+
+#[derive(Args, Debug)]
+pub struct PreviewArgs {
+    /// Snapshot to try the transaction against (path or URL), left as it is
+    #[arg(long)]
+    state: String,
+
+    /// Same types as in the input files, e.g. withdrawal, dispute, transfer
+    #[arg(long = "type")]
+    ty: String,
+
+    #[arg(long)]
+    client: ClientId,
+
+    /// Needed for disputes, resolves and chargebacks. Defaults to the next
+    /// unused ID otherwise.
+    #[arg(long)]
+    tx: Option<TransactionId>,
+
+    #[arg(long)]
+    amount: Option<f64>,
+
+    /// Recipient, for transfers
+    #[arg(long)]
+    to: Option<ClientId>,
+
+    /// For adjustments
+    #[arg(long)]
+    reference: Option<String>,
+
+    /// Check the tx ID against the client's last N (or `all`) like run and
+    /// the daemon do with it, so a duplicate previews as one
+    #[arg(long)]
+    dedup_window: Option<DedupWindow>,
+
+    /// Client metadata CSV (client,tier), so tier fees and limits apply
+    #[arg(long)]
+    client_metadata: Option<String>,
+}
+
+pub fn run(args: PreviewArgs, config: &Config) {
+    let mut tiers = Tiers::new(config.tier.clone());
+    if let Some(location) = &args.client_metadata {
+        let result = open_input(location).and_then(|input| tiers.read_metadata(input));
+        if let Err(err) = result {
+            eprintln!("Error reading client metadata: {}", err);
+            return;
+        }
+    }
+    let key = match state_key(config) {
+        Ok(key) => key,
+        Err(err) => {
+            eprintln!("Error getting the state key: {}", err);
+            return;
+        }
+    };
+    let mut processor = PaymentProcessor::with_config(config.processor_config(
+        Arc::new(tiers),
+        None,
+        args.dedup_window,
+    ));
+    if let Err(err) = load_state(&mut processor, &args.state, key.as_ref()) {
+        eprintln!("Error loading state: {}", err);
+        return;
+    }
+
+    // Goes through the same parsing as a submitted transaction
+    let tx = args.tx.unwrap_or_else(|| {
+        processor
+            .max_transaction_id()
+            .map_or(1, |max| max.saturating_add(1))
+    });
+    let row = json!({
+        "type": args.ty,
+        "client": args.client,
+        "tx": tx,
+        "amount": args.amount,
+        "reference": args.reference,
+        "to": args.to,
+    });
+    let transaction = match serde_json::from_value::<Unrounded<Transaction>>(row) {
+        Ok(transaction) => transaction.round(config.rounding),
+        Err(err) => {
+            eprintln!("Bad transaction: {}", err);
+            return;
+        }
+    };
+
+    let preview = processor.preview(&transaction);
+    let accounts: Vec<_> = preview
+        .accounts
+        .iter()
+        .map(|(client_id, before, after)| {
+            json!({
+                "client": client_id,
+                "before": before.as_ref().map(account),
+                "after": after.as_ref().map(account),
+            })
+        })
+        .collect();
+    let report = json!({
+        "tx": tx,
+        "accepted": preview.is_applied(),
+        "result": preview.result.to_string(),
+        "accounts": accounts,
+    });
+    println!("{:#}", report);
+}
+
+// Same shape as the serve API's accounts
+fn account(account: &Account) -> serde_json::Value {
+    json!({
+        "available": account.available().to_string(),
+        "held": account.held().to_string(),
+        "total": account.total().to_string(),
+        "locked": account.is_locked(),
+    })
+}
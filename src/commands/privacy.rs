@@ -0,0 +1,161 @@
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use clap::Args;
+
+use super::{load_review_queue, load_state, open_audit_log, state_key};
+use crate::config::Config;
+use payments::toy_payments::{ClientId, OperatorAction, PaymentProcessor, create_output};
+
+#[derive(Args, Debug)]
+pub struct ExportClientArgs {
+    /// Snapshot to export the client from (path or URL)
+    #[arg(long)]
+    state: String,
+
+    /// Client ID to export
+    #[arg(long)]
+    client: ClientId,
+
+    /// Also include the client's items from this review queue (path or URL)
+    #[arg(long)]
+    review_queue: Option<String>,
+
+    /// Where to write the JSON (path or URL), defaults to stdout
+    #[arg(long)]
+    output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct EraseClientArgs {
+    /// Snapshot to erase the client from (path or URL)
+    #[arg(long)]
+    state: String,
+
+    /// Client ID to erase
+    #[arg(long)]
+    client: ClientId,
+
+    /// Client ID the data moves to, the highest unused one by default. Only
+    /// pick one if you need to know it, it's the link back to the client.
+    #[arg(long)]
+    pseudonym: Option<ClientId>,
+
+    /// The erasure request's ticket
+    #[arg(long)]
+    reference: String,
+
+    /// Where to save the snapshot without the client (path or URL)
+    #[arg(long)]
+    state_out: String,
+
+    /// Review queue to erase the client from too, rewritten in place (path or URL)
+    #[arg(long)]
+    review_queue: Option<String>,
+
+    /// Write an audit log of the erasure to this path
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+}
+
+pub fn export(args: ExportClientArgs, config: &Config) {
+    if let Err(err) = export_client(&args, config) {
+        eprintln!("Error exporting client {}: {}", args.client, err);
+        std::process::exit(1);
+    }
+}
+
+fn export_client(args: &ExportClientArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut processor = PaymentProcessor::new();
+    load_state(&mut processor, &args.state, state_key(config)?.as_ref())?;
+    let mut export = processor
+        .export_client(args.client)
+        .ok_or("no such client in the snapshot")?;
+    if let Some(location) = &args.review_queue {
+        export.review = load_review_queue(location)?
+            .items()
+            .iter()
+            .filter(|item| item.client_id == args.client)
+            .cloned()
+            .collect();
+    }
+
+    let mut output = create_output(args.output.as_deref())?;
+    serde_json::to_writer_pretty(&mut output, &export)?;
+    writeln!(output)?;
+    output.finish()?;
+    Ok(())
+}
+
+pub fn erase(args: EraseClientArgs, config: &Config) {
+    let client = args.client;
+    if let Err(err) = erase_client(args, config) {
+        eprintln!("Error erasing client {}: {}", client, err);
+        std::process::exit(1);
+    }
+}
+
+fn erase_client(args: EraseClientArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let key = state_key(config).map_err(|err| format!("getting the state key: {}", err))?;
+    let mut processor = PaymentProcessor::new();
+    load_state(&mut processor, &args.state, key.as_ref())
+        .map_err(|err| format!("loading state: {}", err))?;
+    // Loaded up front, so a bad queue doesn't leave the snapshot erased without it
+    let mut queue = args
+        .review_queue
+        .as_deref()
+        .map(load_review_queue)
+        .transpose()
+        .map_err(|err| format!("loading review queue: {}", err))?;
+
+    if let Some(path) = &args.audit_log {
+        let audit_log =
+            open_audit_log(path).map_err(|err| format!("opening audit log: {}", err))?;
+        processor.add_listener(Arc::new(Mutex::new(audit_log)));
+    }
+
+    let pseudonym = args
+        .pseudonym
+        .or_else(|| processor.unused_client_id())
+        .ok_or("every client ID is in use")?;
+    let erase = OperatorAction::EraseClient {
+        client_id: args.client,
+        pseudonym,
+        reference: args.reference,
+    };
+    processor
+        .apply_action(&erase)
+        .map_err(|reason| reason.to_string())?;
+
+    // Both are put together before either is written, and the queue goes
+    // first. If it can't be written the erased snapshot isn't either, so
+    // the two still agree on who's who.
+    let mut snapshot = Vec::new();
+    match &key {
+        Some(key) => processor.save_snapshot_encrypted(&mut snapshot, key)?,
+        None => processor.save_snapshot(&mut snapshot)?,
+    }
+    let mut moved = None;
+    if let (Some(queue), Some(location)) = (&mut queue, &args.review_queue) {
+        moved = Some(queue.erase_client(args.client, pseudonym));
+        let mut csv = Vec::new();
+        queue.write(&mut csv)?;
+        write_output(location, &csv).map_err(|err| format!("saving review queue: {}", err))?;
+    }
+    write_output(&args.state_out, &snapshot).map_err(|err| format!("saving state: {}", err))?;
+
+    if let Some(moved) = moved {
+        eprintln!("Erased {} review item(s)", moved);
+    }
+    eprintln!("Erased client {}", args.client);
+    Ok(())
+}
+
+fn write_output(location: &str, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut output = create_output(Some(location))?;
+    output.write_all(bytes)?;
+    output.finish()?;
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use std::error::Error;
+
+use clap::Args;
+
+use super::{load_state, state_key};
+use crate::config::Config;
+use payments::toy_payments::{PaymentProcessor, Query, ReportField, create_output, write_report};
+
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    /// Snapshot to report on (path or URL)
+    #[arg(long)]
+    state: String,
+
+    /// Only the accounts matching this, e.g. "total > 1000 && locked == true".
+    /// Fields: client, available, held, total, locked, dispute_count,
+    /// total_disputes, chargebacks
+    #[arg(long)]
+    query: Option<Query>,
+
+    /// Comma separated fields to show, all of them by default
+    #[arg(long, value_delimiter = ',')]
+    fields: Vec<ReportField>,
+
+    /// Write the report here instead of stdout
+    #[arg(long)]
+    output: Option<String>,
+}
+
+pub fn run(args: ReportArgs, config: &Config) {
+    if let Err(err) = report(&args, config) {
+        eprintln!("Error writing report: {}", err);
+    }
+}
+
+fn report(args: &ReportArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut processor = PaymentProcessor::new();
+    load_state(&mut processor, &args.state, state_key(config)?.as_ref())?;
+
+    let fields = if args.fields.is_empty() {
+        ReportField::ALL.to_vec()
+    } else {
+        args.fields.clone()
+    };
+    let mut output = create_output(args.output.as_deref())?;
+    let rows = write_report(&mut output, &processor, args.query.as_ref(), &fields)?;
+    output.finish()?;
+    eprintln!(
+        "{} of {} account(s) matched",
+        rows,
+        processor.accounts().len()
+    );
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use clap::{Args, Subcommand};
+
+use super::{
+    load_review_queue, load_state, open_audit_log, save_review_queue, save_state, state_key,
+};
+use crate::config::Config;
+use payments::toy_payments::{PaymentProcessor, ReviewQueue, SnapshotKey};
+
+#[derive(Args, Debug)]
+pub struct ReviewArgs {
+    #[command(subcommand)]
+    command: ReviewCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum ReviewCommand {
+    /// Print the accounts waiting for review as CSV
+    List(ListArgs),
+    /// Unlock the account (and optionally reverse the chargeback that locked it)
+    Approve(ApproveArgs),
+    /// Keep the account locked and take it off the queue
+    Reject(DecisionArgs),
+}
+
+#[derive(Args, Debug)]
+struct ListArgs {
+    /// Review queue written by `--review-queue` (path or URL)
+    #[arg(long)]
+    queue: String,
+
+    /// Include the ones that were already decided
+    #[arg(long, default_value_t = false)]
+    all: bool,
+}
+
+#[derive(Args, Debug)]
+struct DecisionArgs {
+    /// Review queue written by `--review-queue` (path or URL)
+    #[arg(long)]
+    queue: String,
+
+    /// ID of the review item, from `review list`
+    #[arg(long)]
+    id: u64,
+
+    /// Ticket or note recorded with the decision
+    #[arg(long)]
+    reference: String,
+
+    /// Snapshot with the locked account (path or URL)
+    #[arg(long)]
+    state: String,
+
+    /// Write the decision to this audit log
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ApproveArgs {
+    #[command(flatten)]
+    decision: DecisionArgs,
+
+    /// Where to save the snapshot with the account unlocked (path or URL)
+    #[arg(long)]
+    state_out: String,
+
+    /// Also give back the funds of the chargeback that locked the account.
+    /// Not for accounts frozen over the dispute cap, there's no chargeback.
+    #[arg(long, default_value_t = false)]
+    reverse_chargeback: bool,
+}
+
+pub fn run(args: ReviewArgs, config: &Config) {
+    match args.command {
+        ReviewCommand::List(args) => list(args),
+        ReviewCommand::Approve(args) => decide(&args.decision, config, |queue, processor, key| {
+            let decision = &args.decision;
+            queue.approve(
+                decision.id,
+                processor,
+                &decision.reference,
+                args.reverse_chargeback,
+            )?;
+            save_state(processor, &args.state_out, key).map_err(|err| err.to_string())
+        }),
+        ReviewCommand::Reject(args) => decide(&args, config, |queue, processor, _| {
+            queue.reject(args.id, processor, &args.reference)
+        }),
+    }
+}
+
+fn list(args: ListArgs) {
+    let queue = match load_review_queue(&args.queue) {
+        Ok(queue) => queue,
+        Err(err) => {
+            eprintln!("Error reading review queue: {}", err);
+            return;
+        }
+    };
+    let result = match args.all {
+        true => queue.write(io::stdout()),
+        false => queue.write_pending(io::stdout()),
+    };
+    if let Err(err) = result {
+        eprintln!("Error writing review queue: {}", err);
+    }
+}
+
+// Loads the queue and the snapshot, lets `apply` make the decision (and
+// save the snapshot if it changed), then saves the queue if it went through
+fn decide(
+    args: &DecisionArgs,
+    config: &Config,
+    apply: impl FnOnce(
+        &mut ReviewQueue,
+        &mut PaymentProcessor,
+        Option<&SnapshotKey>,
+    ) -> Result<(), String>,
+) {
+    let mut queue = match load_review_queue(&args.queue) {
+        Ok(queue) => queue,
+        Err(err) => {
+            eprintln!("Error reading review queue: {}", err);
+            return;
+        }
+    };
+    let key = match state_key(config) {
+        Ok(key) => key,
+        Err(err) => {
+            eprintln!("Error getting the state key: {}", err);
+            return;
+        }
+    };
+    let mut processor = PaymentProcessor::new();
+    if let Err(err) = load_state(&mut processor, &args.state, key.as_ref()) {
+        eprintln!("Error loading state: {}", err);
+        return;
+    }
+    if let Some(path) = &args.audit_log {
+        match open_audit_log(path) {
+            Ok(audit_log) => processor.add_listener(Arc::new(Mutex::new(audit_log))),
+            Err(err) => {
+                eprintln!("Error opening audit log: {}", err);
+                return;
+            }
+        }
+    }
+
+    if let Err(err) = apply(&mut queue, &mut processor, key.as_ref()) {
+        eprintln!("Review {}: {}", args.id, err);
+        return;
+    }
+    if let Err(err) = save_review_queue(&queue, &args.queue) {
+        eprintln!("Error saving review queue: {}", err);
+    }
+}
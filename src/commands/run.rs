@@ -0,0 +1,1045 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read};
+use std::iter;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, ValueEnum};
+use serde::Deserialize;
+
+use super::{
+    load_review_queue, load_state, open_audit_log, save_review_queue, save_state, state_key,
+};
+use crate::config::{Config, Profile};
+#[cfg(feature = "postgres")]
+use payments::toy_payments::PostgresSink;
+use payments::toy_payments::{
+    Account, Amount, COLUMNAR_CHUNK_ROWS, Chaos, ChaosParams, Checksum, ChunkedTransactionReader,
+    ClientId, ClientSampler, ColumnarBatch, CsvDialect, DedupWindow, DigestHandle, DisputeReport,
+    EnrichmentTable, EventListener, ExpectedTotals, FastTransactionReader, HashingReader,
+    JournalWriter, Locale, LocalizedDisplay, Manifest, OutputSchema, PaymentProcessor,
+    ProcessorConfig, ResultsWriter, RoundingMode, ShardedProcessor, SqlTables, Stats,
+    ThreadTimings, Tiers, Timings, Transaction, TransactionReader, create_output, input_exists,
+    is_valid_table_name, open_input, parse_record, sniff_delimiter, timed, write_alerts,
+};
+
+/// Default mode: process an input file and print the account balances
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Path of the input CSV file (or an object store URL, see --input)
+    #[arg(required_unless_present = "input", conflicts_with = "input")]
+    input_file: Option<String>,
+
+    /// Same as the positional input file. With the object-store feature
+    /// this can also be a URL, e.g. s3://bucket/txns.csv
+    #[arg(long)]
+    input: Option<String>,
+
+    /// Fail the run unless the input hashes to this, e.g. sha256:<hex>.
+    /// Without it, a <input>.sha256 sidecar next to the input is checked if there is one
+    #[arg(long)]
+    verify_checksum: Option<Checksum>,
+
+    /// JSON manifest for the input, with the expected record count and/or checksum
+    #[arg(long)]
+    manifest: Option<String>,
+
+    /// Only process this fraction of clients (all of their transactions),
+    /// and print totals estimated from the sample to stderr
+    #[arg(long, value_parser = parse_sample_rate, conflicts_with_all = ["state_in", "state_out"])]
+    sample: Option<f64>,
+
+    /// Seed picking the sampled clients, the same seed always picks the same ones
+    #[arg(long, default_value_t = 0, requires = "sample")]
+    seed: u64,
+
+    /// Print how long parsing, validation, processing and output took to
+    /// stderr (per thread with --threads)
+    #[arg(long, default_value_t = false)]
+    timings: bool,
+
+    /// Where to write the output instead of stdout. Takes object store
+    /// URLs too, like --input
+    #[arg(long)]
+    output: Option<String>,
+
+    /// Emit debug
+    #[arg(short, long, default_value_t = false)]
+    debug: bool,
+
+    /// CSV with `client` and `tier` columns (path or URL), putting clients
+    /// on the `[tier.<name>]` rules from the config
+    #[arg(long)]
+    client_metadata: Option<String>,
+
+    /// Reference CSV keyed by `tx` with `merchant`, `category` and `channel`
+    /// columns (path or URL), added to the audit log and disputes report
+    #[arg(long)]
+    enrichment: Option<String>,
+
+    /// Ignore adjustments for locked accounts instead of applying them
+    #[arg(long, default_value_t = false)]
+    reject_locked_adjustments: bool,
+
+    /// Write an audit log of every applied/rejected transaction to this path
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+
+    /// Write a result code for every processed row (e.g. OK,
+    /// REJECTED_LOCKED, TRANSFER_DEBIT_OK/CREDIT_REJECTED_LOCKED) to this path
+    #[arg(long)]
+    results: Option<PathBuf>,
+
+    /// Review queue file (path or URL). Every account that locks during the
+    /// run gets queued for `payments review`, on top of what's already in it
+    #[arg(long)]
+    review_queue: Option<String>,
+
+    /// Write every dispute event (opened, resolved, charged back, and
+    /// anything over the `[dispute_cap]` from the config) to this path
+    #[arg(long)]
+    disputes_report: Option<PathBuf>,
+
+    /// Write double-entry journal lines for everything applied to this
+    /// path, against the account codes in the `[journal]` config section
+    #[arg(long)]
+    journal: Option<PathBuf>,
+
+    /// Print a hash of the final balances to stderr (`state-hash:
+    /// sha256:<hex>`), the same for the same input whatever --threads is
+    #[arg(long, default_value_t = false)]
+    state_hash: bool,
+
+    /// Print processing stats to stderr once done
+    #[arg(long, default_value_t = false)]
+    stats: bool,
+
+    /// Thousands separators and decimal comma for the summaries on stderr
+    /// (stats, alerts, audit, sample estimates), e.g. `de-DE` or `$LANG`.
+    /// The CSV/SQL output stays as it is.
+    #[arg(long)]
+    locale: Option<Locale>,
+
+    /// Snapshot from a previous run to start from (path or URL)
+    #[arg(long)]
+    state_in: Option<String>,
+
+    /// Where to save a snapshot of the final state (path or URL)
+    #[arg(long)]
+    state_out: Option<String>,
+
+    /// Only output accounts whose balances or lock status changed
+    /// compared to the --state-in snapshot
+    #[arg(long, default_value_t = false, requires = "state_in")]
+    changed_only: bool,
+
+    /// List clients whose total changed by more than this compared to the
+    /// --state-in snapshot, on stderr and in --alerts-out
+    #[arg(long, value_parser = parse_threshold, requires = "state_in")]
+    alert_delta: Option<f64>,
+
+    /// Where to write the --alert-delta alerts as CSV (path or URL)
+    #[arg(long, requires = "alert_delta")]
+    alerts_out: Option<String>,
+
+    /// Simulate a bad feed: chance of dropping each parsed row
+    #[arg(long, value_parser = parse_probability, default_value_t = 0.0)]
+    chaos_drop: f64,
+
+    /// Simulate a bad feed: chance of a row coming through twice
+    #[arg(long, value_parser = parse_probability, default_value_t = 0.0)]
+    chaos_duplicate: f64,
+
+    /// Simulate a bad feed: shuffle rows in windows of this many
+    #[arg(long, default_value_t = 0)]
+    chaos_reorder: usize,
+
+    /// Seed for the --chaos-* options, the same seed mangles the same way
+    #[arg(long, default_value_t = 0)]
+    chaos_seed: u64,
+
+    /// Number of processor shards (threads). Anything above 1 also
+    /// parses the input in parallel
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    threads: u16,
+
+    /// Field delimiter, a single character or `tab`. Detected from the
+    /// first line (`,` `;` tab or `|`) when not given
+    #[arg(long, value_parser = parse_csv_char)]
+    delimiter: Option<u8>,
+
+    #[arg(long, value_parser = parse_csv_char, default_value = "\"")]
+    quote_char: u8,
+
+    /// Skip lines starting with this character, e.g. `#`
+    #[arg(long, value_parser = parse_csv_char)]
+    comment_char: Option<u8>,
+
+    /// The input has no header row, the columns are taken to be
+    /// type,client,tx,amount,reference,to
+    #[arg(long, default_value_t = false)]
+    no_header: bool,
+
+    /// Parse the input with the hand-written parser instead of serde
+    #[arg(long, default_value_t = false)]
+    fast_parse: bool,
+
+    /// How the rows get applied. columnar (experimental) reads the input in
+    /// large chunks and applies each one grouped by client, same end state
+    /// but nothing comes out until a chunk is done. Single-threaded only.
+    #[arg(long, value_enum, default_value_t = Engine::Streaming)]
+    engine: Engine,
+
+    /// Roughly how many clients to expect, to pre-size the account map
+    #[arg(long, default_value_t = 0)]
+    expect_clients: usize,
+
+    /// Roughly how many rows to expect, to pre-size the transaction store
+    #[arg(long, default_value_t = 0)]
+    expect_rows: usize,
+
+    /// Reject deposits/withdrawals/transfers/adjustments whose tx ID the
+    /// client already used in its last N of them (or `all`), for inputs that
+    /// overlap. The IDs go into --state-out, so the next file is checked too.
+    #[arg(long)]
+    dedup_window: Option<DedupWindow>,
+
+    /// Keep a bloom filter of stored transaction IDs, so disputes,
+    /// resolves and chargebacks for unknown ones skip the store lookup
+    #[arg(long, default_value_t = false)]
+    transaction_filter: bool,
+
+    /// Check the final state for inconsistencies and print a report
+    /// with suggested corrections to stderr
+    #[arg(long, default_value_t = false)]
+    audit: bool,
+
+    /// How to print the final state
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    output_format: OutputFormat,
+
+    /// Column layout of the CSV output. v1 is the original five columns
+    /// and never changes, v2 has exact amounts and the client's tier.
+    #[arg(long, default_value = "v1")]
+    output_schema: OutputSchema,
+
+    /// Table to insert accounts into with --output-format sql
+    #[arg(long, default_value = "accounts", value_parser = parse_table_name)]
+    sql_accounts_table: String,
+
+    /// Table to insert open disputes into with --output-format sql
+    #[arg(long, default_value = "disputes", value_parser = parse_table_name)]
+    sql_disputes_table: String,
+
+    /// Also upsert the final balances into Postgres, using the [postgres]
+    /// section of the config file
+    #[cfg(feature = "postgres")]
+    #[arg(long, default_value_t = false)]
+    write_postgres: bool,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Account balances as CSV
+    Csv,
+    /// INSERT statements for the accounts and open disputes
+    Sql,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// Each row as it's read
+    Streaming,
+    /// Chunks of rows in columns, grouped by client
+    Columnar,
+}
+
+fn parse_sample_rate(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate <= 1.0 => Ok(rate),
+        _ => Err(String::from("expected a fraction in (0, 1], e.g. 0.01")),
+    }
+}
+
+fn parse_probability(probability: &str) -> Result<f64, String> {
+    match probability.parse::<f64>() {
+        Ok(probability) if (0.0..=1.0).contains(&probability) => Ok(probability),
+        _ => Err(String::from("expected a probability in [0, 1], e.g. 0.01")),
+    }
+}
+
+// Rounded once the config is loaded, it's not by the time clap runs
+fn parse_threshold(threshold: &str) -> Result<f64, String> {
+    match threshold.parse::<f64>() {
+        Ok(threshold) if threshold >= 0.0 => Ok(threshold),
+        _ => Err(String::from("expected a non-negative amount, e.g. 10000")),
+    }
+}
+
+fn parse_csv_char(value: &str) -> Result<u8, String> {
+    match value {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => Err(String::from("expected a single ASCII character or `tab`")),
+    }
+}
+
+fn parse_table_name(name: &str) -> Result<String, String> {
+    if is_valid_table_name(name) {
+        Ok(name.to_string())
+    } else {
+        Err(String::from(
+            "expected a plain table name, optionally with a schema (e.g. reporting.accounts)",
+        ))
+    }
+}
+
+/// Fills in everything the profile sets that wasn't given on the command line
+pub fn apply_profile(
+    args: &mut RunArgs,
+    profile: &Profile,
+    matches: &ArgMatches,
+) -> Result<(), String> {
+    let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    macro_rules! apply {
+        ($($field:ident),*) => {
+            $(
+                if let Some(value) = &profile.$field
+                    && !from_cli(stringify!($field))
+                {
+                    args.$field = *value;
+                }
+            )*
+        };
+    }
+    apply!(
+        reject_locked_adjustments,
+        audit,
+        output_format,
+        output_schema,
+        threads,
+        fast_parse,
+        engine,
+        expect_clients,
+        expect_rows,
+        transaction_filter,
+        stats,
+        timings
+    );
+    if let Some(locale) = profile.locale
+        && !from_cli("locale")
+    {
+        args.locale = Some(locale);
+    }
+    if let Some(window) = &profile.dedup_window
+        && !from_cli("dedup_window")
+    {
+        args.dedup_window = Some(window.parse()?);
+    }
+    if let Some(table) = &profile.sql_accounts_table
+        && !from_cli("sql_accounts_table")
+    {
+        args.sql_accounts_table = parse_table_name(table)?;
+    }
+    if let Some(table) = &profile.sql_disputes_table
+        && !from_cli("sql_disputes_table")
+    {
+        args.sql_disputes_table = parse_table_name(table)?;
+    }
+    if args.threads == 0 {
+        return Err(String::from("threads has to be at least 1"));
+    }
+    Ok(())
+}
+
+type Listeners = Vec<Arc<Mutex<dyn EventListener>>>;
+type Input = Box<dyn Read + Send>;
+
+pub fn run(args: RunArgs, config: &Config) {
+    if args.engine == Engine::Columnar && args.threads > 1 {
+        eprintln!("--engine columnar is single-threaded, it can't be used with --threads");
+        return;
+    }
+    let started = Instant::now();
+    let mut timings = Timings::default();
+    let mut tiers = Tiers::new(config.tier.clone());
+    if let Some(location) = &args.client_metadata {
+        let result = open_input(location).and_then(|input| tiers.read_metadata(input));
+        if let Err(err) = result {
+            eprintln!("Error reading client metadata: {}", err);
+            return;
+        }
+    }
+    let tiers = Arc::new(tiers);
+    let enrichment = match &args.enrichment {
+        Some(location) => match open_input(location).and_then(EnrichmentTable::read) {
+            Ok(table) => Some(Arc::new(table)),
+            Err(err) => {
+                eprintln!("Error reading enrichment: {}", err);
+                return;
+            }
+        },
+        None => None,
+    };
+    if enrichment.is_none() && !config.category_limit.is_empty() {
+        eprintln!("Warning: category limits need --enrichment, not enforcing them");
+    }
+    let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+        adjust_locked_accounts: !args.reject_locked_adjustments,
+        transaction_filter: args.transaction_filter,
+        ..config.processor_config(tiers.clone(), enrichment.as_ref(), args.dedup_window)
+    });
+
+    // Only worth asking for a key (maybe a KMS call) if there's state to read or write
+    let key = if args.state_in.is_some() || args.state_out.is_some() {
+        match state_key(config) {
+            Ok(key) => key,
+            Err(err) => {
+                eprintln!("Error getting the state key: {}", err);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Some(path) = &args.state_in {
+        let result = load_state(&mut processor, path, key.as_ref());
+        if let Err(err) = result {
+            eprintln!("Error loading state: {}", err);
+            return;
+        }
+    }
+    let baseline = processor.accounts().clone();
+
+    // Listeners are shared so every shard reports into the same ones
+    let mut listeners: Listeners = Vec::new();
+    if let Some(path) = &args.audit_log {
+        match open_audit_log(path) {
+            Ok(audit_log) => {
+                let audit_log = match &enrichment {
+                    Some(table) => audit_log.enriched(table.clone()),
+                    None => audit_log,
+                };
+                listeners.push(Arc::new(Mutex::new(audit_log)))
+            }
+            Err(err) => {
+                eprintln!("Error opening audit log: {}", err);
+                return;
+            }
+        }
+    }
+    let results = match &args.results {
+        Some(path) => {
+            match File::create(path).and_then(|file| ResultsWriter::new(BufWriter::new(file))) {
+                Ok(results) => {
+                    let results = Arc::new(Mutex::new(results));
+                    listeners.push(results.clone());
+                    Some(results)
+                }
+                Err(err) => {
+                    eprintln!("Error opening results file: {}", err);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    let review_queue = match &args.review_queue {
+        Some(location) => match load_review_queue(location) {
+            Ok(queue) => {
+                let queue = Arc::new(Mutex::new(queue));
+                listeners.push(queue.clone());
+                Some(queue)
+            }
+            Err(err) => {
+                eprintln!("Error reading review queue: {}", err);
+                return;
+            }
+        },
+        None => None,
+    };
+    let disputes_report = match &args.disputes_report {
+        Some(path) => {
+            match File::create(path).and_then(|file| DisputeReport::new(BufWriter::new(file))) {
+                Ok(report) => {
+                    let report = match &enrichment {
+                        Some(table) => report.enriched(table.clone()),
+                        None => report,
+                    };
+                    let report = Arc::new(Mutex::new(report));
+                    listeners.push(report.clone());
+                    Some(report)
+                }
+                Err(err) => {
+                    eprintln!("Error opening disputes report: {}", err);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    let journal = match &args.journal {
+        Some(path) => {
+            let journal = File::create(path).and_then(|file| {
+                JournalWriter::new(BufWriter::new(file), config.journal.clone(), tiers.clone())
+            });
+            match journal {
+                Ok(journal) => {
+                    let journal = Arc::new(Mutex::new(journal));
+                    listeners.push(journal.clone());
+                    Some(journal)
+                }
+                Err(err) => {
+                    eprintln!("Error opening journal file: {}", err);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    let stats = Arc::new(Mutex::new(Stats::new()));
+    if args.stats {
+        listeners.push(stats.clone());
+    }
+
+    let expected = match expected_input(&args) {
+        Ok(expected) => expected,
+        Err(err) => {
+            eprintln!("Error reading input expectations: {}", err);
+            return;
+        }
+    };
+    let (input, digest) = match open_input_file(&args, expected.checksum.is_some()) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("Error opening file: {}", err);
+            return;
+        }
+    };
+    let (input, dialect) = match csv_dialect(&args, input) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("Error reading file: {}", err);
+            return;
+        }
+    };
+
+    let result = if args.threads > 1 {
+        let shard_count = args.threads as usize;
+        let mut shards = processor.into_shards(shard_count);
+        for shard in &mut shards {
+            shard.reserve(
+                args.expect_clients / shard_count,
+                args.expect_rows / shard_count,
+            );
+            add_listeners(shard, &listeners);
+        }
+        process_sharded(
+            &args,
+            &dialect,
+            config.rounding,
+            shards,
+            input,
+            &mut timings,
+        )
+    } else {
+        processor.reserve(args.expect_clients, args.expect_rows);
+        add_listeners(&mut processor, &listeners);
+        process_single(
+            &args,
+            &dialect,
+            config.rounding,
+            processor,
+            input,
+            &mut timings,
+        )
+    };
+    let (processor, records) = match result {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("Error processing input: {}", err);
+            process::exit(1);
+        }
+    };
+
+    // A truncated/corrupted input fails the whole run, before anything gets written
+    if let Err(err) = expected.verify(digest.as_ref(), records) {
+        eprintln!("Input verification failed: {}", err);
+        process::exit(1);
+    }
+
+    let filter =
+        |client_id, account: &_| !args.changed_only || baseline.get(&client_id) != Some(account);
+    let result = timed(&mut timings.output, || {
+        write_output(&args, &processor, filter)
+    });
+    if let Err(err) = result {
+        eprintln!("Error writing output: {}", err);
+    }
+
+    #[cfg(feature = "postgres")]
+    if args.write_postgres {
+        let result = match &config.postgres {
+            Some(postgres) => PostgresSink::new(postgres.clone())
+                .map_err(Into::into)
+                .and_then(|mut sink| processor.write_to_sink(&mut sink, filter)),
+            None => Err("--write-postgres needs a [postgres] section in --config".into()),
+        };
+        if let Err(err) = result {
+            eprintln!("Error writing to postgres: {}", err);
+        }
+    }
+
+    if let Some(path) = &args.state_out {
+        let result = save_state(&processor, path, key.as_ref());
+        if let Err(err) = result {
+            eprintln!("Error saving state: {}", err);
+        }
+    }
+
+    let locale = args.locale.unwrap_or_default();
+    if let Some(threshold) = args.alert_delta {
+        let threshold = Amount::from_f64_rounded(threshold, config.rounding);
+        let alerts = processor.balance_alerts(&baseline, threshold);
+        eprintln!(
+            "{} client(s) changed by more than {}",
+            locale.count(alerts.len() as u64),
+            locale.amount(threshold)
+        );
+        for alert in &alerts {
+            eprintln!("- {}", alert.localized(&locale));
+        }
+        if let Some(location) = &args.alerts_out {
+            let result = create_output(Some(location)).and_then(|mut output| {
+                write_alerts(&mut output, &alerts)?;
+                output.finish()?;
+                Ok(())
+            });
+            if let Err(err) = result {
+                eprintln!("Error writing alerts: {}", err);
+            }
+        }
+    }
+
+    if let Some(results) = &results
+        && let Err(err) = results.lock().unwrap().flush()
+    {
+        eprintln!("Error writing results: {}", err);
+    }
+    if let Some(report) = &disputes_report
+        && let Err(err) = report.lock().unwrap().flush()
+    {
+        eprintln!("Error writing disputes report: {}", err);
+    }
+    if let Some(journal) = &journal
+        && let Err(err) = journal.lock().unwrap().flush()
+    {
+        eprintln!("Error writing journal: {}", err);
+    }
+    if let (Some(location), Some(queue)) = (&args.review_queue, &review_queue) {
+        let queue = queue.lock().unwrap();
+        match save_review_queue(&queue, location) {
+            Ok(()) => eprintln!("{} account(s) waiting for review", queue.pending().count()),
+            Err(err) => eprintln!("Error saving review queue: {}", err),
+        }
+    }
+
+    if args.state_hash {
+        eprintln!("state-hash: {}", processor.state_hash());
+    }
+    if args.stats {
+        eprintln!("{}", stats.lock().unwrap().localized(&locale));
+    }
+
+    if let Some(sampler) = sampler(&args) {
+        eprintln!(
+            "{}",
+            processor.estimate_from_sample(&sampler).localized(&locale)
+        );
+    }
+
+    if args.timings {
+        timings.total = started.elapsed();
+        eprintln!("{}", timings);
+    }
+
+    if args.audit {
+        let violations = processor.check_invariants();
+        eprintln!(
+            "audit: {} issue(s) found",
+            locale.count(violations.len() as u64)
+        );
+        for violation in violations {
+            eprintln!("- {}", violation.localized(&locale));
+            eprintln!("  suggestion: {}", violation.suggestion_for(&locale));
+        }
+    }
+
+    // Totals only hold for the file on its own, not on top of a snapshot
+    // or with rows sampled out/mangled
+    if let Some(totals) = &expected.totals
+        && args.state_in.is_none()
+        && args.sample.is_none()
+        && chaos(&args).is_none()
+    {
+        let actual = ExpectedTotals::of(&processor);
+        if actual != *totals {
+            eprintln!(
+                "Totals don't match the manifest: expected {}, got {}",
+                totals, actual
+            );
+            process::exit(1);
+        }
+    }
+}
+
+fn sampler(args: &RunArgs) -> Option<ClientSampler> {
+    args.sample.map(|rate| ClientSampler::new(rate, args.seed))
+}
+
+fn chaos(args: &RunArgs) -> Option<Chaos> {
+    let params = ChaosParams {
+        drop_rate: args.chaos_drop,
+        duplicate_rate: args.chaos_duplicate,
+        reorder_window: args.chaos_reorder,
+        seed: args.chaos_seed,
+    };
+    let enabled =
+        params.drop_rate > 0.0 || params.duplicate_rate > 0.0 || params.reorder_window > 1;
+    enabled.then(|| Chaos::new(params))
+}
+
+fn input_location(args: &RunArgs) -> &str {
+    args.input_file
+        .as_ref()
+        .or(args.input.as_ref())
+        .expect("input file is required")
+}
+
+/// The input, hashed on the way through if there's a checksum to check
+fn open_input_file(
+    args: &RunArgs,
+    hash: bool,
+) -> Result<(Input, Option<DigestHandle>), Box<dyn std::error::Error>> {
+    let input = open_input(input_location(args))?;
+    if !hash {
+        return Ok((input, None));
+    }
+    let (input, digest) = HashingReader::new(input);
+    Ok((Box::new(input), Some(digest)))
+}
+
+/// The CSV dialect from the flags, sniffing the delimiter off the start of
+/// the input if it wasn't given
+fn csv_dialect(args: &RunArgs, input: Input) -> io::Result<(Input, CsvDialect)> {
+    let mut dialect = CsvDialect {
+        quote: args.quote_char,
+        comment: args.comment_char,
+        has_headers: !args.no_header,
+        ..CsvDialect::default()
+    };
+    match args.delimiter {
+        Some(delimiter) => {
+            dialect.delimiter = delimiter;
+            Ok((input, dialect))
+        }
+        None => {
+            let mut input = BufReader::new(input);
+            dialect.delimiter = sniff_delimiter(&mut input, dialect.comment)?;
+            Ok((Box::new(input), dialect))
+        }
+    }
+}
+
+/// What the input should look like, from --verify-checksum, --manifest
+/// and/or a checksum sidecar
+#[derive(Default)]
+struct ExpectedInput {
+    checksum: Option<Checksum>,
+    records: Option<u64>,
+    /// Only from generated files' manifests
+    totals: Option<ExpectedTotals>,
+}
+
+impl ExpectedInput {
+    fn verify(
+        &self,
+        digest: Option<&DigestHandle>,
+        records: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let (Some(expected), Some(digest)) = (self.checksum, digest) {
+            let actual = digest.checksum();
+            if actual != expected {
+                return Err(
+                    format!("checksum mismatch: expected {}, got {}", expected, actual).into(),
+                );
+            }
+        }
+        match self.records {
+            Some(expected) if records != expected => {
+                Err(format!("expected {} records, read {}", expected, records).into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn expected_input(args: &RunArgs) -> Result<ExpectedInput, Box<dyn std::error::Error>> {
+    let mut expected = ExpectedInput::default();
+    if let Some(location) = &args.manifest {
+        let mut contents = String::new();
+        open_input(location)?.read_to_string(&mut contents)?;
+        let manifest: Manifest = serde_json::from_str(&contents)?;
+        expected.records = manifest.records;
+        expected.totals = manifest.expected;
+        expected.checksum = manifest
+            .checksum
+            .map(|checksum| checksum.parse())
+            .transpose()?;
+    }
+    if args.verify_checksum.is_some() {
+        expected.checksum = args.verify_checksum;
+    }
+
+    if expected.checksum.is_none() {
+        let sidecar = format!("{}.sha256", input_location(args));
+        if input_exists(&sidecar)? {
+            let mut contents = String::new();
+            open_input(&sidecar)?.read_to_string(&mut contents)?;
+            expected.checksum = Some(Checksum::from_sidecar(&contents)?);
+        }
+    }
+    Ok(expected)
+}
+
+fn write_output(
+    args: &RunArgs,
+    processor: &PaymentProcessor,
+    filter: impl Fn(ClientId, &Account) -> bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut output = create_output(args.output.as_deref())?;
+    match args.output_format {
+        OutputFormat::Csv => {
+            processor.write_csv_with_schema(&mut output, args.output_schema, filter)?
+        }
+        OutputFormat::Sql => {
+            let tables = SqlTables {
+                accounts: args.sql_accounts_table.clone(),
+                disputes: args.sql_disputes_table.clone(),
+            };
+            processor.write_sql(&mut output, &tables, filter)?
+        }
+    }
+    output.finish()?;
+    Ok(())
+}
+
+fn add_listeners(processor: &mut PaymentProcessor, listeners: &Listeners) {
+    for listener in listeners {
+        processor.add_listener(listener.clone());
+    }
+}
+
+fn process_single(
+    args: &RunArgs,
+    dialect: &CsvDialect,
+    rounding: RoundingMode,
+    mut processor: PaymentProcessor,
+    input: Input,
+    timings: &mut Timings,
+) -> Result<(PaymentProcessor, u64), Box<dyn std::error::Error>> {
+    let process_time = args.timings.then_some(&mut timings.process);
+    let records = match (args.fast_parse, args.timings) {
+        (true, true) => {
+            // Split by hand, so the CSV side and parse_record get timed separately
+            let mut reader = FastTransactionReader::with_dialect(input, dialect)?;
+            let mut validate = Duration::ZERO;
+            let results = iter::from_fn(|| {
+                let record = timed(&mut timings.parse, || reader.next_record())?;
+                Some(record.and_then(|(record, columns)| {
+                    timed(&mut validate, || parse_record(record, columns, rounding))
+                }))
+            });
+            let records = process_all(args, &mut processor, results, process_time);
+            timings.validate = Some(validate);
+            records
+        }
+        (true, false) => {
+            let reader =
+                FastTransactionReader::with_dialect(input, dialect)?.with_rounding(rounding);
+            process_all(args, &mut processor, reader, process_time)
+        }
+        (false, true) => {
+            let mut reader =
+                TransactionReader::with_dialect(input, dialect).with_rounding(rounding);
+            let mut results = reader.iter();
+            let results = iter::from_fn(|| timed(&mut timings.parse, || results.next()));
+            process_all(args, &mut processor, results, process_time)
+        }
+        (false, false) => {
+            let mut reader =
+                TransactionReader::with_dialect(input, dialect).with_rounding(rounding);
+            process_all(args, &mut processor, reader.iter(), process_time)
+        }
+    };
+    Ok((processor, records))
+}
+
+/// Returns how many records were read, including the ones that failed to parse
+fn process_all<E: Display>(
+    args: &RunArgs,
+    processor: &mut PaymentProcessor,
+    results: impl Iterator<Item = Result<Transaction, E>>,
+    mut process_time: Option<&mut Duration>,
+) -> u64 {
+    let sampler = sampler(args);
+    let mut chaos = chaos(args);
+    let mut columns = (args.engine == Engine::Columnar)
+        .then(|| ColumnarBatch::with_capacity(COLUMNAR_CHUNK_ROWS));
+    let mut mangled = Vec::new();
+    let mut records = 0;
+    for result in results {
+        records += 1;
+        match result {
+            Ok(txn) if sampler.is_some_and(|sampler| !sampler.includes(txn.client_id())) => {}
+            Ok(txn) => match &mut chaos {
+                Some(chaos) => {
+                    chaos.push(txn, &mut mangled);
+                    for txn in mangled.drain(..) {
+                        process_one(args, processor, &mut process_time, &mut columns, txn);
+                    }
+                }
+                None => process_one(args, processor, &mut process_time, &mut columns, txn),
+            },
+            Err(err) => eprintln!("Error reading transaction: {}", err),
+        }
+    }
+    if let Some(chaos) = &mut chaos {
+        chaos.finish(&mut mangled);
+        for txn in mangled.drain(..) {
+            process_one(args, processor, &mut process_time, &mut columns, txn);
+        }
+        eprintln!("{}", chaos.stats());
+    }
+    if let Some(columns) = &mut columns {
+        process_columns(processor, &mut process_time, columns);
+    }
+    records
+}
+
+// With --engine columnar the row only gets queued up, until there's a chunk's worth
+fn process_one(
+    args: &RunArgs,
+    processor: &mut PaymentProcessor,
+    process_time: &mut Option<&mut Duration>,
+    columns: &mut Option<ColumnarBatch>,
+    txn: Transaction,
+) {
+    if args.debug {
+        eprintln!("Processing: {}", txn);
+    }
+    if let Some(columns) = columns {
+        columns.push(txn);
+        if columns.len() >= COLUMNAR_CHUNK_ROWS {
+            process_columns(processor, process_time, columns);
+        }
+        return;
+    }
+    match process_time {
+        Some(process_time) => timed(process_time, || processor.process(&txn)),
+        None => processor.process(&txn),
+    }
+}
+
+fn process_columns(
+    processor: &mut PaymentProcessor,
+    process_time: &mut Option<&mut Duration>,
+    columns: &mut ColumnarBatch,
+) {
+    match process_time {
+        Some(process_time) => timed(process_time, || processor.process_columnar(columns)),
+        None => processor.process_columnar(columns),
+    }
+    columns.clear();
+}
+
+fn process_sharded(
+    args: &RunArgs,
+    dialect: &CsvDialect,
+    rounding: RoundingMode,
+    shards: Vec<PaymentProcessor>,
+    input: Input,
+    timings: &mut Timings,
+) -> Result<(PaymentProcessor, u64), Box<dyn std::error::Error>> {
+    let mut reader = ChunkedTransactionReader::with_dialect(input, dialect)?
+        .with_fast_parse(args.fast_parse)?
+        .with_rounding(rounding)
+        .with_timings(args.timings);
+    let processor = ShardedProcessor::new(shards);
+    let sampler = sampler(args);
+    let mut chaos = chaos(args);
+    let mut records = 0;
+    for batch in &mut reader {
+        records += batch.len() as u64;
+        let mut transactions = Vec::with_capacity(batch.len());
+        for result in batch {
+            match result {
+                Ok(txn) if sampler.is_some_and(|sampler| !sampler.includes(txn.client_id())) => {}
+                Ok(txn) => {
+                    if args.debug {
+                        eprintln!("Processing: {}", txn);
+                    }
+                    match &mut chaos {
+                        Some(chaos) => chaos.push(txn, &mut transactions),
+                        None => transactions.push(txn),
+                    }
+                }
+                Err(err) => eprintln!("Error reading transaction: {}", err),
+            }
+        }
+        processor.process_batch(transactions);
+    }
+    if let Some(chaos) = &mut chaos {
+        let mut transactions = Vec::new();
+        chaos.finish(&mut transactions);
+        processor.process_batch(transactions);
+        eprintln!("{}", chaos.stats());
+    }
+
+    // Nothing gets written, the shards disagree on what the state is
+    let (processor, shard_times) = match processor.finish_timed() {
+        Ok(finished) => finished,
+        Err(err) => {
+            eprintln!("Error merging shards: {}", err);
+            process::exit(1);
+        }
+    };
+    if args.timings {
+        for (thread, time) in reader.parse_times() {
+            timings.add_parse(time);
+            timings.threads.push(ThreadTimings {
+                name: format!("parse thread {}", thread),
+                parse: time.parse,
+                validate: time.validate,
+                process: Duration::ZERO,
+            });
+        }
+        for (shard, time) in shard_times.into_iter().enumerate() {
+            timings.process += time;
+            timings.threads.push(ThreadTimings {
+                name: format!("shard {}", shard),
+                parse: Duration::ZERO,
+                validate: None,
+                process: time,
+            });
+        }
+    }
+    Ok((processor, records))
+}
@@ -0,0 +1,219 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command};
+
+use clap::Args;
+
+#[derive(Args, Debug)]
+pub struct SelftestArgs {
+    /// Print the expected and actual output of failing scenarios
+    #[arg(short, long, default_value_t = false)]
+    verbose: bool,
+}
+
+/// An input file and the balances it has to end up with
+struct Scenario {
+    name: &'static str,
+    input: &'static str,
+    expected: &'static str,
+}
+
+// Every scenario is run once per mode, so the alternative parser, the
+// sharded processor and the columnar engine get smoke-tested too
+const MODES: &[&[&str]] = &[
+    &[],
+    &["--fast-parse"],
+    &["--threads", "2"],
+    &["--engine", "columnar"],
+];
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "deposit and withdraw",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10.0\n\
+                withdrawal,1,2,4.0\n\
+                deposit,2,3,2.5\n",
+        expected: "1,6.0,0.0,6.0,false\n\
+                   2,2.5,0.0,2.5,false\n",
+    },
+    Scenario {
+        name: "withdrawal over the available funds",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,1.0\n\
+                withdrawal,1,2,1.5\n",
+        expected: "1,1.0,0.0,1.0,false\n",
+    },
+    Scenario {
+        name: "dispute holds funds",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10.0\n\
+                deposit,1,2,5.0\n\
+                dispute,1,1,\n",
+        expected: "1,5.0,10.0,15.0,false\n",
+    },
+    Scenario {
+        name: "dispute then resolve",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10.0\n\
+                dispute,1,1,\n\
+                resolve,1,1,\n",
+        expected: "1,10.0,0.0,10.0,false\n",
+    },
+    Scenario {
+        name: "dispute then chargeback locks the account",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10.0\n\
+                deposit,1,2,3.0\n\
+                dispute,1,1,\n\
+                chargeback,1,1,\n\
+                deposit,1,3,100.0\n",
+        expected: "1,3.0,0.0,3.0,true\n",
+    },
+    Scenario {
+        name: "repeated dispute only holds once",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10.0\n\
+                dispute,1,1,\n\
+                dispute,1,1,\n",
+        expected: "1,0.0,10.0,10.0,false\n",
+    },
+    Scenario {
+        name: "resolve and chargeback need an open dispute",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10.0\n\
+                resolve,1,1,\n\
+                chargeback,1,1,\n",
+        expected: "1,10.0,0.0,10.0,false\n",
+    },
+    Scenario {
+        name: "disputes of another client's or unknown transactions",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10.0\n\
+                deposit,2,2,5.0\n\
+                dispute,2,1,\n\
+                dispute,1,99,\n",
+        expected: "1,10.0,0.0,10.0,false\n\
+                   2,5.0,0.0,5.0,false\n",
+    },
+    Scenario {
+        name: "re-dispute after resolve, then chargeback",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,10.0\n\
+                dispute,1,1,\n\
+                resolve,1,1,\n\
+                dispute,1,1,\n\
+                chargeback,1,1,\n",
+        expected: "1,0.0,0.0,0.0,true\n",
+    },
+    Scenario {
+        name: "transfers, both ways between clients",
+        input: "type,client,tx,amount,to\n\
+                deposit,1,1,10.0,\n\
+                transfer,1,2,4.0,2\n\
+                withdrawal,2,3,1.0,\n\
+                transfer,2,4,2.0,1\n\
+                transfer,1,5,50.0,2\n",
+        expected: "1,8.0,0.0,8.0,false\n\
+                   2,1.0,0.0,1.0,false\n",
+    },
+    Scenario {
+        name: "four decimal places",
+        input: "type,client,tx,amount\n\
+                deposit,1,1,0.1234\n\
+                deposit,1,2,0.0001\n\
+                withdrawal,1,3,0.0235\n",
+        expected: "1,0.1,0.0,0.1,false\n",
+    },
+];
+
+/// Runs every scenario through this very binary (as a child process, so
+/// it's the whole CLI being tested) and exits with 1 if any of them fail
+pub fn run(args: SelftestArgs) {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => {
+            eprintln!("Error finding the binary: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let mut failed = 0;
+    for scenario in SCENARIOS {
+        for mode in MODES {
+            let name = if mode.is_empty() {
+                scenario.name.to_string()
+            } else {
+                format!("{} ({})", scenario.name, mode.join(" "))
+            };
+            match run_scenario(&exe, scenario, mode) {
+                Ok(actual) if sorted_lines(&actual) == sorted_lines(scenario.expected) => {
+                    println!("pass  {}", name);
+                }
+                Ok(actual) => {
+                    failed += 1;
+                    println!("FAIL  {}", name);
+                    if args.verbose {
+                        println!("  expected:\n{}", indent(scenario.expected));
+                        println!("  actual:\n{}", indent(&actual));
+                    }
+                }
+                Err(err) => {
+                    failed += 1;
+                    println!("FAIL  {}: {}", name, err);
+                }
+            }
+        }
+    }
+
+    let total = SCENARIOS.len() * MODES.len();
+    println!("{} passed, {} failed", total - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// Output without the header
+fn run_scenario(exe: &Path, scenario: &Scenario, mode: &[&str]) -> Result<String, Box<dyn Error>> {
+    let path = env::temp_dir().join(format!(
+        "payments-selftest-{}-{}.csv",
+        process::id(),
+        scenario
+            .name
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "-")
+    ));
+    fs::write(&path, scenario.input)?;
+    let output = Command::new(exe).arg(&path).args(mode).output();
+    let _ = fs::remove_file(&path);
+
+    let output = output?;
+    if !output.status.success() {
+        return Err(format!(
+            "exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    let stdout = String::from_utf8(output.stdout)?;
+    match stdout.split_once('\n') {
+        Some(("client,available,held,total,locked", rows)) => Ok(rows.to_string()),
+        _ => Err(format!("unexpected header in {:?}", stdout).into()),
+    }
+}
+
+// Accounts come out in hash order
+fn sorted_lines(text: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = text.lines().collect();
+    lines.sort_unstable();
+    lines
+}
+
+fn indent(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("    {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
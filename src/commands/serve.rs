@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use clap::Args;
+use tiny_http::{Header, Request, Response, Server};
+
+use super::{load_state, state_key};
+use crate::config::Config;
+use payments::toy_payments::{ApiResponse, PaymentProcessor, ReadOnlyApi};
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Snapshot to serve (path or URL)
+    #[arg(long)]
+    state: String,
+
+    /// Only serve queries. There's no API for submitting transactions yet,
+    /// so this is required, to make sure nobody assumes otherwise.
+    #[arg(long, required = true)]
+    read_only: bool,
+
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// Number of threads answering requests
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    workers: u16,
+}
+
+pub fn run(args: ServeArgs, config: &Config) {
+    let key = match state_key(config) {
+        Ok(key) => key,
+        Err(err) => {
+            eprintln!("Error getting the state key: {}", err);
+            return;
+        }
+    };
+
+    let mut processor = PaymentProcessor::new();
+    if let Err(err) = load_state(&mut processor, &args.state, key.as_ref()) {
+        eprintln!("Error loading state: {}", err);
+        return;
+    }
+    // Nothing can get a mutable reference to the state from here on
+    let api = Arc::new(ReadOnlyApi::new(processor));
+
+    let server = match Server::http(&args.listen) {
+        Ok(server) => Arc::new(server),
+        Err(err) => {
+            eprintln!("Error listening on {}: {}", args.listen, err);
+            return;
+        }
+    };
+    eprintln!("Serving {} read-only on http://{}", args.state, args.listen);
+
+    let handle = move |request: &mut Request| api.handle(request.method().as_str(), request.url());
+    for worker in spawn_workers(server, args.workers, handle) {
+        let _ = worker.join();
+    }
+}
+
+/// Answers requests on `workers` threads with whatever `handle` makes of them
+pub fn spawn_workers(
+    server: Arc<Server>,
+    workers: u16,
+    handle: impl Fn(&mut Request) -> ApiResponse + Clone + Send + 'static,
+) -> Vec<JoinHandle<()>> {
+    let json = Header::from_bytes("Content-Type", "application/json").unwrap();
+    (0..workers)
+        .map(|_| {
+            let (handle, server, json) = (handle.clone(), server.clone(), json.clone());
+            thread::spawn(move || {
+                for mut request in server.incoming_requests() {
+                    let response = handle(&mut request);
+                    let result = request.respond(
+                        Response::from_string(response.body)
+                            .with_status_code(response.status)
+                            .with_header(json.clone()),
+                    );
+                    if let Err(err) = result {
+                        eprintln!("Error responding: {}", err);
+                    }
+                }
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::commands::run::{Engine, OutputFormat};
+#[cfg(feature = "postgres")]
+use payments::toy_payments::PostgresConfig;
+use payments::toy_payments::{
+    Amount, AmountBounds, CategoryLimit, CategoryLimits, ChargebackLayout, DedupWindow, DisputeCap,
+    DisputeWindow, EnrichmentTable, LedgerCodes, Locale, OutputSchema, ProcessorConfig,
+    RoundingMode, Schedule, TierRules, Tiers,
+};
+
+/// Settings that don't make sense as flags (connection strings and such),
+/// read from the TOML file passed with --config
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// How input amounts with more than 4 decimal places get rounded
+    #[serde(default)]
+    pub rounding: RoundingMode,
+    pub encryption: Option<EncryptionConfig>,
+    pub chargeback_export: Option<ChargebackLayout>,
+    pub daemon: Option<DaemonConfig>,
+    /// `[journal]` section, the GL account codes for --journal
+    #[serde(default)]
+    pub journal: LedgerCodes,
+    /// `[amount_bounds.<type>]` sections with a min and/or max
+    #[serde(default)]
+    pub amount_bounds: AmountBounds,
+    /// `[dispute_cap]` section, how many disputes a client can have open at once
+    pub dispute_cap: Option<DisputeCap>,
+    /// `[dispute_window]` section, how long transactions stay disputable
+    pub dispute_window: Option<DisputeWindow>,
+    /// `[category_limit.<name>]` sections, withdrawal limits by the
+    /// category from --enrichment
+    #[serde(default)]
+    pub category_limit: BTreeMap<String, CategoryLimit>,
+    /// `[tier.<name>]` sections, clients get assigned to them by --client-metadata
+    #[serde(default)]
+    pub tier: BTreeMap<String, TierRules>,
+    /// `[profile.<name>]` sections, picked with --profile
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+    #[cfg(feature = "postgres")]
+    pub postgres: Option<PostgresConfig>,
+}
+
+// The amounts in the config, by section. Every section is a set of
+// `[<section>.<name>]` tables with these fields.
+const AMOUNT_FIELDS: [(&str, &[&str]); 3] = [
+    ("amount_bounds", &["min", "max"]),
+    ("category_limit", &["withdrawal_limit", "per_client"]),
+    ("tier", &["withdrawal_fee", "withdrawal_limit", "overdraft"]),
+];
+
+impl Config {
+    /// Amounts get the same rounding as the input, which isn't known until
+    /// `rounding` has been read. So the first pass reads that (and reports
+    /// any errors with their line), and unless it's truncation a second
+    /// one reads the amounts again, rounded.
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&text)?;
+        if config.rounding == RoundingMode::Truncate {
+            return Ok(config);
+        }
+        let mut table: toml::Table = toml::from_str(&text)?;
+        round_amounts(&mut table, config.rounding);
+        Ok(table.try_into()?)
+    }
+
+    /// What every command applies transactions with. The tiers, enrichment
+    /// and dedup window come from each command's own flags. Category limits
+    /// need the enrichment, they're left out without it.
+    pub fn processor_config(
+        &self,
+        tiers: Arc<Tiers>,
+        enrichment: Option<&Arc<EnrichmentTable>>,
+        dedup_window: Option<DedupWindow>,
+    ) -> ProcessorConfig {
+        let category_limits = enrichment
+            .filter(|_| !self.category_limit.is_empty())
+            .map(|table| {
+                Arc::new(CategoryLimits::new(
+                    self.category_limit.clone(),
+                    table.clone(),
+                ))
+            });
+        ProcessorConfig {
+            amount_bounds: self.amount_bounds.clone(),
+            tiers,
+            dispute_cap: self.dispute_cap,
+            category_limits,
+            dispute_window: self.dispute_window,
+            dedup_window,
+            ..ProcessorConfig::default()
+        }
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile, String> {
+        self.profile.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profile.keys().map(String::as_str).collect();
+            format!(
+                "no profile named {} in the config (there's {})",
+                name,
+                if known.is_empty() {
+                    String::from("none")
+                } else {
+                    known.join(", ")
+                }
+            )
+        })
+    }
+}
+
+fn round_amounts(table: &mut toml::Table, rounding: RoundingMode) {
+    for (section, fields) in AMOUNT_FIELDS {
+        let Some(toml::Value::Table(section)) = table.get_mut(section) else {
+            continue;
+        };
+        for (_, entry) in section.iter_mut() {
+            let Some(entry) = entry.as_table_mut() else {
+                continue;
+            };
+            for field in fields {
+                // Back to a float, one that's exactly the rounded amount
+                // once deserializing truncates it
+                if let Some(toml::Value::Float(amount)) = entry.get_mut(*field) {
+                    *amount = Amount::from_f64_rounded(*amount, rounding).into();
+                }
+            }
+        }
+    }
+}
+
+/// `[encryption]` section. Snapshots are encrypted whenever a key is
+/// available, either from PAYMENTS_STATE_KEY or from running `key_command`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionConfig {
+    /// Shell command that prints the hex key to stdout, e.g. a KMS decrypt call
+    pub key_command: String,
+}
+
+/// `[daemon]` section, when and where the daemon publishes balance reports
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Cron-like, in UTC, e.g. "*/15 * * * *" for every 15 minutes
+    pub schedule: Schedule,
+    /// How many reports to keep in the report directory, older ones get deleted
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+    /// Object store prefix every report also gets uploaded to, e.g.
+    /// s3://bucket/reports/ (nothing gets deleted there)
+    pub upload_to: Option<String>,
+    /// Column layout of the reports, "v1" (default) or "v2" like --output-schema
+    #[serde(default)]
+    pub output_schema: OutputSchema,
+}
+
+fn default_keep() -> usize {
+    24
+}
+
+/// A named set of defaults for the processing flags, so a team can share a
+/// vetted setup (e.g. `[profile.strict]`, `[profile.fast]`). Flags given on
+/// the command line still win. Keys are the flag names with underscores.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    // Policies
+    pub reject_locked_adjustments: Option<bool>,
+    pub audit: Option<bool>,
+    pub dedup_window: Option<String>,
+    // Formats
+    pub output_format: Option<OutputFormat>,
+    pub output_schema: Option<OutputSchema>,
+    pub sql_accounts_table: Option<String>,
+    pub sql_disputes_table: Option<String>,
+    // Performance
+    pub threads: Option<u16>,
+    pub fast_parse: Option<bool>,
+    pub engine: Option<Engine>,
+    pub expect_clients: Option<usize>,
+    pub expect_rows: Option<usize>,
+    pub transaction_filter: Option<bool>,
+    pub stats: Option<bool>,
+    pub timings: Option<bool>,
+    // Summaries
+    pub locale: Option<Locale>,
+}
@@ -0,0 +1 @@
+pub mod toy_payments;
@@ -1,45 +1,126 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 
-mod toy_payments;
-use toy_payments::{PaymentProcessor, TransactionReader};
+mod commands;
+mod config;
 
 /// Processes an input CSV file of payments transactions
 /// and outputs a CSV file of outstanding account balances
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// Path of the input CSV file
-    input_file: PathBuf,
-
-    /// Emit debug
-    #[arg(short, long, default_value_t = false)]
-    debug: bool,
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// TOML config file (e.g. for sink connection settings)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Named `[profile.<name>]` from the config file to take the
+    /// processing flags' defaults from
+    #[arg(long, requires = "config")]
+    profile: Option<String>,
+
+    #[command(flatten)]
+    run: commands::run::RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Apply a corrections file (adjustments, unlocks, forced resolves)
+    /// on top of a snapshot and save the result as a new snapshot
+    Backfill(commands::backfill::BackfillArgs),
+    /// Write the charged-back transactions from a snapshot in the layout
+    /// from the `[chargeback_export]` config section, for representment
+    ExportChargebacks(commands::chargebacks::ExportChargebacksArgs),
+    /// Merge one client into another in a snapshot (balances, history and
+    /// open disputes) and tombstone the old ID
+    MergeClients(commands::merge::MergeClientsArgs),
+    /// Write everything a snapshot holds about one client as JSON
+    /// (balances, stored transactions, disputes), for access requests
+    ExportClient(commands::privacy::ExportClientArgs),
+    /// Move a client's data in a snapshot over to an unused pseudonymous ID,
+    /// so the ledger still adds up without anything pointing back to them
+    EraseClient(commands::privacy::EraseClientArgs),
+    /// Run one input through two configs (or thread counts) and list
+    /// every account that ends up different
+    Compare(commands::compare::CompareArgs),
+    /// Show whether a single transaction would go through against a
+    /// snapshot and the balances it'd leave, without saving anything
+    Preview(commands::preview::PreviewArgs),
+    /// Write the accounts from a snapshot that match a query, with just
+    /// the fields asked for
+    Report(commands::report::ReportArgs),
+    /// List, approve or reject the locked accounts queued by `--review-queue`
+    Review(commands::review::ReviewArgs),
+    /// Serve balances, stored transactions and disputes from a snapshot
+    /// over HTTP, without accepting any changes
+    Serve(commands::serve::ServeArgs),
+    /// Keep ingesting files dropped into an inbox directory and publish the
+    /// balances on the `[daemon]` schedule from the config
+    Daemon(commands::daemon::DaemonArgs),
+    /// Write a reproducible random input file, plus a manifest with the
+    /// seed, parameters and the totals it has to end up with
+    Generate(commands::generate::GenerateArgs),
+    /// Run built-in end-to-end scenarios against this binary and report
+    /// pass/fail (exits with 1 on any failure)
+    Selftest(commands::selftest::SelftestArgs),
+    /// Print a shell completion script
+    Completions(commands::docs::CompletionsArgs),
+    /// Print the man page (or write one per subcommand)
+    Manpage(commands::docs::ManpageArgs),
 }
 
 fn main() {
-    let args = Args::parse();
-
-    let mut processor = PaymentProcessor::new();
-    match TransactionReader::from_path(args.input_file) {
-        Ok(mut reader) => {
-            for result in reader.iter() {
-                match result {
-                    Ok(txn) => {
-                        if args.debug {
-                            eprintln!("Processing: {}", txn);
-                        }
-                        processor.process(&txn);
-                    }
-                    Err(err) => eprintln!("Error reading transaction: {}", err),
-                }
-            }
+    // Parsed by hand to keep the matches around, they tell the profile
+    // which flags were given explicitly
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
 
-            if let Err(err) = processor.dump_csv() {
-                eprintln!("Error writing CSV output: {}", err);
+    let config = match &cli.config {
+        Some(path) => match config::Config::from_path(path) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("Error reading config: {}", err);
+                return;
             }
+        },
+        None => config::Config::default(),
+    };
+
+    if let Some(name) = &cli.profile {
+        let result = config
+            .profile(name)
+            .and_then(|profile| commands::run::apply_profile(&mut cli.run, profile, &matches));
+        if let Err(err) = result {
+            eprintln!("Error applying profile: {}", err);
+            return;
         }
-        Err(err) => eprintln!("Error opening file: {}", err),
+    }
+
+    match cli.command {
+        Some(Command::Backfill(args)) => commands::backfill::run(args, &config),
+        Some(Command::ExportChargebacks(args)) => commands::chargebacks::run(args, &config),
+        Some(Command::MergeClients(args)) => commands::merge::run(args, &config),
+        Some(Command::ExportClient(args)) => commands::privacy::export(args, &config),
+        Some(Command::EraseClient(args)) => commands::privacy::erase(args, &config),
+        Some(Command::Compare(args)) => commands::compare::run(args, &config),
+        Some(Command::Preview(args)) => commands::preview::run(args, &config),
+        Some(Command::Report(args)) => commands::report::run(args, &config),
+        Some(Command::Review(args)) => commands::review::run(args, &config),
+        Some(Command::Serve(args)) => commands::serve::run(args, &config),
+        Some(Command::Daemon(args)) => commands::daemon::run(args, &config),
+        Some(Command::Generate(args)) => commands::generate::run(args),
+        Some(Command::Selftest(args)) => commands::selftest::run(args),
+        Some(Command::Completions(args)) => commands::docs::completions(args, Cli::command()),
+        Some(Command::Manpage(args)) => commands::docs::manpage(args, Cli::command()),
+        None => commands::run::run(cli.run, &config),
     }
 }
@@ -0,0 +1,144 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use super::hashing::HashMap;
+use super::locale::{Locale, LocalizedDisplay};
+use super::{Account, Amount, ClientId, PaymentProcessor};
+
+/// A client whose total moved by more than the alert threshold in one run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceAlert {
+    pub client_id: ClientId,
+    pub before: Amount,
+    pub after: Amount,
+}
+
+impl BalanceAlert {
+    pub fn delta(&self) -> Amount {
+        self.after - self.before
+    }
+}
+
+impl LocalizedDisplay for BalanceAlert {
+    fn fmt_localized(&self, f: &mut fmt::Formatter<'_>, locale: &Locale) -> fmt::Result {
+        write!(
+            f,
+            "client {}: total {} -> {} ({})",
+            self.client_id,
+            locale.amount(self.before),
+            locale.amount(self.after),
+            locale.amount(self.delta())
+        )
+    }
+}
+
+impl fmt::Display for BalanceAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_localized(f, &Locale::default())
+    }
+}
+
+impl PaymentProcessor {
+    /// Clients whose total changed by more than `threshold` (either way)
+    /// compared to `baseline`, e.g. the accounts from a loaded snapshot.
+    /// Clients that weren't in the baseline count as starting from 0.
+    /// Sorted by client.
+    pub fn balance_alerts(
+        &self,
+        baseline: &HashMap<ClientId, Account>,
+        threshold: Amount,
+    ) -> Vec<BalanceAlert> {
+        let mut alerts: Vec<BalanceAlert> = self
+            .accounts
+            .iter()
+            .map(|(client_id, account)| BalanceAlert {
+                client_id: *client_id,
+                before: baseline
+                    .get(client_id)
+                    .map_or(Amount::from_raw(0), Account::total),
+                after: account.total(),
+            })
+            .filter(|alert| alert.delta() > threshold || -alert.delta() > threshold)
+            .collect();
+        alerts.sort_unstable_by_key(|alert| alert.client_id);
+        alerts
+    }
+}
+
+/// CSV with a header, for the alerts file
+pub fn write_alerts(out: &mut impl Write, alerts: &[BalanceAlert]) -> io::Result<()> {
+    writeln!(out, "client,before,after,delta")?;
+    for alert in alerts {
+        writeln!(
+            out,
+            "{},{},{},{}",
+            alert.client_id,
+            alert.before,
+            alert.after,
+            alert.delta()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::Transaction;
+
+    #[test]
+    fn test_balance_alerts() {
+        let mut processor = PaymentProcessor::new();
+        for (client_id, transaction_id, amount) in [(1, 1, 50.0), (2, 2, 200.0), (3, 3, 80.0)] {
+            processor.process(&Transaction::Deposit {
+                client_id,
+                transaction_id,
+                amount: Amount::from(amount),
+            });
+        }
+        let baseline = processor.accounts().clone();
+
+        // 1 goes up by exactly the threshold, 2 drops past it, 4 is new
+        processor.process(&Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 4,
+            amount: Amount::from(100),
+        });
+        processor.process(&Transaction::Withdrawal {
+            client_id: 2,
+            transaction_id: 5,
+            amount: Amount::from(150),
+        });
+        processor.process(&Transaction::Deposit {
+            client_id: 4,
+            transaction_id: 6,
+            amount: Amount::from(500),
+        });
+
+        let alerts = processor.balance_alerts(&baseline, Amount::from(100));
+        assert_eq!(
+            alerts,
+            vec![
+                BalanceAlert {
+                    client_id: 2,
+                    before: Amount::from(200),
+                    after: Amount::from(50),
+                },
+                BalanceAlert {
+                    client_id: 4,
+                    before: Amount::from(0),
+                    after: Amount::from(500),
+                },
+            ]
+        );
+
+        let mut out = Vec::new();
+        write_alerts(&mut out, &alerts).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,before,after,delta\n\
+             2,200.0000,50.0000,-150.0000\n\
+             4,0.0000,500.0000,500.0000\n"
+        );
+    }
+}
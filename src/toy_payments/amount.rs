@@ -1,13 +1,25 @@
+use std::fmt;
 use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
 
+use serde::Deserialize;
+
 // A custom Amount type since we're doing financial transactions
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Amount(i64);
 
 impl Amount {
     pub fn new(whole_units: u64) -> Self {
         Self((whole_units * 10000) as i64)
     }
+
+    /// Raw fixed-point value (in 1/10000ths), for binary formats
+    pub fn to_raw(self) -> i64 {
+        self.0
+    }
+
+    pub fn from_raw(raw: i64) -> Self {
+        Self(raw)
+    }
 }
 
 impl From<u64> for Amount {
@@ -16,18 +28,80 @@ impl From<u64> for Amount {
     }
 }
 
+impl Amount {
+    /// `value` cut down to 4 decimal places. A float can't hold most
+    /// decimals exactly (2.9999 is 29998.99999... once scaled up), so
+    /// anything within float error of 4 decimals, or of a half between
+    /// two, is taken to be exactly that before rounding.
+    pub fn from_f64_rounded(value: f64, mode: RoundingMode) -> Self {
+        let scaled = value * 10000.0;
+        let tolerance = (scaled.abs() * 4.0 * f64::EPSILON).max(1e-9);
+        let nearest = scaled.round();
+        if (scaled - nearest).abs() <= tolerance {
+            return Self(nearest as i64);
+        }
+        let whole = scaled.trunc() as i64;
+        let fraction = (scaled - scaled.trunc()).abs();
+        let tie = (fraction - 0.5).abs() <= tolerance;
+        let away_from_zero = match mode {
+            RoundingMode::Truncate => false,
+            RoundingMode::HalfUp => tie || fraction > 0.5,
+            RoundingMode::HalfEven if tie => whole % 2 != 0,
+            RoundingMode::HalfEven => fraction > 0.5,
+        };
+        if away_from_zero {
+            Self(whole.saturating_add(scaled.signum() as i64))
+        } else {
+            Self(whole)
+        }
+    }
+}
+
+// Truncates, input amounts go through from_f64_rounded with the configured mode
 impl From<f64> for Amount {
     fn from(value: f64) -> Self {
-        Self((value * 10000.0) as i64)
+        Self::from_f64_rounded(value, RoundingMode::Truncate)
     }
 }
 
+/// How input amounts with more than 4 decimal places get cut down to 4
+/// (`rounding` in the config)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// 1.99999 -> 1.9999, -1.99999 -> -1.9999
+    #[default]
+    Truncate,
+    /// Halves go away from zero, 1.00005 -> 1.0001, -1.00005 -> -1.0001
+    HalfUp,
+    /// Halves go to the even digit, 1.00005 -> 1.0000, 1.00015 -> 1.0002
+    HalfEven,
+}
+
+/// Something read from the input with its amount still the float it was
+/// written as, and a zero in its place until `round` puts it in. Lets the
+/// readers (and compare, once per side) pick the RoundingMode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unrounded<T> {
+    pub(crate) value: T,
+    pub(crate) amount: Option<f64>,
+}
+
 impl From<Amount> for f64 {
     fn from(amount: Amount) -> Self {
         amount.0 as f64 / 10000.0
     }
 }
 
+// Exact, always with 4 decimal places (no going through f64)
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let raw = self.0.unsigned_abs();
+        write!(f, "{}{}.{:04}", sign, raw / 10000, raw % 10000)
+    }
+}
+
 impl Add for Amount {
     type Output = Self;
     fn add(self, other: Self) -> Self {
@@ -65,6 +139,71 @@ impl Neg for Amount {
 mod tests {
     use super::*;
 
+    fn parsed(value: &str, mode: RoundingMode) -> String {
+        Amount::from_f64_rounded(value.parse().unwrap(), mode).to_string()
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        use RoundingMode::*;
+        for (input, truncate, half_up, half_even) in [
+            ("1.99999", "1.9999", "2.0000", "2.0000"),
+            ("2.9999", "2.9999", "2.9999", "2.9999"),
+            ("1.00005", "1.0000", "1.0001", "1.0000"),
+            ("1.00015", "1.0001", "1.0002", "1.0002"),
+            ("1.000049", "1.0000", "1.0000", "1.0000"),
+            ("1.000051", "1.0000", "1.0001", "1.0001"),
+            ("-1.00005", "-1.0000", "-1.0001", "-1.0000"),
+            ("-1.00015", "-1.0001", "-1.0002", "-1.0002"),
+            ("-0.00005", "0.0000", "-0.0001", "0.0000"),
+            ("0.00005", "0.0000", "0.0001", "0.0000"),
+            (
+                "123456789.12345",
+                "123456789.1234",
+                "123456789.1235",
+                "123456789.1234",
+            ),
+        ] {
+            assert_eq!(parsed(input, Truncate), truncate, "{} truncated", input);
+            assert_eq!(parsed(input, HalfUp), half_up, "{} half up", input);
+            assert_eq!(parsed(input, HalfEven), half_even, "{} half even", input);
+        }
+    }
+
+    #[test]
+    fn test_rounding_boundaries() {
+        use RoundingMode::*;
+        // Every 4 decimal amount up to 10 and from 10 million up, each with
+        // the half after it
+        let large = 100_000_000_000i64;
+        for raw in (0..100_000).chain(large..large + 100_000) {
+            let (whole, fraction) = (raw / 10000, raw % 10000);
+            let exact = format!("{}.{:04}", whole, fraction);
+            let half = format!("{}.{:04}5", whole, fraction);
+            let even = if fraction % 2 == 0 { raw } else { raw + 1 };
+            for mode in [Truncate, HalfUp, HalfEven] {
+                assert_eq!(parsed(&exact, mode), exact, "{} {:?}", exact, mode);
+            }
+            let expected = [(Truncate, raw), (HalfUp, raw + 1), (HalfEven, even)];
+            for (mode, expected) in expected {
+                assert_eq!(
+                    Amount::from_f64_rounded(half.parse().unwrap(), mode),
+                    Amount::from_raw(expected),
+                    "{} {:?}",
+                    half,
+                    mode
+                );
+                assert_eq!(
+                    Amount::from_f64_rounded(-half.parse::<f64>().unwrap(), mode),
+                    Amount::from_raw(-expected),
+                    "-{} {:?}",
+                    half,
+                    mode
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_repeated_addition_no_drift() {
         let mut total = Amount::from(0.0);
@@ -150,6 +289,14 @@ mod tests {
         assert_ne!(a, b);
     }
 
+    #[test]
+    fn test_display() {
+        assert_eq!(Amount::from(0).to_string(), "0.0000");
+        assert_eq!(Amount::from_raw(12345).to_string(), "1.2345");
+        assert_eq!(Amount::from_raw(-5).to_string(), "-0.0005");
+        assert_eq!(Amount::from(1000000).to_string(), "1000000.0000");
+    }
+
     #[test]
     fn test_decimal_truncation() {
         let a = Amount::from(1.99999);
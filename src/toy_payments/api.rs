@@ -0,0 +1,371 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::hashing::HashMap;
+use super::{
+    Account, ClientId, DisputeState, PaymentProcessor, RoundingMode, StoredTransaction,
+    Transaction, TransactionId, Unrounded,
+};
+
+/// Answers queries about a processor's state (balances, stored
+/// transactions, disputes) without any way of changing it. The HTTP side
+/// lives in the binary, this only maps a method and path to a JSON response.
+///
+/// - `GET /accounts` and `GET /accounts/<client>`
+/// - `GET /accounts/<client>/transactions`, the client's stored
+///   deposits/withdrawals with their dispute state
+/// - `GET /transactions/<tx>`
+/// - `GET /disputes`, every open dispute
+/// - `GET /health`
+pub struct ReadOnlyApi {
+    // Only the state, not the processor, so there's nothing to process
+    // transactions with (and no listeners keeping it from being shared)
+    accounts: HashMap<ClientId, Account>,
+    transactions: HashMap<TransactionId, StoredTransaction>,
+    merged_clients: HashMap<ClientId, ClientId>,
+    by_client: HashMap<ClientId, Vec<TransactionId>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiResponse {
+    pub status: u16,
+    /// Always JSON
+    pub body: String,
+}
+
+// Amounts go out as their exact 4 decimal strings
+#[derive(Serialize)]
+struct AccountView {
+    client: ClientId,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    merged_into: Option<ClientId>,
+}
+
+#[derive(Serialize)]
+struct TransactionView {
+    tx: TransactionId,
+    client: ClientId,
+    amount: String,
+    state: &'static str,
+}
+
+impl ReadOnlyApi {
+    pub fn new(processor: PaymentProcessor) -> Self {
+        // Indexed once up front, the state never changes after this
+        let mut by_client: HashMap<ClientId, Vec<TransactionId>> = HashMap::default();
+        for (transaction_id, stored) in &processor.compressed_transactions {
+            by_client
+                .entry(stored.client_id)
+                .or_default()
+                .push(*transaction_id);
+        }
+        for transaction_ids in by_client.values_mut() {
+            transaction_ids.sort_unstable();
+        }
+        Self {
+            accounts: processor.accounts,
+            transactions: processor.compressed_transactions,
+            merged_clients: processor.merged_clients,
+            by_client,
+        }
+    }
+
+    pub fn handle(&self, method: &str, path: &str) -> ApiResponse {
+        if method != "GET" {
+            return error(405, "read-only, only GET is supported");
+        }
+        let path = path.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match segments.as_slice() {
+            ["health"] => ok(&serde_json::json!({ "status": "ok" })),
+            ["accounts"] => {
+                let mut client_ids: Vec<ClientId> = self.accounts.keys().copied().collect();
+                client_ids.sort_unstable();
+                let accounts: Vec<AccountView> = client_ids
+                    .into_iter()
+                    .map(|client_id| account_view(client_id, &self.accounts[&client_id]))
+                    .collect();
+                ok(&accounts)
+            }
+            ["accounts", client] => match self.client(client) {
+                Ok(client_id) => self.account(client_id),
+                Err(response) => response,
+            },
+            ["accounts", client, "transactions"] => match self.client(client) {
+                Ok(client_id) => {
+                    let transactions: Vec<TransactionView> = self
+                        .by_client
+                        .get(&client_id)
+                        .into_iter()
+                        .flatten()
+                        .map(|transaction_id| self.transaction_view(*transaction_id))
+                        .collect();
+                    ok(&transactions)
+                }
+                Err(response) => response,
+            },
+            ["transactions", tx] => match tx.parse::<TransactionId>() {
+                Ok(transaction_id) if self.transactions.contains_key(&transaction_id) => {
+                    ok(&self.transaction_view(transaction_id))
+                }
+                Ok(_) => error(404, "unknown transaction"),
+                Err(_) => error(400, "transaction IDs are numbers"),
+            },
+            ["disputes"] => {
+                let mut disputes: Vec<TransactionView> = self
+                    .transactions
+                    .iter()
+                    .filter(|(_, stored)| stored.state == DisputeState::Disputed)
+                    .map(|(transaction_id, _)| self.transaction_view(*transaction_id))
+                    .collect();
+                disputes.sort_unstable_by_key(|view| view.tx);
+                ok(&disputes)
+            }
+            _ => error(404, "not found"),
+        }
+    }
+
+    fn client(&self, client: &str) -> Result<ClientId, ApiResponse> {
+        client
+            .parse()
+            .map_err(|_| error(400, "client IDs are numbers"))
+    }
+
+    fn account(&self, client_id: ClientId) -> ApiResponse {
+        if let Some(account) = self.accounts.get(&client_id) {
+            return ok(&account_view(client_id, account));
+        }
+        // Merged-away clients point at where they went
+        match self.merged_clients.get(&client_id) {
+            Some(into) => ok(&AccountView {
+                merged_into: Some(*into),
+                ..account_view(client_id, &Account::new())
+            }),
+            None => error(404, "unknown client"),
+        }
+    }
+
+    fn transaction_view(&self, transaction_id: TransactionId) -> TransactionView {
+        let stored = &self.transactions[&transaction_id];
+        TransactionView {
+            tx: transaction_id,
+            client: stored.client_id,
+            amount: stored.amount.to_string(),
+            state: stored.state.name(),
+        }
+    }
+}
+
+/// Handles `POST /transactions` against a live processor. The body is one
+/// transaction as JSON, with the same fields as a CSV row (`type`,
+/// `client`, `tx`, `amount`, ...), and the response is its result code,
+/// e.g. `{"tx":5,"result":"REJECTED_LOCKED"}`. A rejection is still a 200,
+/// it's an answer about the transaction rather than about the request.
+///
+/// Requests with an `Idempotency-Key` header that was seen before get the
+/// original response back instead of being processed again, so clients can
+/// retry safely. Only the most recent `capacity` keys are remembered.
+pub struct SubmitApi {
+    responses: HashMap<String, ApiResponse>,
+    // Oldest first, for evicting
+    keys: VecDeque<String>,
+    capacity: usize,
+    rounding: RoundingMode,
+}
+
+#[derive(Serialize)]
+struct SubmitView {
+    tx: TransactionId,
+    result: String,
+}
+
+impl SubmitApi {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            responses: HashMap::default(),
+            keys: VecDeque::new(),
+            capacity,
+            rounding: RoundingMode::default(),
+        }
+    }
+
+    /// How submitted amounts with more than 4 decimals get cut down,
+    /// truncated by default
+    pub fn with_rounding(mut self, rounding: RoundingMode) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    pub fn submit(
+        &mut self,
+        processor: &mut PaymentProcessor,
+        idempotency_key: Option<&str>,
+        body: &str,
+    ) -> ApiResponse {
+        if let Some(response) = idempotency_key.and_then(|key| self.responses.get(key)) {
+            return response.clone();
+        }
+        let parsed = serde_json::from_str::<Unrounded<Transaction>>(body);
+        let response = match parsed.map(|transaction| transaction.round(self.rounding)) {
+            Ok(transaction) => ok(&SubmitView {
+                tx: transaction.transaction_id(),
+                result: processor.process_with_result(&transaction).to_string(),
+            }),
+            // Nothing happened, so there's nothing to remember either
+            Err(err) => return error(400, &format!("bad transaction: {}", err)),
+        };
+        if let Some(key) = idempotency_key {
+            self.remember(key.to_string(), response.clone());
+        }
+        response
+    }
+
+    /// The remembered keys and their responses, oldest first
+    pub fn remembered(&self) -> impl Iterator<Item = (&str, &ApiResponse)> {
+        self.keys
+            .iter()
+            .map(|key| (key.as_str(), &self.responses[key]))
+    }
+
+    /// Answer `key` with `response` from now on, e.g. for keys another
+    /// process saw
+    pub fn remember(&mut self, key: String, response: ApiResponse) {
+        if self.capacity == 0 || self.responses.contains_key(&key) {
+            return;
+        }
+        if self.keys.len() == self.capacity
+            && let Some(oldest) = self.keys.pop_front()
+        {
+            self.responses.remove(&oldest);
+        }
+        self.keys.push_back(key.clone());
+        self.responses.insert(key, response);
+    }
+}
+
+fn account_view(client_id: ClientId, account: &Account) -> AccountView {
+    AccountView {
+        client: client_id,
+        available: account.available().to_string(),
+        held: account.held().to_string(),
+        total: account.total().to_string(),
+        locked: account.is_locked(),
+        merged_into: None,
+    }
+}
+
+fn ok(body: &impl Serialize) -> ApiResponse {
+    ApiResponse {
+        status: 200,
+        body: serde_json::to_string(body).expect("views always serialize"),
+    }
+}
+
+fn error(status: u16, message: &str) -> ApiResponse {
+    ApiResponse {
+        status,
+        body: serde_json::json!({ "error": message }).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, Transaction};
+
+    fn api() -> ReadOnlyApi {
+        let mut processor = PaymentProcessor::new();
+        for transaction in [
+            Transaction::Deposit {
+                client_id: 1,
+                transaction_id: 1,
+                amount: Amount::from(10),
+            },
+            Transaction::Deposit {
+                client_id: 1,
+                transaction_id: 2,
+                amount: Amount::from(2.5),
+            },
+            Transaction::Dispute {
+                client_id: 1,
+                transaction_id: 2,
+            },
+            Transaction::Deposit {
+                client_id: 2,
+                transaction_id: 3,
+                amount: Amount::from(1),
+            },
+        ] {
+            processor.process(&transaction);
+        }
+        ReadOnlyApi::new(processor)
+    }
+
+    #[test]
+    fn test_queries() {
+        let api = api();
+        assert_eq!(
+            api.handle("GET", "/accounts/1").body,
+            r#"{"client":1,"available":"10.0000","held":"2.5000","total":"12.5000","locked":false}"#
+        );
+        assert_eq!(
+            api.handle("GET", "/accounts/1/transactions").body,
+            r#"[{"tx":1,"client":1,"amount":"10.0000","state":"undisputed"},{"tx":2,"client":1,"amount":"2.5000","state":"disputed"}]"#
+        );
+        assert_eq!(
+            api.handle("GET", "/disputes").body,
+            r#"[{"tx":2,"client":1,"amount":"2.5000","state":"disputed"}]"#
+        );
+        assert_eq!(
+            api.handle("GET", "/transactions/3?pretty").body,
+            r#"{"tx":3,"client":2,"amount":"1.0000","state":"undisputed"}"#
+        );
+        assert_eq!(api.handle("GET", "/accounts").status, 200);
+        assert_eq!(api.handle("GET", "/accounts/9/transactions").body, "[]");
+    }
+
+    #[test]
+    fn test_errors() {
+        let api = api();
+        assert_eq!(api.handle("POST", "/accounts/1").status, 405);
+        assert_eq!(api.handle("DELETE", "/accounts/1").status, 405);
+        assert_eq!(api.handle("GET", "/accounts/9").status, 404);
+        assert_eq!(api.handle("GET", "/accounts/x").status, 400);
+        assert_eq!(api.handle("GET", "/transactions/99").status, 404);
+        assert_eq!(api.handle("GET", "/nope").status, 404);
+    }
+
+    #[test]
+    fn test_submit() {
+        let mut processor = PaymentProcessor::new();
+        let mut submit = SubmitApi::new(1);
+        let deposit = r#"{"type":"deposit","client":1,"tx":1,"amount":10.5}"#;
+        assert_eq!(
+            submit.submit(&mut processor, Some("a"), deposit).body,
+            r#"{"tx":1,"result":"OK"}"#
+        );
+        // A retry gets the same answer and doesn't deposit twice
+        assert_eq!(
+            submit.submit(&mut processor, Some("a"), deposit).body,
+            r#"{"tx":1,"result":"OK"}"#
+        );
+        assert_eq!(processor.accounts()[&1].available(), Amount::from(10.5));
+
+        let withdrawal = r#"{"type":"withdrawal","client":1,"tx":2,"amount":20}"#;
+        assert_eq!(
+            submit.submit(&mut processor, Some("b"), withdrawal).body,
+            r#"{"tx":2,"result":"REJECTED_INSUFFICIENT_FUNDS"}"#
+        );
+        // Only one key is remembered, so "a" is gone and gets processed again
+        submit.submit(&mut processor, Some("a"), deposit);
+        assert_eq!(processor.accounts()[&1].available(), Amount::from(21));
+
+        let response = submit.submit(&mut processor, None, r#"{"type":"deposit"}"#);
+        assert_eq!(response.status, 400);
+    }
+}
@@ -0,0 +1,200 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use super::clock::{Clock, format_timestamp};
+use super::enrichment::EnrichmentTable;
+use super::events::{EventListener, RejectionReason};
+use super::{Account, ClientId, OperatorAction, Transaction};
+
+/// Writes a line for every processed transaction and every account lock.
+/// Adjustments are marked with `[ADJUSTMENT]` so manual corrections stand
+/// out when going back through the log.
+pub struct AuditLog<W: Write + Send> {
+    writer: W,
+    clock: Option<Arc<dyn Clock>>,
+    enrichment: Option<Arc<EnrichmentTable>>,
+}
+
+impl<W: Write + Send> AuditLog<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            clock: None,
+            enrichment: None,
+        }
+    }
+
+    /// Prefixes every line with the time from `clock`
+    pub fn with_clock(writer: W, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            writer,
+            clock: Some(clock),
+            enrichment: None,
+        }
+    }
+
+    /// Adds the merchant/category/channel of each transaction to its lines
+    pub fn enriched(mut self, enrichment: Arc<EnrichmentTable>) -> Self {
+        self.enrichment = Some(enrichment);
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_line(&mut self, line: std::fmt::Arguments) {
+        let result = match &self.clock {
+            Some(clock) => writeln!(self.writer, "[{}] {}", format_timestamp(clock.now()), line),
+            None => writeln!(self.writer, "{}", line),
+        };
+        if let Err(err) = result {
+            eprintln!("Error writing audit log: {}", err);
+        }
+    }
+}
+
+impl<W: Write + Send> AuditLog<W> {
+    fn enrichment(&self, transaction: &Transaction) -> &dyn std::fmt::Display {
+        match self
+            .enrichment
+            .as_ref()
+            .and_then(|table| table.get(transaction.transaction_id()))
+        {
+            Some(enrichment) => enrichment,
+            None => &"",
+        }
+    }
+}
+
+fn marker(transaction: &Transaction) -> &'static str {
+    match transaction {
+        Transaction::Adjustment { .. } => "[ADJUSTMENT] ",
+        _ => "",
+    }
+}
+
+impl<W: Write + Send> EventListener for AuditLog<W> {
+    fn on_applied(&mut self, transaction: &Transaction) {
+        let enrichment = self.enrichment(transaction).to_string();
+        self.write_line(format_args!(
+            "{}applied: {}{}",
+            marker(transaction),
+            transaction,
+            enrichment
+        ));
+    }
+
+    fn on_rejected(&mut self, transaction: &Transaction, reason: RejectionReason) {
+        let enrichment = self.enrichment(transaction).to_string();
+        self.write_line(format_args!(
+            "{}rejected ({}): {}{}",
+            marker(transaction),
+            reason,
+            transaction,
+            enrichment
+        ));
+    }
+
+    fn on_account_locked(&mut self, client_id: ClientId, transaction: &Transaction, _: &Account) {
+        let enrichment = self.enrichment(transaction).to_string();
+        self.write_line(format_args!(
+            "locked: client: {}, by: {}{}",
+            client_id, transaction, enrichment
+        ));
+    }
+
+    fn on_operator_action(&mut self, action: &OperatorAction, result: Result<(), RejectionReason>) {
+        match result {
+            Ok(()) => self.write_line(format_args!("[OPERATOR] applied: {}", action)),
+            Err(reason) => {
+                self.write_line(format_args!("[OPERATOR] rejected ({}): {}", reason, action))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, ManualClock};
+    use std::time::Duration;
+
+    #[test]
+    fn test_adjustments_flagged() {
+        let mut log = AuditLog::new(Vec::new());
+        let adjustment = Transaction::Adjustment {
+            client_id: 1,
+            transaction_id: 7,
+            amount: Amount::from(2.5),
+            reference: String::from("INC-1"),
+        };
+        let deposit = Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 8,
+            amount: Amount::from(1),
+        };
+
+        log.on_applied(&adjustment);
+        log.on_rejected(&deposit, RejectionReason::AccountLocked);
+
+        assert_eq!(
+            String::from_utf8(log.writer).unwrap(),
+            "[ADJUSTMENT] applied: type: adjustment, client: 1, tx: 7, amount: 2.5000, reference: INC-1\n\
+             rejected (account locked): type: deposit, client: 1, tx: 8, amount: 1.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_enriched() {
+        let table =
+            EnrichmentTable::read("tx,merchant,category\n1,ACME,groceries\n".as_bytes()).unwrap();
+        let mut log = AuditLog::new(Vec::new()).enriched(Arc::new(table));
+        let deposit = Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Amount::from(1),
+        };
+        let dispute = Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 1,
+        };
+        let other = Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 2,
+            amount: Amount::from(1),
+        };
+
+        log.on_applied(&deposit);
+        log.on_applied(&dispute);
+        log.on_applied(&other);
+
+        assert_eq!(
+            String::from_utf8(log.writer).unwrap(),
+            "applied: type: deposit, client: 1, tx: 1, amount: 1.0000, merchant: ACME, category: groceries\n\
+             applied: type: dispute, client: 1, tx: 1, merchant: ACME, category: groceries\n\
+             applied: type: deposit, client: 1, tx: 2, amount: 1.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let clock = Arc::new(ManualClock::from_secs(1_700_000_000));
+        let mut log = AuditLog::with_clock(Vec::new(), clock.clone());
+        let deposit = Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Amount::from(1),
+        };
+
+        log.on_applied(&deposit);
+        clock.advance(Duration::from_millis(250));
+        log.on_account_locked(1, &deposit, &Account::new());
+
+        assert_eq!(
+            String::from_utf8(log.writer).unwrap(),
+            "[1700000000.000] applied: type: deposit, client: 1, tx: 1, amount: 1.0000\n\
+             [1700000000.250] locked: client: 1, by: type: deposit, client: 1, tx: 1, amount: 1.0000\n"
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use std::fmt;
+use std::{fs::File, path::PathBuf};
+
+use csv::{Reader, ReaderBuilder};
+use serde::{Deserialize, Deserializer};
+
+use super::amount::{Amount, RoundingMode, Unrounded};
+use super::events::RejectionReason;
+use super::{ClientId, OperatorAction, PaymentProcessor, Transaction, TransactionId};
+
+/// One row of a corrections file. Same columns as a transaction file
+/// (`type, client, tx, amount, reference`), where the type is one of
+/// `adjustment_credit`, `adjustment_debit`, `unlock` or `force_resolve`,
+/// and every row needs a reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Correction {
+    /// Always a Transaction::Adjustment
+    Adjustment(Transaction),
+    Action(OperatorAction),
+}
+
+impl Correction {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Correction::Adjustment(Transaction::Adjustment { amount, .. })
+                if *amount < Amount::from(0) =>
+            {
+                "adjustment_debit"
+            }
+            Correction::Adjustment(_) => "adjustment_credit",
+            Correction::Action(OperatorAction::Unlock { .. }) => "unlock",
+            Correction::Action(OperatorAction::ForceResolve { .. }) => "force_resolve",
+            Correction::Action(OperatorAction::ReverseChargeback { .. }) => "reverse_chargeback",
+            Correction::Action(OperatorAction::KeepLocked { .. }) => "keep_locked",
+            Correction::Action(OperatorAction::MergeClient { .. }) => "merge",
+            Correction::Action(OperatorAction::EraseClient { .. }) => "erase",
+        }
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            Correction::Adjustment(transaction) => transaction.client_id(),
+            Correction::Action(OperatorAction::Unlock { client_id, .. })
+            | Correction::Action(OperatorAction::ForceResolve { client_id, .. })
+            | Correction::Action(OperatorAction::ReverseChargeback { client_id, .. })
+            | Correction::Action(OperatorAction::KeepLocked { client_id, .. })
+            | Correction::Action(OperatorAction::MergeClient { client_id, .. })
+            | Correction::Action(OperatorAction::EraseClient { client_id, .. }) => *client_id,
+        }
+    }
+
+    /// Unlocks aren't tied to a transaction
+    pub fn transaction_id(&self) -> Option<TransactionId> {
+        match self {
+            Correction::Adjustment(transaction) => Some(transaction.transaction_id()),
+            Correction::Action(OperatorAction::Unlock { .. })
+            | Correction::Action(OperatorAction::KeepLocked { .. })
+            | Correction::Action(OperatorAction::MergeClient { .. })
+            | Correction::Action(OperatorAction::EraseClient { .. }) => None,
+            Correction::Action(OperatorAction::ForceResolve { transaction_id, .. })
+            | Correction::Action(OperatorAction::ReverseChargeback { transaction_id, .. }) => {
+                Some(*transaction_id)
+            }
+        }
+    }
+
+    pub fn reference(&self) -> &str {
+        match self {
+            Correction::Adjustment(Transaction::Adjustment { reference, .. })
+            | Correction::Action(OperatorAction::Unlock { reference, .. })
+            | Correction::Action(OperatorAction::ForceResolve { reference, .. })
+            | Correction::Action(OperatorAction::ReverseChargeback { reference, .. })
+            | Correction::Action(OperatorAction::KeepLocked { reference, .. })
+            | Correction::Action(OperatorAction::MergeClient { reference, .. })
+            | Correction::Action(OperatorAction::EraseClient { reference, .. }) => reference,
+            Correction::Adjustment(_) => "",
+        }
+    }
+}
+
+impl fmt::Display for Correction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Correction::Adjustment(transaction) => write!(f, "{}", transaction),
+            Correction::Action(action) => write!(f, "{}", action),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CorrectionRow {
+    #[serde(rename = "type")]
+    ty: String,
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "tx", default)]
+    transaction_id: Option<TransactionId>,
+    #[serde(default)]
+    amount: Option<f64>,
+    #[serde(default)]
+    reference: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for Unrounded<Correction> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let row = CorrectionRow::deserialize(deserializer)?;
+        let reference = row
+            .reference
+            .filter(|reference| !reference.is_empty())
+            .ok_or_else(|| D::Error::custom("missing reference for correction"))?;
+        let transaction_id = || {
+            row.transaction_id
+                .ok_or_else(|| D::Error::custom(format!("missing tx for {}", row.ty)))
+        };
+        // Only checked for here, `round` fills it in
+        let amount = || match row.amount {
+            Some(_) => Ok(Amount::default()),
+            None => Err(D::Error::custom(format!("missing amount for {}", row.ty))),
+        };
+
+        let value = match row.ty.trim() {
+            "adjustment_credit" | "adjustment_debit" => {
+                Correction::Adjustment(Transaction::Adjustment {
+                    client_id: row.client_id,
+                    transaction_id: transaction_id()?,
+                    amount: amount()?,
+                    reference,
+                })
+            }
+            "unlock" => Correction::Action(OperatorAction::Unlock {
+                client_id: row.client_id,
+                reference,
+            }),
+            "force_resolve" => Correction::Action(OperatorAction::ForceResolve {
+                client_id: row.client_id,
+                transaction_id: transaction_id()?,
+                reference,
+            }),
+            "reverse_chargeback" => Correction::Action(OperatorAction::ReverseChargeback {
+                client_id: row.client_id,
+                transaction_id: transaction_id()?,
+                reference,
+            }),
+            ty => return Err(D::Error::custom(format!("unknown correction type: {}", ty))),
+        };
+        let amount = match row.ty.trim() {
+            "adjustment_debit" => row.amount.map(|amount| -amount),
+            _ => row.amount,
+        };
+        Ok(Unrounded { value, amount })
+    }
+}
+
+impl Unrounded<Correction> {
+    pub fn round(mut self, mode: RoundingMode) -> Correction {
+        if let (Some(raw), Correction::Adjustment(transaction)) = (self.amount, &mut self.value)
+            && let Some(amount) = transaction.amount_mut()
+        {
+            *amount = Amount::from_f64_rounded(raw, mode);
+        }
+        self.value
+    }
+}
+
+// Truncates, like Amount::from(f64). CorrectionReader takes a RoundingMode.
+impl<'de> Deserialize<'de> for Correction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Unrounded::<Correction>::deserialize(deserializer)?.round(RoundingMode::Truncate))
+    }
+}
+
+pub struct CorrectionReader {
+    reader: Reader<File>,
+    rounding: RoundingMode,
+}
+
+impl CorrectionReader {
+    pub fn from_path(path: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        let reader = ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+
+        Ok(Self {
+            reader,
+            rounding: RoundingMode::default(),
+        })
+    }
+
+    /// How amounts with more than 4 decimals get cut down, truncated by default
+    pub fn with_rounding(mut self, rounding: RoundingMode) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    pub fn iter(&mut self) -> impl Iterator<Item = csv::Result<Correction>> + '_ {
+        let rounding = self.rounding;
+        self.reader
+            .deserialize::<Unrounded<Correction>>()
+            .map(move |result| result.map(|correction| correction.round(rounding)))
+    }
+}
+
+impl PaymentProcessor {
+    pub fn apply_correction(&mut self, correction: &Correction) -> Result<(), RejectionReason> {
+        match correction {
+            Correction::Adjustment(transaction) => self.try_process(transaction),
+            Correction::Action(action) => self.apply_action(action),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(csv: &str) -> Vec<Result<Correction, String>> {
+        ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(csv.as_bytes())
+            .deserialize()
+            .map(|result| result.map_err(|err: csv::Error| err.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_corrections() {
+        let corrections = parse(
+            "type, client, tx, amount, reference
+             adjustment_debit, 1, 10, 2.5, INC-1
+             unlock, 2, , , INC-2
+             force_resolve, 3, 7, , INC-3
+             unlock, 2, , ,
+             force_resolve, 3, , , INC-4
+             refund, 3, 7, , INC-5",
+        );
+
+        assert_eq!(
+            corrections[0],
+            Ok(Correction::Adjustment(Transaction::Adjustment {
+                client_id: 1,
+                transaction_id: 10,
+                amount: -Amount::from(2.5),
+                reference: String::from("INC-1"),
+            }))
+        );
+        assert_eq!(
+            corrections[0].as_ref().unwrap().type_name(),
+            "adjustment_debit"
+        );
+        assert_eq!(
+            corrections[1],
+            Ok(Correction::Action(OperatorAction::Unlock {
+                client_id: 2,
+                reference: String::from("INC-2"),
+            }))
+        );
+        assert_eq!(
+            corrections[2],
+            Ok(Correction::Action(OperatorAction::ForceResolve {
+                client_id: 3,
+                transaction_id: 7,
+                reference: String::from("INC-3"),
+            }))
+        );
+        assert!(
+            corrections[3]
+                .as_ref()
+                .unwrap_err()
+                .contains("missing reference")
+        );
+        assert!(corrections[4].as_ref().unwrap_err().contains("missing tx"));
+        assert!(
+            corrections[5]
+                .as_ref()
+                .unwrap_err()
+                .contains("unknown correction type")
+        );
+    }
+
+    #[test]
+    fn test_round_corrections() {
+        let correction: Unrounded<Correction> = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(
+                "type, client, tx, amount, reference\nadjustment_debit, 1, 10, 2.00005, INC-1"
+                    .as_bytes(),
+            )
+            .deserialize()
+            .next()
+            .unwrap()
+            .unwrap();
+        let debit = |raw| {
+            Correction::Adjustment(Transaction::Adjustment {
+                client_id: 1,
+                transaction_id: 10,
+                amount: Amount::from_raw(raw),
+                reference: String::from("INC-1"),
+            })
+        };
+
+        assert_eq!(
+            correction.clone().round(RoundingMode::HalfUp),
+            debit(-20001)
+        );
+        assert_eq!(correction.round(RoundingMode::Truncate), debit(-20000));
+    }
+}
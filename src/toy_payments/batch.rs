@@ -0,0 +1,198 @@
+use std::error::Error;
+use std::fmt;
+
+use super::{Account, ClientId, PaymentProcessor, RejectionReason, Transaction};
+
+/// What happens to the rest of a batch when one of its transactions gets
+/// rejected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BatchPolicy {
+    /// Nothing from the batch is applied
+    #[default]
+    AllOrNothing,
+    /// The transactions before the rejected one stay applied, the ones
+    /// after it aren't tried
+    KeepApplied,
+}
+
+/// How a batch went through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchReceipt {
+    pub applied: usize,
+    /// Every account the batch touched, after it, by client ID
+    pub accounts: Vec<(ClientId, Account)>,
+}
+
+/// The transaction that stopped a batch, by its position in it, and how
+/// many of the ones before it were kept (see BatchPolicy)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchError {
+    pub index: usize,
+    pub reason: RejectionReason,
+    pub applied: usize,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "transaction {} of the batch rejected ({}), {} applied",
+            self.index, self.reason, self.applied
+        )
+    }
+}
+
+impl Error for BatchError {}
+
+impl PaymentProcessor {
+    /// Applies `transactions` as one unit, for callers submitting things
+    /// like a payout run for a merchant. The batch is tried on a copy of the
+    /// state first and only then applied here, so the listeners never hear
+    /// about anything that gets rolled back. The copy includes the whole
+    /// transaction store, so this is for logical groups, not whole files.
+    pub fn process_batch(
+        &mut self,
+        transactions: &[Transaction],
+    ) -> Result<BatchReceipt, BatchError> {
+        let mut copy = self.copy_state();
+        let rejected = transactions
+            .iter()
+            .enumerate()
+            .find_map(|(index, transaction)| {
+                copy.try_process(transaction)
+                    .err()
+                    .map(|reason| (index, reason))
+            });
+        let applied = match (rejected, self.config.batch_policy) {
+            (None, _) => transactions.len(),
+            (Some(_), BatchPolicy::AllOrNothing) => 0,
+            (Some((index, _)), BatchPolicy::KeepApplied) => index,
+        };
+
+        // Same state as the copy, so these go the same way
+        for transaction in &transactions[..applied] {
+            let result = self.try_process(transaction);
+            debug_assert!(result.is_ok(), "batch went differently the second time");
+        }
+        if let Some((index, reason)) = rejected {
+            return Err(BatchError {
+                index,
+                reason,
+                applied,
+            });
+        }
+
+        let mut client_ids: Vec<_> = transactions
+            .iter()
+            .flat_map(|transaction| match transaction {
+                Transaction::Transfer {
+                    client_id,
+                    to_client_id,
+                    ..
+                } => vec![*client_id, *to_client_id],
+                _ => vec![transaction.client_id()],
+            })
+            .collect();
+        client_ids.sort_unstable();
+        client_ids.dedup();
+        let accounts = client_ids
+            .into_iter()
+            .filter_map(|client_id| {
+                let account = self.accounts.get(&client_id)?;
+                Some((client_id, account.clone()))
+            })
+            .collect();
+        Ok(BatchReceipt { applied, accounts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::toy_payments::{Amount, EventListener, ProcessorConfig};
+
+    #[derive(Clone, Default)]
+    struct Applied(Arc<Mutex<usize>>);
+
+    impl EventListener for Applied {
+        fn on_applied(&mut self, _transaction: &Transaction) {
+            *self.0.lock().unwrap() += 1;
+        }
+    }
+
+    fn payouts(amounts: &[u64]) -> Vec<Transaction> {
+        (0u16..)
+            .zip(amounts)
+            .map(|(i, amount)| Transaction::Transfer {
+                client_id: 1,
+                transaction_id: 100 + u32::from(i),
+                to_client_id: 10 + i,
+                amount: Amount::from(*amount),
+            })
+            .collect()
+    }
+
+    fn merchant(batch_policy: BatchPolicy) -> (PaymentProcessor, Applied) {
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            batch_policy,
+            ..ProcessorConfig::default()
+        });
+        processor.process(&Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Amount::from(100),
+        });
+        let applied = Applied::default();
+        processor.add_listener(applied.clone());
+        (processor, applied)
+    }
+
+    #[test]
+    fn test_batch() {
+        let (mut processor, applied) = merchant(BatchPolicy::AllOrNothing);
+        let receipt = processor.process_batch(&payouts(&[30, 20])).unwrap();
+        assert_eq!(receipt.applied, 2);
+        let clients: Vec<_> = receipt
+            .accounts
+            .iter()
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        assert_eq!(clients, [1, 10, 11]);
+        assert_eq!(receipt.accounts[0].1.available(), Amount::from(50));
+        assert_eq!(*applied.0.lock().unwrap(), 2);
+
+        // The third one can't be paid, so none of them are
+        let before = processor.accounts().clone();
+        assert_eq!(
+            processor.process_batch(&payouts(&[20, 20, 20])),
+            Err(BatchError {
+                index: 2,
+                reason: RejectionReason::InsufficientFunds,
+                applied: 0,
+            })
+        );
+        assert_eq!(processor.accounts(), &before);
+        assert_eq!(*applied.0.lock().unwrap(), 2);
+        assert!(processor.check_invariants().is_empty());
+    }
+
+    #[test]
+    fn test_batch_keep_applied() {
+        let (mut processor, applied) = merchant(BatchPolicy::KeepApplied);
+        let mut batch = payouts(&[40, 40, 40]);
+        batch.push(Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 2,
+            amount: Amount::from(100),
+        });
+        let err = processor.process_batch(&batch).unwrap_err();
+        assert_eq!((err.index, err.applied), (2, 2));
+        assert_eq!(processor.accounts()[&1].available(), Amount::from(20));
+        assert!(!processor.accounts().contains_key(&12));
+        // The deposit after it never went in
+        assert!(!processor.transactions().contains_key(&2));
+        assert_eq!(*applied.0.lock().unwrap(), 2);
+    }
+}
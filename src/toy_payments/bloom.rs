@@ -0,0 +1,152 @@
+use super::TransactionId;
+
+// ~1% false positives at capacity
+pub(super) const BITS_PER_ITEM: usize = 10;
+const HASHES: u64 = 7;
+
+/// Bloom filter over transaction IDs, for answering "not in the store"
+/// without touching the store. Most transactions never get disputed, but
+/// disputes for unknown IDs (typos, other systems' IDs) still cost a full
+/// lookup otherwise. Can't remove anything, so it gets rebuilt once the
+/// store no longer matches it.
+#[derive(Debug, Clone)]
+pub struct TransactionFilter {
+    bits: Vec<u64>,
+    len: usize,
+    removed: usize,
+    capacity: usize,
+}
+
+impl TransactionFilter {
+    pub fn with_capacity(capacity: usize) -> Self {
+        let words = (capacity.max(1) * BITS_PER_ITEM).div_ceil(64);
+        Self {
+            bits: vec![0; words],
+            len: 0,
+            removed: 0,
+            capacity,
+        }
+    }
+
+    pub fn insert(&mut self, transaction_id: TransactionId) {
+        for bit in bit_indexes(self.bits.len(), u64::from(transaction_id)) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    /// For when an ID leaves the store. Its bits stay set (other IDs may
+    /// share them), so it just turns into a false positive.
+    pub fn remove(&mut self) {
+        self.len = self.len.saturating_sub(1);
+        self.removed += 1;
+    }
+
+    /// False means definitely not inserted, true means probably
+    pub fn may_contain(&self, transaction_id: TransactionId) -> bool {
+        bit_indexes(self.bits.len(), u64::from(transaction_id))
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// How many IDs went in
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// How many IDs were removed, each one leaving bits set
+    pub fn removed(&self) -> usize {
+        self.removed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How many IDs it's sized for, false positives go up past that
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+// Double hashing, the k indexes come from two halves of one mix
+pub(super) fn bit_indexes(words: usize, key: u64) -> impl Iterator<Item = usize> {
+    let hash = mix(key);
+    let (first, second) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+    let bits = (words * 64) as u64;
+    (0..HASHES).map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % bits) as usize)
+}
+
+// splitmix64's finalizer, sequential IDs need spreading out
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{
+        Amount, PaymentProcessor, ProcessorConfig, RejectionReason, Transaction,
+    };
+
+    #[test]
+    fn test_filter() {
+        let mut filter = TransactionFilter::with_capacity(10_000);
+        for transaction_id in 0..10_000 {
+            filter.insert(transaction_id * 3);
+        }
+        assert_eq!(filter.len(), 10_000);
+        assert!((0..10_000).all(|transaction_id| filter.may_contain(transaction_id * 3)));
+        let false_positives = (0..10_000)
+            .filter(|transaction_id| filter.may_contain(transaction_id * 3 + 1))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_processor_filter() {
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            transaction_filter: true,
+            ..ProcessorConfig::default()
+        });
+        for transaction_id in 1..=100 {
+            processor.process(&Transaction::Deposit {
+                client_id: 1,
+                transaction_id,
+                amount: Amount::from(1),
+            });
+        }
+        let dispute = |transaction_id| Transaction::Dispute {
+            client_id: 1,
+            transaction_id,
+        };
+        assert_eq!(
+            processor.try_process(&dispute(500)),
+            Err(RejectionReason::UnknownTransaction)
+        );
+        // Stored after the filter was built
+        processor.process(&Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 500,
+            amount: Amount::from(1),
+        });
+        assert_eq!(processor.try_process(&dispute(500)), Ok(()));
+
+        // Loading a snapshot replaces the store behind its back
+        let mut other = PaymentProcessor::new();
+        other.process(&Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 900,
+            amount: Amount::from(1),
+        });
+        let mut snapshot = Vec::new();
+        other.save_snapshot(&mut snapshot).unwrap();
+        processor.load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(processor.try_process(&dispute(900)), Ok(()));
+        assert_eq!(
+            processor.try_process(&dispute(1)),
+            Err(RejectionReason::UnknownTransaction)
+        );
+    }
+}
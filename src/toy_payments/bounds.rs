@@ -0,0 +1,138 @@
+use serde::Deserialize;
+
+use super::amount::Amount;
+use super::events::RejectionReason;
+use super::{Transaction, deserialize_amount};
+
+/// Smallest and largest amount allowed, both inclusive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bounds {
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub min: Option<Amount>,
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub max: Option<Amount>,
+}
+
+impl Bounds {
+    fn check(&self, amount: Amount) -> Result<(), RejectionReason> {
+        if self.min.is_some_and(|min| amount < min) {
+            return Err(RejectionReason::AmountBelowMinimum);
+        }
+        if self.max.is_some_and(|max| amount > max) {
+            return Err(RejectionReason::AmountAboveMaximum);
+        }
+        Ok(())
+    }
+}
+
+/// Per transaction type amount limits (`[amount_bounds.<type>]` in the
+/// config), checked before a transaction gets anywhere near an account.
+/// Adjustments are checked by size, so a debit of 50 counts as 50.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AmountBounds {
+    #[serde(default)]
+    pub deposit: Bounds,
+    #[serde(default)]
+    pub withdrawal: Bounds,
+    #[serde(default)]
+    pub adjustment: Bounds,
+    #[serde(default)]
+    pub transfer: Bounds,
+}
+
+impl AmountBounds {
+    pub fn check(&self, transaction: &Transaction) -> Result<(), RejectionReason> {
+        match transaction {
+            Transaction::Deposit { amount, .. } => self.deposit.check(*amount),
+            Transaction::Withdrawal { amount, .. } => self.withdrawal.check(*amount),
+            Transaction::Adjustment { amount, .. } => {
+                let size = if *amount < Amount::from_raw(0) {
+                    -*amount
+                } else {
+                    *amount
+                };
+                self.adjustment.check(size)
+            }
+            Transaction::Transfer { amount, .. } => self.transfer.check(*amount),
+            Transaction::Dispute { .. }
+            | Transaction::Resolve { .. }
+            | Transaction::Chargeback { .. }
+            | Transaction::AutoChargeback { .. } => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{PaymentProcessor, ProcessorConfig, Stats};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_bounds() {
+        let bounds: AmountBounds = toml::from_str(
+            "[deposit]\n\
+             min = 0.0001\n\
+             [withdrawal]\n\
+             max = 10000.0\n\
+             [adjustment]\n\
+             max = 100\n",
+        )
+        .unwrap();
+        let stats = Arc::new(Mutex::new(Stats::new()));
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            amount_bounds: bounds,
+            ..ProcessorConfig::default()
+        });
+        processor.add_listener(stats.clone());
+
+        let results: Vec<_> = [
+            Transaction::Deposit {
+                client_id: 1,
+                transaction_id: 1,
+                amount: Amount::from(20000),
+            },
+            Transaction::Deposit {
+                client_id: 1,
+                transaction_id: 2,
+                amount: Amount::from(0),
+            },
+            Transaction::Withdrawal {
+                client_id: 1,
+                transaction_id: 3,
+                amount: Amount::from(10000),
+            },
+            Transaction::Withdrawal {
+                client_id: 1,
+                transaction_id: 4,
+                amount: Amount::from(10000.0001),
+            },
+            Transaction::Adjustment {
+                client_id: 1,
+                transaction_id: 5,
+                amount: -Amount::from(150),
+                reference: String::from("INC-1"),
+            },
+        ]
+        .iter()
+        .map(|transaction| processor.try_process(transaction))
+        .collect();
+
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Err(RejectionReason::AmountBelowMinimum),
+                Ok(()),
+                Err(RejectionReason::AmountAboveMaximum),
+                Err(RejectionReason::AmountAboveMaximum),
+            ]
+        );
+        assert_eq!(processor.accounts()[&1].available(), Amount::from(10000));
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats.rejected[&RejectionReason::AmountAboveMaximum], 2);
+        assert_eq!(stats.rejected[&RejectionReason::AmountBelowMinimum], 1);
+    }
+}
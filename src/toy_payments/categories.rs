@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use super::amount::Amount;
+use super::enrichment::EnrichmentTable;
+use super::{TransactionId, deserialize_amount};
+
+/// Caps on withdrawals in one category (`[category_limit.<name>]` in the
+/// config), going by the category the enrichment file gives them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CategoryLimit {
+    /// Largest single withdrawal
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub withdrawal_limit: Option<Amount>,
+    /// Most a client can withdraw in the category altogether
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub per_client: Option<Amount>,
+}
+
+/// The category limits from the config, and the enrichment table to find
+/// each withdrawal's category in. Withdrawals without a category (or with
+/// one that has no limits) go through as usual.
+#[derive(Debug, Clone)]
+pub struct CategoryLimits {
+    names: Vec<String>,
+    limits: Vec<CategoryLimit>,
+    enrichment: Arc<EnrichmentTable>,
+}
+
+impl CategoryLimits {
+    pub fn new(limits: BTreeMap<String, CategoryLimit>, enrichment: Arc<EnrichmentTable>) -> Self {
+        let (names, limits) = limits.into_iter().unzip();
+        Self {
+            names,
+            limits,
+            enrichment,
+        }
+    }
+
+    /// Index of the transaction's category (for keeping per-client totals)
+    /// and its limits
+    pub(crate) fn lookup(&self, transaction_id: TransactionId) -> Option<(usize, CategoryLimit)> {
+        let category = &self.enrichment.get(transaction_id)?.category;
+        let index = self.names.iter().position(|name| name == category)?;
+        Some((index, self.limits[index]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{PaymentProcessor, ProcessorConfig, RejectionReason, Transaction};
+
+    fn withdrawal(transaction_id: TransactionId, amount: u64) -> Transaction {
+        Transaction::Withdrawal {
+            client_id: 1,
+            transaction_id,
+            amount: Amount::from(amount),
+        }
+    }
+
+    #[test]
+    fn test_category_limits() {
+        let config: BTreeMap<String, CategoryLimit> = toml::from_str(
+            "[gambling]\n\
+             withdrawal_limit = 50\n\
+             per_client = 80\n",
+        )
+        .unwrap();
+        let enrichment = EnrichmentTable::read(
+            "tx,category\n\
+             2,gambling\n\
+             3,gambling\n\
+             4,gambling\n\
+             5,groceries\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let limits = CategoryLimits::new(config, Arc::new(enrichment));
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            category_limits: Some(Arc::new(limits)),
+            ..ProcessorConfig::default()
+        });
+        processor.process(&Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Amount::from(500),
+        });
+
+        assert_eq!(
+            processor.try_process(&withdrawal(2, 60)),
+            Err(RejectionReason::CategoryLimitExceeded)
+        );
+        assert_eq!(processor.try_process(&withdrawal(3, 50)), Ok(()));
+        // 50 + 40 is over the 80 for the client
+        assert_eq!(
+            processor.try_process(&withdrawal(4, 40)),
+            Err(RejectionReason::CategoryLimitExceeded)
+        );
+        // Other categories and uncategorized ones aren't limited
+        assert_eq!(processor.try_process(&withdrawal(5, 100)), Ok(()));
+        assert_eq!(processor.try_process(&withdrawal(6, 100)), Ok(()));
+        assert_eq!(processor.accounts()[&1].available(), Amount::from(250));
+
+        // Totals come back from the stored withdrawals after a snapshot
+        let mut snapshot = Vec::new();
+        processor.save_snapshot(&mut snapshot).unwrap();
+        let mut restored = PaymentProcessor::with_config(processor.config.clone());
+        restored.load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(
+            restored.try_process(&withdrawal(4, 40)),
+            Err(RejectionReason::CategoryLimitExceeded)
+        );
+    }
+}
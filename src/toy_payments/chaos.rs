@@ -0,0 +1,162 @@
+use std::fmt;
+
+use super::Transaction;
+use super::sampling::Rng;
+
+/// How badly to mangle the feed. The same parameters and input always
+/// give the same mangled stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChaosParams {
+    /// Chance of a row being dropped
+    pub drop_rate: f64,
+    /// Chance of a (kept) row coming through twice
+    pub duplicate_rate: f64,
+    /// Rows get shuffled in consecutive windows of this size, so none moves
+    /// further than that. 0 or 1 keeps the order.
+    pub reorder_window: usize,
+    pub seed: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChaosStats {
+    pub dropped: u64,
+    pub duplicated: u64,
+}
+
+impl fmt::Display for ChaosStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "chaos: dropped {}, duplicated {}",
+            self.dropped, self.duplicated
+        )
+    }
+}
+
+/// Injects feed failures into parsed transactions, to see how downstream
+/// reconciliation copes with an imperfect feed
+pub struct Chaos {
+    params: ChaosParams,
+    rng: Rng,
+    window: Vec<Transaction>,
+    stats: ChaosStats,
+}
+
+impl Chaos {
+    pub fn new(params: ChaosParams) -> Self {
+        Self {
+            params,
+            rng: Rng(params.seed),
+            window: Vec::with_capacity(params.reorder_window),
+            stats: ChaosStats::default(),
+        }
+    }
+
+    /// Feeds one transaction through, adding whatever comes out the other
+    /// end (possibly nothing, possibly earlier ones) to `out`
+    pub fn push(&mut self, transaction: Transaction, out: &mut Vec<Transaction>) {
+        if self.rng.fraction() < self.params.drop_rate {
+            self.stats.dropped += 1;
+            return;
+        }
+        if self.rng.fraction() < self.params.duplicate_rate {
+            self.stats.duplicated += 1;
+            self.reorder(transaction.clone(), out);
+        }
+        self.reorder(transaction, out);
+    }
+
+    /// Flushes whatever is still waiting in the reorder window
+    pub fn finish(&mut self, out: &mut Vec<Transaction>) {
+        self.shuffle_window(out);
+    }
+
+    pub fn stats(&self) -> ChaosStats {
+        self.stats
+    }
+
+    fn reorder(&mut self, transaction: Transaction, out: &mut Vec<Transaction>) {
+        if self.params.reorder_window <= 1 {
+            out.push(transaction);
+            return;
+        }
+        self.window.push(transaction);
+        if self.window.len() == self.params.reorder_window {
+            self.shuffle_window(out);
+        }
+    }
+
+    // Fisher-Yates
+    fn shuffle_window(&mut self, out: &mut Vec<Transaction>) {
+        for index in (1..self.window.len()).rev() {
+            let other = self.rng.below(index as u64 + 1) as usize;
+            self.window.swap(index, other);
+        }
+        out.append(&mut self.window);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::Amount;
+
+    fn mangle(params: ChaosParams, count: u32) -> (Vec<u32>, ChaosStats) {
+        let mut chaos = Chaos::new(params);
+        let mut out = Vec::new();
+        for transaction_id in 0..count {
+            chaos.push(
+                Transaction::Deposit {
+                    client_id: 1,
+                    transaction_id,
+                    amount: Amount::from(1),
+                },
+                &mut out,
+            );
+        }
+        chaos.finish(&mut out);
+        let ids = out.iter().map(Transaction::transaction_id).collect();
+        (ids, chaos.stats())
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let params = ChaosParams {
+            drop_rate: 0.1,
+            duplicate_rate: 0.1,
+            reorder_window: 5,
+            seed: 42,
+        };
+        let (ids, stats) = mangle(params, 1000);
+        assert_eq!(mangle(params, 1000), (ids.clone(), stats));
+        assert_ne!(mangle(ChaosParams { seed: 43, ..params }, 1000).0, ids);
+
+        assert!(stats.dropped > 50 && stats.dropped < 150);
+        assert!(stats.duplicated > 50 && stats.duplicated < 150);
+        assert_eq!(ids.len() as u64, 1000 - stats.dropped + stats.duplicated);
+    }
+
+    #[test]
+    fn test_reorder_stays_in_window() {
+        let params = ChaosParams {
+            drop_rate: 0.0,
+            duplicate_rate: 0.0,
+            reorder_window: 4,
+            seed: 1,
+        };
+        let (ids, _) = mangle(params, 102);
+        assert_ne!(ids, (0..102).collect::<Vec<_>>());
+        for (position, id) in ids.iter().enumerate() {
+            assert_eq!(position / 4, *id as usize / 4);
+        }
+
+        let (ids, _) = mangle(
+            ChaosParams {
+                reorder_window: 0,
+                ..params
+            },
+            10,
+        );
+        assert_eq!(ids, (0..10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+
+use super::{DisputeState, PaymentProcessor, StoredTransaction, TransactionId};
+
+/// When a transaction went through each step of the dispute flow, as far
+/// as the audit log(s) know. Anything the logs don't cover stays `None`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DisputeTimeline {
+    pub created_at: Option<SystemTime>,
+    pub disputed_at: Option<SystemTime>,
+    pub resolved_at: Option<SystemTime>,
+    pub charged_back_at: Option<SystemTime>,
+}
+
+/// Picks the dispute timeline for every transaction out of an audit log
+/// written with a clock (`[secs.millis] applied: ...` lines), adding to
+/// `timelines` so a day's worth of logs can be read one after another.
+/// Rejections, locks and untimestamped lines are skipped. If a transaction
+/// gets disputed more than once, the latest dispute wins.
+pub fn read_dispute_timelines(
+    reader: impl BufRead,
+    timelines: &mut HashMap<TransactionId, DisputeTimeline>,
+) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        let Some((time, kind, transaction_id)) = parse_audit_line(&line) else {
+            continue;
+        };
+        let timeline = timelines.entry(transaction_id).or_default();
+        match kind {
+            "deposit" | "withdrawal" => timeline.created_at = Some(time),
+            "dispute" => timeline.disputed_at = Some(time),
+            "resolve" | "force_resolve" => timeline.resolved_at = Some(time),
+            "chargeback" => timeline.charged_back_at = Some(time),
+            "auto_chargeback" => {
+                timeline.disputed_at = Some(time);
+                timeline.charged_back_at = Some(time);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn parse_audit_line(line: &str) -> Option<(SystemTime, &str, TransactionId)> {
+    let (time, rest) = line.strip_prefix('[')?.split_once("] ")?;
+    let rest = rest.strip_prefix("[OPERATOR] ").unwrap_or(rest);
+    let fields = rest.strip_prefix("applied: ")?;
+
+    let mut kind = None;
+    let mut transaction_id = None;
+    for field in fields.split(", ") {
+        match field.split_once(": ") {
+            Some(("type", value)) => kind = Some(value),
+            Some(("tx", value)) => transaction_id = value.parse().ok(),
+            _ => {}
+        }
+    }
+    Some((parse_timestamp(time)?, kind?, transaction_id?))
+}
+
+// The reverse of clock::format_timestamp
+fn parse_timestamp(time: &str) -> Option<SystemTime> {
+    let (secs, millis) = time.split_once('.')?;
+    let since_epoch =
+        Duration::from_secs(secs.parse().ok()?) + Duration::from_millis(millis.parse().ok()?);
+    Some(SystemTime::UNIX_EPOCH + since_epoch)
+}
+
+/// `[chargeback_export]` config section: how the bank wants the
+/// representment file laid out. Without it, a CSV with a header and the
+/// default fields is written.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChargebackLayout {
+    #[serde(default)]
+    pub format: LayoutFormat,
+    /// CSV only
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    /// CSV only, the header uses each field's `name`
+    #[serde(default = "default_header")]
+    pub header: bool,
+    #[serde(default = "default_fields")]
+    pub fields: Vec<FieldSpec>,
+}
+
+impl Default for ChargebackLayout {
+    fn default() -> Self {
+        Self {
+            format: LayoutFormat::default(),
+            delimiter: default_delimiter(),
+            header: default_header(),
+            fields: default_fields(),
+        }
+    }
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_header() -> bool {
+    true
+}
+
+fn default_fields() -> Vec<FieldSpec> {
+    [
+        Field::Tx,
+        Field::Client,
+        Field::Amount,
+        Field::CreatedAt,
+        Field::DisputedAt,
+        Field::ChargedBackAt,
+    ]
+    .into_iter()
+    .map(FieldSpec::new)
+    .collect()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutFormat {
+    #[default]
+    Csv,
+    /// Every field padded to its `width`, no separators
+    FixedWidth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Tx,
+    Client,
+    /// 4 decimal places, e.g. `12.5000`
+    Amount,
+    /// In hundredths with no decimal point, e.g. `1250`. Anything below a
+    /// cent is dropped.
+    AmountMinor,
+    CreatedAt,
+    DisputedAt,
+    ResolvedAt,
+    ChargedBackAt,
+    /// The spec's `value`, for record type codes and filler
+    Literal,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Align {
+    #[default]
+    Left,
+    Right,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFormat {
+    /// `2023-11-14T22:13:20Z`
+    #[default]
+    Iso8601,
+    /// `20231114`
+    Date,
+    EpochMillis,
+}
+
+/// One column of the export
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    pub field: Field,
+    /// CSV header, defaults to the field's own name
+    pub name: Option<String>,
+    /// Required for fixed-width. Values longer than this are an error
+    /// rather than getting cut off.
+    pub width: Option<usize>,
+    #[serde(default)]
+    pub align: Align,
+    #[serde(default = "default_pad")]
+    pub pad: char,
+    /// For `literal` fields
+    pub value: Option<String>,
+    /// For the timeline fields
+    #[serde(default)]
+    pub time_format: TimeFormat,
+}
+
+fn default_pad() -> char {
+    ' '
+}
+
+impl FieldSpec {
+    pub fn new(field: Field) -> Self {
+        Self {
+            field,
+            name: None,
+            width: None,
+            align: Align::default(),
+            pad: default_pad(),
+            value: None,
+            time_format: TimeFormat::default(),
+        }
+    }
+
+    fn header(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        let name = match self.field {
+            Field::Tx => "tx",
+            Field::Client => "client",
+            Field::Amount => "amount",
+            Field::AmountMinor => "amount_minor",
+            Field::CreatedAt => "created_at",
+            Field::DisputedAt => "disputed_at",
+            Field::ResolvedAt => "resolved_at",
+            Field::ChargedBackAt => "charged_back_at",
+            Field::Literal => "literal",
+        };
+        String::from(name)
+    }
+
+    fn render(
+        &self,
+        transaction_id: TransactionId,
+        stored: &StoredTransaction,
+        timeline: &DisputeTimeline,
+    ) -> io::Result<String> {
+        let time = |time: Option<SystemTime>| {
+            time.map(|time| format_time(time, self.time_format))
+                .unwrap_or_default()
+        };
+        let value = match self.field {
+            Field::Tx => transaction_id.to_string(),
+            Field::Client => stored.client_id.to_string(),
+            Field::Amount => stored.amount.to_string(),
+            Field::AmountMinor => (stored.amount.to_raw() / 100).to_string(),
+            Field::CreatedAt => time(timeline.created_at),
+            Field::DisputedAt => time(timeline.disputed_at),
+            Field::ResolvedAt => time(timeline.resolved_at),
+            Field::ChargedBackAt => time(timeline.charged_back_at),
+            Field::Literal => self.value.clone().unwrap_or_default(),
+        };
+
+        let Some(width) = self.width else {
+            return Ok(value);
+        };
+        let len = value.chars().count();
+        if len > width {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} for tx {} is {} characters, the layout only allows {}",
+                    self.header(),
+                    transaction_id,
+                    len,
+                    width
+                ),
+            ));
+        }
+        let padding: String = std::iter::repeat_n(self.pad, width - len).collect();
+        Ok(match self.align {
+            Align::Left => value + &padding,
+            Align::Right => padding + &value,
+        })
+    }
+}
+
+fn format_time(time: SystemTime, format: TimeFormat) -> String {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days(secs / 86400);
+    match format {
+        TimeFormat::Iso8601 => format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            secs % 86400 / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        ),
+        TimeFormat::Date => format!("{:04}{:02}{:02}", year, month, day),
+        TimeFormat::EpochMillis => since_epoch.as_millis().to_string(),
+    }
+}
+
+// Days since 1970-01-01 to a (year, month, day) date in UTC, from
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub(crate) fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+impl PaymentProcessor {
+    /// Every charged-back transaction, sorted by ID
+    pub fn chargebacks(&self) -> Vec<(TransactionId, StoredTransaction)> {
+        let mut chargebacks: Vec<_> = self
+            .compressed_transactions
+            .iter()
+            .filter(|(_, stored)| stored.state == DisputeState::ChargedBack)
+            .map(|(transaction_id, stored)| (*transaction_id, *stored))
+            .collect();
+        chargebacks.sort_unstable_by_key(|(transaction_id, _)| *transaction_id);
+        chargebacks
+    }
+
+    /// Writes the charged-back transactions in the bank's layout, with the
+    /// timeline fields filled in from `timelines`. Returns how many
+    /// chargebacks were written.
+    pub fn write_chargebacks(
+        &self,
+        out: &mut impl Write,
+        layout: &ChargebackLayout,
+        timelines: &HashMap<TransactionId, DisputeTimeline>,
+    ) -> io::Result<usize> {
+        let chargebacks = self.chargebacks();
+        let no_timeline = DisputeTimeline::default();
+        match layout.format {
+            LayoutFormat::Csv => {
+                if !layout.delimiter.is_ascii() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "the CSV delimiter has to be a single ASCII character",
+                    ));
+                }
+                let mut writer = csv::WriterBuilder::new()
+                    .delimiter(layout.delimiter as u8)
+                    .from_writer(out);
+                if layout.header {
+                    writer.write_record(layout.fields.iter().map(FieldSpec::header))?;
+                }
+                for (transaction_id, stored) in &chargebacks {
+                    let timeline = timelines.get(transaction_id).unwrap_or(&no_timeline);
+                    let record = layout
+                        .fields
+                        .iter()
+                        .map(|spec| spec.render(*transaction_id, stored, timeline))
+                        .collect::<io::Result<Vec<_>>>()?;
+                    writer.write_record(record)?;
+                }
+                writer.flush()?;
+            }
+            LayoutFormat::FixedWidth => {
+                if let Some(spec) = layout.fields.iter().find(|spec| spec.width.is_none()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("fixed-width field {} has no width", spec.header()),
+                    ));
+                }
+                for (transaction_id, stored) in &chargebacks {
+                    let timeline = timelines.get(transaction_id).unwrap_or(&no_timeline);
+                    for spec in &layout.fields {
+                        out.write_all(spec.render(*transaction_id, stored, timeline)?.as_bytes())?;
+                    }
+                    out.write_all(b"\n")?;
+                }
+            }
+        }
+        Ok(chargebacks.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, AuditLog, ManualClock, Transaction};
+    use std::sync::{Arc, Mutex};
+
+    // Deposits 1 and 2 for client 1, disputes both a day apart and charges
+    // back 2, with the audit log's clock ticking along
+    fn processor_with_log() -> (PaymentProcessor, Vec<u8>) {
+        let clock = Arc::new(ManualClock::from_secs(1_700_000_000));
+        let log = Arc::new(Mutex::new(AuditLog::with_clock(Vec::new(), clock.clone())));
+        let mut processor = PaymentProcessor::new();
+        processor.add_listener(log.clone());
+
+        let transactions = [
+            Transaction::Deposit {
+                client_id: 1,
+                transaction_id: 1,
+                amount: Amount::from(5),
+            },
+            Transaction::Deposit {
+                client_id: 1,
+                transaction_id: 2,
+                amount: Amount::from(12.5),
+            },
+            Transaction::Dispute {
+                client_id: 1,
+                transaction_id: 2,
+            },
+            Transaction::Dispute {
+                client_id: 1,
+                transaction_id: 1,
+            },
+            Transaction::Chargeback {
+                client_id: 1,
+                transaction_id: 2,
+            },
+        ];
+        for transaction in transactions {
+            processor.try_process(&transaction).unwrap();
+            clock.advance(Duration::from_secs(86400));
+        }
+
+        drop(processor.listeners.pop());
+        let log = Arc::into_inner(log).unwrap().into_inner().unwrap();
+        (processor, log.into_inner())
+    }
+
+    #[test]
+    fn test_read_timelines() {
+        let (_, log) = processor_with_log();
+        let mut timelines = HashMap::new();
+        read_dispute_timelines(log.as_slice(), &mut timelines).unwrap();
+
+        let at = |days: u64| {
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + days * 86400))
+        };
+        assert_eq!(
+            timelines[&2],
+            DisputeTimeline {
+                created_at: at(1),
+                disputed_at: at(2),
+                resolved_at: None,
+                charged_back_at: at(4),
+            }
+        );
+        assert_eq!(timelines[&1].disputed_at, at(3));
+        assert_eq!(timelines[&1].charged_back_at, None);
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let (processor, log) = processor_with_log();
+        let mut timelines = HashMap::new();
+        read_dispute_timelines(log.as_slice(), &mut timelines).unwrap();
+
+        let mut out = Vec::new();
+        let written = processor
+            .write_chargebacks(&mut out, &ChargebackLayout::default(), &timelines)
+            .unwrap();
+        assert_eq!(written, 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "tx,client,amount,created_at,disputed_at,charged_back_at\n\
+             2,1,12.5000,2023-11-15T22:13:20Z,2023-11-16T22:13:20Z,2023-11-18T22:13:20Z\n"
+        );
+    }
+
+    #[test]
+    fn test_fixed_width_export() {
+        let (processor, log) = processor_with_log();
+        let mut timelines = HashMap::new();
+        read_dispute_timelines(log.as_slice(), &mut timelines).unwrap();
+
+        let layout: ChargebackLayout = toml::from_str(
+            r#"
+            format = "fixed_width"
+            fields = [
+                { field = "literal", value = "CB", width = 2 },
+                { field = "tx", width = 8, align = "right", pad = "0" },
+                { field = "amount_minor", width = 10, align = "right", pad = "0" },
+                { field = "disputed_at", width = 8, time_format = "date" },
+                { field = "resolved_at", width = 8 },
+                { field = "client", width = 6 },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        processor
+            .write_chargebacks(&mut out, &layout, &timelines)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "CB00000002000000125020231116        1     \n"
+        );
+    }
+
+    #[test]
+    fn test_fixed_width_overflow() {
+        let (processor, _) = processor_with_log();
+        let layout = ChargebackLayout {
+            format: LayoutFormat::FixedWidth,
+            fields: vec![FieldSpec {
+                width: Some(3),
+                ..FieldSpec::new(Field::Amount)
+            }],
+            ..ChargebackLayout::default()
+        };
+
+        let err = processor
+            .write_chargebacks(&mut Vec::new(), &layout, &HashMap::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+    }
+}
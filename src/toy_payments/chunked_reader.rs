@@ -0,0 +1,395 @@
+#[cfg(feature = "arena")]
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use csv::{ByteRecord, Position, StringRecord, Trim};
+use rayon::prelude::*;
+
+use super::{
+    Columns, CsvDialect, DEFAULT_COLUMNS, ParseError, ParseTime, RoundingMode, Transaction,
+    Unrounded, parse_record, timed,
+};
+
+const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+
+#[cfg(feature = "arena")]
+thread_local! {
+    // One per rayon worker, reset for every chunk it parses
+    static ARENA: RefCell<bumpalo::Bump> = RefCell::new(bumpalo::Bump::new());
+}
+
+/// Reads the input in chunks cut at line boundaries and parses a batch of
+/// chunks at a time on the rayon pool. Each batch comes back in file order,
+/// so every client's transactions keep their original relative order.
+///
+/// Cutting at newlines assumes no quoted field spans lines, which holds for
+/// transaction files (four plain columns).
+pub struct ChunkedTransactionReader<R = File> {
+    reader: BufReader<R>,
+    dialect: CsvDialect,
+    headers: StringRecord,
+    // Only set when the records should go through the FastTransactionReader parsing
+    fast_columns: Option<Columns>,
+    rounding: RoundingMode,
+    // Where the next chunk starts in the file, so parse errors point at the right line
+    byte: u64,
+    line: u64,
+    chunk_size: usize,
+    chunks_per_batch: usize,
+    done: bool,
+    // Per rayon thread, only tracked with_timings
+    parse_times: Option<Mutex<BTreeMap<usize, ParseTime>>>,
+}
+
+impl ChunkedTransactionReader {
+    pub fn from_path(path: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_reader(File::open(path)?)
+    }
+}
+
+impl<R: Read> ChunkedTransactionReader<R> {
+    pub fn from_reader(reader: R) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_dialect(reader, &CsvDialect::default())
+    }
+
+    pub fn with_dialect(
+        reader: R,
+        dialect: &CsvDialect,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = BufReader::new(reader);
+
+        // Comments before the header get skipped over with it
+        let mut byte = 0;
+        let mut line = 1;
+        let headers = if dialect.has_headers {
+            let mut header_line = Vec::new();
+            loop {
+                header_line.clear();
+                byte += reader.read_until(b'\n', &mut header_line)? as u64;
+                line += 1;
+                if dialect.comment.is_none() || header_line.first() != dialect.comment.as_ref() {
+                    break;
+                }
+            }
+            let mut builder = dialect.builder();
+            builder.trim(Trim::All);
+            builder
+                .from_reader(header_line.as_slice())
+                .headers()?
+                .clone()
+        } else {
+            StringRecord::from(DEFAULT_COLUMNS.to_vec())
+        };
+
+        Ok(Self {
+            reader,
+            dialect: *dialect,
+            headers,
+            fast_columns: None,
+            rounding: RoundingMode::default(),
+            byte,
+            line,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            chunks_per_batch: rayon::current_num_threads() * 2,
+            done: false,
+            parse_times: None,
+        })
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Parse records by hand like FastTransactionReader instead of through serde
+    pub fn with_fast_parse(mut self, fast_parse: bool) -> Result<Self, ParseError> {
+        self.fast_columns = if fast_parse {
+            Some(Columns::from_headers(
+                &self.headers.as_byte_record().clone(),
+            )?)
+        } else {
+            None
+        };
+        Ok(self)
+    }
+
+    /// How amounts with more than 4 decimals get cut down, truncated by default
+    pub fn with_rounding(mut self, rounding: RoundingMode) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Track how long each rayon thread spends parsing (and validating, with
+    /// fast parse), see parse_times
+    pub fn with_timings(mut self, timings: bool) -> Self {
+        self.parse_times = timings.then(Default::default);
+        self
+    }
+
+    /// Time spent per rayon thread index so far
+    pub fn parse_times(&self) -> Vec<(usize, ParseTime)> {
+        match &self.parse_times {
+            Some(parse_times) => parse_times
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(thread, time)| (*thread, *time))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn read_chunk(&mut self) -> io::Result<Option<(Vec<u8>, Position)>> {
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+        (&mut self.reader)
+            .take(self.chunk_size as u64)
+            .read_to_end(&mut chunk)?;
+        if chunk.is_empty() {
+            return Ok(None);
+        }
+        // Finish off whatever record the chunk size cut through
+        if chunk.last() != Some(&b'\n') {
+            self.reader.read_until(b'\n', &mut chunk)?;
+        }
+
+        // Records are counted from the header, same as the sequential reader
+        let mut start = Position::new();
+        start
+            .set_byte(self.byte)
+            .set_line(self.line)
+            .set_record(self.line - 1);
+        self.byte += chunk.len() as u64;
+        self.line += chunk.iter().filter(|byte| **byte == b'\n').count() as u64;
+        Ok(Some((chunk, start)))
+    }
+}
+
+impl<R: Read> Iterator for ChunkedTransactionReader<R> {
+    type Item = Vec<Result<Transaction, ParseError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut chunks = Vec::with_capacity(self.chunks_per_batch);
+        let mut read_error = None;
+        while chunks.len() < self.chunks_per_batch {
+            match self.read_chunk() {
+                Ok(Some(chunk)) => chunks.push(chunk),
+                Ok(None) => break,
+                Err(err) => {
+                    read_error = Some(err);
+                    break;
+                }
+            }
+        }
+        if chunks.len() < self.chunks_per_batch {
+            self.done = true;
+        }
+        if chunks.is_empty() && read_error.is_none() {
+            return None;
+        }
+
+        let dialect = &self.dialect;
+        let headers = &self.headers;
+        let fast_columns = self.fast_columns.as_ref();
+        let rounding = self.rounding;
+        let parse_times = self.parse_times.as_ref();
+        let parsed: Vec<Vec<Result<Transaction, ParseError>>> = chunks
+            .par_iter()
+            .map(|(chunk, start)| {
+                let Some(parse_times) = parse_times else {
+                    return parse_chunk(
+                        chunk,
+                        start,
+                        dialect,
+                        headers,
+                        fast_columns,
+                        rounding,
+                        None,
+                    );
+                };
+                let mut time = ParseTime::default();
+                let parsed = parse_chunk(
+                    chunk,
+                    start,
+                    dialect,
+                    headers,
+                    fast_columns,
+                    rounding,
+                    Some(&mut time),
+                );
+                let thread = rayon::current_thread_index().unwrap_or(0);
+                parse_times
+                    .lock()
+                    .unwrap()
+                    .entry(thread)
+                    .or_default()
+                    .add(time);
+                parsed
+            })
+            .collect();
+        let mut batch: Vec<_> = parsed.into_iter().flatten().collect();
+        if let Some(err) = read_error {
+            batch.push(Err(ParseError::Csv(err.into())));
+        }
+        Some(batch)
+    }
+}
+
+fn parse_chunk(
+    chunk: &[u8],
+    start: &Position,
+    dialect: &CsvDialect,
+    headers: &StringRecord,
+    fast_columns: Option<&Columns>,
+    rounding: RoundingMode,
+    time: Option<&mut ParseTime>,
+) -> Vec<Result<Transaction, ParseError>> {
+    let start_time = Instant::now();
+    let mut reader = dialect
+        .builder()
+        .has_headers(false)
+        .trim(if fast_columns.is_some() {
+            Trim::None
+        } else {
+            Trim::All
+        })
+        .from_reader(Cursor::new(chunk));
+    if let Err(err) = reader.seek_raw(io::SeekFrom::Start(0), start.clone()) {
+        return vec![Err(err.into())];
+    }
+
+    match fast_columns {
+        Some(columns) => {
+            let mut record = ByteRecord::new();
+            // Only split up per record when asked to, Instant::now() isn't free
+            let mut validate = time.is_some().then(Duration::default);
+            let mut failed = false;
+            let records = std::iter::from_fn(|| {
+                if failed {
+                    return None;
+                }
+                match reader.read_byte_record(&mut record) {
+                    Ok(true) => Some(match &mut validate {
+                        Some(validate) => {
+                            timed(validate, || parse_record(&record, columns, rounding))
+                        }
+                        None => parse_record(&record, columns, rounding),
+                    }),
+                    Ok(false) => None,
+                    Err(err) => {
+                        failed = true;
+                        Some(Err(err.into()))
+                    }
+                }
+            });
+            let parsed = collect_chunk(records);
+            if let (Some(time), Some(validate)) = (time, validate) {
+                time.parse += start_time.elapsed().saturating_sub(validate);
+                *time.validate.get_or_insert_default() += validate;
+            }
+            parsed
+        }
+        None => {
+            let parsed = collect_chunk(reader.records().map(|record| {
+                record
+                    .and_then(|record| record.deserialize::<Unrounded<Transaction>>(Some(headers)))
+                    .map(|transaction| transaction.round(rounding))
+                    .map_err(ParseError::from)
+            }));
+            if let Some(time) = time {
+                time.parse += start_time.elapsed();
+            }
+            parsed
+        }
+    }
+}
+
+// With the arena feature the results grow in the worker's arena and get
+// copied out once at their final size, instead of going back to the global
+// allocator (shared by every worker) each time the Vec doubles
+#[cfg(feature = "arena")]
+fn collect_chunk<T>(items: impl Iterator<Item = T>) -> Vec<T> {
+    ARENA.with_borrow_mut(|arena| {
+        arena.reset();
+        let staged = bumpalo::collections::Vec::from_iter_in(items, arena);
+        let mut parsed = Vec::with_capacity(staged.len());
+        parsed.extend(staged);
+        parsed
+    })
+}
+
+#[cfg(not(feature = "arena"))]
+fn collect_chunk<T>(items: impl Iterator<Item = T>) -> Vec<T> {
+    items.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::TransactionReader;
+
+    #[test]
+    fn test_matches_sequential_reader() {
+        let path = PathBuf::from(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/resources/test-data-3.csv"
+        ));
+        let expected: Vec<String> = TransactionReader::from_path(path.clone())
+            .unwrap()
+            .iter()
+            .map(|result| result.unwrap().to_string())
+            .collect();
+
+        for fast_parse in [false, true] {
+            // Tiny chunks so records get cut all over the place
+            let actual: Vec<String> = ChunkedTransactionReader::from_path(path.clone())
+                .unwrap()
+                .with_chunk_size(7)
+                .with_fast_parse(fast_parse)
+                .unwrap()
+                .flatten()
+                .map(|result| result.unwrap().to_string())
+                .collect();
+
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_dialect() {
+        let input =
+            "# comment\ntype;client;tx;amount\ndeposit;1;1;1.5\n# another\nwithdrawal;1;2;0.5\n";
+        let dialect = CsvDialect {
+            delimiter: b';',
+            comment: Some(b'#'),
+            ..CsvDialect::default()
+        };
+        let expected: Vec<String> = TransactionReader::with_dialect(input.as_bytes(), &dialect)
+            .iter()
+            .map(|result| result.unwrap().to_string())
+            .collect();
+        assert_eq!(expected.len(), 2);
+
+        for fast_parse in [false, true] {
+            let actual: Vec<String> =
+                ChunkedTransactionReader::with_dialect(input.as_bytes(), &dialect)
+                    .unwrap()
+                    .with_chunk_size(5)
+                    .with_fast_parse(fast_parse)
+                    .unwrap()
+                    .flatten()
+                    .map(|result| result.unwrap().to_string())
+                    .collect();
+
+            assert_eq!(actual, expected);
+        }
+    }
+}
@@ -0,0 +1,554 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use reqwest::{RequestBuilder, StatusCode};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::amount::Amount;
+use super::{ClientId, Transaction, TransactionId};
+
+/// Typed async client for the HTTP API of `payments daemon --listen`
+/// (`serve` answers the same queries, but has nothing to submit to)
+pub struct ApiClient {
+    base_url: String,
+    http: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+/// Connection failures, timeouts and 5xx responses get retried, waiting
+/// `backoff` and then twice as long each time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Including the first one
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    /// The server answered, but not with a success
+    Status {
+        status: u16,
+        body: String,
+    },
+    Decode(serde_json::Error),
+    /// The account list wasn't a well formed array of objects
+    Malformed(&'static str),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(err) => write!(f, "request failed: {}", err),
+            ClientError::Status { status, body } => write!(f, "server said {}: {}", status, body),
+            ClientError::Decode(err) => write!(f, "unexpected response: {}", err),
+            ClientError::Malformed(what) => write!(f, "unexpected response: {}", what),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(err: reqwest::Error) -> Self {
+        ClientError::Http(err)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(err: serde_json::Error) -> Self {
+        ClientError::Decode(err)
+    }
+}
+
+/// One account, as `GET /accounts` returns it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AccountBalance {
+    pub client: ClientId,
+    #[serde(deserialize_with = "deserialize_amount_str")]
+    pub available: Amount,
+    #[serde(deserialize_with = "deserialize_amount_str")]
+    pub held: Amount,
+    #[serde(deserialize_with = "deserialize_amount_str")]
+    pub total: Amount,
+    pub locked: bool,
+    /// Set for merged-away clients, whose balances are all zero
+    #[serde(default)]
+    pub merged_into: Option<ClientId>,
+}
+
+/// What happened to a submitted transaction
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SubmitOutcome {
+    pub tx: TransactionId,
+    /// Result code, e.g. `OK` or `REJECTED_INSUFFICIENT_FUNDS`
+    pub result: String,
+}
+
+impl SubmitOutcome {
+    pub fn is_applied(&self) -> bool {
+        !self.result.contains("REJECTED")
+    }
+}
+
+// Amounts come as exact decimal strings
+fn deserialize_amount_str<'de, D>(deserializer: D) -> Result<Amount, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let amount = String::deserialize(deserializer)?;
+    amount
+        .parse::<f64>()
+        .map(Amount::from)
+        .map_err(serde::de::Error::custom)
+}
+
+// Same fields as a CSV row, which is what the server parses
+#[derive(Serialize)]
+struct SubmitBody<'a> {
+    #[serde(rename = "type")]
+    ty: &'static str,
+    client: ClientId,
+    tx: TransactionId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reference: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<ClientId>,
+}
+
+impl<'a> From<&'a Transaction> for SubmitBody<'a> {
+    fn from(transaction: &'a Transaction) -> Self {
+        let mut body = SubmitBody {
+            ty: transaction.type_name(),
+            client: transaction.client_id(),
+            tx: transaction.transaction_id(),
+            amount: None,
+            reference: None,
+            to: None,
+        };
+        match transaction {
+            Transaction::Deposit { amount, .. } | Transaction::Withdrawal { amount, .. } => {
+                body.amount = Some((*amount).into());
+            }
+            Transaction::Adjustment {
+                amount, reference, ..
+            } => {
+                // The sign is in the type already
+                let amount: f64 = (*amount).into();
+                body.amount = Some(amount.abs());
+                body.reference = Some(reference);
+            }
+            Transaction::Transfer {
+                to_client_id,
+                amount,
+                ..
+            } => {
+                body.amount = Some((*amount).into());
+                body.to = Some(*to_client_id);
+            }
+            Transaction::Dispute { .. }
+            | Transaction::Resolve { .. }
+            | Transaction::Chargeback { .. }
+            | Transaction::AutoChargeback { .. } => {}
+        }
+        body
+    }
+}
+
+impl ApiClient {
+    /// `base_url` is where the server listens, e.g. `http://127.0.0.1:8080`
+    pub fn new(base_url: &str) -> Self {
+        Self::with_retry(base_url, RetryPolicy::default())
+    }
+
+    pub fn with_retry(base_url: &str, retry: RetryPolicy) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+            retry,
+        }
+    }
+
+    /// Submits one transaction under a fresh idempotency key, which every
+    /// retry reuses, so it's applied at most once however many it takes
+    pub async fn submit(&self, transaction: &Transaction) -> Result<SubmitOutcome, ClientError> {
+        self.submit_with_key(transaction, &idempotency_key(transaction))
+            .await
+    }
+
+    /// Same as submit, with the caller's own key, e.g. to keep it across
+    /// restarts of the calling service
+    pub async fn submit_with_key(
+        &self,
+        transaction: &Transaction,
+        idempotency_key: &str,
+    ) -> Result<SubmitOutcome, ClientError> {
+        let body = serde_json::to_string(&SubmitBody::from(transaction))?;
+        let url = format!("{}/transactions", self.base_url);
+        let response = self
+            .send(|| {
+                self.http
+                    .post(&url)
+                    .header("Idempotency-Key", idempotency_key)
+                    .header("Content-Type", "application/json")
+                    .body(body.clone())
+            })
+            .await?;
+        Ok(serde_json::from_slice(&success(response).await?)?)
+    }
+
+    /// `None` for a client the server has never seen
+    pub async fn get_account(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<AccountBalance>, ClientError> {
+        let url = format!("{}/accounts/{}", self.base_url, client_id);
+        let response = self.send(|| self.http.get(&url)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&success(response).await?)?))
+    }
+
+    /// Every account, parsed one at a time as the response comes in
+    /// instead of holding the whole list
+    pub async fn stream_accounts(&self) -> Result<AccountStream, ClientError> {
+        let url = format!("{}/accounts", self.base_url);
+        let response = self.send(|| self.http.get(&url)).await?;
+        if !response.status().is_success() {
+            return Err(status_error(response).await);
+        }
+        Ok(AccountStream {
+            response,
+            buffer: Vec::new(),
+            scanner: ObjectScanner::default(),
+        })
+    }
+
+    async fn send(
+        &self,
+        request: impl Fn() -> RequestBuilder,
+    ) -> Result<reqwest::Response, ClientError> {
+        let mut backoff = self.retry.backoff;
+        let mut attempt = 1;
+        loop {
+            let result = request().send().await;
+            let retryable = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(err) => err.is_connect() || err.is_timeout(),
+            };
+            if !retryable || attempt >= self.retry.attempts {
+                return Ok(result?);
+            }
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+async fn success(response: reqwest::Response) -> Result<Vec<u8>, ClientError> {
+    if !response.status().is_success() {
+        return Err(status_error(response).await);
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+async fn status_error(response: reqwest::Response) -> ClientError {
+    let status = response.status().as_u16();
+    match response.text().await {
+        Ok(body) => ClientError::Status { status, body },
+        Err(err) => err.into(),
+    }
+}
+
+// Unique enough without pulling in a UUID crate: process, time and a counter
+fn idempotency_key(transaction: &Transaction) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!(
+        "{}-{:x}-{:x}-{:x}",
+        transaction.transaction_id(),
+        std::process::id(),
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Accounts from `GET /accounts`, see ApiClient::stream_accounts
+pub struct AccountStream {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+    scanner: ObjectScanner,
+}
+
+impl AccountStream {
+    pub async fn next(&mut self) -> Option<Result<AccountBalance, ClientError>> {
+        loop {
+            let scanned = self.scanner.scan(&self.buffer);
+            if let Err(err) = scanned {
+                self.buffer.clear();
+                self.scanner = ObjectScanner::default();
+                return Some(Err(err));
+            }
+            if let Ok(Some(end)) = scanned {
+                let start = self.scanner.start;
+                let account = serde_json::from_slice(&self.buffer[start..end]);
+                self.buffer.drain(..end);
+                self.scanner = ObjectScanner::default();
+                return Some(account.map_err(Into::into));
+            }
+            match self.response.chunk().await {
+                Ok(Some(chunk)) => self.buffer.extend_from_slice(&chunk),
+                // Anything left over is an object that never finished
+                Ok(None) if self.scanner.depth > 0 => {
+                    self.buffer.clear();
+                    self.scanner = ObjectScanner::default();
+                    return Some(Err(ClientError::Malformed("response ended mid-object")));
+                }
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
+
+// Finds where each top level object of a JSON array starts and ends, so
+// they can be parsed as soon as they're complete. Carries on from where it
+// got to when more of the response arrives.
+#[derive(Default)]
+struct ObjectScanner {
+    position: usize,
+    start: usize,
+    depth: u32,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl ObjectScanner {
+    /// End (exclusive) of the next complete object in `buffer`, if there's one yet
+    fn scan(&mut self, buffer: &[u8]) -> Result<Option<usize>, ClientError> {
+        while self.position < buffer.len() {
+            let byte = buffer[self.position];
+            self.position += 1;
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' => {
+                    if self.depth == 0 {
+                        self.start = self.position - 1;
+                    }
+                    self.depth += 1;
+                }
+                b'}' => {
+                    if self.depth == 0 {
+                        return Err(ClientError::Malformed("unbalanced '}'"));
+                    }
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        return Ok(Some(self.position));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{PaymentProcessor, ReadOnlyApi, SubmitApi};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use tiny_http::{Response, Server};
+
+    // A server that fails the first `failures` requests with a 503, then
+    // answers like the daemon would
+    fn server(failures: usize) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let keys = Arc::new(Mutex::new(Vec::new()));
+        let seen = keys.clone();
+        thread::spawn(move || {
+            let mut processor = PaymentProcessor::new();
+            let mut submit = SubmitApi::new(10);
+            for (index, mut request) in server.incoming_requests().enumerate() {
+                let key = request
+                    .headers()
+                    .iter()
+                    .find(|header| header.field.equiv("Idempotency-Key"))
+                    .map(|header| header.value.to_string());
+                seen.lock().unwrap().push(key.clone());
+                if index < failures {
+                    let _ = request.respond(Response::empty(503));
+                    continue;
+                }
+                let response = if request.method().as_str() == "POST" {
+                    let mut body = String::new();
+                    request.as_reader().read_to_string(&mut body).unwrap();
+                    submit.submit(&mut processor, key.as_deref(), &body)
+                } else {
+                    // Not worth keeping one around for a test
+                    let snapshot = {
+                        let mut bytes = Vec::new();
+                        processor.save_snapshot(&mut bytes).unwrap();
+                        bytes
+                    };
+                    let mut copy = PaymentProcessor::new();
+                    copy.load_snapshot(snapshot.as_slice()).unwrap();
+                    ReadOnlyApi::new(copy).handle("GET", request.url())
+                };
+                let _ = request.respond(
+                    Response::from_string(response.body).with_status_code(response.status),
+                );
+            }
+        });
+        (url, keys)
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn test_submit_and_query() {
+        let (url, keys) = server(1);
+        let client = ApiClient::with_retry(
+            &url,
+            RetryPolicy {
+                attempts: 2,
+                backoff: Duration::from_millis(1),
+            },
+        );
+        block_on(async {
+            let outcome = client
+                .submit(&Transaction::Deposit {
+                    client_id: 1,
+                    transaction_id: 1,
+                    amount: Amount::from(10.5),
+                })
+                .await
+                .unwrap();
+            assert!(outcome.is_applied());
+            let outcome = client
+                .submit(&Transaction::Withdrawal {
+                    client_id: 1,
+                    transaction_id: 2,
+                    amount: Amount::from(20),
+                })
+                .await
+                .unwrap();
+            assert_eq!(outcome.result, "REJECTED_INSUFFICIENT_FUNDS");
+
+            let account = client.get_account(1).await.unwrap().unwrap();
+            assert_eq!(account.available, Amount::from(10.5));
+            assert_eq!(client.get_account(9).await.unwrap(), None);
+
+            let mut accounts = client.stream_accounts().await.unwrap();
+            let mut clients = Vec::new();
+            while let Some(account) = accounts.next().await {
+                clients.push(account.unwrap().client);
+            }
+            assert_eq!(clients, vec![1]);
+        });
+
+        // The retried submit went out with the same key
+        let keys = keys.lock().unwrap();
+        assert!(keys[0].is_some());
+        assert_eq!(keys[0], keys[1]);
+        assert_ne!(keys[1], keys[2]);
+    }
+
+    #[test]
+    fn test_gives_up() {
+        let (url, _) = server(usize::MAX);
+        let client = ApiClient::with_retry(
+            &url,
+            RetryPolicy {
+                attempts: 3,
+                backoff: Duration::from_millis(1),
+            },
+        );
+        match block_on(client.get_account(1)) {
+            Err(ClientError::Status { status, .. }) => assert_eq!(status, 503),
+            other => panic!("expected a 503, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_object_scanner() {
+        let json = br#"[{"a":"}{\"x"},{"b":{"c":1}}]"#;
+        let mut scanner = ObjectScanner::default();
+        // Split anywhere, objects only come out once they're complete
+        assert_eq!(scanner.scan(&json[..10]).unwrap(), None);
+        let end = scanner.scan(json).unwrap().unwrap();
+        assert_eq!(&json[scanner.start..end], br#"{"a":"}{\"x"}"#);
+        let mut scanner = ObjectScanner {
+            position: end,
+            ..ObjectScanner::default()
+        };
+        let next = scanner.scan(json).unwrap().unwrap();
+        assert_eq!(&json[scanner.start..next], br#"{"b":{"c":1}}"#);
+        assert_eq!(scanner.scan(json).unwrap(), None);
+
+        let mut scanner = ObjectScanner::default();
+        assert!(matches!(scanner.scan(br#"[{"a":1}}]"#), Ok(Some(8))));
+        assert!(matches!(
+            scanner.scan(br#"[{"a":1}}]"#),
+            Err(ClientError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_truncated_accounts() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        thread::spawn(move || {
+            if let Ok(request) = server.recv() {
+                let body = r#"[{"client":1,"available":"1.0","held":"0.0","total":"1.0","locked":false},{"client":2,"#;
+                let _ = request.respond(Response::from_string(body));
+            }
+        });
+        let client = ApiClient::new(&url);
+        block_on(async {
+            let mut accounts = client.stream_accounts().await.unwrap();
+            assert_eq!(accounts.next().await.unwrap().unwrap().client, 1);
+            match accounts.next().await {
+                Some(Err(ClientError::Malformed(_))) => {}
+                other => panic!("expected a malformed response, got {:?}", other),
+            }
+            assert!(accounts.next().await.is_none());
+        });
+    }
+}
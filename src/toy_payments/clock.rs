@@ -0,0 +1,115 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use super::TransactionId;
+
+/// Source of the current time, so anything time-dependent can be driven
+/// by a ManualClock in tests instead of the wall clock
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock that only moves when told to
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<SystemTime>,
+}
+
+impl ManualClock {
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(start),
+        }
+    }
+
+    /// Starts at the given number of seconds after the Unix epoch
+    pub fn from_secs(secs: u64) -> Self {
+        Self::new(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+
+    pub fn set(&self, to: SystemTime) {
+        *self.now.lock().unwrap() = to;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Hands out IDs for transactions the engine creates itself (rather than
+/// reading them from the input)
+pub trait IdGenerator: Send {
+    fn next_id(&mut self) -> TransactionId;
+}
+
+/// Counts up from a starting ID. Start it past the highest ID already in
+/// use so generated transactions can't collide with real ones.
+#[derive(Debug, Clone)]
+pub struct SequentialIds {
+    next: TransactionId,
+}
+
+impl SequentialIds {
+    pub fn starting_at(next: TransactionId) -> Self {
+        Self { next }
+    }
+}
+
+impl IdGenerator for SequentialIds {
+    fn next_id(&mut self) -> TransactionId {
+        let id = self.next;
+        self.next = self
+            .next
+            .checked_add(1)
+            .expect("ran out of transaction IDs");
+        id
+    }
+}
+
+/// Formats a time as seconds.millis since the Unix epoch
+pub fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "{}.{:03}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::from_secs(1_700_000_000);
+        assert_eq!(format_timestamp(clock.now()), "1700000000.000");
+
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(format_timestamp(clock.now()), "1700000001.500");
+    }
+
+    #[test]
+    fn test_sequential_ids() {
+        let mut ids = SequentialIds::starting_at(41);
+        assert_eq!(ids.next_id(), 41);
+        assert_eq!(ids.next_id(), 42);
+    }
+}
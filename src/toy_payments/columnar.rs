@@ -0,0 +1,341 @@
+use super::hashing::HashMap;
+use super::{Amount, ClientId, PaymentProcessor, Transaction, TransactionId};
+
+/// How many rows `--engine columnar` reads in before applying them
+pub const COLUMNAR_CHUNK_ROWS: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Adjustment,
+    Transfer,
+    AutoChargeback,
+}
+
+/// A chunk of transactions stored as one array per field instead of one
+/// Transaction per row, for `PaymentProcessor::process_columnar`. The
+/// columns every row has are dense, the rare ones (transfer recipients,
+/// adjustment references) are kept by row on the side.
+#[derive(Debug, Clone, Default)]
+pub struct ColumnarBatch {
+    client_ids: Vec<ClientId>,
+    transaction_ids: Vec<TransactionId>,
+    kinds: Vec<Kind>,
+    // Zero for the types without one
+    amounts: Vec<Amount>,
+    to_client_ids: HashMap<u32, ClientId>,
+    references: HashMap<u32, String>,
+}
+
+impl ColumnarBatch {
+    pub fn with_capacity(rows: usize) -> Self {
+        Self {
+            client_ids: Vec::with_capacity(rows),
+            transaction_ids: Vec::with_capacity(rows),
+            kinds: Vec::with_capacity(rows),
+            amounts: Vec::with_capacity(rows),
+            ..Self::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.kinds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.client_ids.clear();
+        self.transaction_ids.clear();
+        self.kinds.clear();
+        self.amounts.clear();
+        self.to_client_ids.clear();
+        self.references.clear();
+    }
+
+    pub fn push(&mut self, transaction: Transaction) {
+        let row = self.len() as u32;
+        self.client_ids.push(transaction.client_id());
+        self.transaction_ids.push(transaction.transaction_id());
+        let (kind, amount) = match transaction {
+            Transaction::Deposit { amount, .. } => (Kind::Deposit, amount),
+            Transaction::Withdrawal { amount, .. } => (Kind::Withdrawal, amount),
+            Transaction::Dispute { .. } => (Kind::Dispute, Amount::default()),
+            Transaction::Resolve { .. } => (Kind::Resolve, Amount::default()),
+            Transaction::Chargeback { .. } => (Kind::Chargeback, Amount::default()),
+            Transaction::Adjustment {
+                amount, reference, ..
+            } => {
+                self.references.insert(row, reference);
+                (Kind::Adjustment, amount)
+            }
+            Transaction::Transfer {
+                to_client_id,
+                amount,
+                ..
+            } => {
+                self.to_client_ids.insert(row, to_client_id);
+                (Kind::Transfer, amount)
+            }
+            Transaction::AutoChargeback { .. } => (Kind::AutoChargeback, Amount::default()),
+        };
+        self.kinds.push(kind);
+        self.amounts.push(amount);
+    }
+
+    fn transaction(&self, row: u32) -> Transaction {
+        let index = row as usize;
+        let client_id = self.client_ids[index];
+        let transaction_id = self.transaction_ids[index];
+        let amount = self.amounts[index];
+        match self.kinds[index] {
+            Kind::Deposit => Transaction::Deposit {
+                client_id,
+                transaction_id,
+                amount,
+            },
+            Kind::Withdrawal => Transaction::Withdrawal {
+                client_id,
+                transaction_id,
+                amount,
+            },
+            Kind::Dispute => Transaction::Dispute {
+                client_id,
+                transaction_id,
+            },
+            Kind::Resolve => Transaction::Resolve {
+                client_id,
+                transaction_id,
+            },
+            Kind::Chargeback => Transaction::Chargeback {
+                client_id,
+                transaction_id,
+            },
+            Kind::Adjustment => Transaction::Adjustment {
+                client_id,
+                transaction_id,
+                amount,
+                reference: self.references[&row].clone(),
+            },
+            Kind::Transfer => Transaction::Transfer {
+                client_id,
+                transaction_id,
+                to_client_id: self.to_client_ids[&row],
+                amount,
+            },
+            Kind::AutoChargeback => Transaction::AutoChargeback {
+                client_id,
+                transaction_id,
+            },
+        }
+    }
+}
+
+impl PaymentProcessor {
+    /// Applies a whole batch, one client at a time instead of in input
+    /// order, so each account stays in cache while its rows go through.
+    /// Every client's rows still go in the order they came, so the end
+    /// state is what `process` row by row would give. Transfers touch two
+    /// clients, so they're applied where they are in the input, with the
+    /// rows before them done first. Listeners hear about the rows in the
+    /// order they were applied.
+    ///
+    /// The dispute and dedup windows count per client, so they don't mind
+    /// the reordering, but the store is shared: a tx ID used by more than
+    /// one client makes the order across clients matter (whose deposit the
+    /// store keeps, who gets to dispute it). A batch with one of those, in
+    /// it or against the store, is applied in input order instead.
+    pub fn process_columnar(&mut self, batch: &ColumnarBatch) {
+        // Unlike when streaming, how much the store grows is known up front
+        let stored = batch
+            .kinds
+            .iter()
+            .filter(|kind| matches!(kind, Kind::Deposit | Kind::Withdrawal))
+            .count();
+        self.compressed_transactions.reserve(stored);
+
+        if self.shares_transaction_ids(batch) {
+            for row in 0..batch.len() as u32 {
+                self.process(&batch.transaction(row));
+            }
+            return;
+        }
+
+        let mut rows = Vec::with_capacity(batch.len());
+        for row in 0..batch.len() as u32 {
+            if batch.kinds[row as usize] == Kind::Transfer {
+                self.process_by_client(batch, &mut rows);
+                self.process(&batch.transaction(row));
+            } else {
+                rows.push(row);
+            }
+        }
+        self.process_by_client(batch, &mut rows);
+    }
+
+    fn shares_transaction_ids(&self, batch: &ColumnarBatch) -> bool {
+        let mut owners: HashMap<TransactionId, ClientId> = HashMap::default();
+        owners.reserve(batch.len());
+        batch
+            .transaction_ids
+            .iter()
+            .zip(&batch.client_ids)
+            .any(|(transaction_id, client_id)| {
+                *owners.entry(*transaction_id).or_insert(*client_id) != *client_id
+                    || self
+                        .compressed_transactions
+                        .get(transaction_id)
+                        .is_some_and(|stored| stored.client_id != *client_id)
+            })
+    }
+
+    fn process_by_client(&mut self, batch: &ColumnarBatch, rows: &mut Vec<u32>) {
+        rows.sort_unstable_by_key(|row| (batch.client_ids[*row as usize], *row));
+        for row in rows.drain(..) {
+            self.process(&batch.transaction(row));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::toy_payments::{
+        DisputeWindow, EventListener, GeneratorParams, ProcessorConfig, TransactionReader, generate,
+    };
+
+    #[derive(Clone, Default)]
+    struct Applied(Arc<Mutex<Vec<TransactionId>>>);
+
+    impl EventListener for Applied {
+        fn on_applied(&mut self, transaction: &Transaction) {
+            self.0.lock().unwrap().push(transaction.transaction_id());
+        }
+    }
+
+    #[test]
+    fn test_columnar_matches_row_by_row() {
+        let mut csv = Vec::new();
+        generate(
+            &GeneratorParams {
+                seed: 3,
+                clients: 20,
+                rows: 5000,
+                dispute_rate: 0.05,
+                chargeback_rate: 0.2,
+            },
+            &mut csv,
+        )
+        .unwrap();
+
+        let mut row_by_row = PaymentProcessor::new();
+        let mut batch = ColumnarBatch::default();
+        for transaction in TransactionReader::from_reader(csv.as_slice()).iter() {
+            let transaction = transaction.unwrap();
+            row_by_row.process(&transaction);
+            batch.push(transaction);
+        }
+        let mut columnar = PaymentProcessor::new();
+        columnar.process_columnar(&batch);
+        assert_eq!(columnar.state_hash(), row_by_row.state_hash());
+        assert!(
+            row_by_row
+                .accounts()
+                .values()
+                .any(|account| account.is_locked())
+        );
+    }
+
+    #[test]
+    fn test_columnar_with_dispute_window() {
+        let config = ProcessorConfig {
+            dispute_window: Some(DisputeWindow { max_age: 2 }),
+            ..ProcessorConfig::default()
+        };
+        let deposit = |client_id, transaction_id| Transaction::Deposit {
+            client_id,
+            transaction_id,
+            amount: Amount::from(10),
+        };
+        let dispute = |client_id, transaction_id| Transaction::Dispute {
+            client_id,
+            transaction_id,
+        };
+        // Client 2 reuses client 1's tx 1, so by client 1's dispute it's
+        // client 2's. Sorted by client, 1's dispute would go through.
+        let first = [deposit(2, 10), deposit(1, 1), deposit(2, 1), dispute(1, 1)];
+        // IDs unique to their client from here. The window expires 3, 10,
+        // 11 and 12, so only the dispute of 13 goes through
+        let second = [
+            deposit(1, 3),
+            deposit(2, 11),
+            deposit(1, 4),
+            deposit(2, 12),
+            deposit(1, 5),
+            deposit(2, 13),
+            dispute(1, 3),
+            dispute(2, 11),
+            dispute(2, 13),
+        ];
+
+        let mut row_by_row = PaymentProcessor::with_config(config.clone());
+        let mut columnar = PaymentProcessor::with_config(config);
+        for chunk in [&first[..], &second[..]] {
+            let mut batch = ColumnarBatch::default();
+            for transaction in chunk {
+                row_by_row.process(transaction);
+                batch.push(transaction.clone());
+            }
+            columnar.process_columnar(&batch);
+            assert_eq!(columnar.state_hash(), row_by_row.state_hash());
+            assert_eq!(columnar.transactions(), row_by_row.transactions());
+            assert_eq!(
+                columnar.expired_transactions(),
+                row_by_row.expired_transactions()
+            );
+        }
+        assert_eq!(columnar.expired_transactions(), 4);
+        assert_eq!(columnar.accounts()[&2].held(), Amount::from(10));
+    }
+
+    #[test]
+    fn test_columnar_transfers_in_place() {
+        let deposit = |client_id, transaction_id, amount: u64| Transaction::Deposit {
+            client_id,
+            transaction_id,
+            amount: Amount::from(amount),
+        };
+        let mut batch = ColumnarBatch::default();
+        batch.push(deposit(2, 1, 5));
+        batch.push(deposit(1, 2, 5));
+        batch.push(Transaction::Transfer {
+            client_id: 2,
+            transaction_id: 3,
+            to_client_id: 1,
+            amount: Amount::from(5),
+        });
+        // Only goes through with the transfer applied first
+        batch.push(Transaction::Withdrawal {
+            client_id: 1,
+            transaction_id: 4,
+            amount: Amount::from(10),
+        });
+        batch.push(deposit(2, 5, 1));
+
+        let mut processor = PaymentProcessor::new();
+        let applied = Applied::default();
+        processor.add_listener(applied.clone());
+        processor.process_columnar(&batch);
+        assert_eq!(*applied.0.lock().unwrap(), [2, 1, 3, 4, 5]);
+        assert_eq!(processor.accounts()[&1].total(), Amount::from(0));
+        assert_eq!(processor.accounts()[&2].total(), Amount::from(1));
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use super::{Account, ClientId, PaymentProcessor};
+
+/// A client whose final balances differ between two processors, with its
+/// account on each side (`None` if it has none there)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountDifference {
+    pub client_id: ClientId,
+    pub left: Option<Account>,
+    pub right: Option<Account>,
+}
+
+/// Every client whose available/held funds or locked flag differ, by client
+/// ID. Bookkeeping that isn't part of the output (like open dispute counts)
+/// is left out.
+pub fn compare_accounts(
+    left: &PaymentProcessor,
+    right: &PaymentProcessor,
+) -> Vec<AccountDifference> {
+    let client_ids: BTreeSet<ClientId> = left
+        .accounts()
+        .keys()
+        .chain(right.accounts().keys())
+        .copied()
+        .collect();
+    let balances = |account: Option<&Account>| {
+        account.map(|account| (account.available(), account.held(), account.is_locked()))
+    };
+    client_ids
+        .into_iter()
+        .filter_map(|client_id| {
+            let (left, right) = (
+                left.accounts().get(&client_id),
+                right.accounts().get(&client_id),
+            );
+            (balances(left) != balances(right)).then(|| AccountDifference {
+                client_id,
+                left: left.cloned(),
+                right: right.cloned(),
+            })
+        })
+        .collect()
+}
+
+/// CSV with both sides next to each other, columns empty for a side
+/// without the account
+pub fn write_differences(
+    out: &mut impl Write,
+    differences: &[AccountDifference],
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "client,left_available,left_held,left_total,left_locked,\
+         right_available,right_held,right_total,right_locked"
+    )?;
+    for difference in differences {
+        write!(out, "{}", difference.client_id)?;
+        for account in [&difference.left, &difference.right] {
+            match account {
+                Some(account) => write!(
+                    out,
+                    ",{},{},{},{}",
+                    account.available(),
+                    account.held(),
+                    account.total(),
+                    account.is_locked()
+                )?,
+                None => write!(out, ",,,,")?,
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, not(feature = "concurrency_model")))]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{
+        Amount, DisputeCap, OverCap, ProcessorConfig, ShardedProcessor, Transaction,
+    };
+
+    #[test]
+    fn test_compare() {
+        let transactions = [
+            Transaction::Deposit {
+                client_id: 1,
+                transaction_id: 1,
+                amount: Amount::from(10),
+            },
+            Transaction::Deposit {
+                client_id: 2,
+                transaction_id: 2,
+                amount: Amount::from(5),
+            },
+            Transaction::Dispute {
+                client_id: 2,
+                transaction_id: 2,
+            },
+        ];
+        let mut default = PaymentProcessor::new();
+        let mut strict = PaymentProcessor::with_config(ProcessorConfig {
+            dispute_cap: Some(DisputeCap {
+                max_open: 0,
+                over_cap: OverCap::Reject,
+                freeze: false,
+            }),
+            ..ProcessorConfig::default()
+        });
+        let sharded = ShardedProcessor::new(PaymentProcessor::new().into_shards(2));
+        for transaction in &transactions {
+            default.process(transaction);
+            strict.process(transaction);
+        }
+        sharded.process_batch(transactions.to_vec());
+        let sharded = sharded.finish().unwrap();
+
+        assert!(compare_accounts(&default, &sharded).is_empty());
+        let differences = compare_accounts(&default, &strict);
+        assert_eq!(differences.len(), 1);
+        assert_eq!(differences[0].client_id, 2);
+
+        let mut csv = Vec::new();
+        write_differences(&mut csv, &differences).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "client,left_available,left_held,left_total,left_locked,\
+             right_available,right_held,right_total,right_locked\n\
+             2,0.0000,5.0000,5.0000,false,5.0000,0.0000,5.0000,false\n"
+        );
+
+        // A client only one side has
+        let mut empty = PaymentProcessor::new();
+        empty.process(&transactions[0]);
+        let mut csv = Vec::new();
+        write_differences(&mut csv, &compare_accounts(&empty, &default)).unwrap();
+        assert!(
+            String::from_utf8(csv)
+                .unwrap()
+                .ends_with("\n2,,,,,0.0000,5.0000,5.0000,false\n")
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use super::hashing::HashSet;
+use super::{ClientId, PaymentProcessor, Transaction, TransactionId};
+
+/// How far back a new transaction's ID is checked against (`--dedup-window`),
+/// for inputs that overlap at the boundaries. Like the dispute window it's
+/// counted in the client's own transactions, so it comes out the same
+/// however the clients are sharded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupWindow {
+    /// The client's last n deposits/withdrawals/transfers/adjustments
+    Last(usize),
+    /// Every one the client ever had
+    All,
+}
+
+impl FromStr for DedupWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(DedupWindow::All),
+            _ => match s.parse() {
+                Ok(0) | Err(_) => Err(format!(
+                    "dedup window has to be a number above 0 or all, got {}",
+                    s
+                )),
+                Ok(last) => Ok(DedupWindow::Last(last)),
+            },
+        }
+    }
+}
+
+/// A client's transaction IDs that duplicates are checked against, with
+/// the order they came in for dropping the oldest
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SeenTransactions {
+    ids: HashSet<TransactionId>,
+    order: VecDeque<TransactionId>,
+}
+
+impl SeenTransactions {
+    // Without a window nothing gets dropped, it's only the window that says
+    // how many to keep
+    fn insert(&mut self, transaction_id: TransactionId, window: Option<DedupWindow>) {
+        if !self.ids.insert(transaction_id) {
+            return;
+        }
+        self.order.push_back(transaction_id);
+        if let Some(DedupWindow::Last(last)) = window {
+            while self.order.len() > last {
+                let oldest = self.order.pop_front().unwrap();
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+
+    /// Oldest first
+    pub(crate) fn ids(&self) -> impl Iterator<Item = TransactionId> + '_ {
+        self.order.iter().copied()
+    }
+}
+
+impl PaymentProcessor {
+    // Checked before anything else about the transaction. The first time an
+    // ID comes up it's remembered whether it goes through or not, a second
+    // copy of a rejected row is still the same row.
+    pub(crate) fn is_duplicate(&mut self, transaction: &Transaction) -> bool {
+        let Some(window) = self.config.dedup_window else {
+            return false;
+        };
+        if !matches!(
+            transaction,
+            Transaction::Deposit { .. }
+                | Transaction::Withdrawal { .. }
+                | Transaction::Transfer { .. }
+                | Transaction::Adjustment { .. }
+        ) {
+            // The rest refer to one of those
+            return false;
+        }
+        let seen = self
+            .seen_transactions
+            .entry(transaction.client_id())
+            .or_default();
+        if seen.ids.contains(&transaction.transaction_id()) {
+            return true;
+        }
+        seen.insert(transaction.transaction_id(), Some(window));
+        false
+    }
+
+    // Restoring from a snapshot, or merging clients. Kept even without a
+    // window, so loading and saving the state without one (backfill, review
+    // and so on) doesn't lose them for the next run that has one.
+    pub(crate) fn remember_seen(
+        &mut self,
+        client_id: ClientId,
+        transaction_ids: impl IntoIterator<Item = TransactionId>,
+    ) {
+        let window = self.config.dedup_window;
+        let seen = self.seen_transactions.entry(client_id).or_default();
+        for transaction_id in transaction_ids {
+            seen.insert(transaction_id, window);
+        }
+    }
+
+    pub(crate) fn merge_seen(&mut self, client_id: ClientId, into: ClientId) {
+        if let Some(merged) = self.seen_transactions.remove(&client_id) {
+            self.remember_seen(into, merged.order);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, ProcessorConfig, RejectionReason};
+
+    fn deposit(client_id: ClientId, transaction_id: TransactionId) -> Transaction {
+        Transaction::Deposit {
+            client_id,
+            transaction_id,
+            amount: Amount::from(1),
+        }
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        assert_eq!("all".parse(), Ok(DedupWindow::All));
+        assert_eq!("3".parse(), Ok(DedupWindow::Last(3)));
+        assert!("0".parse::<DedupWindow>().is_err());
+
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            dedup_window: Some(DedupWindow::Last(2)),
+            ..ProcessorConfig::default()
+        });
+        for transaction_id in 1..=3 {
+            processor.process(&deposit(1, transaction_id));
+        }
+        // Other clients don't push client 1's IDs out
+        for transaction_id in 10..20 {
+            processor.process(&deposit(2, transaction_id));
+        }
+        assert_eq!(
+            processor.try_process(&deposit(1, 3)),
+            Err(RejectionReason::DuplicateTransaction)
+        );
+        // Disputes refer to a seen ID, they're not copies of it
+        assert_eq!(
+            processor.try_process(&Transaction::Dispute {
+                client_id: 1,
+                transaction_id: 3,
+            }),
+            Ok(())
+        );
+        // Tx 1 is out of the window, so it's applied again
+        assert_eq!(processor.try_process(&deposit(1, 1)), Ok(()));
+        assert_eq!(processor.accounts()[&1].total(), Amount::from(4));
+
+        // Still there in the next run
+        let mut snapshot = Vec::new();
+        processor.save_snapshot(&mut snapshot).unwrap();
+        let mut next = PaymentProcessor::with_config(processor.config.clone());
+        next.load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(
+            next.try_process(&deposit(2, 19)),
+            Err(RejectionReason::DuplicateTransaction)
+        );
+        assert_eq!(
+            next.try_process(&deposit(1, 3)),
+            Err(RejectionReason::DuplicateTransaction)
+        );
+
+        // Without a window nothing is checked
+        let mut processor = PaymentProcessor::new();
+        processor.process(&deposit(1, 1));
+        assert_eq!(processor.try_process(&deposit(1, 1)), Ok(()));
+    }
+
+    #[test]
+    fn test_seen_kept_without_window() {
+        let config = ProcessorConfig {
+            dedup_window: Some(DedupWindow::Last(10)),
+            ..ProcessorConfig::default()
+        };
+        let mut processor = PaymentProcessor::with_config(config.clone());
+        processor.process(&deposit(1, 1));
+        let mut snapshot = Vec::new();
+        processor.save_snapshot(&mut snapshot).unwrap();
+
+        // A pass without the window in between, like backfill
+        let mut between = PaymentProcessor::new();
+        between.load_snapshot(snapshot.as_slice()).unwrap();
+        let mut resaved = Vec::new();
+        between.save_snapshot(&mut resaved).unwrap();
+
+        let mut next = PaymentProcessor::with_config(config);
+        next.load_snapshot(resaved.as_slice()).unwrap();
+        assert_eq!(
+            next.try_process(&deposit(1, 1)),
+            Err(RejectionReason::DuplicateTransaction)
+        );
+        assert_eq!(next.accounts()[&1].total(), Amount::from(1));
+    }
+}
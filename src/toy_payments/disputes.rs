@@ -0,0 +1,297 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use super::amount::Amount;
+use super::enrichment::EnrichmentTable;
+use super::events::EventListener;
+use super::{Account, ClientId, Transaction, TransactionId};
+
+/// Cap on how many disputes a client can have open at once (`[dispute_cap]`
+/// in the config). Auto-disputed chargebacks close their dispute straight
+/// away, so they don't count against it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DisputeCap {
+    pub max_open: u32,
+    /// What happens to disputes past the cap
+    #[serde(default)]
+    pub over_cap: OverCap,
+    /// Also lock the account the first time it goes over, so it ends up in
+    /// the review queue
+    #[serde(default)]
+    pub freeze: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverCap {
+    /// Rejected as `TOO_MANY_DISPUTES`
+    #[default]
+    Reject,
+    /// Opened like any other, but reported
+    Flag,
+}
+
+/// Everything that happens to disputes, as CSV with
+/// `client,tx,event,amount,open_disputes`. Events are `opened`, `resolved`,
+/// `charged_back`, `over_cap_rejected`/`over_cap_flagged` (with how many
+/// disputes were already open) and `frozen` when going over the cap locked
+/// the account. The `merchant,category,channel` columns are filled in from
+/// the enrichment file, if there is one.
+pub struct DisputeReport<W: Write + Send> {
+    writer: W,
+    enrichment: Option<Arc<EnrichmentTable>>,
+}
+
+impl<W: Write + Send> DisputeReport<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(
+            writer,
+            "client,tx,event,amount,open_disputes,merchant,category,channel"
+        )?;
+        Ok(Self {
+            writer,
+            enrichment: None,
+        })
+    }
+
+    pub fn enriched(mut self, enrichment: Arc<EnrichmentTable>) -> Self {
+        self.enrichment = Some(enrichment);
+        self
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn row(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        event: &str,
+        amount: Option<Amount>,
+        open: Option<u32>,
+    ) {
+        let enrichment = self
+            .enrichment
+            .as_ref()
+            .and_then(|table| table.get(transaction_id))
+            .cloned()
+            .unwrap_or_default();
+        let written = writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{},{}",
+            client_id,
+            transaction_id,
+            event,
+            amount.map(|amount| amount.to_string()).unwrap_or_default(),
+            open.map(|open| open.to_string()).unwrap_or_default(),
+            quoted(&enrichment.merchant),
+            quoted(&enrichment.category),
+            quoted(&enrichment.channel)
+        );
+        if let Err(err) = written {
+            eprintln!("Error writing disputes report: {}", err);
+        }
+    }
+}
+
+// Merchant names have commas in them often enough
+fn quoted(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+impl<W: Write + Send> EventListener for DisputeReport<W> {
+    fn on_dispute_opened(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    ) {
+        self.row(client_id, transaction_id, "opened", Some(amount), None);
+    }
+
+    fn on_dispute_resolved(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    ) {
+        self.row(client_id, transaction_id, "resolved", Some(amount), None);
+    }
+
+    fn on_charged_back(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    ) {
+        self.row(
+            client_id,
+            transaction_id,
+            "charged_back",
+            Some(amount),
+            None,
+        );
+    }
+
+    fn on_dispute_over_cap(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        open: u32,
+        flagged: bool,
+    ) {
+        let event = match flagged {
+            true => "over_cap_flagged",
+            false => "over_cap_rejected",
+        };
+        self.row(client_id, transaction_id, event, None, Some(open));
+    }
+
+    fn on_account_locked(&mut self, client_id: ClientId, transaction: &Transaction, _: &Account) {
+        // Chargebacks lock too, those already have their own row
+        if let Transaction::Dispute { transaction_id, .. } = transaction {
+            self.row(client_id, *transaction_id, "frozen", None, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{
+        PaymentProcessor, ProcessorConfig, RejectionReason, ReviewQueue, Stats,
+    };
+    use std::sync::{Arc, Mutex};
+
+    fn processor(over_cap: OverCap) -> PaymentProcessor {
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            dispute_cap: Some(DisputeCap {
+                max_open: 2,
+                over_cap,
+                freeze: true,
+            }),
+            ..ProcessorConfig::default()
+        });
+        for transaction_id in 1..=4 {
+            processor.process(&Transaction::Deposit {
+                client_id: 1,
+                transaction_id,
+                amount: Amount::from(1),
+            });
+        }
+        processor
+    }
+
+    fn dispute(transaction_id: TransactionId) -> Transaction {
+        Transaction::Dispute {
+            client_id: 1,
+            transaction_id,
+        }
+    }
+
+    #[test]
+    fn test_reject_over_cap() {
+        let mut processor = processor(OverCap::Reject);
+        let report = Arc::new(Mutex::new(DisputeReport::new(Vec::new()).unwrap()));
+        let (stats, queue) = (
+            Arc::new(Mutex::new(Stats::new())),
+            Arc::new(Mutex::new(ReviewQueue::new())),
+        );
+        processor.add_listener(report.clone());
+        processor.add_listener(stats.clone());
+        processor.add_listener(queue.clone());
+
+        assert_eq!(processor.try_process(&dispute(1)), Ok(()));
+        assert_eq!(processor.try_process(&dispute(2)), Ok(()));
+        // Still rejected for what's actually wrong with it
+        assert_eq!(
+            processor.try_process(&dispute(2)),
+            Err(RejectionReason::AlreadyDisputed)
+        );
+        assert_eq!(
+            processor.try_process(&dispute(3)),
+            Err(RejectionReason::TooManyDisputes)
+        );
+        assert_eq!(
+            processor.try_process(&dispute(4)),
+            Err(RejectionReason::TooManyDisputes)
+        );
+        let account = &processor.accounts()[&1];
+        assert!(account.is_locked());
+        assert_eq!(account.open_disputes(), 2);
+        assert_eq!(account.held(), Amount::from(2));
+
+        // Resolving makes room again. Still frozen, which isn't a violation
+        // with fewer disputes open either
+        processor.process(&Transaction::Resolve {
+            client_id: 1,
+            transaction_id: 1,
+        });
+        assert!(processor.accounts()[&1].is_locked());
+        assert_eq!(processor.check_invariants(), vec![]);
+        assert_eq!(processor.try_process(&dispute(3)), Ok(()));
+
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats.rejected[&RejectionReason::TooManyDisputes], 2);
+        assert_eq!(stats.disputes_over_cap, 2);
+        assert_eq!(stats.accounts_frozen, 1);
+        assert_eq!(queue.lock().unwrap().pending().count(), 1);
+        drop(processor);
+        let report = Arc::into_inner(report).unwrap().into_inner().unwrap();
+        assert_eq!(
+            String::from_utf8(report.writer).unwrap(),
+            "client,tx,event,amount,open_disputes,merchant,category,channel\n\
+             1,1,opened,1.0000,,,,\n\
+             1,2,opened,1.0000,,,,\n\
+             1,3,over_cap_rejected,,2,,,\n\
+             1,3,frozen,,,,,\n\
+             1,4,over_cap_rejected,,2,,,\n\
+             1,1,resolved,1.0000,,,,\n\
+             1,3,opened,1.0000,,,,\n"
+        );
+    }
+
+    #[test]
+    fn test_flag_over_cap() {
+        let mut processor = processor(OverCap::Flag);
+        let stats = Arc::new(Mutex::new(Stats::new()));
+        processor.add_listener(stats.clone());
+        for transaction_id in 1..=4 {
+            assert_eq!(processor.try_process(&dispute(transaction_id)), Ok(()));
+        }
+        let account = &processor.accounts()[&1];
+        assert!(account.is_locked());
+        assert_eq!(account.open_disputes(), 4);
+        assert!(processor.check_invariants().is_empty());
+
+        // Counts come back from the stored transactions after a snapshot
+        let mut snapshot = Vec::new();
+        processor.save_snapshot(&mut snapshot).unwrap();
+        let mut restored = PaymentProcessor::new();
+        restored.load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.accounts()[&1].open_disputes(), 4);
+        // The freeze is in the snapshot, not worked out from the config
+        for transaction_id in 1..=4 {
+            restored.process(&Transaction::Resolve {
+                client_id: 1,
+                transaction_id,
+            });
+        }
+        assert!(restored.accounts()[&1].is_locked());
+        assert!(restored.check_invariants().is_empty());
+
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats.disputes_opened, 4);
+        assert_eq!(stats.disputes_over_cap, 2);
+        assert_eq!(stats.accounts_frozen, 1);
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+
+use super::PaymentProcessor;
+use super::integrity::decode_hex;
+
+const MAGIC: &[u8; 6] = b"TPSENC";
+const VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+
+/// 256-bit key for encrypting snapshots at rest
+#[derive(Clone)]
+pub struct SnapshotKey(Key<Aes256Gcm>);
+
+impl SnapshotKey {
+    /// Expects 64 hex characters (surrounding whitespace is ignored)
+    pub fn from_hex(hex: &str) -> Result<Self, String> {
+        let mut key = [0u8; 32];
+        decode_hex(hex, &mut key).map_err(|err| format!("invalid key: {}", err))?;
+        Ok(Self(key.into()))
+    }
+}
+
+// Keep the key out of any debug output
+impl fmt::Debug for SnapshotKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SnapshotKey(..)")
+    }
+}
+
+/// Encrypted snapshots wrap the plain snapshot bytes in AES-256-GCM:
+/// - magic `TPSENC`, version byte (both also authenticated as associated data)
+/// - 12 byte random nonce
+/// - ciphertext with the 16 byte tag at the end
+///
+/// The whole snapshot goes through memory, since GCM can't verify anything
+/// before it has seen all of it.
+impl PaymentProcessor {
+    pub fn save_snapshot_encrypted<W: Write>(
+        &self,
+        mut writer: W,
+        key: &SnapshotKey,
+    ) -> io::Result<()> {
+        let mut plaintext = Vec::new();
+        self.save_snapshot(&mut plaintext)?;
+
+        let header = header();
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = Aes256Gcm::new(&key.0)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: &header,
+                },
+            )
+            .map_err(|_| io::Error::other("failed to encrypt snapshot"))?;
+
+        writer.write_all(&header)?;
+        writer.write_all(&nonce)?;
+        writer.write_all(&ciphertext)?;
+        writer.flush()
+    }
+
+    /// Loads encrypted as well as plain snapshots, so existing plain state
+    /// can be picked up and written back encrypted. Encrypted ones need `key`.
+    pub fn load_snapshot_with_key<R: Read>(
+        &mut self,
+        mut reader: R,
+        key: Option<&SnapshotKey>,
+    ) -> io::Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let header = header();
+        let Some(rest) = data.strip_prefix(&header[..MAGIC.len()]) else {
+            return self.load_snapshot(data.as_slice());
+        };
+        let Some(key) = key else {
+            return Err(invalid_data("snapshot is encrypted but no key was given"));
+        };
+        match rest.split_first() {
+            Some((&VERSION, rest)) if rest.len() >= NONCE_LEN => {
+                let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+                let plaintext = Aes256Gcm::new(&key.0)
+                    .decrypt(
+                        Nonce::from_slice(nonce),
+                        Payload {
+                            msg: ciphertext,
+                            aad: &header,
+                        },
+                    )
+                    .map_err(|_| invalid_data("failed to decrypt snapshot (wrong key?)"))?;
+                self.load_snapshot(plaintext.as_slice())
+            }
+            Some((&VERSION, _)) => Err(invalid_data("truncated encrypted snapshot")),
+            _ => Err(invalid_data("unsupported encrypted snapshot version")),
+        }
+    }
+}
+
+fn header() -> [u8; 7] {
+    let mut header = [0u8; 7];
+    header[..MAGIC.len()].copy_from_slice(MAGIC);
+    header[MAGIC.len()] = VERSION;
+    header
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, Transaction};
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn processor() -> PaymentProcessor {
+        let mut processor = PaymentProcessor::new();
+        processor.process(&Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Amount::from(12.5),
+        });
+        processor
+    }
+
+    #[test]
+    fn test_encrypted_roundtrip() {
+        let key = SnapshotKey::from_hex(KEY).unwrap();
+        let mut encrypted = Vec::new();
+        processor()
+            .save_snapshot_encrypted(&mut encrypted, &key)
+            .unwrap();
+
+        let mut plain = Vec::new();
+        processor().save_snapshot(&mut plain).unwrap();
+        assert!(!encrypted.windows(plain.len()).any(|window| window == plain));
+
+        let mut loaded = PaymentProcessor::new();
+        loaded
+            .load_snapshot_with_key(encrypted.as_slice(), Some(&key))
+            .unwrap();
+        assert_eq!(loaded.accounts(), processor().accounts());
+
+        // Wrong key, no key, tampered data
+        let other = SnapshotKey::from_hex(&KEY.replace("00", "ff")).unwrap();
+        assert!(
+            PaymentProcessor::new()
+                .load_snapshot_with_key(encrypted.as_slice(), Some(&other))
+                .is_err()
+        );
+        assert!(
+            PaymentProcessor::new()
+                .load_snapshot_with_key(encrypted.as_slice(), None)
+                .is_err()
+        );
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+        assert!(
+            PaymentProcessor::new()
+                .load_snapshot_with_key(encrypted.as_slice(), Some(&key))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_plain_snapshot_still_loads() {
+        let key = SnapshotKey::from_hex(KEY).unwrap();
+        let mut plain = Vec::new();
+        processor().save_snapshot(&mut plain).unwrap();
+
+        let mut loaded = PaymentProcessor::new();
+        loaded
+            .load_snapshot_with_key(plain.as_slice(), Some(&key))
+            .unwrap();
+        assert_eq!(loaded.accounts(), processor().accounts());
+    }
+
+    #[test]
+    fn test_key_parsing() {
+        assert!(SnapshotKey::from_hex(&format!(" {}\n", KEY)).is_ok());
+        assert!(SnapshotKey::from_hex("abcd").is_err());
+        assert!(SnapshotKey::from_hex(&KEY.replace("0f", "zz")).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+
+use csv::{ReaderBuilder, Trim};
+use serde::Deserialize;
+
+use super::TransactionId;
+use super::hashing::HashMap;
+
+/// Reference data about a transaction from outside the input, any of it
+/// can be empty
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Enrichment {
+    #[serde(default)]
+    pub merchant: String,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub channel: String,
+}
+
+// The fields that are set, as `, merchant: ..` to tack onto a log line
+impl fmt::Display for Enrichment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in [
+            ("merchant", &self.merchant),
+            ("category", &self.category),
+            ("channel", &self.channel),
+        ] {
+            if !value.is_empty() {
+                write!(f, ", {}: {}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct EnrichmentRow {
+    tx: TransactionId,
+    #[serde(flatten)]
+    enrichment: Enrichment,
+}
+
+/// Enrichment by transaction ID, from a CSV with a `tx` column and any of
+/// `merchant`, `category` and `channel`. Disputes, resolves and chargebacks
+/// pick up the data of the transaction they refer to.
+#[derive(Debug, Clone, Default)]
+pub struct EnrichmentTable {
+    rows: HashMap<TransactionId, Enrichment>,
+}
+
+impl EnrichmentTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Later rows for the same tx win
+    pub fn read(reader: impl Read) -> Result<Self, Box<dyn Error>> {
+        let mut reader = ReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(reader);
+        let mut table = Self::new();
+        for row in reader.deserialize() {
+            let row: EnrichmentRow = row?;
+            table.rows.insert(row.tx, row.enrichment);
+        }
+        Ok(table)
+    }
+
+    pub fn get(&self, transaction_id: TransactionId) -> Option<&Enrichment> {
+        self.rows.get(&transaction_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let table = EnrichmentTable::read(
+            "tx,merchant,category,channel\n\
+             1, ACME ,groceries,pos\n\
+             2,Books Ltd,,online\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(1).unwrap().merchant, "ACME");
+        assert_eq!(
+            table.get(2).unwrap().to_string(),
+            ", merchant: Books Ltd, channel: online"
+        );
+        assert!(table.get(3).is_none());
+
+        // Columns can be left out
+        let table = EnrichmentTable::read("tx,channel\n5,atm\n".as_bytes()).unwrap();
+        assert_eq!(table.get(5).unwrap().to_string(), ", channel: atm");
+    }
+}
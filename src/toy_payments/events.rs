@@ -0,0 +1,259 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use super::amount::Amount;
+use super::{Account, ClientId, OperatorAction, RowResult, Transaction, TransactionId};
+
+/// Why the processor refused to apply a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RejectionReason {
+    AccountLocked,
+    AmountAboveMaximum,
+    AmountBelowMinimum,
+    AlreadyChargedBack,
+    AlreadyDisputed,
+    CategoryLimitExceeded,
+    ClientMerged,
+    ClientMismatch,
+    CrossShard,
+    DisputeWindowExpired,
+    DuplicateTransaction,
+    InsufficientFunds,
+    MergeIntoSelf,
+    NonPositiveAmount,
+    NotChargedBack,
+    NotDisputed,
+    NotLocked,
+    OverWithdrawalLimit,
+    PseudonymInUse,
+    TooManyDisputes,
+    UnknownClient,
+    UnknownTransaction,
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RejectionReason::AccountLocked => "account locked",
+            RejectionReason::AmountAboveMaximum => "amount above the maximum for its type",
+            RejectionReason::AmountBelowMinimum => "amount below the minimum for its type",
+            RejectionReason::AlreadyChargedBack => "transaction already charged back",
+            RejectionReason::AlreadyDisputed => "transaction already disputed",
+            RejectionReason::CategoryLimitExceeded => "over the category's withdrawal limit",
+            RejectionReason::ClientMerged => "client was merged into another",
+            RejectionReason::ClientMismatch => "transaction belongs to another client",
+            RejectionReason::CrossShard => "other client is handled by another shard",
+            RejectionReason::DisputeWindowExpired => "transaction too old to dispute",
+            RejectionReason::DuplicateTransaction => "transaction ID already seen",
+            RejectionReason::InsufficientFunds => "insufficient funds",
+            RejectionReason::MergeIntoSelf => "can't merge a client into itself",
+            RejectionReason::NonPositiveAmount => "amount has to be positive",
+            RejectionReason::NotChargedBack => "transaction not charged back",
+            RejectionReason::NotDisputed => "transaction not disputed",
+            RejectionReason::NotLocked => "account not locked",
+            RejectionReason::OverWithdrawalLimit => "over the tier's withdrawal limit",
+            RejectionReason::PseudonymInUse => "pseudonym is already in use",
+            RejectionReason::TooManyDisputes => "too many open disputes",
+            RejectionReason::UnknownClient => "unknown client",
+            RejectionReason::UnknownTransaction => "unknown transaction",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl RejectionReason {
+    /// Short machine-readable name, used in result codes
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectionReason::AccountLocked => "LOCKED",
+            RejectionReason::AmountAboveMaximum => "AMOUNT_ABOVE_MAX",
+            RejectionReason::AmountBelowMinimum => "AMOUNT_BELOW_MIN",
+            RejectionReason::AlreadyChargedBack => "ALREADY_CHARGED_BACK",
+            RejectionReason::AlreadyDisputed => "ALREADY_DISPUTED",
+            RejectionReason::CategoryLimitExceeded => "CATEGORY_LIMIT",
+            RejectionReason::ClientMerged => "CLIENT_MERGED",
+            RejectionReason::ClientMismatch => "CLIENT_MISMATCH",
+            RejectionReason::CrossShard => "CROSS_SHARD",
+            RejectionReason::DisputeWindowExpired => "DISPUTE_WINDOW_EXPIRED",
+            RejectionReason::DuplicateTransaction => "DUPLICATE",
+            RejectionReason::InsufficientFunds => "INSUFFICIENT_FUNDS",
+            RejectionReason::MergeIntoSelf => "MERGE_INTO_SELF",
+            RejectionReason::NonPositiveAmount => "NON_POSITIVE_AMOUNT",
+            RejectionReason::NotChargedBack => "NOT_CHARGED_BACK",
+            RejectionReason::NotDisputed => "NOT_DISPUTED",
+            RejectionReason::NotLocked => "NOT_LOCKED",
+            RejectionReason::OverWithdrawalLimit => "TIER_LIMIT",
+            RejectionReason::PseudonymInUse => "PSEUDONYM_IN_USE",
+            RejectionReason::TooManyDisputes => "TOO_MANY_DISPUTES",
+            RejectionReason::UnknownClient => "UNKNOWN_CLIENT",
+            RejectionReason::UnknownTransaction => "UNKNOWN_TRANSACTION",
+        }
+    }
+}
+
+/// Callbacks fired by the PaymentProcessor as it goes through transactions.
+/// Every method defaults to doing nothing, so listeners only need to
+/// implement the ones they care about.
+///
+/// `on_applied`/`on_rejected` fire exactly once per processed transaction.
+/// The more specific callbacks fire after `on_applied` for the transaction
+/// that caused them, and `on_result` comes last with the per-step outcome.
+pub trait EventListener: Send {
+    fn on_applied(&mut self, _transaction: &Transaction) {}
+
+    fn on_rejected(&mut self, _transaction: &Transaction, _reason: RejectionReason) {}
+
+    fn on_dispute_opened(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: Amount,
+    ) {
+    }
+
+    fn on_dispute_resolved(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: Amount,
+    ) {
+    }
+
+    fn on_charged_back(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: Amount,
+    ) {
+    }
+
+    /// A dispute came in for a client that already had `open` disputes open,
+    /// which is at or over the cap. Flagged ones still went through (and got
+    /// `on_dispute_opened`), the others were rejected.
+    fn on_dispute_over_cap(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _open: u32,
+        _flagged: bool,
+    ) {
+    }
+
+    /// `account` is how the account looks right after it got locked, by a
+    /// chargeback or by going over the dispute cap
+    fn on_account_locked(
+        &mut self,
+        _client_id: ClientId,
+        _transaction: &Transaction,
+        _account: &Account,
+    ) {
+    }
+
+    /// Fires once per processed transaction, after everything else for it.
+    /// Multi-part transactions (transfers, auto-disputed chargebacks) say
+    /// which of their steps went through here.
+    fn on_result(&mut self, _transaction: &Transaction, _result: &RowResult) {}
+
+    /// Fires for every operator action, whether it went through or not
+    fn on_operator_action(
+        &mut self,
+        _action: &OperatorAction,
+        _result: Result<(), RejectionReason>,
+    ) {
+    }
+}
+
+// Lets callers keep a handle on a listener (e.g. to read stats back out)
+// after handing it over to the processor. A macro so the same forwarding
+// also covers loom's Arc/Mutex under the concurrency_model feature.
+macro_rules! forward_to_locked_listener {
+    ($arc:ident, $mutex:ident) => {
+        impl<L: EventListener + ?Sized> EventListener for $arc<$mutex<L>> {
+            fn on_applied(&mut self, transaction: &Transaction) {
+                self.lock().unwrap().on_applied(transaction)
+            }
+
+            fn on_rejected(&mut self, transaction: &Transaction, reason: RejectionReason) {
+                self.lock().unwrap().on_rejected(transaction, reason)
+            }
+
+            fn on_dispute_opened(
+                &mut self,
+                client_id: ClientId,
+                transaction_id: TransactionId,
+                amount: Amount,
+            ) {
+                self.lock()
+                    .unwrap()
+                    .on_dispute_opened(client_id, transaction_id, amount)
+            }
+
+            fn on_dispute_resolved(
+                &mut self,
+                client_id: ClientId,
+                transaction_id: TransactionId,
+                amount: Amount,
+            ) {
+                self.lock()
+                    .unwrap()
+                    .on_dispute_resolved(client_id, transaction_id, amount)
+            }
+
+            fn on_charged_back(
+                &mut self,
+                client_id: ClientId,
+                transaction_id: TransactionId,
+                amount: Amount,
+            ) {
+                self.lock()
+                    .unwrap()
+                    .on_charged_back(client_id, transaction_id, amount)
+            }
+
+            fn on_dispute_over_cap(
+                &mut self,
+                client_id: ClientId,
+                transaction_id: TransactionId,
+                open: u32,
+                flagged: bool,
+            ) {
+                self.lock()
+                    .unwrap()
+                    .on_dispute_over_cap(client_id, transaction_id, open, flagged)
+            }
+
+            fn on_account_locked(
+                &mut self,
+                client_id: ClientId,
+                transaction: &Transaction,
+                account: &Account,
+            ) {
+                self.lock()
+                    .unwrap()
+                    .on_account_locked(client_id, transaction, account)
+            }
+
+            fn on_result(&mut self, transaction: &Transaction, result: &RowResult) {
+                self.lock().unwrap().on_result(transaction, result)
+            }
+
+            fn on_operator_action(
+                &mut self,
+                action: &OperatorAction,
+                result: Result<(), RejectionReason>,
+            ) {
+                self.lock().unwrap().on_operator_action(action, result)
+            }
+        }
+    };
+}
+
+forward_to_locked_listener!(Arc, Mutex);
+
+#[cfg(feature = "concurrency_model")]
+mod loom_listener {
+    use super::*;
+    use loom::sync::{Arc, Mutex};
+
+    forward_to_locked_listener!(Arc, Mutex);
+}
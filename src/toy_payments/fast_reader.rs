@@ -0,0 +1,276 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use csv::{ByteRecord, Position, Reader};
+
+use super::amount::Amount;
+use super::{CsvDialect, ParseError, RoundingMode, Transaction};
+
+/// Where the known columns live in each record, resolved from the header once
+#[derive(Debug, Clone)]
+pub struct Columns {
+    ty: usize,
+    client: usize,
+    tx: usize,
+    amount: usize,
+    reference: Option<usize>,
+    to: Option<usize>,
+}
+
+impl Columns {
+    pub fn from_headers(headers: &ByteRecord) -> Result<Self, ParseError> {
+        let find = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim_ascii() == name.as_bytes())
+        };
+        let require = |name: &str| {
+            find(name).ok_or_else(|| ParseError::Record {
+                position: Position::new(),
+                message: format!("missing column: {}", name),
+            })
+        };
+        Ok(Self {
+            ty: require("type")?,
+            client: require("client")?,
+            tx: require("tx")?,
+            amount: require("amount")?,
+            reference: find("reference"),
+            to: find("to"),
+        })
+    }
+}
+
+/// Alternative to TransactionReader for the hot path. It skips serde and
+/// reuses a single ByteRecord, slicing the known columns out and parsing
+/// them by hand, so the common rows don't allocate at all.
+///
+/// Amounts go through the same f64 conversion as the serde path, so both
+/// readers always produce the same transactions.
+pub struct FastTransactionReader<R = File> {
+    reader: Reader<R>,
+    record: ByteRecord,
+    columns: Columns,
+    rounding: RoundingMode,
+}
+
+impl FastTransactionReader {
+    pub fn from_path(path: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_reader(File::open(path)?)
+    }
+}
+
+impl<R: Read> FastTransactionReader<R> {
+    pub fn from_reader(reader: R) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_dialect(reader, &CsvDialect::default())
+    }
+
+    pub fn with_dialect(
+        reader: R,
+        dialect: &CsvDialect,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // No trimming here, the fields get trimmed as they're parsed
+        let mut reader = dialect.open(&dialect.builder(), reader);
+        let columns = Columns::from_headers(reader.byte_headers()?)?;
+
+        Ok(Self {
+            reader,
+            record: ByteRecord::new(),
+            columns,
+            rounding: RoundingMode::default(),
+        })
+    }
+
+    /// How amounts with more than 4 decimals get cut down, truncated by default
+    pub fn with_rounding(mut self, rounding: RoundingMode) -> Self {
+        self.rounding = rounding;
+        self
+    }
+}
+
+impl<R: Read> FastTransactionReader<R> {
+    /// Just the CSV side of next(), for timing it separately from parse_record
+    pub fn next_record(&mut self) -> Option<Result<(&ByteRecord, &Columns), ParseError>> {
+        match self.reader.read_byte_record(&mut self.record) {
+            Ok(true) => Some(Ok((&self.record, &self.columns))),
+            Ok(false) => None,
+            Err(err) => Some(Err(ParseError::Csv(err))),
+        }
+    }
+}
+
+impl<R: Read> Iterator for FastTransactionReader<R> {
+    type Item = Result<Transaction, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.read_byte_record(&mut self.record) {
+            Ok(true) => Some(parse_record(&self.record, &self.columns, self.rounding)),
+            Ok(false) => None,
+            Err(err) => Some(Err(ParseError::Csv(err))),
+        }
+    }
+}
+
+pub fn parse_record(
+    record: &ByteRecord,
+    columns: &Columns,
+    rounding: RoundingMode,
+) -> Result<Transaction, ParseError> {
+    let field = |index: usize| record.get(index).unwrap_or_default().trim_ascii();
+    let fail = |message: String| ParseError::Record {
+        position: record.position().cloned().unwrap_or_else(Position::new),
+        message,
+    };
+
+    let client_id = parse_uint(field(columns.client))
+        .and_then(|value| u16::try_from(value).ok())
+        .ok_or_else(|| fail(format!("invalid client: {}", lossy(field(columns.client)))))?;
+    let transaction_id = parse_uint(field(columns.tx))
+        .and_then(|value| u32::try_from(value).ok())
+        .ok_or_else(|| fail(format!("invalid tx: {}", lossy(field(columns.tx)))))?;
+    let amount = || -> Result<Amount, ParseError> {
+        let raw = field(columns.amount);
+        if raw.is_empty() {
+            return Err(fail(format!(
+                "missing amount for {}",
+                lossy(field(columns.ty))
+            )));
+        }
+        std::str::from_utf8(raw)
+            .ok()
+            .and_then(|raw| raw.parse::<f64>().ok())
+            .map(|amount| Amount::from_f64_rounded(amount, rounding))
+            .ok_or_else(|| fail(format!("invalid amount: {}", lossy(raw))))
+    };
+
+    match field(columns.ty) {
+        b"deposit" => Ok(Transaction::Deposit {
+            client_id,
+            transaction_id,
+            amount: amount()?,
+        }),
+        b"withdrawal" => Ok(Transaction::Withdrawal {
+            client_id,
+            transaction_id,
+            amount: amount()?,
+        }),
+        b"dispute" => Ok(Transaction::Dispute {
+            client_id,
+            transaction_id,
+        }),
+        b"resolve" => Ok(Transaction::Resolve {
+            client_id,
+            transaction_id,
+        }),
+        b"chargeback" => Ok(Transaction::Chargeback {
+            client_id,
+            transaction_id,
+        }),
+        b"auto_chargeback" => Ok(Transaction::AutoChargeback {
+            client_id,
+            transaction_id,
+        }),
+        b"transfer" => {
+            let amount = amount()?;
+            let to = columns.to.map(field).unwrap_or_default();
+            let to_client_id = if to.is_empty() {
+                return Err(fail(String::from("missing recipient for transfer")));
+            } else {
+                parse_uint(to)
+                    .and_then(|value| u16::try_from(value).ok())
+                    .ok_or_else(|| fail(format!("invalid to: {}", lossy(to))))?
+            };
+            Ok(Transaction::Transfer {
+                client_id,
+                transaction_id,
+                to_client_id,
+                amount,
+            })
+        }
+        ty @ (b"adjustment_credit" | b"adjustment_debit") => {
+            let amount = amount()?;
+            let reference = columns
+                .reference
+                .map(field)
+                .filter(|reference| !reference.is_empty())
+                .ok_or_else(|| fail(String::from("missing reference for adjustment")))?;
+            Ok(Transaction::Adjustment {
+                client_id,
+                transaction_id,
+                amount: if ty == b"adjustment_debit" {
+                    -amount
+                } else {
+                    amount
+                },
+                reference: lossy(reference),
+            })
+        }
+        ty => Err(fail(format!("unknown transaction type: {}", lossy(ty)))),
+    }
+}
+
+fn parse_uint(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 19 {
+        return None;
+    }
+    let mut value: u64 = 0;
+    for digit in digits {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        value = value * 10 + (digit - b'0') as u64;
+    }
+    Some(value)
+}
+
+fn lossy(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::TransactionReader;
+
+    fn resource(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("resources")
+            .join(name)
+    }
+
+    #[test]
+    fn test_matches_serde_reader() {
+        for name in [
+            "test-data.csv",
+            "test-data-2.csv",
+            "test-data-3.csv",
+            "test-data-4.csv",
+            "adjustments.csv",
+            "transfers.csv",
+            "bad-transaction.csv",
+        ] {
+            let expected: Vec<Option<String>> = TransactionReader::from_path(resource(name))
+                .unwrap()
+                .iter()
+                .map(|result| result.ok().map(|txn| txn.to_string()))
+                .collect();
+            let actual: Vec<Option<String>> = FastTransactionReader::from_path(resource(name))
+                .unwrap()
+                .map(|result| result.ok().map(|txn| txn.to_string()))
+                .collect();
+
+            assert_eq!(actual, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_parse_uint() {
+        assert_eq!(parse_uint(b"0"), Some(0));
+        assert_eq!(parse_uint(b"65535"), Some(65535));
+        assert_eq!(parse_uint(b""), None);
+        assert_eq!(parse_uint(b"12a"), None);
+        assert_eq!(parse_uint(b"-1"), None);
+        assert_eq!(parse_uint(b"99999999999999999999"), None);
+    }
+}
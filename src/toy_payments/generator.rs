@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::amount::Amount;
+use super::integrity::Checksum;
+use super::sampling::Rng;
+use super::{Manifest, PaymentProcessor, TransactionId};
+
+/// Everything that decides what `generate` writes. The same parameters
+/// always give byte-for-byte the same file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeneratorParams {
+    pub seed: u64,
+    pub clients: u16,
+    pub rows: u32,
+    /// Chance of a row opening a dispute (and, separately, of closing one)
+    pub dispute_rate: f64,
+    /// Fraction of closed disputes that end in a chargeback rather than a resolve
+    pub chargeback_rate: f64,
+}
+
+/// Totals the generated file has to end up with. Amounts are kept as
+/// their exact 4 decimal strings, so they compare the same in JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpectedTotals {
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked_accounts: u64,
+}
+
+impl ExpectedTotals {
+    pub fn of(processor: &PaymentProcessor) -> Self {
+        let mut totals = Totals::default();
+        for account in processor.accounts().values() {
+            totals.add(account.available(), account.held(), account.is_locked());
+        }
+        totals.into()
+    }
+}
+
+impl fmt::Display for ExpectedTotals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "available {}, held {}, total {}, {} locked",
+            self.available, self.held, self.total, self.locked_accounts
+        )
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    available: Amount,
+    held: Amount,
+    locked_accounts: u64,
+}
+
+impl Totals {
+    fn add(&mut self, available: Amount, held: Amount, locked: bool) {
+        self.available += available;
+        self.held += held;
+        self.locked_accounts += locked as u64;
+    }
+}
+
+impl From<Totals> for ExpectedTotals {
+    fn from(totals: Totals) -> Self {
+        Self {
+            available: totals.available.to_string(),
+            held: totals.held.to_string(),
+            total: (totals.available + totals.held).to_string(),
+            locked_accounts: totals.locked_accounts,
+        }
+    }
+}
+
+// What the generator knows about a client, enough to only emit rows
+// whose effect is known up front
+#[derive(Default)]
+struct ClientModel {
+    available: Amount,
+    held: Amount,
+    locked: bool,
+    // Recent deposits that can still be disputed. Capped so the model
+    // stays small for huge files.
+    disputable: VecDeque<(TransactionId, Amount)>,
+    disputed: Vec<(TransactionId, Amount)>,
+}
+
+const DISPUTABLE_DEPOSITS: usize = 8;
+const WITHDRAWAL_RATE: f64 = 0.3;
+/// Largest deposit, in 1/10000ths
+const MAX_DEPOSIT: u64 = 1000 * 10000;
+
+/// Writes a random but reproducible input file and returns the manifest
+/// describing it: the parameters to regenerate it, its record count and
+/// checksum, and the totals processing it has to give.
+///
+/// Rows are only generated when their effect is known up front (no
+/// withdrawals over the available funds, disputes only on the client's
+/// own deposits, resolves/chargebacks only on open disputes), so the
+/// totals come from the generator's own bookkeeping rather than from
+/// running the processor. The one exception on purpose: clients keep
+/// getting deposits after a chargeback, which have to be rejected.
+pub fn generate(params: &GeneratorParams, out: &mut impl Write) -> io::Result<Manifest> {
+    if params.clients == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "need at least one client",
+        ));
+    }
+    let mut rng = Rng(params.seed);
+    let mut clients: Vec<ClientModel> = (0..params.clients)
+        .map(|_| ClientModel::default())
+        .collect();
+    let mut hasher = Sha256::new();
+    let mut line = String::from("type,client,tx,amount\n");
+
+    for transaction_id in 1..=params.rows {
+        out.write_all(line.as_bytes())?;
+        hasher.update(line.as_bytes());
+        line.clear();
+
+        let index = rng.below(params.clients as u64) as usize;
+        let client_id = index + 1;
+        let client = &mut clients[index];
+        let roll = rng.fraction();
+
+        if !client.locked && !client.disputed.is_empty() && roll < params.dispute_rate {
+            let (disputed_id, amount) = client
+                .disputed
+                .swap_remove(rng.below(client.disputed.len() as u64) as usize);
+            client.held -= amount;
+            if rng.fraction() < params.chargeback_rate {
+                client.locked = true;
+                writeln!(line, "chargeback,{},{},", client_id, disputed_id).unwrap();
+            } else {
+                client.available += amount;
+                writeln!(line, "resolve,{},{},", client_id, disputed_id).unwrap();
+            }
+        } else if !client.locked
+            && !client.disputable.is_empty()
+            && roll < 2.0 * params.dispute_rate
+        {
+            let picked = rng.below(client.disputable.len() as u64) as usize;
+            let (disputed_id, amount) = client.disputable.remove(picked).unwrap();
+            client.available -= amount;
+            client.held += amount;
+            client.disputed.push((disputed_id, amount));
+            writeln!(line, "dispute,{},{},", client_id, disputed_id).unwrap();
+        } else if !client.locked && client.available > Amount::from_raw(0) && roll < WITHDRAWAL_RATE
+        {
+            let raw = rng.below(client.available.to_raw() as u64) + 1;
+            let (text, amount) = amount_text(raw);
+            if amount <= client.available {
+                client.available -= amount;
+            }
+            writeln!(line, "withdrawal,{},{},{}", client_id, transaction_id, text).unwrap();
+        } else {
+            let (text, amount) = amount_text(rng.below(MAX_DEPOSIT) + 1);
+            if !client.locked {
+                client.available += amount;
+                client.disputable.push_back((transaction_id, amount));
+                if client.disputable.len() > DISPUTABLE_DEPOSITS {
+                    client.disputable.pop_front();
+                }
+            }
+            writeln!(line, "deposit,{},{},{}", client_id, transaction_id, text).unwrap();
+        }
+    }
+    out.write_all(line.as_bytes())?;
+    hasher.update(line.as_bytes());
+
+    let mut totals = Totals::default();
+    for client in &clients {
+        totals.add(client.available, client.held, client.locked);
+    }
+    Ok(Manifest {
+        records: Some(params.rows as u64),
+        checksum: Some(Checksum::Sha256(hasher.finalize().into()).to_string()),
+        generator: Some(params.clone()),
+        expected: Some(totals.into()),
+    })
+}
+
+// The amount as written, and as the readers will make of it (they go
+// through f64, which doesn't always land on the same 1/10000th)
+fn amount_text(raw: u64) -> (String, Amount) {
+    let text = Amount::from_raw(raw as i64).to_string();
+    let amount = Amount::from(text.parse::<f64>().unwrap());
+    (text, amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::TransactionReader;
+
+    fn params(seed: u64) -> GeneratorParams {
+        GeneratorParams {
+            seed,
+            clients: 20,
+            rows: 5000,
+            dispute_rate: 0.05,
+            chargeback_rate: 0.2,
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_file() {
+        let (mut a, mut b, mut c) = (Vec::new(), Vec::new(), Vec::new());
+        let manifest = generate(&params(1), &mut a).unwrap();
+        assert_eq!(generate(&params(1), &mut b).unwrap(), manifest);
+        assert_ne!(
+            generate(&params(2), &mut c).unwrap().checksum,
+            manifest.checksum
+        );
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_expected_totals_match_processing() {
+        let mut csv = Vec::new();
+        let manifest = generate(&params(7), &mut csv).unwrap();
+
+        let mut processor = PaymentProcessor::new();
+        let mut reader = TransactionReader::from_reader(csv.as_slice());
+        let mut records = 0;
+        for transaction in reader.iter() {
+            processor.process(&transaction.unwrap());
+            records += 1;
+        }
+
+        assert_eq!(manifest.records, Some(records));
+        assert_eq!(manifest.expected, Some(ExpectedTotals::of(&processor)));
+        // Make sure the interesting paths actually came up
+        let expected = manifest.expected.unwrap();
+        assert!(expected.locked_accounts > 0);
+        assert_ne!(expected.held, "0.0000");
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use super::{ApiResponse, PaymentProcessor, SubmitApi};
+
+// Sent by the new process to ask for the state. Bumped whenever what
+// follows changes, so mismatched versions refuse rather than misread.
+const HELLO: &str = "payments-handoff v1";
+const ACK: &[u8] = b"ok";
+
+/// Everything a daemon hands over to the process replacing it: the state
+/// (as a snapshot) and the idempotency keys of submitted transactions, so
+/// retries that straddle the switch still aren't applied twice
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handoff {
+    pub snapshot: Vec<u8>,
+    pub submitted: Vec<(String, ApiResponse)>,
+}
+
+impl Handoff {
+    pub fn new(processor: &PaymentProcessor, submit: &SubmitApi) -> io::Result<Self> {
+        let mut snapshot = Vec::new();
+        processor.save_snapshot(&mut snapshot)?;
+        let submitted = submit
+            .remembered()
+            .map(|(key, response)| (key.to_string(), response.clone()))
+            .collect();
+        Ok(Self {
+            snapshot,
+            submitted,
+        })
+    }
+
+    /// Loads the state into `processor` and the keys into `submit`
+    pub fn restore(
+        self,
+        processor: &mut PaymentProcessor,
+        submit: &mut SubmitApi,
+    ) -> io::Result<()> {
+        processor.load_snapshot(self.snapshot.as_slice())?;
+        for (key, response) in self.submitted {
+            submit.remember(key, response);
+        }
+        Ok(())
+    }
+}
+
+/// The new process's side: asks for the state over `stream` and reads it.
+/// The old process keeps going until it gets the acknowledgement, so
+/// `acknowledge` once it's restored (or failed to).
+pub fn request_handoff(stream: &mut (impl Read + Write)) -> io::Result<Handoff> {
+    writeln!(stream, "{}", HELLO)?;
+    stream.flush()?;
+    let snapshot = read_frame(stream)?;
+    let submitted = serde_json::from_slice(&read_frame(stream)?)?;
+    Ok(Handoff {
+        snapshot,
+        submitted,
+    })
+}
+
+pub fn acknowledge(stream: &mut impl Write, result: Result<(), String>) -> io::Result<()> {
+    match result {
+        Ok(()) => write_frame(stream, ACK),
+        Err(err) => write_frame(stream, err.as_bytes()),
+    }
+}
+
+/// The old process's side, for a connection on its handoff socket: checks
+/// the other end speaks the same version, sends the state and waits for it
+/// to be taken over. An error means the old process still owns the state.
+pub fn send_handoff(
+    stream: &mut (impl Read + Write),
+    handoff: impl FnOnce() -> io::Result<Handoff>,
+) -> io::Result<()> {
+    // Never reads past the line, the rest of the stream is frames
+    let mut hello = String::new();
+    BufReader::new(Read::by_ref(stream).take(HELLO.len() as u64 + 1)).read_line(&mut hello)?;
+    if hello.trim_end() != HELLO {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected {:?}, got {:?}", HELLO, hello.trim_end()),
+        ));
+    }
+    let handoff = handoff()?;
+    write_frame(stream, &handoff.snapshot)?;
+    write_frame(stream, &serde_json::to_vec(&handoff.submitted)?)?;
+    stream.flush()?;
+    let ack = read_frame(stream)?;
+    if ack != ACK {
+        return Err(io::Error::other(format!(
+            "new process didn't take over: {}",
+            String::from_utf8_lossy(&ack)
+        )));
+    }
+    Ok(())
+}
+
+// Length prefixed, big endian like the snapshots
+fn write_frame(stream: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    stream.write_all(&(bytes.len() as u64).to_be_bytes())?;
+    stream.write_all(bytes)
+}
+
+fn read_frame(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0; 8];
+    stream.read_exact(&mut len)?;
+    let mut bytes = Vec::new();
+    stream
+        .by_ref()
+        .take(u64::from_be_bytes(len))
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != u64::from_be_bytes(len) {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    use super::*;
+    use crate::toy_payments::{Amount, Transaction};
+
+    #[test]
+    fn test_handoff() {
+        let mut old = PaymentProcessor::new();
+        old.process(&Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Amount::from(10),
+        });
+        let mut old_submit = SubmitApi::new(10);
+        let body = r#"{"type":"withdrawal","client":1,"tx":2,"amount":4.0}"#;
+        let response = old_submit.submit(&mut old, Some("retry-me"), body);
+
+        let (mut old_end, mut new_end) = UnixStream::pair().unwrap();
+        let new = thread::spawn(move || {
+            let handoff = request_handoff(&mut new_end).unwrap();
+            let mut processor = PaymentProcessor::new();
+            let mut submit = SubmitApi::new(10);
+            handoff.restore(&mut processor, &mut submit).unwrap();
+            acknowledge(&mut new_end, Ok(())).unwrap();
+            (processor, submit)
+        });
+        send_handoff(&mut old_end, || Handoff::new(&old, &old_submit)).unwrap();
+        let (mut new, mut new_submit) = new.join().unwrap();
+
+        assert_eq!(new.state_hash(), old.state_hash());
+        // A retry after the switch gets the original answer, not a second withdrawal
+        assert_eq!(
+            new_submit.submit(&mut new, Some("retry-me"), body),
+            response
+        );
+        assert_eq!(new.accounts()[&1].available(), Amount::from(6));
+    }
+
+    #[test]
+    fn test_handoff_refused() {
+        let old = PaymentProcessor::new();
+        let submit = SubmitApi::new(10);
+
+        let (mut old_end, mut new_end) = UnixStream::pair().unwrap();
+        let new = thread::spawn(move || {
+            request_handoff(&mut new_end).unwrap();
+            acknowledge(&mut new_end, Err("bad snapshot".to_string())).unwrap();
+        });
+        let err = send_handoff(&mut old_end, || Handoff::new(&old, &submit)).unwrap_err();
+        assert!(err.to_string().contains("bad snapshot"));
+        new.join().unwrap();
+
+        // Someone that isn't a newer daemon
+        let (mut old_end, mut other) = UnixStream::pair().unwrap();
+        writeln!(other, "payments-handoff v0").unwrap();
+        assert_eq!(
+            send_handoff(&mut old_end, || Handoff::new(&old, &submit))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,19 @@
+use std::collections;
+
+// The processor's maps are keyed by client and transaction IDs, which are
+// small integers. SipHash (std's default) is built to resist collision
+// attacks, which we pay for on every lookup without needing it for batch
+// files, so a faster hasher is used unless both features are turned off.
+// `ahash` wins over `fxhash` if both are enabled.
+
+#[cfg(feature = "ahash")]
+pub type BuildHasher = ahash::RandomState;
+
+#[cfg(all(feature = "fxhash", not(feature = "ahash")))]
+pub type BuildHasher = rustc_hash::FxBuildHasher;
+
+#[cfg(not(any(feature = "fxhash", feature = "ahash")))]
+pub type BuildHasher = collections::hash_map::RandomState;
+
+pub type HashMap<K, V> = collections::HashMap<K, V, BuildHasher>;
+pub type HashSet<K> = collections::HashSet<K, BuildHasher>;
@@ -0,0 +1,213 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{ExpectedTotals, GeneratorParams, PaymentProcessor};
+
+/// Expected digest of an input file, written as `sha256:<hex>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    Sha256([u8; 32]),
+}
+
+impl Checksum {
+    /// Reads a `.sha256` sidecar, i.e. `sha256sum` output (`<hex>  <file name>`)
+    /// or just the hex digest
+    pub fn from_sidecar(contents: &str) -> Result<Self, String> {
+        let hex = contents
+            .split_whitespace()
+            .next()
+            .ok_or("empty checksum file")?;
+        let mut digest = [0u8; 32];
+        decode_hex(hex, &mut digest)?;
+        Ok(Checksum::Sha256(digest))
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("sha256", hex)) => {
+                let mut digest = [0u8; 32];
+                decode_hex(hex, &mut digest)?;
+                Ok(Checksum::Sha256(digest))
+            }
+            Some((algorithm, _)) => Err(format!("unsupported checksum algorithm: {}", algorithm)),
+            None => Err(String::from(
+                "expected <algorithm>:<hex>, e.g. sha256:ab12..",
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Checksum::Sha256(digest) => {
+                write!(f, "sha256:")?;
+                digest.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+            }
+        }
+    }
+}
+
+/// Fills `out` from a hex string of exactly the right length
+pub(crate) fn decode_hex(hex: &str, out: &mut [u8]) -> Result<(), String> {
+    let hex = hex.trim().as_bytes();
+    if hex.len() != out.len() * 2 {
+        return Err(format!(
+            "expected {} hex characters, got {}",
+            out.len() * 2,
+            hex.len()
+        ));
+    }
+    for (byte, pair) in out.iter_mut().zip(hex.chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| "invalid hex")?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| "invalid hex")?;
+    }
+    Ok(())
+}
+
+/// Hashes everything read through it, so the input gets verified in the
+/// same pass that parses it. The digest is read through the DigestHandle,
+/// since the reader itself ends up buried inside the transaction readers.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Arc<Mutex<Sha256>>,
+}
+
+#[derive(Clone)]
+pub struct DigestHandle(Arc<Mutex<Sha256>>);
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> (Self, DigestHandle) {
+        let hasher = Arc::new(Mutex::new(Sha256::new()));
+        let handle = DigestHandle(hasher.clone());
+        (Self { inner, hasher }, handle)
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.lock().unwrap().update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl DigestHandle {
+    /// Checksum of everything read so far
+    pub fn checksum(&self) -> Checksum {
+        Checksum::Sha256(self.0.lock().unwrap().clone().finalize().into())
+    }
+}
+
+impl PaymentProcessor {
+    /// Hash of the final balances that doesn't depend on how they were
+    /// reached: accounts by client ID, amounts as their fixed-point raw
+    /// values. The same input has to give the same hash with any number of
+    /// threads.
+    pub fn state_hash(&self) -> Checksum {
+        let mut client_ids: Vec<_> = self.accounts.keys().copied().collect();
+        client_ids.sort_unstable();
+        let mut hasher = Sha256::new();
+        hasher.update(b"payments-state-v1");
+        for client_id in client_ids {
+            let account = &self.accounts[&client_id];
+            hasher.update(client_id.to_be_bytes());
+            hasher.update(account.available_funds.to_raw().to_be_bytes());
+            hasher.update(account.held_funds.to_raw().to_be_bytes());
+            hasher.update([u8::from(account.is_locked)]);
+        }
+        Checksum::Sha256(hasher.finalize().into())
+    }
+}
+
+/// Optional JSON file describing what an input file should contain
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Number of records, not counting the header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub records: Option<u64>,
+    /// Same `sha256:<hex>` format as --verify-checksum
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// For files from `generate`, what it takes to make the file again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generator: Option<GeneratorParams>,
+    /// Totals processing the file has to end up with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<ExpectedTotals>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, Transaction};
+
+    // sha256("abc")
+    const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn test_hashing_reader() {
+        let (mut reader, handle) = HashingReader::new("abc".as_bytes());
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).unwrap();
+
+        assert_eq!(handle.checksum().to_string(), format!("sha256:{}", ABC));
+        assert_eq!(
+            handle.checksum(),
+            format!("sha256:{}", ABC).parse::<Checksum>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_checksums() {
+        let expected = format!("sha256:{}", ABC).parse::<Checksum>().unwrap();
+        assert_eq!(
+            Checksum::from_sidecar(&format!("{}  txns.csv\n", ABC)),
+            Ok(expected)
+        );
+        assert_eq!(Checksum::from_sidecar(ABC), Ok(expected));
+        assert!(Checksum::from_sidecar("").is_err());
+        assert!(format!("md5:{}", ABC).parse::<Checksum>().is_err());
+        assert!("sha256:abcd".parse::<Checksum>().is_err());
+        assert!(ABC.parse::<Checksum>().is_err());
+    }
+
+    #[test]
+    fn test_state_hash() {
+        let deposit = |client_id, transaction_id| Transaction::Deposit {
+            client_id,
+            transaction_id,
+            amount: Amount::from(1.5),
+        };
+        let mut first = PaymentProcessor::new();
+        first.process(&deposit(1, 1));
+        first.process(&deposit(2, 2));
+        let mut second = PaymentProcessor::new();
+        second.process(&deposit(2, 2));
+        second.process(&deposit(1, 1));
+        assert_eq!(first.state_hash(), second.state_hash());
+
+        second.process(&Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 1,
+        });
+        assert_ne!(first.state_hash(), second.state_hash());
+        assert!(first.state_hash().to_string().starts_with("sha256:"));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let manifest: Manifest = serde_json::from_str(r#"{"records": 9}"#).unwrap();
+        assert_eq!(manifest.records, Some(9));
+        assert_eq!(manifest.checksum, None);
+    }
+}
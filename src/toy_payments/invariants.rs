@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::amount::Amount;
+use super::locale::{Locale, LocalizedDisplay};
+use super::{ClientId, DisputeState, PaymentProcessor, TransactionId};
+
+/// State that can't come out of processing transactions one by one, so
+/// something else went wrong (a bad snapshot, a bug, manual state edits)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// An open dispute for a client that doesn't have an account
+    OrphanDispute {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    },
+    /// Held funds don't add up to the client's open disputes
+    HeldMismatch {
+        client_id: ClientId,
+        held: Amount,
+        open_disputes: Amount,
+    },
+    /// Account is locked but none of its transactions were charged back
+    LockedWithoutChargeback { client_id: ClientId },
+}
+
+impl Violation {
+    /// What an operator could do about it
+    pub fn suggestion(&self) -> String {
+        self.suggestion_for(&Locale::default())
+    }
+
+    /// Same as suggestion, with the amounts written for `locale`
+    pub fn suggestion_for(&self, locale: &Locale) -> String {
+        match self {
+            Violation::OrphanDispute {
+                transaction_id,
+                amount,
+                ..
+            } => format!(
+                "force-resolve tx {} so the {} it holds isn't stuck in a dispute",
+                transaction_id,
+                locale.amount(*amount)
+            ),
+            Violation::HeldMismatch {
+                held,
+                open_disputes,
+                ..
+            } => {
+                let difference = *held - *open_disputes;
+                if difference > Amount::from(0) {
+                    format!(
+                        "release {} from held back to available",
+                        locale.amount(difference)
+                    )
+                } else {
+                    format!("move {} from available to held", locale.amount(-difference))
+                }
+            }
+            Violation::LockedWithoutChargeback { client_id } => {
+                format!("unlock client {} after review", client_id)
+            }
+        }
+    }
+}
+
+impl LocalizedDisplay for Violation {
+    fn fmt_localized(&self, f: &mut fmt::Formatter<'_>, locale: &Locale) -> fmt::Result {
+        match self {
+            Violation::OrphanDispute {
+                client_id,
+                transaction_id,
+                amount,
+            } => write!(
+                f,
+                "tx {}: open dispute over {} for client {}, which has no account",
+                transaction_id,
+                locale.amount(*amount),
+                client_id
+            ),
+            Violation::HeldMismatch {
+                client_id,
+                held,
+                open_disputes,
+            } => write!(
+                f,
+                "client {}: held {} doesn't match open disputes totalling {}",
+                client_id,
+                locale.amount(*held),
+                locale.amount(*open_disputes)
+            ),
+            Violation::LockedWithoutChargeback { client_id } => {
+                write!(f, "client {}: locked without any chargeback", client_id)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_localized(f, &Locale::default())
+    }
+}
+
+impl PaymentProcessor {
+    /// Cross-checks the accounts against the transaction store. Results
+    /// are ordered by client, then transaction, so reports are stable.
+    pub fn check_invariants(&self) -> Vec<Violation> {
+        let mut open_disputes: BTreeMap<ClientId, Amount> = BTreeMap::new();
+        let mut charged_back: BTreeMap<ClientId, bool> = BTreeMap::new();
+        let mut orphans = Vec::new();
+
+        for (transaction_id, stored) in &self.compressed_transactions {
+            match stored.state {
+                DisputeState::Disputed => {
+                    if self.accounts.contains_key(&stored.client_id) {
+                        *open_disputes
+                            .entry(stored.client_id)
+                            .or_insert(Amount::from(0)) += stored.amount;
+                    } else {
+                        orphans.push((stored.client_id, *transaction_id, stored.amount));
+                    }
+                }
+                DisputeState::ChargedBack => {
+                    charged_back.insert(stored.client_id, true);
+                }
+                DisputeState::Undisputed | DisputeState::Resolved => {}
+            }
+        }
+
+        let mut client_ids: Vec<&ClientId> = self.accounts.keys().collect();
+        client_ids.sort();
+
+        let mut violations = Vec::new();
+        for client_id in client_ids {
+            let account = &self.accounts[client_id];
+            let open_total = open_disputes
+                .get(client_id)
+                .copied()
+                .unwrap_or(Amount::from(0));
+            if account.held_funds != open_total {
+                violations.push(Violation::HeldMismatch {
+                    client_id: *client_id,
+                    held: account.held_funds,
+                    open_disputes: open_total,
+                });
+            }
+            // Freezing over the dispute cap locks without a chargeback too
+            if account.is_locked && !charged_back.contains_key(client_id) && !account.frozen {
+                violations.push(Violation::LockedWithoutChargeback {
+                    client_id: *client_id,
+                });
+            }
+        }
+
+        orphans.sort();
+        violations.extend(
+            orphans
+                .into_iter()
+                .map(
+                    |(client_id, transaction_id, amount)| Violation::OrphanDispute {
+                        client_id,
+                        transaction_id,
+                        amount,
+                    },
+                ),
+        );
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Account, StoredTransaction, Transaction};
+
+    fn processor() -> PaymentProcessor {
+        let mut processor = PaymentProcessor::new();
+        for (client_id, transaction_id) in [(1, 1), (1, 2), (2, 3)] {
+            processor.process(&Transaction::Deposit {
+                client_id,
+                transaction_id,
+                amount: Amount::from(10),
+            });
+        }
+        processor.process(&Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 1,
+        });
+        processor.process(&Transaction::Dispute {
+            client_id: 2,
+            transaction_id: 3,
+        });
+        processor.process(&Transaction::Chargeback {
+            client_id: 2,
+            transaction_id: 3,
+        });
+        processor
+    }
+
+    #[test]
+    fn test_clean_state() {
+        assert_eq!(processor().check_invariants(), vec![]);
+    }
+
+    #[test]
+    fn test_violations() {
+        let mut processor = processor();
+        processor.accounts.get_mut(&1).unwrap().held_funds = Amount::from(15);
+        processor.accounts.insert(
+            3,
+            Account {
+                is_locked: true,
+                ..Account::new()
+            },
+        );
+        processor.compressed_transactions.insert(
+            9,
+            StoredTransaction {
+                client_id: 7,
+                amount: Amount::from(4),
+                state: DisputeState::Disputed,
+            },
+        );
+
+        let violations = processor.check_invariants();
+        assert_eq!(
+            violations,
+            vec![
+                Violation::HeldMismatch {
+                    client_id: 1,
+                    held: Amount::from(15),
+                    open_disputes: Amount::from(10),
+                },
+                Violation::LockedWithoutChargeback { client_id: 3 },
+                Violation::OrphanDispute {
+                    client_id: 7,
+                    transaction_id: 9,
+                    amount: Amount::from(4),
+                },
+            ]
+        );
+        assert_eq!(
+            violations[0].suggestion(),
+            "release 5.0000 from held back to available"
+        );
+    }
+}
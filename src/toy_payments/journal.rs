@@ -0,0 +1,339 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use super::amount::Amount;
+use super::events::EventListener;
+use super::tiers::Tiers;
+use super::{ClientId, Transaction, TransactionId};
+
+/// GL account codes the journal lines get booked against (`[journal]` in
+/// the config)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct LedgerCodes {
+    pub cash: String,
+    /// What we owe clients, i.e. their balances
+    pub customer_liability: String,
+    pub chargeback_expense: String,
+    /// Withdrawal fees from the client tiers
+    pub fee_income: String,
+    /// The other side of manual adjustments
+    pub adjustments: String,
+}
+
+impl Default for LedgerCodes {
+    fn default() -> Self {
+        Self {
+            cash: String::from("1000"),
+            customer_liability: String::from("2000"),
+            chargeback_expense: String::from("5000"),
+            fee_income: String::from("4000"),
+            adjustments: String::from("2900"),
+        }
+    }
+}
+
+/// Turns applied transactions into double-entry journal lines, as CSV with
+/// `entry,tx,client,account,debit,credit,memo`. Every entry is a debit and
+/// a credit of the same amount, so each one (and the file) balances.
+///
+/// - deposit: cash to customer liability
+/// - withdrawal: customer liability to cash, plus the tier fee (if any)
+///   from customer liability to fee income
+/// - adjustment: between the adjustments account and customer liability
+/// - transfer: customer liability of the sender to that of the recipient
+/// - chargeback: the money going back out is booked as chargeback expense
+///   against cash, then recovered from the client's held funds
+///
+/// Disputes opening and resolving only move funds between available and
+/// held, which are both customer liability, so they don't get entries.
+pub struct JournalWriter<W: Write + Send> {
+    writer: csv::Writer<W>,
+    codes: LedgerCodes,
+    tiers: Arc<Tiers>,
+    entries: u64,
+}
+
+impl<W: Write + Send> JournalWriter<W> {
+    /// `tiers` should be the processor's, for the withdrawal fees
+    pub fn new(writer: W, codes: LedgerCodes, tiers: Arc<Tiers>) -> io::Result<Self> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record([
+            "entry", "tx", "client", "account", "debit", "credit", "memo",
+        ])?;
+        Ok(Self {
+            writer,
+            codes,
+            tiers,
+            entries: 0,
+        })
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    // One balanced entry: `amount` from the debited account to the credited one
+    fn entry(
+        &mut self,
+        transaction_id: TransactionId,
+        debit: (Ledger, ClientId),
+        credit: (Ledger, ClientId),
+        amount: Amount,
+        memo: &str,
+    ) {
+        self.entries += 1;
+        let (entry, tx, amount) = (
+            self.entries.to_string(),
+            transaction_id.to_string(),
+            amount.to_string(),
+        );
+        for ((ledger, client_id), debit, credit) in
+            [(debit, amount.as_str(), ""), (credit, "", amount.as_str())]
+        {
+            let result = self.writer.write_record([
+                entry.as_str(),
+                tx.as_str(),
+                &client_id.to_string(),
+                self.codes.code(ledger),
+                debit,
+                credit,
+                memo,
+            ]);
+            if let Err(err) = result {
+                eprintln!("Error writing journal: {}", err);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Ledger {
+    Cash,
+    CustomerLiability,
+    ChargebackExpense,
+    FeeIncome,
+    Adjustments,
+}
+
+impl LedgerCodes {
+    fn code(&self, ledger: Ledger) -> &str {
+        match ledger {
+            Ledger::Cash => &self.cash,
+            Ledger::CustomerLiability => &self.customer_liability,
+            Ledger::ChargebackExpense => &self.chargeback_expense,
+            Ledger::FeeIncome => &self.fee_income,
+            Ledger::Adjustments => &self.adjustments,
+        }
+    }
+}
+
+impl<W: Write + Send> EventListener for JournalWriter<W> {
+    fn on_applied(&mut self, transaction: &Transaction) {
+        use Ledger::*;
+
+        match transaction {
+            Transaction::Deposit {
+                client_id,
+                transaction_id,
+                amount,
+            } => self.entry(
+                *transaction_id,
+                (Cash, *client_id),
+                (CustomerLiability, *client_id),
+                *amount,
+                "deposit",
+            ),
+            Transaction::Withdrawal {
+                client_id,
+                transaction_id,
+                amount,
+            } => {
+                self.entry(
+                    *transaction_id,
+                    (CustomerLiability, *client_id),
+                    (Cash, *client_id),
+                    *amount,
+                    "withdrawal",
+                );
+                let fee = self.tiers.rules(*client_id).withdrawal_fee;
+                if fee != Amount::default() {
+                    self.entry(
+                        *transaction_id,
+                        (CustomerLiability, *client_id),
+                        (FeeIncome, *client_id),
+                        fee,
+                        "withdrawal fee",
+                    );
+                }
+            }
+            Transaction::Adjustment {
+                client_id,
+                transaction_id,
+                amount,
+                reference,
+            } => {
+                let memo = format!("adjustment {}", reference);
+                if *amount < Amount::default() {
+                    self.entry(
+                        *transaction_id,
+                        (CustomerLiability, *client_id),
+                        (Adjustments, *client_id),
+                        -*amount,
+                        &memo,
+                    );
+                } else {
+                    self.entry(
+                        *transaction_id,
+                        (Adjustments, *client_id),
+                        (CustomerLiability, *client_id),
+                        *amount,
+                        &memo,
+                    );
+                }
+            }
+            Transaction::Transfer {
+                client_id,
+                transaction_id,
+                to_client_id,
+                amount,
+            } => self.entry(
+                *transaction_id,
+                (CustomerLiability, *client_id),
+                (CustomerLiability, *to_client_id),
+                *amount,
+                "transfer",
+            ),
+            // Chargebacks come through on_charged_back, the rest don't move money
+            Transaction::Dispute { .. }
+            | Transaction::Resolve { .. }
+            | Transaction::Chargeback { .. }
+            | Transaction::AutoChargeback { .. } => {}
+        }
+    }
+
+    fn on_charged_back(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    ) {
+        use Ledger::*;
+
+        self.entry(
+            transaction_id,
+            (ChargebackExpense, client_id),
+            (Cash, client_id),
+            amount,
+            "chargeback",
+        );
+        self.entry(
+            transaction_id,
+            (CustomerLiability, client_id),
+            (ChargebackExpense, client_id),
+            amount,
+            "chargeback recovered from held funds",
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{PaymentProcessor, ProcessorConfig, TierRules};
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_journal() {
+        let mut tiers = Tiers::new(BTreeMap::from([(
+            String::from("basic"),
+            TierRules {
+                withdrawal_fee: Amount::from(1),
+                ..TierRules::default()
+            },
+        )]));
+        tiers.assign(1, "basic").unwrap();
+        let tiers = Arc::new(tiers);
+        let codes = LedgerCodes {
+            cash: String::from("1010"),
+            ..LedgerCodes::default()
+        };
+        let journal = Arc::new(Mutex::new(
+            JournalWriter::new(Vec::new(), codes, tiers.clone()).unwrap(),
+        ));
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            tiers,
+            ..ProcessorConfig::default()
+        });
+        processor.add_listener(journal.clone());
+
+        for transaction in [
+            Transaction::Deposit {
+                client_id: 1,
+                transaction_id: 1,
+                amount: Amount::from(10),
+            },
+            Transaction::Withdrawal {
+                client_id: 1,
+                transaction_id: 2,
+                amount: Amount::from(2),
+            },
+            // Rejected, nothing booked
+            Transaction::Withdrawal {
+                client_id: 1,
+                transaction_id: 3,
+                amount: Amount::from(100),
+            },
+            Transaction::Transfer {
+                client_id: 1,
+                transaction_id: 4,
+                to_client_id: 2,
+                amount: Amount::from(3),
+            },
+            Transaction::Deposit {
+                client_id: 2,
+                transaction_id: 5,
+                amount: Amount::from(5),
+            },
+            Transaction::AutoChargeback {
+                client_id: 2,
+                transaction_id: 5,
+            },
+            Transaction::Adjustment {
+                client_id: 2,
+                transaction_id: 6,
+                amount: -Amount::from(1),
+                reference: String::from("INC-1, refund"),
+            },
+        ] {
+            processor.process(&transaction);
+        }
+
+        drop(processor);
+        let journal = Arc::into_inner(journal).unwrap().into_inner().unwrap();
+        let csv = String::from_utf8(journal.writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "entry,tx,client,account,debit,credit,memo\n\
+             1,1,1,1010,10.0000,,deposit\n\
+             1,1,1,2000,,10.0000,deposit\n\
+             2,2,1,2000,2.0000,,withdrawal\n\
+             2,2,1,1010,,2.0000,withdrawal\n\
+             3,2,1,2000,1.0000,,withdrawal fee\n\
+             3,2,1,4000,,1.0000,withdrawal fee\n\
+             4,4,1,2000,3.0000,,transfer\n\
+             4,4,2,2000,,3.0000,transfer\n\
+             5,5,2,1010,5.0000,,deposit\n\
+             5,5,2,2000,,5.0000,deposit\n\
+             6,5,2,5000,5.0000,,chargeback\n\
+             6,5,2,1010,,5.0000,chargeback\n\
+             7,5,2,2000,5.0000,,chargeback recovered from held funds\n\
+             7,5,2,5000,,5.0000,chargeback recovered from held funds\n\
+             8,6,2,2000,1.0000,,\"adjustment INC-1, refund\"\n\
+             8,6,2,2900,,1.0000,\"adjustment INC-1, refund\"\n"
+        );
+    }
+}
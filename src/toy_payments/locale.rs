@@ -0,0 +1,186 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use super::amount::Amount;
+
+/// How numbers are written in the human-readable summaries (stats, audit,
+/// alerts and such). Machine formats (CSV, JSON, SQL) never use this, they
+/// stay canonical. The default is how they've always been written: no
+/// thousands separators and a decimal point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Locale {
+    group: Option<char>,
+    decimal: char,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            group: None,
+            decimal: '.',
+        }
+    }
+}
+
+const fn locale(group: char, decimal: char) -> Locale {
+    Locale {
+        group: Some(group),
+        decimal,
+    }
+}
+
+// Region specific ones first, then by language. Not exhaustive, just the
+// places people reading these are in.
+const REGIONS: &[(&str, Locale)] = &[
+    ("de-ch", locale('\'', '.')),
+    ("fr-ch", locale('\'', '.')),
+    ("it-ch", locale('\'', '.')),
+    ("pt-pt", locale('\u{a0}', ',')),
+];
+const LANGUAGES: &[(&str, Locale)] = &[
+    ("en", locale(',', '.')),
+    ("ja", locale(',', '.')),
+    ("zh", locale(',', '.')),
+    ("de", locale('.', ',')),
+    ("nl", locale('.', ',')),
+    ("it", locale('.', ',')),
+    ("es", locale('.', ',')),
+    ("pt", locale('.', ',')),
+    ("da", locale('.', ',')),
+    ("fr", locale('\u{a0}', ',')),
+    ("sv", locale('\u{a0}', ',')),
+    ("nb", locale('\u{a0}', ',')),
+    ("fi", locale('\u{a0}', ',')),
+    ("pl", locale('\u{a0}', ',')),
+    ("cs", locale('\u{a0}', ',')),
+];
+
+/// Takes tags like `de`, `de-DE`, or straight from `$LANG` (`de_DE.UTF-8`).
+/// `C`/`POSIX` give the default.
+impl FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tag = s
+            .split(['.', '@'])
+            .next()
+            .unwrap_or_default()
+            .replace('_', "-")
+            .to_ascii_lowercase();
+        if tag == "c" || tag == "posix" {
+            return Ok(Locale::default());
+        }
+        let language = tag.split('-').next().unwrap_or_default();
+        REGIONS
+            .iter()
+            .find(|(name, _)| *name == tag)
+            .or_else(|| LANGUAGES.iter().find(|(name, _)| *name == language))
+            .map(|(_, locale)| *locale)
+            .ok_or_else(|| format!("unsupported locale {}", s))
+    }
+}
+
+impl TryFrom<String> for Locale {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Locale {
+    pub fn amount(&self, amount: Amount) -> String {
+        // Amount's Display is exact, only the separators change
+        let plain = amount.to_string();
+        let (sign, plain) = match plain.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", plain.as_str()),
+        };
+        let (whole, fraction) = plain.split_once('.').unwrap_or((plain, ""));
+        format!(
+            "{}{}{}{}",
+            sign,
+            self.group_digits(whole),
+            self.decimal,
+            fraction
+        )
+    }
+
+    pub fn count(&self, count: impl Into<u64>) -> String {
+        self.group_digits(&count.into().to_string())
+    }
+
+    /// For plain decimals like a sample rate, which aren't big enough to
+    /// need grouping
+    pub fn fraction(&self, value: f64) -> String {
+        value.to_string().replace('.', &self.decimal.to_string())
+    }
+
+    fn group_digits(&self, digits: &str) -> String {
+        let Some(group) = self.group else {
+            return digits.to_string();
+        };
+        let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+        for (index, digit) in digits.chars().enumerate() {
+            if index > 0 && (digits.len() - index).is_multiple_of(3) {
+                grouped.push(group);
+            }
+            grouped.push(digit);
+        }
+        grouped
+    }
+}
+
+/// Summaries that can be written out for a locale. Their Display is the
+/// same thing with the default locale.
+pub trait LocalizedDisplay {
+    fn fmt_localized(&self, f: &mut fmt::Formatter<'_>, locale: &Locale) -> fmt::Result;
+
+    fn localized<'a>(&'a self, locale: &'a Locale) -> Localized<'a, Self>
+    where
+        Self: Sized,
+    {
+        Localized {
+            value: self,
+            locale,
+        }
+    }
+}
+
+pub struct Localized<'a, T> {
+    value: &'a T,
+    locale: &'a Locale,
+}
+
+impl<T: LocalizedDisplay> fmt::Display for Localized<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt_localized(f, self.locale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locales() {
+        let amount = Amount::from_raw(-12_345_678_912);
+        let format = |tag: &str| tag.parse::<Locale>().unwrap().amount(amount);
+        assert_eq!(Locale::default().amount(amount), "-1234567.8912");
+        assert_eq!(format("C"), "-1234567.8912");
+        assert_eq!(format("en-US"), "-1,234,567.8912");
+        assert_eq!(format("de_DE.UTF-8"), "-1.234.567,8912");
+        assert_eq!(format("fr"), "-1\u{a0}234\u{a0}567,8912");
+        assert_eq!(format("de-CH"), "-1'234'567.8912");
+        assert!("xx".parse::<Locale>().is_err());
+
+        let german: Locale = "de".parse().unwrap();
+        assert_eq!(german.amount(Amount::from(999)), "999,0000");
+        assert_eq!(german.count(1000u64), "1.000");
+        assert_eq!(german.count(100u32), "100");
+        assert_eq!(german.fraction(0.25), "0,25");
+    }
+}
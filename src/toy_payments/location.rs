@@ -0,0 +1,202 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Stdout, Write};
+
+/// Where the input/output of a run lives. Plain paths are local files, and
+/// with the `object-store` feature anything that looks like a URL
+/// (`s3://bucket/key`, `file:///tmp/x.csv`, ..) goes through object_store.
+fn is_url(location: &str) -> bool {
+    location.contains("://")
+}
+
+pub fn open_input(location: &str) -> Result<Box<dyn Read + Send>, Box<dyn Error>> {
+    if is_url(location) {
+        #[cfg(feature = "object-store")]
+        return Ok(Box::new(object_io::open(location)?));
+        #[cfg(not(feature = "object-store"))]
+        return Err(no_object_store(location));
+    }
+    Ok(Box::new(File::open(location)?))
+}
+
+/// For optional companion files (e.g. checksum sidecars)
+pub fn input_exists(location: &str) -> Result<bool, Box<dyn Error>> {
+    if is_url(location) {
+        #[cfg(feature = "object-store")]
+        return object_io::exists(location);
+        #[cfg(not(feature = "object-store"))]
+        return Err(no_object_store(location));
+    }
+    Ok(std::path::Path::new(location).exists())
+}
+
+/// `None` is stdout
+pub fn create_output(location: Option<&str>) -> Result<Output, Box<dyn Error>> {
+    match location {
+        None => Ok(Output::Stdout(io::stdout())),
+        Some(location) if is_url(location) => {
+            #[cfg(feature = "object-store")]
+            return Ok(Output::Object(Box::new(object_io::create(location)?)));
+            #[cfg(not(feature = "object-store"))]
+            return Err(no_object_store(location));
+        }
+        Some(location) => Ok(Output::File(BufWriter::new(File::create(location)?))),
+    }
+}
+
+#[cfg(not(feature = "object-store"))]
+fn no_object_store(location: &str) -> Box<dyn Error> {
+    format!(
+        "{}: URLs need a build with the object-store feature",
+        location
+    )
+    .into()
+}
+
+pub enum Output {
+    Stdout(Stdout),
+    File(BufWriter<File>),
+    #[cfg(feature = "object-store")]
+    Object(Box<object_io::ObjectWriter>),
+}
+
+impl Output {
+    /// Has to be called once everything is written. Uploads only complete
+    /// here, dropping an unfinished Output throws the upload away.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Stdout(mut stdout) => stdout.flush(),
+            Output::File(mut file) => file.flush(),
+            #[cfg(feature = "object-store")]
+            Output::Object(writer) => writer.finish(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(buf),
+            Output::File(file) => file.write(buf),
+            #[cfg(feature = "object-store")]
+            Output::Object(writer) => writer.0.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File(file) => file.flush(),
+            // Parts get uploaded as the buffer fills up, and the rest on finish
+            #[cfg(feature = "object-store")]
+            Output::Object(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "object-store")]
+mod object_io {
+    use std::error::Error;
+    use std::io;
+    use std::sync::{Arc, OnceLock};
+
+    use object_store::ObjectStore;
+    use object_store::buffered::{BufReader, BufWriter};
+    use object_store::path::Path;
+    use tokio::runtime::Runtime;
+    use tokio_util::io::SyncIoBridge;
+
+    // Reads are done in ranged GETs of this size, each one retried on its own
+    const READ_CAPACITY: usize = 8 << 20;
+
+    fn runtime() -> &'static Runtime {
+        static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+        RUNTIME.get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(2)
+                .enable_all()
+                .build()
+                .expect("failed to start the object store runtime")
+        })
+    }
+
+    /// Credentials/region/endpoint come from the usual AWS_* variables.
+    /// Retries use object_store's defaults (backoff, up to 10 tries per request).
+    fn parse(location: &str) -> Result<(Arc<dyn ObjectStore>, Path), Box<dyn Error>> {
+        let url = location.parse()?;
+        let options = std::env::vars()
+            .filter(|(key, _)| key.starts_with("AWS_"))
+            .map(|(key, value)| (key.to_ascii_lowercase(), value));
+        let (store, path) = object_store::parse_url_opts(&url, options)?;
+        Ok((store.into(), path))
+    }
+
+    pub fn open(location: &str) -> Result<SyncIoBridge<BufReader>, Box<dyn Error>> {
+        let (store, path) = parse(location)?;
+        let meta = runtime().block_on(store.head(&path))?;
+        let reader = BufReader::with_capacity(store, &meta, READ_CAPACITY);
+        Ok(SyncIoBridge::new_with_handle(
+            reader,
+            runtime().handle().clone(),
+        ))
+    }
+
+    // Buffered on our side too, since every write into the bridge means a
+    // trip through the runtime
+    pub fn exists(location: &str) -> Result<bool, Box<dyn Error>> {
+        let (store, path) = parse(location)?;
+        match runtime().block_on(store.head(&path)) {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub struct ObjectWriter(pub(super) io::BufWriter<SyncIoBridge<BufWriter>>);
+
+    pub fn create(location: &str) -> Result<ObjectWriter, Box<dyn Error>> {
+        let (store, path) = parse(location)?;
+        Ok(ObjectWriter(io::BufWriter::new(
+            SyncIoBridge::new_with_handle(BufWriter::new(store, path), runtime().handle().clone()),
+        )))
+    }
+
+    impl ObjectWriter {
+        pub fn finish(self) -> io::Result<()> {
+            self.0
+                .into_inner()
+                .map_err(|err| err.into_error())?
+                .shutdown()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_roundtrip() {
+        let path = std::env::temp_dir().join(format!("location-test-{}.csv", std::process::id()));
+        let location = path.to_str().unwrap();
+
+        let mut output = create_output(Some(location)).unwrap();
+        output.write_all(b"client,available\n1,2.0\n").unwrap();
+        output.finish().unwrap();
+
+        let mut contents = String::new();
+        open_input(location)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "client,available\n1,2.0\n");
+    }
+
+    #[cfg(not(feature = "object-store"))]
+    #[test]
+    fn test_urls_need_feature() {
+        assert!(open_input("s3://bucket/txns.csv").is_err());
+        assert!(create_output(Some("s3://bucket/balances.csv")).is_err());
+    }
+}
@@ -1,6 +1,103 @@
+mod alerts;
 mod amount;
+mod api;
+mod audit;
+mod backfill;
+mod batch;
+mod bloom;
+mod bounds;
+mod categories;
+mod chaos;
+mod chargeback;
+mod chunked_reader;
+#[cfg(feature = "client")]
+mod client;
+mod clock;
+mod columnar;
+mod compare;
+mod dedup;
+mod disputes;
+mod encryption;
+mod enrichment;
+mod events;
+mod fast_reader;
+mod generator;
+mod handoff;
+mod hashing;
+mod integrity;
+mod invariants;
+mod journal;
+mod locale;
+mod location;
+mod operator;
+#[cfg(feature = "postgres")]
+mod postgres_sink;
+mod preview;
+mod privacy;
 mod processor;
+mod query;
 mod reader;
+mod results;
+mod review;
+mod sampling;
+mod schedule;
+mod sharded;
+mod sink;
+mod snapshot;
+mod sql;
+mod stats;
+mod sync;
+mod tiers;
+mod timings;
+mod window;
 
+pub use alerts::*;
+pub use amount::{Amount, RoundingMode, Unrounded};
+pub use api::*;
+pub use audit::*;
+pub use backfill::*;
+pub use batch::*;
+pub use bloom::*;
+pub use bounds::*;
+pub use categories::*;
+pub use chaos::*;
+pub use chargeback::*;
+pub use chunked_reader::*;
+#[cfg(feature = "client")]
+pub use client::*;
+pub use clock::*;
+pub use columnar::*;
+pub use compare::*;
+pub use dedup::*;
+pub use disputes::*;
+pub use encryption::*;
+pub use enrichment::*;
+pub use events::*;
+pub use fast_reader::*;
+pub use generator::*;
+pub use handoff::*;
+pub use hashing::*;
+pub use integrity::*;
+pub use invariants::*;
+pub use journal::*;
+pub use locale::*;
+pub use location::*;
+pub use operator::*;
+#[cfg(feature = "postgres")]
+pub use postgres_sink::*;
+pub use preview::*;
+pub use privacy::*;
 pub use processor::*;
+pub use query::*;
 pub use reader::*;
+pub use results::*;
+pub use review::*;
+pub use sampling::*;
+pub use schedule::*;
+pub use sharded::*;
+pub use sink::*;
+pub use sql::*;
+pub use stats::*;
+pub use tiers::*;
+pub use timings::*;
+pub use window::*;
@@ -0,0 +1,511 @@
+use std::fmt;
+
+use super::events::RejectionReason;
+use super::{ClientId, DisputeState, PaymentProcessor, TransactionId};
+
+/// Changes an operator makes to the state outside of the regular
+/// transaction flow. Each one carries the operator's reference (e.g. the
+/// incident ticket), same as adjustments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperatorAction {
+    /// Lifts the lock on an account
+    Unlock {
+        client_id: ClientId,
+        reference: String,
+    },
+    /// Closes an open dispute like a resolve would, but also when the
+    /// client has no account anymore (then nothing is holding the funds,
+    /// so only the dispute gets closed)
+    ForceResolve {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        reference: String,
+    },
+    /// Undoes a chargeback the network has since reversed: the funds go
+    /// back to the client's available balance and the transaction counts as
+    /// resolved. Doesn't unlock the account, that's an Unlock of its own.
+    ReverseChargeback {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        reference: String,
+    },
+    /// Changes nothing, but records that someone looked at a locked account
+    /// and decided it stays locked
+    KeepLocked {
+        client_id: ClientId,
+        reference: String,
+    },
+    /// Folds `client_id` into `into` when duplicate customer records get
+    /// consolidated: balances are added up, the transaction history (open
+    /// disputes included) moves over, and the old ID is tombstoned so
+    /// anything still arriving for it gets rejected. The merged account is
+    /// locked if either of them was.
+    MergeClient {
+        client_id: ClientId,
+        into: ClientId,
+        reference: String,
+    },
+    /// Erasure request for a client: everything of theirs moves over to
+    /// `pseudonym`, a client ID nothing else uses, so the ledger still adds
+    /// up but nothing in the state leads back to them anymore. IDs that were
+    /// merged into them lose their tombstones too, same customer. Nothing
+    /// is kept about the old ID, so it'd just be a new client if it comes up
+    /// again.
+    EraseClient {
+        client_id: ClientId,
+        pseudonym: ClientId,
+        reference: String,
+    },
+}
+
+impl fmt::Display for OperatorAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OperatorAction::Unlock {
+                client_id,
+                reference,
+            } => write!(
+                f,
+                "type: unlock, client: {}, reference: {}",
+                client_id, reference
+            ),
+            OperatorAction::ForceResolve {
+                client_id,
+                transaction_id,
+                reference,
+            } => write!(
+                f,
+                "type: force_resolve, client: {}, tx: {}, reference: {}",
+                client_id, transaction_id, reference
+            ),
+            OperatorAction::ReverseChargeback {
+                client_id,
+                transaction_id,
+                reference,
+            } => write!(
+                f,
+                "type: reverse_chargeback, client: {}, tx: {}, reference: {}",
+                client_id, transaction_id, reference
+            ),
+            OperatorAction::KeepLocked {
+                client_id,
+                reference,
+            } => write!(
+                f,
+                "type: keep_locked, client: {}, reference: {}",
+                client_id, reference
+            ),
+            OperatorAction::MergeClient {
+                client_id,
+                into,
+                reference,
+            } => write!(
+                f,
+                "type: merge, client: {}, into: {}, reference: {}",
+                client_id, into, reference
+            ),
+            // Not the pseudonym, this ends up in audit logs
+            OperatorAction::EraseClient {
+                client_id,
+                reference,
+                ..
+            } => write!(
+                f,
+                "type: erase, client: {}, reference: {}",
+                client_id, reference
+            ),
+        }
+    }
+}
+
+impl PaymentProcessor {
+    pub fn apply_action(&mut self, action: &OperatorAction) -> Result<(), RejectionReason> {
+        let result = match action {
+            OperatorAction::Unlock { client_id, .. } => self.unlock(*client_id),
+            OperatorAction::ForceResolve {
+                client_id,
+                transaction_id,
+                ..
+            } => self.force_resolve(*client_id, *transaction_id),
+            OperatorAction::ReverseChargeback {
+                client_id,
+                transaction_id,
+                ..
+            } => self.reverse_chargeback(*client_id, *transaction_id),
+            OperatorAction::KeepLocked { client_id, .. } => self.keep_locked(*client_id),
+            OperatorAction::MergeClient {
+                client_id, into, ..
+            } => self.merge_client(*client_id, *into),
+            OperatorAction::EraseClient {
+                client_id,
+                pseudonym,
+                ..
+            } => self.erase_client(*client_id, *pseudonym),
+        };
+        self.notify(|listener| listener.on_operator_action(action, result));
+        result
+    }
+
+    fn unlock(&mut self, client_id: ClientId) -> Result<(), RejectionReason> {
+        let account = self
+            .accounts
+            .get_mut(&client_id)
+            .ok_or(RejectionReason::UnknownClient)?;
+        if !account.is_locked {
+            return Err(RejectionReason::NotLocked);
+        }
+        account.is_locked = false;
+        account.frozen = false;
+        Ok(())
+    }
+
+    fn force_resolve(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<(), RejectionReason> {
+        let stored = self.find_transaction(client_id, transaction_id)?;
+        if stored.state != DisputeState::Disputed {
+            return Err(RejectionReason::NotDisputed);
+        }
+        stored.state = DisputeState::Resolved;
+        let amount = stored.amount;
+
+        if let Some(account) = self.accounts.get_mut(&client_id) {
+            account.available_funds += amount;
+            account.held_funds -= amount;
+            account.open_disputes = account.open_disputes.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    fn reverse_chargeback(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<(), RejectionReason> {
+        if !self.accounts.contains_key(&client_id) {
+            return Err(RejectionReason::UnknownClient);
+        }
+        let stored = self.find_transaction(client_id, transaction_id)?;
+        if stored.state != DisputeState::ChargedBack {
+            return Err(RejectionReason::NotChargedBack);
+        }
+        stored.state = DisputeState::Resolved;
+        let amount = stored.amount;
+        if let Some(account) = self.accounts.get_mut(&client_id) {
+            account.available_funds += amount;
+        }
+        Ok(())
+    }
+
+    fn keep_locked(&self, client_id: ClientId) -> Result<(), RejectionReason> {
+        match self.accounts.get(&client_id) {
+            Some(account) if account.is_locked => Ok(()),
+            Some(_) => Err(RejectionReason::NotLocked),
+            None => Err(RejectionReason::UnknownClient),
+        }
+    }
+
+    fn merge_client(&mut self, client_id: ClientId, into: ClientId) -> Result<(), RejectionReason> {
+        if client_id == into {
+            return Err(RejectionReason::MergeIntoSelf);
+        }
+        if self.merged_clients.contains_key(&client_id) || self.merged_clients.contains_key(&into) {
+            return Err(RejectionReason::ClientMerged);
+        }
+        let merged = self
+            .accounts
+            .remove(&client_id)
+            .ok_or(RejectionReason::UnknownClient)?;
+
+        let account = self.accounts.entry(into).or_default();
+        account.available_funds += merged.available_funds;
+        account.held_funds += merged.held_funds;
+        account.is_locked |= merged.is_locked;
+        account.frozen |= merged.frozen;
+        account.open_disputes += merged.open_disputes;
+        account.total_disputes += merged.total_disputes;
+        account.chargebacks += merged.chargebacks;
+
+        let withdrawn: Vec<_> = self
+            .category_withdrawn
+            .extract_if(|(owner, _), _| *owner == client_id)
+            .collect();
+        for ((_, index), amount) in withdrawn {
+            *self.category_withdrawn.entry((into, index)).or_default() += amount;
+        }
+
+        self.merge_dispute_window(client_id, into);
+        self.merge_seen(client_id, into);
+
+        for stored in self.compressed_transactions.values_mut() {
+            if stored.client_id == client_id {
+                stored.client_id = into;
+            }
+        }
+        // Older tombstones follow along, so they always point at a live client
+        for target in self.merged_clients.values_mut() {
+            if *target == client_id {
+                *target = into;
+            }
+        }
+        self.merged_clients.insert(client_id, into);
+        Ok(())
+    }
+
+    fn erase_client(
+        &mut self,
+        client_id: ClientId,
+        pseudonym: ClientId,
+    ) -> Result<(), RejectionReason> {
+        if self.merged_clients.contains_key(&client_id) {
+            // Their data is under the client they were merged into
+            return Err(RejectionReason::ClientMerged);
+        }
+        let in_use = self.clients_in_use();
+        if !in_use.contains(&client_id) {
+            return Err(RejectionReason::UnknownClient);
+        }
+        if in_use.contains(&pseudonym) {
+            return Err(RejectionReason::PseudonymInUse);
+        }
+
+        if let Some(account) = self.accounts.remove(&client_id) {
+            self.accounts.insert(pseudonym, account);
+        }
+        let withdrawn: Vec<_> = self
+            .category_withdrawn
+            .extract_if(|(owner, _), _| *owner == client_id)
+            .collect();
+        for ((_, index), amount) in withdrawn {
+            self.category_withdrawn.insert((pseudonym, index), amount);
+        }
+        self.merge_dispute_window(client_id, pseudonym);
+        self.merge_seen(client_id, pseudonym);
+        for stored in self.compressed_transactions.values_mut() {
+            if stored.client_id == client_id {
+                stored.client_id = pseudonym;
+            }
+        }
+        self.merged_clients.retain(|_, into| *into != client_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, StoredTransaction, Transaction};
+
+    fn locked_processor() -> PaymentProcessor {
+        let mut processor = PaymentProcessor::new();
+        processor.process(&Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Amount::from(10),
+        });
+        processor.process(&Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 2,
+            amount: Amount::from(5),
+        });
+        processor.process(&Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 1,
+        });
+        processor.process(&Transaction::Chargeback {
+            client_id: 1,
+            transaction_id: 1,
+        });
+        processor.process(&Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 2,
+        });
+        processor
+    }
+
+    #[test]
+    fn test_unlock() {
+        let mut processor = locked_processor();
+        let unlock = OperatorAction::Unlock {
+            client_id: 1,
+            reference: String::from("INC-1"),
+        };
+
+        assert_eq!(processor.apply_action(&unlock), Ok(()));
+        assert!(!processor.accounts[&1].is_locked);
+        assert_eq!(
+            processor.apply_action(&unlock),
+            Err(RejectionReason::NotLocked)
+        );
+        assert_eq!(
+            processor.apply_action(&OperatorAction::Unlock {
+                client_id: 9,
+                reference: String::from("INC-1"),
+            }),
+            Err(RejectionReason::UnknownClient)
+        );
+    }
+
+    #[test]
+    fn test_reverse_chargeback() {
+        let mut processor = locked_processor();
+        let reverse = OperatorAction::ReverseChargeback {
+            client_id: 1,
+            transaction_id: 1,
+            reference: String::from("REP-1"),
+        };
+        let keep_locked = OperatorAction::KeepLocked {
+            client_id: 1,
+            reference: String::from("REP-1"),
+        };
+
+        assert_eq!(processor.apply_action(&keep_locked), Ok(()));
+        assert_eq!(processor.apply_action(&reverse), Ok(()));
+        let account = &processor.accounts[&1];
+        assert_eq!(account.available(), Amount::from(10));
+        assert_eq!(account.held(), Amount::from(5));
+        // Still locked until someone unlocks it
+        assert!(account.is_locked);
+        assert_eq!(
+            processor.compressed_transactions[&1].state,
+            DisputeState::Resolved
+        );
+        assert_eq!(
+            processor.apply_action(&reverse),
+            Err(RejectionReason::NotChargedBack)
+        );
+
+        processor.accounts.get_mut(&1).unwrap().is_locked = false;
+        assert_eq!(
+            processor.apply_action(&keep_locked),
+            Err(RejectionReason::NotLocked)
+        );
+    }
+
+    #[test]
+    fn test_force_resolve() {
+        let mut processor = locked_processor();
+        let force_resolve = OperatorAction::ForceResolve {
+            client_id: 1,
+            transaction_id: 2,
+            reference: String::from("INC-2"),
+        };
+
+        // Goes through even though the account is locked
+        assert_eq!(processor.apply_action(&force_resolve), Ok(()));
+        assert_eq!(processor.accounts[&1].available_funds, Amount::from(5));
+        assert_eq!(processor.accounts[&1].held_funds, Amount::from(0));
+        assert_eq!(
+            processor.apply_action(&force_resolve),
+            Err(RejectionReason::NotDisputed)
+        );
+    }
+
+    #[test]
+    fn test_force_resolve_orphan() {
+        let mut processor = PaymentProcessor::new();
+        processor.compressed_transactions.insert(
+            3,
+            StoredTransaction {
+                client_id: 7,
+                amount: Amount::from(4),
+                state: DisputeState::Disputed,
+            },
+        );
+
+        let result = processor.apply_action(&OperatorAction::ForceResolve {
+            client_id: 7,
+            transaction_id: 3,
+            reference: String::from("INC-3"),
+        });
+
+        assert_eq!(result, Ok(()));
+        assert!(processor.accounts.is_empty());
+        assert!(processor.check_invariants().is_empty());
+    }
+
+    #[test]
+    fn test_merge_client() {
+        let mut processor = PaymentProcessor::new();
+        processor.process(&Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Amount::from(10),
+        });
+        processor.process(&Transaction::Deposit {
+            client_id: 2,
+            transaction_id: 2,
+            amount: Amount::from(5),
+        });
+        processor.process(&Transaction::Dispute {
+            client_id: 2,
+            transaction_id: 2,
+        });
+        let merge = OperatorAction::MergeClient {
+            client_id: 2,
+            into: 1,
+            reference: String::from("CRM-7"),
+        };
+
+        assert_eq!(processor.apply_action(&merge), Ok(()));
+        assert!(!processor.accounts.contains_key(&2));
+        assert_eq!(processor.accounts[&1].available_funds, Amount::from(10));
+        assert_eq!(processor.accounts[&1].held_funds, Amount::from(5));
+        assert_eq!(processor.merged_clients()[&2], 1);
+        assert!(processor.check_invariants().is_empty());
+
+        // The open dispute moved over with the history
+        processor.process(&Transaction::Resolve {
+            client_id: 1,
+            transaction_id: 2,
+        });
+        assert_eq!(processor.accounts[&1].available_funds, Amount::from(15));
+
+        // The old ID is tombstoned
+        assert_eq!(
+            processor.try_process(&Transaction::Deposit {
+                client_id: 2,
+                transaction_id: 3,
+                amount: Amount::from(1),
+            }),
+            Err(RejectionReason::ClientMerged)
+        );
+        assert_eq!(
+            processor.apply_action(&merge),
+            Err(RejectionReason::ClientMerged)
+        );
+        assert_eq!(
+            processor.apply_action(&OperatorAction::MergeClient {
+                client_id: 1,
+                into: 1,
+                reference: String::from("CRM-8"),
+            }),
+            Err(RejectionReason::MergeIntoSelf)
+        );
+    }
+
+    #[test]
+    fn test_merge_chain() {
+        let mut processor = locked_processor();
+        processor.process(&Transaction::Deposit {
+            client_id: 2,
+            transaction_id: 3,
+            amount: Amount::from(1),
+        });
+        for (client_id, into) in [(1, 2), (2, 3)] {
+            let result = processor.apply_action(&OperatorAction::MergeClient {
+                client_id,
+                into,
+                reference: String::from("CRM-9"),
+            });
+            assert_eq!(result, Ok(()));
+        }
+
+        assert!(processor.accounts[&3].is_locked);
+        assert_eq!(processor.accounts[&3].total(), Amount::from(6));
+        assert_eq!(processor.merged_clients()[&1], 3);
+        assert_eq!(processor.merged_clients()[&2], 3);
+    }
+}
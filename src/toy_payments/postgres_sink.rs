@@ -0,0 +1,149 @@
+use std::error::Error;
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+use postgres::error::SqlState;
+use postgres::{Client, NoTls};
+use serde::Deserialize;
+
+use super::{Account, AccountSink, ClientId, is_valid_table_name};
+
+/// `[postgres]` section of the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostgresConfig {
+    /// libpq-style connection string, e.g. "host=localhost user=payments dbname=reporting"
+    pub url: String,
+    /// Needs a unique constraint on `client` for the upsert
+    #[serde(default = "default_table")]
+    pub table: String,
+    /// How many more times to try after a connection drop/serialization failure
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled on every retry after that
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+}
+
+fn default_table() -> String {
+    String::from("accounts")
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    500
+}
+
+/// Upserts the balances into Postgres. Every write is a single transaction:
+/// the accounts get COPY'd into a temp table and then merged into the real
+/// one with `INSERT .. ON CONFLICT`, so readers either see the whole batch
+/// or none of it. That also makes it safe to retry the whole thing.
+pub struct PostgresSink {
+    config: PostgresConfig,
+    client: Option<Client>,
+}
+
+impl PostgresSink {
+    pub fn new(config: PostgresConfig) -> Result<Self, String> {
+        if !is_valid_table_name(&config.table) {
+            return Err(format!("invalid postgres table name: {}", config.table));
+        }
+        Ok(Self {
+            config,
+            client: None,
+        })
+    }
+
+    fn try_write(&mut self, accounts: &[(ClientId, Account)]) -> Result<(), Box<dyn Error>> {
+        let client = match &mut self.client {
+            Some(client) if !client.is_closed() => client,
+            client => client.insert(Client::connect(&self.config.url, NoTls)?),
+        };
+
+        let mut transaction = client.transaction()?;
+        transaction.batch_execute(&format!(
+            "CREATE TEMP TABLE payments_staging (LIKE {} INCLUDING DEFAULTS) ON COMMIT DROP",
+            self.config.table
+        ))?;
+
+        let mut copy = transaction
+            .copy_in("COPY payments_staging (client, available, held, total, locked) FROM STDIN")?;
+        for (client_id, account) in accounts {
+            // Text format, amounts are written exactly so they can go into numeric columns
+            writeln!(
+                copy,
+                "{}\t{}\t{}\t{}\t{}",
+                client_id,
+                account.available(),
+                account.held(),
+                account.total(),
+                if account.is_locked() { "t" } else { "f" },
+            )?;
+        }
+        copy.finish()?;
+
+        transaction.batch_execute(&format!(
+            "INSERT INTO {table} (client, available, held, total, locked)
+             SELECT client, available, held, total, locked FROM payments_staging
+             ON CONFLICT (client) DO UPDATE SET
+                available = EXCLUDED.available,
+                held = EXCLUDED.held,
+                total = EXCLUDED.total,
+                locked = EXCLUDED.locked",
+            table = self.config.table
+        ))?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Connection problems and serialization failures/deadlocks are worth
+/// another go, anything else (missing table, bad credentials..) isn't
+fn is_transient(err: &(dyn Error + 'static)) -> bool {
+    // Writing the COPY data only fails if the connection does
+    let Some(err) = err.downcast_ref::<postgres::Error>() else {
+        return err.is::<std::io::Error>();
+    };
+    match err.code() {
+        Some(code) => {
+            *code == SqlState::T_R_SERIALIZATION_FAILURE
+                || *code == SqlState::T_R_DEADLOCK_DETECTED
+                || code.code().starts_with("08")
+        }
+        None => err.is_closed() || err.as_db_error().is_none(),
+    }
+}
+
+impl AccountSink for PostgresSink {
+    fn write_accounts(&mut self, accounts: &[(ClientId, Account)]) -> Result<(), Box<dyn Error>> {
+        let mut backoff = Duration::from_millis(self.config.retry_backoff_ms);
+        let mut attempt = 0;
+        loop {
+            match self.try_write(accounts) {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < self.config.max_retries && is_transient(err.as_ref()) => {
+                    attempt += 1;
+                    eprintln!(
+                        "postgres write failed ({}), retrying in {:?} ({}/{})",
+                        err, backoff, attempt, self.config.max_retries
+                    );
+                    self.client = None;
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                Err(err) => {
+                    // postgres::Error only says "db error", the details are in the DbError
+                    let db_error = err
+                        .downcast_ref::<postgres::Error>()
+                        .and_then(|err| err.as_db_error())
+                        .map(|db_error| db_error.to_string());
+                    return Err(db_error.map_or(err, Into::into));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use super::{Account, ClientId, PaymentProcessor, RowResult, Transaction};
+
+/// What processing a transaction would do, see `PaymentProcessor::preview`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preview {
+    pub result: RowResult,
+    /// Every account the transaction touches (the recipient too, for
+    /// transfers) as (client, before, after). `None` if there's no account.
+    pub accounts: Vec<(ClientId, Option<Account>, Option<Account>)>,
+}
+
+impl Preview {
+    pub fn is_applied(&self) -> bool {
+        self.result.is_applied()
+    }
+}
+
+impl PaymentProcessor {
+    /// Runs `transaction` against a copy of the state and reports how it
+    /// went, without changing anything here or telling the listeners.
+    /// Copies the transaction store, so it's for one-off questions, not
+    /// for every row of a file.
+    pub fn preview(&self, transaction: &Transaction) -> Preview {
+        let mut copy = self.copy_state();
+        let result = copy.process_with_result(transaction);
+
+        let mut client_ids = vec![transaction.client_id()];
+        if let Transaction::Transfer { to_client_id, .. } = transaction {
+            client_ids.push(*to_client_id);
+        }
+        let accounts = client_ids
+            .into_iter()
+            .map(|client_id| {
+                (
+                    client_id,
+                    self.accounts.get(&client_id).cloned(),
+                    copy.accounts.get(&client_id).cloned(),
+                )
+            })
+            .collect();
+        Preview { result, accounts }
+    }
+
+    // Everything that decides how a transaction goes, without the listeners
+    pub(crate) fn copy_state(&self) -> PaymentProcessor {
+        PaymentProcessor {
+            accounts: self.accounts.clone(),
+            compressed_transactions: self.compressed_transactions.clone(),
+            merged_clients: self.merged_clients.clone(),
+            category_withdrawn: self.category_withdrawn.clone(),
+            dispute_windows: self.dispute_windows.clone(),
+            expired_transactions: self.expired_transactions.clone(),
+            seen_transactions: self.seen_transactions.clone(),
+            shard: self.shard,
+            ..PaymentProcessor::with_config(self.config.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, RejectionReason, Step};
+
+    #[test]
+    fn test_preview() {
+        let mut processor = PaymentProcessor::new();
+        processor.process(&Transaction::Deposit {
+            client_id: 9,
+            transaction_id: 1,
+            amount: Amount::from(300),
+        });
+        let before = processor.accounts()[&9].clone();
+
+        let withdrawal = Transaction::Withdrawal {
+            client_id: 9,
+            transaction_id: 2,
+            amount: Amount::from(250),
+        };
+        let preview = processor.preview(&withdrawal);
+        assert!(preview.is_applied());
+        let (client_id, was, now) = &preview.accounts[0];
+        assert_eq!(*client_id, 9);
+        assert_eq!(was.as_ref(), Some(&before));
+        assert_eq!(now.as_ref().unwrap().available(), Amount::from(50));
+        // Nothing changed for real
+        assert_eq!(processor.accounts()[&9], before);
+        assert!(!processor.transactions().contains_key(&2));
+
+        let preview = processor.preview(&Transaction::Transfer {
+            client_id: 9,
+            transaction_id: 3,
+            to_client_id: 4,
+            amount: Amount::from(500),
+        });
+        assert_eq!(
+            preview.result,
+            RowResult::composite(
+                "TRANSFER",
+                (Step::Debit, Err(RejectionReason::InsufficientFunds)),
+                (Step::Credit, None),
+            )
+        );
+        assert_eq!(preview.accounts.len(), 2);
+        assert_eq!(preview.accounts[1], (4, None, None));
+    }
+}
@@ -0,0 +1,232 @@
+use serde::Serialize;
+
+use super::hashing::HashSet;
+use super::{
+    ClientId, DisputeState, PaymentProcessor, ReviewItem, StoredTransaction, TransactionId,
+};
+
+/// Everything the state holds about one client, for access requests.
+/// Amounts are the exact 4 decimal strings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientExport {
+    pub client: ClientId,
+    /// None when only the history is left (e.g. a dispute force-resolved
+    /// after the account went away)
+    pub account: Option<ExportedAccount>,
+    /// Set when the client was merged away, everything else is then
+    /// under that ID
+    pub merged_into: Option<ClientId>,
+    /// Older IDs that were merged into this one
+    pub merged_from: Vec<ClientId>,
+    /// Every stored deposit/withdrawal, by tx ID
+    pub transactions: Vec<ExportedTransaction>,
+    /// The ones from `transactions` that went through a dispute
+    pub disputes: Vec<ExportedTransaction>,
+    /// Their entries in the review queue, the caller fills these in
+    pub review: Vec<ReviewItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExportedAccount {
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+    pub open_disputes: u32,
+    pub total_disputes: u32,
+    pub chargebacks: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExportedTransaction {
+    pub tx: TransactionId,
+    pub amount: String,
+    pub state: &'static str,
+}
+
+impl PaymentProcessor {
+    /// None if the state doesn't know the client at all. A client that was
+    /// merged away only has the tombstone, the rest is under the one it was
+    /// merged into.
+    pub fn export_client(&self, client_id: ClientId) -> Option<ClientExport> {
+        if !self.clients_in_use().contains(&client_id) {
+            return None;
+        }
+        let account = self
+            .accounts
+            .get(&client_id)
+            .map(|account| ExportedAccount {
+                available: account.available().to_string(),
+                held: account.held().to_string(),
+                total: account.total().to_string(),
+                locked: account.is_locked(),
+                open_disputes: account.open_disputes(),
+                total_disputes: account.total_disputes(),
+                chargebacks: account.chargebacks(),
+            });
+
+        let mut merged_from: Vec<ClientId> = self
+            .merged_clients
+            .iter()
+            .filter(|(_, into)| **into == client_id)
+            .map(|(merged, _)| *merged)
+            .collect();
+        merged_from.sort_unstable();
+
+        let mut stored: Vec<_> = self
+            .compressed_transactions
+            .iter()
+            .filter(|(_, stored)| stored.client_id == client_id)
+            .collect();
+        stored.sort_unstable_by_key(|(transaction_id, _)| **transaction_id);
+        let exported =
+            |(transaction_id, stored): &(&TransactionId, &StoredTransaction)| ExportedTransaction {
+                tx: **transaction_id,
+                amount: stored.amount.to_string(),
+                state: stored.state.name(),
+            };
+        let transactions = stored.iter().map(exported).collect();
+        let disputes = stored
+            .iter()
+            .filter(|(_, stored)| stored.state != DisputeState::Undisputed)
+            .map(exported)
+            .collect();
+
+        Some(ClientExport {
+            client: client_id,
+            account,
+            merged_into: self.merged_clients.get(&client_id).copied(),
+            merged_from,
+            transactions,
+            disputes,
+            review: Vec::new(),
+        })
+    }
+
+    /// A client ID for an erasure to move a client's data to: the highest
+    /// one that doesn't show up anywhere in the state
+    pub fn unused_client_id(&self) -> Option<ClientId> {
+        let in_use = self.clients_in_use();
+        (0..=ClientId::MAX)
+            .rev()
+            .find(|client_id| !in_use.contains(client_id))
+    }
+
+    // Anything that would tie data to the ID, tombstones on either side included
+    pub(crate) fn clients_in_use(&self) -> HashSet<ClientId> {
+        let mut in_use: HashSet<ClientId> = self.accounts.keys().copied().collect();
+        in_use.extend(
+            self.compressed_transactions
+                .values()
+                .map(|stored| stored.client_id),
+        );
+        for (client_id, into) in &self.merged_clients {
+            in_use.insert(*client_id);
+            in_use.insert(*into);
+        }
+        in_use
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, OperatorAction, RejectionReason, Transaction};
+
+    fn processor() -> PaymentProcessor {
+        let mut processor = PaymentProcessor::new();
+        for (client_id, transaction_id, amount) in [(1, 1, 10), (1, 2, 5), (2, 3, 7), (3, 4, 1)] {
+            processor.process(&Transaction::Deposit {
+                client_id,
+                transaction_id,
+                amount: Amount::from(amount),
+            });
+        }
+        processor.process(&Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 2,
+        });
+        processor
+            .apply_action(&OperatorAction::MergeClient {
+                client_id: 3,
+                into: 1,
+                reference: String::from("CRM-1"),
+            })
+            .unwrap();
+        processor
+    }
+
+    fn erase(client_id: ClientId, pseudonym: ClientId) -> OperatorAction {
+        OperatorAction::EraseClient {
+            client_id,
+            pseudonym,
+            reference: String::from("DSR-1"),
+        }
+    }
+
+    fn ledger_total(processor: &PaymentProcessor) -> Amount {
+        processor
+            .accounts()
+            .values()
+            .fold(Amount::from(0), |total, account| total + account.total())
+    }
+
+    #[test]
+    fn test_export_client() {
+        let processor = processor();
+        let export = processor.export_client(1).unwrap();
+        assert_eq!(export.account.as_ref().unwrap().total, "16.0000");
+        assert_eq!(export.account.as_ref().unwrap().open_disputes, 1);
+        assert_eq!(export.merged_from, [3]);
+        let transactions: Vec<_> = export.transactions.iter().map(|tx| tx.tx).collect();
+        assert_eq!(transactions, [1, 2, 4]);
+        assert_eq!(export.disputes.len(), 1);
+        assert_eq!(export.disputes[0].state, "disputed");
+
+        // Merged away, all that's left is the pointer
+        let merged = processor.export_client(3).unwrap();
+        assert_eq!(merged.merged_into, Some(1));
+        assert!(merged.account.is_none() && merged.transactions.is_empty());
+        assert!(processor.export_client(9).is_none());
+    }
+
+    #[test]
+    fn test_erase_client() {
+        let mut processor = processor();
+        let total_before = ledger_total(&processor);
+        let pseudonym = processor.unused_client_id().unwrap();
+        assert_eq!(pseudonym, ClientId::MAX);
+
+        assert_eq!(
+            processor.apply_action(&erase(3, pseudonym)),
+            Err(RejectionReason::ClientMerged)
+        );
+        assert_eq!(
+            processor.apply_action(&erase(1, 2)),
+            Err(RejectionReason::PseudonymInUse)
+        );
+        assert_eq!(processor.apply_action(&erase(1, pseudonym)), Ok(()));
+
+        // Nothing about 1 (or 3, merged into it) is left
+        assert!(processor.export_client(1).is_none());
+        assert!(processor.export_client(3).is_none());
+        let moved = processor.export_client(pseudonym).unwrap();
+        assert_eq!(moved.transactions.len(), 3);
+        assert!(moved.merged_from.is_empty());
+        // The ledger still adds up
+        assert_eq!(ledger_total(&processor), total_before);
+        assert!(processor.check_invariants().is_empty());
+
+        // The open dispute carries on under the pseudonym
+        processor.process(&Transaction::Resolve {
+            client_id: pseudonym,
+            transaction_id: 2,
+        });
+        assert_eq!(processor.accounts()[&pseudonym].held(), Amount::from(0));
+        assert_eq!(processor.unused_client_id(), Some(ClientId::MAX - 1));
+        assert_eq!(
+            processor.apply_action(&erase(1, ClientId::MAX - 1)),
+            Err(RejectionReason::UnknownClient)
+        );
+    }
+}
@@ -1,16 +1,29 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::collections::HashMap;
 use std::fmt;
-
-use super::amount::Amount;
-
-type TransactionId = u32;
-type ClientId = u16;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::amount::{Amount, RoundingMode, Unrounded};
+use super::batch::BatchPolicy;
+use super::bloom::TransactionFilter;
+use super::bounds::AmountBounds;
+use super::categories::CategoryLimits;
+use super::dedup::{DedupWindow, SeenTransactions};
+use super::disputes::{DisputeCap, OverCap};
+use super::events::{EventListener, RejectionReason};
+use super::hashing::HashMap;
+use super::results::{RowResult, Step};
+use super::sharded::shard_for;
+use super::tiers::Tiers;
+use super::window::{ClientWindow, DisputeWindow, ExpiredTransactions};
+
+pub type TransactionId = u32;
+pub type ClientId = u16;
 
 /// Transaction enum where specific types contain
 /// amounts while others just rely on existing
 /// transaction IDs
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Transaction {
     Deposit {
         client_id: ClientId,
@@ -34,6 +47,32 @@ pub enum Transaction {
         client_id: ClientId,
         transaction_id: TransactionId,
     },
+    /// Manual correction issued by an operator. Positive amounts
+    /// credit and negative amounts debit the available funds.
+    /// Every adjustment must carry the operator's reference
+    /// (e.g. the incident ticket) so it can be traced later
+    Adjustment {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+        reference: String,
+    },
+    /// Moves funds from one client's available funds to another's. Either
+    /// both sides go through or neither does. Transfers aren't stored, so
+    /// they can't be disputed.
+    Transfer {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        to_client_id: ClientId,
+        amount: Amount,
+    },
+    /// Chargeback the network reports without a dispute having been opened
+    /// first. Opens the dispute and charges it back in one go, or does
+    /// neither.
+    AutoChargeback {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
 }
 
 /// Dedicated struct for CSV parsing
@@ -47,59 +86,127 @@ struct TransactionRow {
     client_id: ClientId,
     #[serde(rename = "tx")]
     transaction_id: TransactionId,
-    #[serde(deserialize_with = "deserialize_amount")]
-    amount: Option<Amount>,
+    amount: Option<f64>,
+    // Only adjustments need this, so most files won't even have the column
+    #[serde(default)]
+    reference: Option<String>,
+    // Same for transfers and their recipient
+    #[serde(default)]
+    to: Option<ClientId>,
 }
 
-impl<'de> Deserialize<'de> for Transaction {
+impl<'de> Deserialize<'de> for Unrounded<Transaction> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let row = TransactionRow::deserialize(deserializer)?;
-
-        match row.ty {
-            TransactionType::Deposit => {
-                let amount = row
-                    .amount
-                    .ok_or_else(|| serde::de::Error::custom("missing amount for deposit"))?;
-                Ok(Transaction::Deposit {
-                    client_id: row.client_id,
-                    transaction_id: row.transaction_id,
+        TransactionRow::deserialize(deserializer)?.into_transaction()
+    }
+}
+
+impl TransactionRow {
+    fn into_transaction<E: serde::de::Error>(self) -> Result<Unrounded<Transaction>, E> {
+        // Only checked for here, `round` fills it in
+        let amount = |ty: &str| match self.amount {
+            Some(_) => Ok(Amount::default()),
+            None => Err(E::custom(format!("missing amount for {}", ty))),
+        };
+
+        let value = match self.ty {
+            TransactionType::Deposit => Transaction::Deposit {
+                client_id: self.client_id,
+                transaction_id: self.transaction_id,
+                amount: amount("deposit")?,
+            },
+            TransactionType::Withdrawal => Transaction::Withdrawal {
+                client_id: self.client_id,
+                transaction_id: self.transaction_id,
+                amount: amount("withdrawal")?,
+            },
+            TransactionType::Dispute => Transaction::Dispute {
+                client_id: self.client_id,
+                transaction_id: self.transaction_id,
+            },
+            TransactionType::Resolve => Transaction::Resolve {
+                client_id: self.client_id,
+                transaction_id: self.transaction_id,
+            },
+            TransactionType::Chargeback => Transaction::Chargeback {
+                client_id: self.client_id,
+                transaction_id: self.transaction_id,
+            },
+            TransactionType::AutoChargeback => Transaction::AutoChargeback {
+                client_id: self.client_id,
+                transaction_id: self.transaction_id,
+            },
+            TransactionType::Transfer => {
+                let amount = amount("transfer")?;
+                let to_client_id = self
+                    .to
+                    .ok_or_else(|| E::custom("missing recipient for transfer"))?;
+                Transaction::Transfer {
+                    client_id: self.client_id,
+                    transaction_id: self.transaction_id,
+                    to_client_id,
                     amount,
-                })
+                }
             }
-            TransactionType::Withdrawal => {
-                let amount = row
-                    .amount
-                    .ok_or_else(|| serde::de::Error::custom("missing amount for withdrawal"))?;
-                Ok(Transaction::Withdrawal {
-                    client_id: row.client_id,
-                    transaction_id: row.transaction_id,
+            TransactionType::AdjustmentCredit | TransactionType::AdjustmentDebit => {
+                let amount = amount("adjustment")?;
+                let reference = self
+                    .reference
+                    .filter(|reference| !reference.is_empty())
+                    .ok_or_else(|| E::custom("missing reference for adjustment"))?;
+                Transaction::Adjustment {
+                    client_id: self.client_id,
+                    transaction_id: self.transaction_id,
                     amount,
-                })
+                    reference,
+                }
             }
-            TransactionType::Dispute => Ok(Transaction::Dispute {
-                client_id: row.client_id,
-                transaction_id: row.transaction_id,
-            }),
-            TransactionType::Resolve => Ok(Transaction::Resolve {
-                client_id: row.client_id,
-                transaction_id: row.transaction_id,
-            }),
-            TransactionType::Chargeback => Ok(Transaction::Chargeback {
-                client_id: row.client_id,
-                transaction_id: row.transaction_id,
-            }),
+        };
+        // Debits come out negative, rounding goes the same either side of zero
+        let amount = match self.ty {
+            TransactionType::AdjustmentDebit => self.amount.map(|amount| -amount),
+            _ => self.amount,
+        };
+        Ok(Unrounded { value, amount })
+    }
+}
+
+impl Unrounded<Transaction> {
+    pub fn round(mut self, mode: RoundingMode) -> Transaction {
+        if let (Some(raw), Some(amount)) = (self.amount, self.value.amount_mut()) {
+            *amount = Amount::from_f64_rounded(raw, mode);
         }
+        self.value
     }
 }
 
-#[derive(Debug)]
+// Truncates, like Amount::from(f64). The readers take a RoundingMode.
+impl<'de> Deserialize<'de> for Transaction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Unrounded::<Transaction>::deserialize(deserializer)?.round(RoundingMode::Truncate))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Account {
-    available_funds: Amount,
-    held_funds: Amount,
-    is_locked: bool,
+    pub(crate) available_funds: Amount,
+    pub(crate) held_funds: Amount,
+    pub(crate) is_locked: bool,
+    /// Locked by going over the dispute cap (with `freeze`) rather than by
+    /// a chargeback, until it's unlocked
+    pub(crate) frozen: bool,
+    /// Not in snapshots, recounted from the stored transactions on load
+    pub(crate) open_disputes: u32,
+    /// Every dispute ever opened, including ones on the same transaction
+    /// again after it was resolved
+    pub(crate) total_disputes: u32,
+    pub(crate) chargebacks: u32,
 }
 
 impl Account {
@@ -108,42 +215,623 @@ impl Account {
             available_funds: Amount::from(0),
             held_funds: Amount::from(0),
             is_locked: false,
+            frozen: false,
+            open_disputes: 0,
+            total_disputes: 0,
+            chargebacks: 0,
         }
     }
 }
 
+impl Account {
+    pub fn available(&self) -> Amount {
+        self.available_funds
+    }
+
+    pub fn held(&self) -> Amount {
+        self.held_funds
+    }
+
+    pub fn total(&self) -> Amount {
+        self.available_funds + self.held_funds
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.is_locked
+    }
+
+    pub fn open_disputes(&self) -> u32 {
+        self.open_disputes
+    }
+
+    pub fn total_disputes(&self) -> u32 {
+        self.total_disputes
+    }
+
+    pub fn chargebacks(&self) -> u32 {
+        self.chargebacks
+    }
+}
+
 impl Default for Account {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Knobs for behavior that isn't pinned down by the requirements
+#[derive(Debug, Clone)]
+pub struct ProcessorConfig {
+    /// Whether operator adjustments still apply to locked accounts
+    pub adjust_locked_accounts: bool,
+    pub amount_bounds: AmountBounds,
+    /// Shared between shards, the client map can get big
+    pub tiers: Arc<Tiers>,
+    /// Limit on how many disputes a client can have open at once
+    pub dispute_cap: Option<DisputeCap>,
+    /// Withdrawal limits by enrichment category
+    pub category_limits: Option<Arc<CategoryLimits>>,
+    /// Check a bloom filter before the transaction store when looking up
+    /// what a dispute/resolve/chargeback refers to
+    pub transaction_filter: bool,
+    /// Drop deposits/withdrawals from the store once they're too old to
+    /// dispute
+    pub dispute_window: Option<DisputeWindow>,
+    /// What a rejection in process_batch does to the rest of the batch
+    pub batch_policy: BatchPolicy,
+    /// Reject transactions whose ID the client already used
+    pub dedup_window: Option<DedupWindow>,
+}
+
+impl Default for ProcessorConfig {
+    fn default() -> Self {
+        Self {
+            adjust_locked_accounts: true,
+            amount_bounds: AmountBounds::default(),
+            tiers: Arc::default(),
+            dispute_cap: None,
+            category_limits: None,
+            transaction_filter: false,
+            dispute_window: None,
+            batch_policy: BatchPolicy::default(),
+            dedup_window: None,
+        }
+    }
+}
+
+/// Side effects of an applied transaction that listeners
+/// get told about on top of the transaction itself
+#[derive(Clone, Copy)]
+enum Effect {
+    DisputeOpened {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    },
+    DisputeResolved {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    },
+    ChargedBack {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    },
+}
+
+/// Where a stored transaction is in the dispute flow. Disputes can only
+/// be opened on transactions that aren't already disputed or charged back,
+/// and only an open dispute can be resolved or charged back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeState {
+    Undisputed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl DisputeState {
+    /// As it shows up in JSON
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisputeState::Undisputed => "undisputed",
+            DisputeState::Disputed => "disputed",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "charged_back",
+        }
+    }
+}
+
+/// What we keep around per deposit/withdrawal for later disputes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoredTransaction {
+    pub client_id: ClientId,
+    pub amount: Amount,
+    pub state: DisputeState,
+}
+
 pub struct PaymentProcessor {
-    accounts: HashMap<ClientId, Account>,
-    compressed_transactions: HashMap<TransactionId, Amount>,
+    pub(crate) config: ProcessorConfig,
+    pub(crate) accounts: HashMap<ClientId, Account>,
+    pub(crate) compressed_transactions: HashMap<TransactionId, StoredTransaction>,
+    /// Tombstones for clients that were merged into another one
+    pub(crate) merged_clients: HashMap<ClientId, ClientId>,
+    /// Withdrawn so far by (client, category index), for the category
+    /// limits. Not in snapshots, recounted from the stored withdrawals on load.
+    pub(crate) category_withdrawn: HashMap<(ClientId, usize), Amount>,
+    /// Built on first use when the config asks for it, dropped whenever
+    /// the store gets replaced wholesale
+    pub(crate) transaction_filter: Option<TransactionFilter>,
+    /// Only kept with a dispute window in the config
+    pub(crate) dispute_windows: HashMap<ClientId, ClientWindow>,
+    /// What the dispute window dropped from the store, probably. Shared
+    /// with preview copies until one of them expires something.
+    pub(crate) expired_transactions: Arc<ExpiredTransactions>,
+    /// Only added to with a dedup window in the config, but whatever a
+    /// snapshot had is kept either way
+    pub(crate) seen_transactions: HashMap<ClientId, SeenTransactions>,
+    /// (index, count) when this is one of several shards, see into_shards
+    pub(crate) shard: Option<(usize, usize)>,
+    pub(crate) listeners: Vec<Box<dyn EventListener>>,
 }
 
 impl PaymentProcessor {
     pub fn new() -> Self {
+        Self::with_config(ProcessorConfig::default())
+    }
+
+    pub fn with_config(config: ProcessorConfig) -> Self {
         Self {
-            accounts: HashMap::new(),
-            compressed_transactions: HashMap::new(),
+            config,
+            accounts: HashMap::default(),
+            compressed_transactions: HashMap::default(),
+            merged_clients: HashMap::default(),
+            category_withdrawn: HashMap::default(),
+            transaction_filter: None,
+            dispute_windows: HashMap::default(),
+            expired_transactions: Arc::default(),
+            seen_transactions: HashMap::default(),
+            shard: None,
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Pre-sizes the maps for roughly this many clients and stored
+    /// transactions, to avoid rehashing over and over on big inputs
+    pub fn with_capacity(clients: usize, transactions: usize) -> Self {
+        let mut processor = Self::new();
+        processor.reserve(clients, transactions);
+        processor
+    }
+
+    pub fn reserve(&mut self, clients: usize, transactions: usize) {
+        self.accounts.reserve(clients);
+        self.compressed_transactions.reserve(transactions);
+    }
+
+    pub fn accounts(&self) -> &HashMap<ClientId, Account> {
+        &self.accounts
+    }
+
+    /// Highest ID in the transaction store, for seeding an IdGenerator
+    pub fn max_transaction_id(&self) -> Option<TransactionId> {
+        self.compressed_transactions.keys().max().copied()
+    }
+
+    pub fn transactions(&self) -> &HashMap<TransactionId, StoredTransaction> {
+        &self.compressed_transactions
+    }
+
+    /// Merged-away client -> the client it was merged into
+    pub fn merged_clients(&self) -> &HashMap<ClientId, ClientId> {
+        &self.merged_clients
+    }
+
+    // Clients can only reference their own transactions
+    pub(crate) fn find_transaction(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<&mut StoredTransaction, RejectionReason> {
+        if (self.config.transaction_filter && !self.may_be_stored(transaction_id))
+            || !self.compressed_transactions.contains_key(&transaction_id)
+        {
+            return Err(
+                if self
+                    .expired_transactions
+                    .contains(client_id, transaction_id)
+                {
+                    RejectionReason::DisputeWindowExpired
+                } else {
+                    RejectionReason::UnknownTransaction
+                },
+            );
+        }
+        match self.compressed_transactions.get_mut(&transaction_id) {
+            Some(stored) if stored.client_id == client_id => Ok(stored),
+            Some(_) => Err(RejectionReason::ClientMismatch),
+            None => Err(RejectionReason::UnknownTransaction),
         }
     }
 
-    fn find_transaction(&self, transaction_id: TransactionId) -> Option<&Amount> {
-        self.compressed_transactions.get(&transaction_id)
+    fn may_be_stored(&mut self, transaction_id: TransactionId) -> bool {
+        // Anything stored without going through store_transaction makes the
+        // counts differ
+        let stored = self.compressed_transactions.len();
+        let stale = self.transaction_filter.as_ref().is_none_or(|filter| {
+            filter.len() != stored
+                || stored > filter.capacity()
+                || filter.removed() > filter.capacity() / 2
+        });
+        if stale {
+            let mut filter = TransactionFilter::with_capacity((stored * 2).max(1024));
+            for transaction_id in self.compressed_transactions.keys() {
+                filter.insert(*transaction_id);
+            }
+            self.transaction_filter = Some(filter);
+        }
+        self.transaction_filter
+            .as_ref()
+            .is_some_and(|filter| filter.may_contain(transaction_id))
     }
 
-    fn store_transaction(&mut self, transaction_id: TransactionId, amount: Amount) {
-        self.compressed_transactions.insert(transaction_id, amount);
+    fn store_transaction(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    ) {
+        let replaced = self.compressed_transactions.insert(
+            transaction_id,
+            StoredTransaction {
+                client_id,
+                amount,
+                state: DisputeState::Undisputed,
+            },
+        );
+        if replaced.is_none() {
+            if let Some(filter) = &mut self.transaction_filter {
+                filter.insert(transaction_id);
+            }
+            self.track_for_expiry(client_id, transaction_id);
+        }
     }
 
     fn get_account(&mut self, client_id: ClientId) -> &mut Account {
-        self.accounts.entry(client_id).or_insert_with(Account::new)
+        self.accounts.entry(client_id).or_default()
+    }
+
+    pub fn add_listener(&mut self, listener: impl EventListener + 'static) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    pub(crate) fn notify(&mut self, callback: impl Fn(&mut dyn EventListener)) {
+        for listener in self.listeners.iter_mut() {
+            callback(listener.as_mut());
+        }
     }
 
     pub fn process(&mut self, transaction: &Transaction) {
+        // Rejections are already reported to the listeners
+        let _ = self.try_process(transaction);
+    }
+
+    /// Same as process, but also hands back why the transaction got rejected
+    pub fn try_process(&mut self, transaction: &Transaction) -> Result<(), RejectionReason> {
+        self.process_row(transaction).0
+    }
+
+    /// Same as try_process, but with the per-step detail for transfers and
+    /// auto chargebacks
+    pub fn process_with_result(&mut self, transaction: &Transaction) -> RowResult {
+        self.process_row(transaction).1
+    }
+
+    fn process_row(
+        &mut self,
+        transaction: &Transaction,
+    ) -> (Result<(), RejectionReason>, RowResult) {
+        // A duplicate is as if the row wasn't there, it doesn't age anything
+        let duplicate = self.is_duplicate(transaction);
+        if !duplicate {
+            self.advance_dispute_window(transaction.client_id());
+        }
+        // Validation comes first, anything out of bounds never touches an account
+        let over_cap = self.over_dispute_cap(transaction);
+        let (result, row) = if duplicate {
+            let reason = RejectionReason::DuplicateTransaction;
+            (Err(reason), RowResult::Rejected(reason))
+        } else if let Err(reason) = self.config.amount_bounds.check(transaction) {
+            (Err(reason), RowResult::Rejected(reason))
+        } else if let Some((cap, _)) = over_cap
+            && cap.over_cap == OverCap::Reject
+        {
+            let reason = RejectionReason::TooManyDisputes;
+            (Err(reason), RowResult::Rejected(reason))
+        } else {
+            match transaction {
+                Transaction::Transfer {
+                    client_id,
+                    to_client_id,
+                    amount,
+                    ..
+                } => self.apply_transfer(*client_id, *to_client_id, *amount),
+                Transaction::AutoChargeback {
+                    client_id,
+                    transaction_id,
+                } => self.apply_auto_chargeback(*client_id, *transaction_id),
+                _ => {
+                    let result = self.apply(transaction);
+                    let row = match result {
+                        Ok(_) => RowResult::Applied,
+                        Err(reason) => RowResult::Rejected(reason),
+                    };
+                    (result.map(|effect| [effect, None]), row)
+                }
+            }
+        };
+        let result = match result {
+            Ok(effects) => {
+                self.notify(|listener| listener.on_applied(transaction));
+                for effect in effects.into_iter().flatten() {
+                    self.notify_effect(transaction, effect);
+                }
+                Ok(())
+            }
+            Err(reason) => {
+                self.notify(|listener| listener.on_rejected(transaction, reason));
+                Err(reason)
+            }
+        };
+        if let Some((cap, open)) = over_cap {
+            self.enforce_dispute_cap(transaction, cap, open);
+        }
+        self.notify(|listener| listener.on_result(transaction, &row));
+        (result, row)
+    }
+
+    // The cap only counts against disputes that would otherwise go through,
+    // so a bad one still gets rejected for what's actually wrong with it.
+    // Gives back the cap and how many disputes the client has open.
+    fn over_dispute_cap(&self, transaction: &Transaction) -> Option<(DisputeCap, u32)> {
+        let cap = self.config.dispute_cap?;
+        let Transaction::Dispute {
+            client_id,
+            transaction_id,
+        } = transaction
+        else {
+            return None;
+        };
+        let stored = self.compressed_transactions.get(transaction_id)?;
+        let disputable = matches!(
+            stored.state,
+            DisputeState::Undisputed | DisputeState::Resolved
+        );
+        if stored.client_id != *client_id
+            || !disputable
+            || self.merged_clients.contains_key(client_id)
+        {
+            return None;
+        }
+        let open = self
+            .accounts
+            .get(client_id)
+            .map_or(0, Account::open_disputes);
+        (open >= cap.max_open).then_some((cap, open))
+    }
+
+    // The withdrawal's category index if it has limits, or the rejection if
+    // it's over them
+    fn category_limit(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    ) -> Result<Option<usize>, RejectionReason> {
+        let Some((index, limit)) = self
+            .config
+            .category_limits
+            .as_ref()
+            .and_then(|limits| limits.lookup(transaction_id))
+        else {
+            return Ok(None);
+        };
+        let withdrawn = self
+            .category_withdrawn
+            .get(&(client_id, index))
+            .copied()
+            .unwrap_or_default();
+        if limit.withdrawal_limit.is_some_and(|max| amount > max)
+            || limit.per_client.is_some_and(|max| withdrawn + amount > max)
+        {
+            return Err(RejectionReason::CategoryLimitExceeded);
+        }
+        Ok(Some(index))
+    }
+
+    fn enforce_dispute_cap(&mut self, transaction: &Transaction, cap: DisputeCap, open: u32) {
+        let (client_id, transaction_id) = (transaction.client_id(), transaction.transaction_id());
+        let flagged = cap.over_cap == OverCap::Flag;
+        self.notify(|listener| {
+            listener.on_dispute_over_cap(client_id, transaction_id, open, flagged)
+        });
+        if !cap.freeze {
+            return;
+        }
+        let account = self.get_account(client_id);
+        if account.is_locked {
+            return;
+        }
+        account.is_locked = true;
+        account.frozen = true;
+        let account = account.clone();
+        self.notify(|listener| listener.on_account_locked(client_id, transaction, &account));
+    }
+
+    fn notify_effect(&mut self, transaction: &Transaction, effect: Effect) {
+        match effect {
+            Effect::DisputeOpened {
+                client_id,
+                transaction_id,
+                amount,
+            } => self
+                .notify(|listener| listener.on_dispute_opened(client_id, transaction_id, amount)),
+            Effect::DisputeResolved {
+                client_id,
+                transaction_id,
+                amount,
+            } => self
+                .notify(|listener| listener.on_dispute_resolved(client_id, transaction_id, amount)),
+            Effect::ChargedBack {
+                client_id,
+                transaction_id,
+                amount,
+            } => {
+                self.notify(|listener| listener.on_charged_back(client_id, transaction_id, amount));
+                let account = self.accounts[&client_id].clone();
+                self.notify(|listener| {
+                    listener.on_account_locked(client_id, transaction, &account)
+                });
+            }
+        }
+    }
+
+    // Debits the sender, then credits the recipient. If the credit fails the
+    // sender's account is put back the way it was.
+    fn apply_transfer(
+        &mut self,
+        client_id: ClientId,
+        to_client_id: ClientId,
+        amount: Amount,
+    ) -> (Result<[Option<Effect>; 2], RejectionReason>, RowResult) {
+        let before = self.accounts.get(&client_id).cloned();
+        // A negative one would pull money out of the recipient
+        let debited = if amount <= Amount::default() {
+            Err(RejectionReason::NonPositiveAmount)
+        } else {
+            self.debit(client_id, amount)
+        };
+        if let Err(reason) = debited {
+            let row =
+                RowResult::composite("TRANSFER", (Step::Debit, Err(reason)), (Step::Credit, None));
+            return (Err(reason), row);
+        }
+        if let Err(reason) = self.credit(to_client_id, amount) {
+            self.restore_account(client_id, before);
+            let row = RowResult::composite(
+                "TRANSFER",
+                (Step::Debit, Ok(())),
+                (Step::Credit, Some(Err(reason))),
+            );
+            return (Err(reason), row);
+        }
+        let row = RowResult::composite(
+            "TRANSFER",
+            (Step::Debit, Ok(())),
+            (Step::Credit, Some(Ok(()))),
+        );
+        (Ok([None, None]), row)
+    }
+
+    fn debit(&mut self, client_id: ClientId, amount: Amount) -> Result<(), RejectionReason> {
+        if self.merged_clients.contains_key(&client_id) {
+            return Err(RejectionReason::ClientMerged);
+        }
+        let account = self.get_account(client_id);
+        if account.is_locked {
+            return Err(RejectionReason::AccountLocked);
+        }
+        if account.available_funds < amount {
+            return Err(RejectionReason::InsufficientFunds);
+        }
+        account.available_funds -= amount;
+        Ok(())
+    }
+
+    fn credit(&mut self, client_id: ClientId, amount: Amount) -> Result<(), RejectionReason> {
+        if self.merged_clients.contains_key(&client_id) {
+            return Err(RejectionReason::ClientMerged);
+        }
+        // The recipient's account has to live on this shard, otherwise
+        // merging the shards back would lose one of the two versions of it
+        if let Some((index, count)) = self.shard
+            && shard_for(client_id, count) != index
+        {
+            return Err(RejectionReason::CrossShard);
+        }
+        let account = self.get_account(client_id);
+        if account.is_locked {
+            return Err(RejectionReason::AccountLocked);
+        }
+        account.available_funds += amount;
+        Ok(())
+    }
+
+    fn restore_account(&mut self, client_id: ClientId, before: Option<Account>) {
+        match before {
+            Some(account) => self.accounts.insert(client_id, account),
+            None => self.accounts.remove(&client_id),
+        };
+    }
+
+    // Same as a dispute followed by a chargeback, rolling the dispute back
+    // if the chargeback doesn't go through
+    fn apply_auto_chargeback(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> (Result<[Option<Effect>; 2], RejectionReason>, RowResult) {
+        let account_before = self.accounts.get(&client_id).cloned();
+        let stored_before = self.compressed_transactions.get(&transaction_id).copied();
+
+        let opened = match self.apply(&Transaction::Dispute {
+            client_id,
+            transaction_id,
+        }) {
+            Ok(effect) => effect,
+            Err(reason) => {
+                let row = RowResult::composite(
+                    "AUTO_CHARGEBACK",
+                    (Step::Dispute, Err(reason)),
+                    (Step::Chargeback, None),
+                );
+                return (Err(reason), row);
+            }
+        };
+        match self.apply(&Transaction::Chargeback {
+            client_id,
+            transaction_id,
+        }) {
+            Ok(charged_back) => {
+                let row = RowResult::composite(
+                    "AUTO_CHARGEBACK",
+                    (Step::Dispute, Ok(())),
+                    (Step::Chargeback, Some(Ok(()))),
+                );
+                (Ok([opened, charged_back]), row)
+            }
+            Err(reason) => {
+                self.restore_account(client_id, account_before);
+                if let Some(stored) = stored_before {
+                    self.compressed_transactions.insert(transaction_id, stored);
+                }
+                let row = RowResult::composite(
+                    "AUTO_CHARGEBACK",
+                    (Step::Dispute, Ok(())),
+                    (Step::Chargeback, Some(Err(reason))),
+                );
+                (Err(reason), row)
+            }
+        }
+    }
+
+    fn apply(&mut self, transaction: &Transaction) -> Result<Option<Effect>, RejectionReason> {
+        // Merged-away IDs are tombstoned, anything still coming in for them
+        // has to be looked at upstream
+        if self.merged_clients.contains_key(&transaction.client_id()) {
+            return Err(RejectionReason::ClientMerged);
+        }
         match transaction {
             Transaction::Deposit {
                 client_id,
@@ -152,55 +840,130 @@ impl PaymentProcessor {
             } => {
                 let account = self.get_account(*client_id);
                 // See test for details why we skip locked accounts
-                if !account.is_locked {
-                    account.available_funds += *amount;
-                    self.store_transaction(*transaction_id, *amount);
+                if account.is_locked {
+                    return Err(RejectionReason::AccountLocked);
                 }
+                account.available_funds += *amount;
+                self.store_transaction(*client_id, *transaction_id, *amount);
+                Ok(None)
             }
             Transaction::Withdrawal {
                 client_id,
                 transaction_id,
                 amount,
             } => {
+                let rules = *self.config.tiers.rules(*client_id);
+                let category = self.category_limit(*client_id, *transaction_id, *amount);
                 let account = self.get_account(*client_id);
+                if account.is_locked {
+                    return Err(RejectionReason::AccountLocked);
+                }
+                if rules.withdrawal_limit.is_some_and(|limit| *amount > limit) {
+                    return Err(RejectionReason::OverWithdrawalLimit);
+                }
+                let category = category?;
                 // Only process withdrawal if there are sufficient available funds
-                // Ignore any withdrawals that go beyond the available amount (per requirements)
-                if !account.is_locked && account.available_funds >= *amount {
-                    account.available_funds -= *amount;
-                    // We can represent withdrawals as negative amounts, so we only need to store
-                    // the amount and its transaction ID for a more compressed log
-                    self.store_transaction(*transaction_id, -*amount);
+                // Ignore any withdrawals that go beyond the available amount (per requirements).
+                // The client's tier can add a fee and allow an overdraft.
+                let cost = *amount + rules.withdrawal_fee;
+                if account.available_funds + rules.overdraft < cost {
+                    return Err(RejectionReason::InsufficientFunds);
                 }
+                account.available_funds -= cost;
+                if let Some(index) = category {
+                    *self
+                        .category_withdrawn
+                        .entry((*client_id, index))
+                        .or_default() += *amount;
+                }
+                // We can represent withdrawals as negative amounts, so we only need to store
+                // the amount and its transaction ID for a more compressed log
+                self.store_transaction(*client_id, *transaction_id, -*amount);
+                Ok(None)
             }
             Transaction::Dispute {
                 client_id,
                 transaction_id,
             } => {
-                if let Some(txn_amount) = self.find_transaction(*transaction_id).copied() {
-                    let account = self.get_account(*client_id);
-                    account.available_funds -= txn_amount;
-                    account.held_funds += txn_amount;
+                let stored = self.find_transaction(*client_id, *transaction_id)?;
+                match stored.state {
+                    DisputeState::Undisputed | DisputeState::Resolved => {}
+                    DisputeState::Disputed => return Err(RejectionReason::AlreadyDisputed),
+                    DisputeState::ChargedBack => return Err(RejectionReason::AlreadyChargedBack),
                 }
+                stored.state = DisputeState::Disputed;
+                let txn_amount = stored.amount;
+
+                let account = self.get_account(*client_id);
+                account.available_funds -= txn_amount;
+                account.held_funds += txn_amount;
+                account.open_disputes += 1;
+                account.total_disputes += 1;
+                Ok(Some(Effect::DisputeOpened {
+                    client_id: *client_id,
+                    transaction_id: *transaction_id,
+                    amount: txn_amount,
+                }))
             }
             Transaction::Resolve {
                 client_id,
                 transaction_id,
             } => {
-                if let Some(txn_amount) = self.find_transaction(*transaction_id).copied() {
-                    let account = self.get_account(*client_id);
-                    account.available_funds += txn_amount;
-                    account.held_funds -= txn_amount;
+                let stored = self.find_transaction(*client_id, *transaction_id)?;
+                if stored.state != DisputeState::Disputed {
+                    return Err(RejectionReason::NotDisputed);
                 }
+                stored.state = DisputeState::Resolved;
+                let txn_amount = stored.amount;
+
+                let account = self.get_account(*client_id);
+                account.available_funds += txn_amount;
+                account.held_funds -= txn_amount;
+                account.open_disputes = account.open_disputes.saturating_sub(1);
+                Ok(Some(Effect::DisputeResolved {
+                    client_id: *client_id,
+                    transaction_id: *transaction_id,
+                    amount: txn_amount,
+                }))
             }
             Transaction::Chargeback {
                 client_id,
                 transaction_id,
             } => {
-                if let Some(txn_amount) = self.find_transaction(*transaction_id).copied() {
-                    let account = self.get_account(*client_id);
-                    account.held_funds -= txn_amount;
-                    account.is_locked = true;
+                let stored = self.find_transaction(*client_id, *transaction_id)?;
+                if stored.state != DisputeState::Disputed {
+                    return Err(RejectionReason::NotDisputed);
+                }
+                stored.state = DisputeState::ChargedBack;
+                let txn_amount = stored.amount;
+
+                let account = self.get_account(*client_id);
+                account.held_funds -= txn_amount;
+                account.open_disputes = account.open_disputes.saturating_sub(1);
+                account.chargebacks += 1;
+                account.is_locked = true;
+                Ok(Some(Effect::ChargedBack {
+                    client_id: *client_id,
+                    transaction_id: *transaction_id,
+                    amount: txn_amount,
+                }))
+            }
+            Transaction::Adjustment {
+                client_id, amount, ..
+            } => {
+                let adjust_locked_accounts = self.config.adjust_locked_accounts;
+                let account = self.get_account(*client_id);
+                if account.is_locked && !adjust_locked_accounts {
+                    return Err(RejectionReason::AccountLocked);
                 }
+                // Adjustments are corrections made after the fact, so they go straight
+                // to the available funds without a funds check. They're also not stored,
+                // since there's nothing for a client to dispute about them
+                account.available_funds += *amount;
+                Ok(None)
+            }
+            Transaction::Transfer { .. } | Transaction::AutoChargeback { .. } => {
+                unreachable!("multi-part transactions are applied step by step in try_process")
             }
         }
     }
@@ -209,9 +972,69 @@ impl PaymentProcessor {
     // meets the requirements we have for this. As an extension, I'd want to have
     // some "exporter" that could be styled/formatted/controlled separately.
     pub fn dump_csv(&self) -> Result<(), Box<dyn std::error::Error>> {
-        use csv::Writer;
+        self.dump_csv_filtered(|_, _| true)
+    }
 
-        let mut wtr = Writer::from_writer(std::io::stdout());
+    /// Same as dump_csv, but only for the accounts `filter` returns true for
+    pub fn dump_csv_filtered(
+        &self,
+        filter: impl Fn(ClientId, &Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_csv(std::io::stdout(), filter)
+    }
+
+    /// Same as dump_csv_filtered, but into any writer
+    pub fn write_csv(
+        &self,
+        out: impl std::io::Write,
+        filter: impl Fn(ClientId, &Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_csv_with_schema(out, OutputSchema::V1, filter)
+    }
+
+    /// Same as write_csv, in the given column layout
+    pub fn write_csv_with_schema(
+        &self,
+        out: impl std::io::Write,
+        schema: OutputSchema,
+        filter: impl Fn(ClientId, &Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        use csv::WriterBuilder;
+
+        // Header is written by hand so that it's there even when no account passes the filter
+        let mut wtr = WriterBuilder::new().has_headers(false).from_writer(out);
+        if schema == OutputSchema::V2 {
+            wtr.write_record([
+                "client",
+                "available",
+                "held",
+                "total",
+                "locked",
+                "tier",
+                "open_disputes",
+                "total_disputes",
+                "chargebacks",
+            ])?;
+            for (client_id, account) in &self.accounts {
+                if !filter(*client_id, account) {
+                    continue;
+                }
+                wtr.write_record([
+                    client_id.to_string().as_str(),
+                    &account.available_funds.to_string(),
+                    &account.held_funds.to_string(),
+                    &account.total().to_string(),
+                    if account.is_locked { "true" } else { "false" },
+                    self.config.tiers.tier(*client_id).unwrap_or_default(),
+                    &account.open_disputes.to_string(),
+                    &account.total_disputes.to_string(),
+                    &account.chargebacks.to_string(),
+                ])?;
+            }
+            wtr.flush()?;
+            return Ok(());
+        }
+        wtr.write_record(["client", "available", "held", "total", "locked"])?;
 
         // TODO: Write in here for now, put in a separate class later
         #[derive(Serialize)]
@@ -238,6 +1061,9 @@ impl PaymentProcessor {
 
         for client_id in self.accounts.keys() {
             let account = &self.accounts[client_id];
+            if !filter(*client_id, account) {
+                continue;
+            }
             wtr.serialize(AccountRow {
                 client_id: *client_id,
                 available_funds: account.available_funds,
@@ -252,6 +1078,33 @@ impl PaymentProcessor {
     }
 }
 
+/// Column layout of the CSV balances. New columns only go into a new
+/// version, so parsers written against an older one keep working.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputSchema {
+    /// `client,available,held,total,locked`, amounts as short as they go
+    /// (e.g. `1.5`). Never changes.
+    #[default]
+    V1,
+    /// v1's columns with every amount at 4 decimals, plus the client's
+    /// `tier` (empty without one) and its `open_disputes`, `total_disputes`
+    /// and `chargebacks` counts
+    V2,
+}
+
+impl FromStr for OutputSchema {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(OutputSchema::V1),
+            "v2" => Ok(OutputSchema::V2),
+            _ => Err(format!("unknown output schema {}, expected v1 or v2", s)),
+        }
+    }
+}
+
 impl Default for PaymentProcessor {
     fn default() -> Self {
         Self::new()
@@ -331,51 +1184,145 @@ impl fmt::Display for Transaction {
                     client_id, transaction_id
                 )
             }
-        }
-    }
-}
-
-impl Transaction {
-    #[cfg(test)]
-    fn new(
-        ty: TransactionType,
-        client_id: ClientId,
-        transaction_id: TransactionId,
-        amount: Amount,
-    ) -> Self {
-        match ty {
-            TransactionType::Deposit => Transaction::Deposit {
+            Transaction::Adjustment {
                 client_id,
                 transaction_id,
                 amount,
-            },
-            TransactionType::Withdrawal => Transaction::Withdrawal {
+                reference,
+            } => {
+                let amount_float: f64 = (*amount).into();
+                write!(
+                    f,
+                    "type: adjustment, client: {}, tx: {}, amount: {:.4}, reference: {}",
+                    client_id, transaction_id, amount_float, reference
+                )
+            }
+            Transaction::Transfer {
                 client_id,
                 transaction_id,
+                to_client_id,
                 amount,
-            },
-            TransactionType::Dispute => Transaction::Dispute {
-                client_id,
-                transaction_id,
-            },
-            TransactionType::Resolve => Transaction::Resolve {
-                client_id,
-                transaction_id,
-            },
-            TransactionType::Chargeback => Transaction::Chargeback {
+            } => {
+                let amount_float: f64 = (*amount).into();
+                write!(
+                    f,
+                    "type: transfer, client: {}, tx: {}, to: {}, amount: {:.4}",
+                    client_id, transaction_id, to_client_id, amount_float
+                )
+            }
+            Transaction::AutoChargeback {
                 client_id,
                 transaction_id,
-            },
+            } => {
+                write!(
+                    f,
+                    "type: auto_chargeback, client: {}, tx: {}",
+                    client_id, transaction_id
+                )
+            }
+        }
+    }
+}
+
+impl Transaction {
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            Transaction::Deposit { client_id, .. }
+            | Transaction::Withdrawal { client_id, .. }
+            | Transaction::Dispute { client_id, .. }
+            | Transaction::Resolve { client_id, .. }
+            | Transaction::Chargeback { client_id, .. }
+            | Transaction::Adjustment { client_id, .. }
+            | Transaction::Transfer { client_id, .. }
+            | Transaction::AutoChargeback { client_id, .. } => *client_id,
+        }
+    }
+
+    pub fn transaction_id(&self) -> TransactionId {
+        match self {
+            Transaction::Deposit { transaction_id, .. }
+            | Transaction::Withdrawal { transaction_id, .. }
+            | Transaction::Dispute { transaction_id, .. }
+            | Transaction::Resolve { transaction_id, .. }
+            | Transaction::Chargeback { transaction_id, .. }
+            | Transaction::Adjustment { transaction_id, .. }
+            | Transaction::Transfer { transaction_id, .. }
+            | Transaction::AutoChargeback { transaction_id, .. } => *transaction_id,
+        }
+    }
+
+    /// The type column as it appears in the input
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Transaction::Deposit { .. } => "deposit",
+            Transaction::Withdrawal { .. } => "withdrawal",
+            Transaction::Dispute { .. } => "dispute",
+            Transaction::Resolve { .. } => "resolve",
+            Transaction::Chargeback { .. } => "chargeback",
+            Transaction::Adjustment { amount, .. } if *amount < Amount::from_raw(0) => {
+                "adjustment_debit"
+            }
+            Transaction::Adjustment { .. } => "adjustment_credit",
+            Transaction::Transfer { .. } => "transfer",
+            Transaction::AutoChargeback { .. } => "auto_chargeback",
+        }
+    }
+
+    pub(crate) fn amount_mut(&mut self) -> Option<&mut Amount> {
+        match self {
+            Transaction::Deposit { amount, .. }
+            | Transaction::Withdrawal { amount, .. }
+            | Transaction::Adjustment { amount, .. }
+            | Transaction::Transfer { amount, .. } => Some(amount),
+            Transaction::Dispute { .. }
+            | Transaction::Resolve { .. }
+            | Transaction::Chargeback { .. }
+            | Transaction::AutoChargeback { .. } => None,
+        }
+    }
+
+    // Through the same path as a CSV row, so transfers come out as the
+    // missing recipient error. Build those directly.
+    #[cfg(test)]
+    fn new(
+        ty: TransactionType,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    ) -> Self {
+        let amount = match ty {
+            TransactionType::AdjustmentDebit => -amount,
+            _ => amount,
+        };
+        let row = TransactionRow {
+            ty,
+            client_id,
+            transaction_id,
+            amount: Some(0.0),
+            reference: Some(String::from("test")),
+            to: None,
+        };
+        let mut transaction = row
+            .into_transaction::<serde::de::value::Error>()
+            .unwrap()
+            .value;
+        if let Some(slot) = transaction.amount_mut() {
+            *slot = amount;
         }
+        transaction
     }
 }
 
 #[derive(Debug)]
 enum TransactionType {
+    AdjustmentCredit,
+    AdjustmentDebit,
+    AutoChargeback,
     Chargeback,
     Deposit,
     Dispute,
     Resolve,
+    Transfer,
     Withdrawal,
 }
 
@@ -391,6 +1338,10 @@ impl<'de> Deserialize<'de> for TransactionType {
             "dispute" => Ok(TransactionType::Dispute),
             "resolve" => Ok(TransactionType::Resolve),
             "chargeback" => Ok(TransactionType::Chargeback),
+            "adjustment_credit" => Ok(TransactionType::AdjustmentCredit),
+            "adjustment_debit" => Ok(TransactionType::AdjustmentDebit),
+            "transfer" => Ok(TransactionType::Transfer),
+            "auto_chargeback" => Ok(TransactionType::AutoChargeback),
             _ => Err(serde::de::Error::custom(format!(
                 "unknown transaction type: {}",
                 s
@@ -404,6 +1355,7 @@ impl<'de> Deserialize<'de> for TransactionType {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn test_deposit_only() {
@@ -617,7 +1569,7 @@ mod tests {
         let account = &processor.accounts[&1];
         assert_eq!(account.available_funds, Amount::from(0));
         assert_eq!(account.held_funds, Amount::from(0));
-        assert_eq!(account.is_locked, true);
+        assert!(account.is_locked);
     }
 
     #[test]
@@ -658,7 +1610,7 @@ mod tests {
         let account = &processor.accounts[&1];
         assert_eq!(account.available_funds, Amount::from(150));
         assert_eq!(account.held_funds, Amount::from(0));
-        assert_eq!(account.is_locked, true);
+        assert!(account.is_locked);
     }
 
     #[test]
@@ -691,6 +1643,122 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dispute_other_clients_transaction() {
+        let mut processor = PaymentProcessor::new();
+
+        processor.process(&Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::from(100),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::Deposit,
+            2,
+            2,
+            Amount::from(10),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::Dispute,
+            2,
+            1,
+            Amount::from(0),
+        ));
+
+        assert_eq!(processor.accounts[&1].held_funds, Amount::from(0));
+        assert_eq!(processor.accounts[&2].available_funds, Amount::from(10));
+        assert_eq!(processor.accounts[&2].held_funds, Amount::from(0));
+    }
+
+    #[test]
+    fn test_dispute_state_transitions() {
+        let mut processor = PaymentProcessor::new();
+        let listener = Arc::new(Mutex::new(RecordingListener::default()));
+        processor.add_listener(listener.clone());
+
+        processor.process(&Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::from(10),
+        ));
+        // Nothing to resolve or charge back before a dispute
+        processor.process(&Transaction::new(
+            TransactionType::Resolve,
+            1,
+            1,
+            Amount::from(0),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::Chargeback,
+            1,
+            1,
+            Amount::from(0),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::Dispute,
+            1,
+            1,
+            Amount::from(0),
+        ));
+        // Holding twice would double count
+        processor.process(&Transaction::new(
+            TransactionType::Dispute,
+            1,
+            1,
+            Amount::from(0),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::Resolve,
+            1,
+            1,
+            Amount::from(0),
+        ));
+        // Resolved transactions can be disputed again
+        processor.process(&Transaction::new(
+            TransactionType::Dispute,
+            1,
+            1,
+            Amount::from(0),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::Chargeback,
+            1,
+            1,
+            Amount::from(0),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::Dispute,
+            1,
+            1,
+            Amount::from(0),
+        ));
+
+        let account = &processor.accounts[&1];
+        assert_eq!(account.available_funds, Amount::from(0));
+        assert_eq!(account.held_funds, Amount::from(0));
+        assert!(account.is_locked);
+
+        let rejections: Vec<String> = listener
+            .lock()
+            .unwrap()
+            .events
+            .iter()
+            .filter(|event| event.starts_with("rejected"))
+            .cloned()
+            .collect();
+        assert_eq!(
+            rejections,
+            vec![
+                "rejected NotDisputed",
+                "rejected NotDisputed",
+                "rejected AlreadyDisputed",
+                "rejected AlreadyChargedBack",
+            ]
+        );
+    }
+
     // This isn't specified directly in the requirements
     // but this seems to be one of the "sensible requirements"
     // for a bank account
@@ -724,7 +1792,7 @@ mod tests {
             ));
 
             let account_before = &processor.accounts[&1];
-            assert_eq!(account_before.is_locked, true);
+            assert!(account_before.is_locked);
             let available_before = account_before.available_funds;
             let held_before = account_before.held_funds;
 
@@ -733,7 +1801,384 @@ mod tests {
             let account_after = &processor.accounts[&1];
             assert_eq!(account_after.available_funds, available_before);
             assert_eq!(account_after.held_funds, held_before);
-            assert_eq!(account_after.is_locked, true);
+            assert!(account_after.is_locked);
+        }
+    }
+
+    #[test]
+    fn test_adjustment_credit_debit() {
+        let mut processor = PaymentProcessor::new();
+
+        processor.process(&Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::from(10),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::AdjustmentCredit,
+            1,
+            2,
+            Amount::from(2.5),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::AdjustmentDebit,
+            1,
+            3,
+            Amount::from(1),
+        ));
+
+        let account = &processor.accounts[&1];
+        assert_eq!(account.available_funds, Amount::from(11.5));
+        assert_eq!(account.held_funds, Amount::from(0));
+        // Adjustments can't be disputed
+        assert!(!processor.compressed_transactions.contains_key(&2));
+    }
+
+    fn lock_account(processor: &mut PaymentProcessor, client_id: ClientId) {
+        processor.process(&Transaction::new(
+            TransactionType::Deposit,
+            client_id,
+            1,
+            Amount::from(100),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::Dispute,
+            client_id,
+            1,
+            Amount::from(0),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::Chargeback,
+            client_id,
+            1,
+            Amount::from(0),
+        ));
+    }
+
+    #[test]
+    fn test_adjustment_on_locked() {
+        let mut processor = PaymentProcessor::new();
+        lock_account(&mut processor, 1);
+
+        processor.process(&Transaction::new(
+            TransactionType::AdjustmentCredit,
+            1,
+            2,
+            Amount::from(100),
+        ));
+
+        let account = &processor.accounts[&1];
+        assert_eq!(account.available_funds, Amount::from(100));
+        assert!(account.is_locked);
+    }
+
+    #[test]
+    fn test_adjustment_on_locked_disabled() {
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            adjust_locked_accounts: false,
+            ..ProcessorConfig::default()
+        });
+        lock_account(&mut processor, 1);
+
+        processor.process(&Transaction::new(
+            TransactionType::AdjustmentCredit,
+            1,
+            2,
+            Amount::from(100),
+        ));
+
+        let account = &processor.accounts[&1];
+        assert_eq!(account.available_funds, Amount::from(0));
+        assert!(account.is_locked);
+    }
+
+    #[derive(Default)]
+    struct RecordingListener {
+        events: Vec<String>,
+    }
+
+    impl EventListener for RecordingListener {
+        fn on_applied(&mut self, transaction: &Transaction) {
+            self.events.push(format!("applied {}", transaction));
+        }
+
+        fn on_rejected(&mut self, _transaction: &Transaction, reason: RejectionReason) {
+            self.events.push(format!("rejected {:?}", reason));
+        }
+
+        fn on_dispute_opened(
+            &mut self,
+            client_id: ClientId,
+            transaction_id: TransactionId,
+            _: Amount,
+        ) {
+            self.events
+                .push(format!("opened {} {}", client_id, transaction_id));
+        }
+
+        fn on_charged_back(
+            &mut self,
+            client_id: ClientId,
+            transaction_id: TransactionId,
+            _: Amount,
+        ) {
+            self.events
+                .push(format!("charged back {} {}", client_id, transaction_id));
+        }
+
+        fn on_account_locked(&mut self, client_id: ClientId, _: &Transaction, _: &Account) {
+            self.events.push(format!("locked {}", client_id));
+        }
+    }
+
+    #[test]
+    fn test_listener_events() {
+        let listener = Arc::new(Mutex::new(RecordingListener::default()));
+        let mut processor = PaymentProcessor::new();
+        processor.add_listener(listener.clone());
+
+        lock_account(&mut processor, 1);
+        processor.process(&Transaction::new(
+            TransactionType::Deposit,
+            1,
+            2,
+            Amount::from(5),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::Withdrawal,
+            2,
+            3,
+            Amount::from(5),
+        ));
+        processor.process(&Transaction::new(
+            TransactionType::Resolve,
+            2,
+            999,
+            Amount::from(0),
+        ));
+
+        assert_eq!(
+            listener.lock().unwrap().events,
+            vec![
+                "applied type: deposit, client: 1, tx: 1, amount: 100.0000",
+                "applied type: dispute, client: 1, tx: 1",
+                "opened 1 1",
+                "applied type: chargeback, client: 1, tx: 1",
+                "charged back 1 1",
+                "locked 1",
+                "rejected AccountLocked",
+                "rejected InsufficientFunds",
+                "rejected UnknownTransaction",
+            ]
+        );
+    }
+
+    fn transfer(
+        transaction_id: TransactionId,
+        from: ClientId,
+        to: ClientId,
+        amount: f64,
+    ) -> Transaction {
+        Transaction::Transfer {
+            client_id: from,
+            transaction_id,
+            to_client_id: to,
+            amount: Amount::from(amount),
+        }
+    }
+
+    fn results(processor: &mut PaymentProcessor, transactions: &[Transaction]) -> Vec<String> {
+        let results = Arc::new(Mutex::new(Vec::new()));
+        struct Collect(Arc<Mutex<Vec<String>>>);
+        impl EventListener for Collect {
+            fn on_result(&mut self, _transaction: &Transaction, result: &RowResult) {
+                self.0.lock().unwrap().push(result.to_string());
+            }
+        }
+        processor.add_listener(Collect(results.clone()));
+        for transaction in transactions {
+            processor.process(transaction);
+        }
+        processor.listeners.clear();
+        Arc::try_unwrap(results).unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut processor = PaymentProcessor::new();
+        lock_account(&mut processor, 3);
+        processor.process(&Transaction::new(
+            TransactionType::Deposit,
+            1,
+            10,
+            Amount::from(10),
+        ));
+        let codes = results(
+            &mut processor,
+            &[
+                transfer(11, 1, 2, 4.0),
+                transfer(12, 1, 2, 7.0),
+                transfer(13, 1, 3, 1.0),
+                transfer(14, 3, 1, 1.0),
+            ],
+        );
+
+        assert_eq!(
+            codes,
+            vec![
+                "TRANSFER_DEBIT_OK/CREDIT_OK",
+                "TRANSFER_DEBIT_REJECTED_INSUFFICIENT_FUNDS",
+                "TRANSFER_DEBIT_OK/CREDIT_REJECTED_LOCKED",
+                "TRANSFER_DEBIT_REJECTED_LOCKED",
+            ]
+        );
+        // The debit of the transfer to the locked account got rolled back
+        assert_eq!(processor.accounts[&1].available(), Amount::from(6));
+        assert_eq!(processor.accounts[&2].available(), Amount::from(4));
+        assert_eq!(processor.accounts[&3].total(), Amount::from(0));
+        // Not stored, so not disputable
+        assert_eq!(
+            processor.try_process(&Transaction::new(
+                TransactionType::Dispute,
+                1,
+                11,
+                Amount::from(0)
+            )),
+            Err(RejectionReason::UnknownTransaction)
+        );
+    }
+
+    #[test]
+    fn test_transfer_amount_has_to_be_positive() {
+        let mut processor = PaymentProcessor::new();
+        for (client_id, transaction_id) in [(1, 1), (2, 2)] {
+            processor.process(&Transaction::new(
+                TransactionType::Deposit,
+                client_id,
+                transaction_id,
+                Amount::from(10),
+            ));
+        }
+        let codes = results(
+            &mut processor,
+            &[transfer(3, 1, 2, -5.0), transfer(4, 1, 2, 0.0)],
+        );
+
+        assert_eq!(
+            codes,
+            vec![
+                "TRANSFER_DEBIT_REJECTED_NON_POSITIVE_AMOUNT",
+                "TRANSFER_DEBIT_REJECTED_NON_POSITIVE_AMOUNT",
+            ]
+        );
+        assert_eq!(processor.accounts[&1].total(), Amount::from(10));
+        assert_eq!(processor.accounts[&2].total(), Amount::from(10));
+    }
+
+    #[test]
+    fn test_transfer_across_shards() {
+        let mut shards = PaymentProcessor::new().into_shards(2);
+        let codes = results(
+            &mut shards[1],
+            &[
+                Transaction::new(TransactionType::Deposit, 1, 1, Amount::from(5)),
+                transfer(2, 1, 2, 1.0),
+                transfer(3, 1, 3, 1.0),
+            ],
+        );
+
+        assert_eq!(
+            codes,
+            vec![
+                "OK",
+                "TRANSFER_DEBIT_OK/CREDIT_REJECTED_CROSS_SHARD",
+                "TRANSFER_DEBIT_OK/CREDIT_OK",
+            ]
+        );
+        assert!(!shards[1].accounts.contains_key(&2));
+        assert_eq!(shards[1].accounts[&1].available(), Amount::from(4));
+    }
+
+    #[test]
+    fn test_auto_chargeback() {
+        let listener = Arc::new(Mutex::new(RecordingListener::default()));
+        let mut processor = PaymentProcessor::new();
+        processor.process(&Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::from(10),
+        ));
+        processor.add_listener(listener.clone());
+        let auto_chargeback =
+            Transaction::new(TransactionType::AutoChargeback, 1, 1, Amount::from(0));
+        let codes = results(&mut processor, &[auto_chargeback.clone(), auto_chargeback]);
+
+        assert_eq!(
+            codes,
+            vec![
+                "AUTO_CHARGEBACK_DISPUTE_OK/CHARGEBACK_OK",
+                "AUTO_CHARGEBACK_DISPUTE_REJECTED_ALREADY_CHARGED_BACK",
+            ]
+        );
+        assert_eq!(
+            listener.lock().unwrap().events,
+            vec![
+                "applied type: auto_chargeback, client: 1, tx: 1",
+                "opened 1 1",
+                "charged back 1 1",
+                "locked 1",
+                "rejected AlreadyChargedBack",
+            ]
+        );
+        assert!(processor.accounts[&1].is_locked());
+        assert_eq!(processor.accounts[&1].total(), Amount::from(0));
+    }
+
+    #[test]
+    fn test_output_schemas() {
+        let mut tiers = Tiers::new(
+            [(String::from("basic"), Default::default())]
+                .into_iter()
+                .collect(),
+        );
+        tiers.assign(1, "basic").unwrap();
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            tiers: Arc::new(tiers),
+            ..ProcessorConfig::default()
+        });
+        processor.process(&Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::from(1.5),
+        ));
+        for ty in [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Dispute,
+            TransactionType::Chargeback,
+        ] {
+            processor.process(&Transaction::new(ty, 1, 1, Amount::from(0)));
         }
+
+        let write = |schema| {
+            let mut csv = Vec::new();
+            processor
+                .write_csv_with_schema(&mut csv, schema, |_, _| true)
+                .unwrap();
+            String::from_utf8(csv).unwrap()
+        };
+        assert_eq!(
+            write(OutputSchema::V1),
+            "client,available,held,total,locked\n1,0.0,0.0,0.0,true\n"
+        );
+        assert_eq!(
+            write(OutputSchema::V2),
+            "client,available,held,total,locked,tier,open_disputes,total_disputes,chargebacks\n\
+             1,0.0000,0.0000,0.0000,true,basic,0,2,1\n"
+        );
+        assert_eq!("v2".parse(), Ok(OutputSchema::V2));
     }
 }
@@ -0,0 +1,494 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use super::amount::Amount;
+use super::{Account, ClientId, PaymentProcessor};
+
+/// An account column a query can look at or a report can show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportField {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    DisputeCount,
+    TotalDisputes,
+    Chargebacks,
+}
+
+impl ReportField {
+    pub const ALL: [ReportField; 8] = [
+        ReportField::Client,
+        ReportField::Available,
+        ReportField::Held,
+        ReportField::Total,
+        ReportField::Locked,
+        ReportField::DisputeCount,
+        ReportField::TotalDisputes,
+        ReportField::Chargebacks,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ReportField::Client => "client",
+            ReportField::Available => "available",
+            ReportField::Held => "held",
+            ReportField::Total => "total",
+            ReportField::Locked => "locked",
+            ReportField::DisputeCount => "dispute_count",
+            ReportField::TotalDisputes => "total_disputes",
+            ReportField::Chargebacks => "chargebacks",
+        }
+    }
+
+    fn value(self, client_id: ClientId, account: &Account) -> Value {
+        let count = |count: u32| Value::Number(Amount::from(u64::from(count)));
+        match self {
+            ReportField::Client => Value::Number(Amount::from(u64::from(client_id))),
+            ReportField::Available => Value::Number(account.available()),
+            ReportField::Held => Value::Number(account.held()),
+            ReportField::Total => Value::Number(account.total()),
+            ReportField::Locked => Value::Bool(account.is_locked()),
+            ReportField::DisputeCount => count(account.open_disputes()),
+            ReportField::TotalDisputes => count(account.total_disputes()),
+            ReportField::Chargebacks => count(account.chargebacks()),
+        }
+    }
+
+    fn is_bool(self) -> bool {
+        self == ReportField::Locked
+    }
+
+    // Same text as the balances CSV, counts without decimals
+    fn format(self, client_id: ClientId, account: &Account) -> String {
+        match self {
+            ReportField::Client => client_id.to_string(),
+            ReportField::DisputeCount => account.open_disputes().to_string(),
+            ReportField::TotalDisputes => account.total_disputes().to_string(),
+            ReportField::Chargebacks => account.chargebacks().to_string(),
+            _ => self.value(client_id, account).to_string(),
+        }
+    }
+}
+
+impl FromStr for ReportField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ReportField::ALL
+            .into_iter()
+            .find(|field| field.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = ReportField::ALL.iter().map(|field| field.name()).collect();
+                format!("unknown field {}, expected one of {}", s, names.join(", "))
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
+enum Value {
+    Number(Amount),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(amount) => write!(f, "{}", amount),
+            Value::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    ReportField(ReportField),
+    Literal(Value),
+}
+
+impl Operand {
+    fn is_bool(&self) -> bool {
+        match self {
+            Operand::ReportField(field) => field.is_bool(),
+            Operand::Literal(value) => matches!(value, Value::Bool(_)),
+        }
+    }
+
+    fn value(&self, client_id: ClientId, account: &Account) -> Value {
+        match self {
+            Operand::ReportField(field) => field.value(client_id, account),
+            Operand::Literal(value) => *value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Comparison, Operand),
+    // A bare bool field or literal, e.g. `locked`
+    Is(Operand),
+}
+
+impl Expr {
+    fn matches(&self, client_id: ClientId, account: &Account) -> bool {
+        match self {
+            Expr::Or(left, right) => {
+                left.matches(client_id, account) || right.matches(client_id, account)
+            }
+            Expr::And(left, right) => {
+                left.matches(client_id, account) && right.matches(client_id, account)
+            }
+            Expr::Not(expr) => !expr.matches(client_id, account),
+            Expr::Compare(left, comparison, right) => {
+                let (left, right) = (
+                    left.value(client_id, account),
+                    right.value(client_id, account),
+                );
+                match comparison {
+                    Comparison::Eq => left == right,
+                    Comparison::Ne => left != right,
+                    Comparison::Lt => left < right,
+                    Comparison::Le => left <= right,
+                    Comparison::Gt => left > right,
+                    Comparison::Ge => left >= right,
+                }
+            }
+            Expr::Is(operand) => operand.value(client_id, account) == Value::Bool(true),
+        }
+    }
+}
+
+/// A filter over the final accounts, like `total > 1000 && locked == true`.
+/// Fields are compared with numbers or `true`/`false`, and combined with
+/// `&&`, `||`, `!` and parentheses. `&&` binds tighter than `||`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    expr: Expr,
+}
+
+impl Query {
+    pub fn matches(&self, client_id: ClientId, account: &Account) -> bool {
+        self.expr.matches(client_id, account)
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(Query { expr }),
+            Some(token) => Err(format!("unexpected {} in query", token)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Number(Amount),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Number(amount) => write!(f, "'{}'", amount),
+            Token::Op(op) => write!(f, "'{}'", op),
+        }
+    }
+}
+
+// Longest first, so `<=` isn't read as `<` then `=`
+const OPS: [&str; 11] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")"];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPS.into_iter().find(|op| rest.starts_with(op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                let c = rest.chars().next().unwrap_or_default();
+                return Err(format!("unexpected '{}' in query", c));
+            }
+            let word = &rest[..end];
+            if word.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') {
+                let number: f64 = word
+                    .parse()
+                    .map_err(|_| format!("bad number '{}' in query", word))?;
+                tokens.push(Token::Number(Amount::from(number)));
+            } else {
+                tokens.push(Token::Word(word.to_string()));
+            }
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or("query ends too early")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Op(next)) if *next == op);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                return Err("missing ')' in query".to_string());
+            }
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.operand()?;
+        let comparison = match self.peek() {
+            Some(Token::Op("==")) => Comparison::Eq,
+            Some(Token::Op("!=")) => Comparison::Ne,
+            Some(Token::Op("<")) => Comparison::Lt,
+            Some(Token::Op("<=")) => Comparison::Le,
+            Some(Token::Op(">")) => Comparison::Gt,
+            Some(Token::Op(">=")) => Comparison::Ge,
+            _ if left.is_bool() => return Ok(Expr::Is(left)),
+            _ => return Err(format!("{} needs comparing to something", describe(&left))),
+        };
+        self.position += 1;
+        let right = self.operand()?;
+        if left.is_bool() != right.is_bool() {
+            return Err(format!(
+                "can't compare {} with {}",
+                describe(&left),
+                describe(&right)
+            ));
+        }
+        if left.is_bool() && !matches!(comparison, Comparison::Eq | Comparison::Ne) {
+            return Err("true/false can only be compared with == or !=".to_string());
+        }
+        Ok(Expr::Compare(left, comparison, right))
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next()? {
+            Token::Number(amount) => Ok(Operand::Literal(Value::Number(amount))),
+            Token::Word(word) => match word.as_str() {
+                "true" => Ok(Operand::Literal(Value::Bool(true))),
+                "false" => Ok(Operand::Literal(Value::Bool(false))),
+                _ => Ok(Operand::ReportField(word.parse()?)),
+            },
+            Token::Op(op) => Err(format!("unexpected '{}' in query", op)),
+        }
+    }
+}
+
+fn describe(operand: &Operand) -> String {
+    match operand {
+        Operand::ReportField(field) => field.name().to_string(),
+        Operand::Literal(value) => value.to_string(),
+    }
+}
+
+/// CSV of the given fields for every account the query matches (all of
+/// them without one), by client ID
+pub fn write_report(
+    out: &mut impl Write,
+    processor: &PaymentProcessor,
+    query: Option<&Query>,
+    fields: &[ReportField],
+) -> io::Result<usize> {
+    let header: Vec<_> = fields.iter().map(|field| field.name()).collect();
+    writeln!(out, "{}", header.join(","))?;
+    let mut client_ids: Vec<_> = processor.accounts().keys().copied().collect();
+    client_ids.sort_unstable();
+    let mut rows = 0;
+    for client_id in client_ids {
+        let account = &processor.accounts()[&client_id];
+        if query.is_some_and(|query| !query.matches(client_id, account)) {
+            continue;
+        }
+        let row: Vec<_> = fields
+            .iter()
+            .map(|field| field.format(client_id, account))
+            .collect();
+        writeln!(out, "{}", row.join(","))?;
+        rows += 1;
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::Transaction;
+
+    fn processor() -> PaymentProcessor {
+        let mut processor = PaymentProcessor::new();
+        for (client_id, amount) in [(1, 500), (2, 1500), (3, 2000)] {
+            processor.process(&Transaction::Deposit {
+                client_id,
+                transaction_id: u32::from(client_id),
+                amount: Amount::from(amount),
+            });
+        }
+        processor.process(&Transaction::Dispute {
+            client_id: 2,
+            transaction_id: 2,
+        });
+        processor.process(&Transaction::Dispute {
+            client_id: 3,
+            transaction_id: 3,
+        });
+        processor.process(&Transaction::Chargeback {
+            client_id: 3,
+            transaction_id: 3,
+        });
+        processor
+    }
+
+    fn clients(processor: &PaymentProcessor, query: &str) -> Vec<ClientId> {
+        let query: Query = query.parse().unwrap();
+        let mut client_ids: Vec<_> = processor
+            .accounts()
+            .iter()
+            .filter(|(client_id, account)| query.matches(**client_id, account))
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        client_ids.sort_unstable();
+        client_ids
+    }
+
+    #[test]
+    fn test_query() {
+        let processor = processor();
+        assert_eq!(clients(&processor, "total > 1000"), [2]);
+        assert_eq!(clients(&processor, "total >= 0 && locked == true"), [3]);
+        assert_eq!(clients(&processor, "locked || held > 0"), [2, 3]);
+        assert_eq!(clients(&processor, "!locked && !(dispute_count == 1)"), [1]);
+        assert_eq!(
+            clients(&processor, "total_disputes >= 1 && chargebacks == 0"),
+            [2]
+        );
+        assert_eq!(
+            clients(&processor, "available<1000.5||client!=2&&locked"),
+            [1, 2, 3]
+        );
+        assert_eq!(
+            clients(&processor, "(available < 1000.5 || client != 2) && locked"),
+            [3]
+        );
+        assert_eq!(
+            clients(&processor, "available > -0.0001 && 500 == total"),
+            [1]
+        );
+
+        for (query, error) in [
+            ("total > ", "query ends too early"),
+            ("balance > 1", "unknown field balance"),
+            ("locked > false", "only be compared with == or !="),
+            ("total == true", "can't compare total with true"),
+            ("held", "held needs comparing"),
+            ("(locked", "missing ')'"),
+            ("locked locked", "unexpected 'locked'"),
+            ("total > 1 & locked", "unexpected '&'"),
+        ] {
+            let err = query.parse::<Query>().unwrap_err();
+            assert!(err.contains(error), "{}: {}", query, err);
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let processor = processor();
+        let mut csv = Vec::new();
+        let query = "dispute_count > 0 || locked".parse().unwrap();
+        let rows = write_report(
+            &mut csv,
+            &processor,
+            Some(&query),
+            &[
+                ReportField::Client,
+                ReportField::Total,
+                ReportField::DisputeCount,
+            ],
+        )
+        .unwrap();
+        assert_eq!(rows, 2);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "client,total,dispute_count\n\
+             2,1500.0000,1\n\
+             3,0.0000,0\n"
+        );
+    }
+}
@@ -1,24 +1,258 @@
-use std::{fs::File, path::PathBuf};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, Read},
+    path::PathBuf,
+};
 
-use super::Transaction;
-use csv::{DeserializeRecordsIter, Reader, ReaderBuilder};
+use super::{RoundingMode, Transaction, Unrounded};
+use csv::{DeserializeRecordsIter, Reader, ReaderBuilder, StringRecord};
 
-pub struct TransactionReader {
-    reader: Reader<File>,
+/// Column order assumed for files without a header row
+pub const DEFAULT_COLUMNS: [&str; 6] = ["type", "client", "tx", "amount", "reference", "to"];
+
+/// How a feed's CSV is laid out, shared by all the readers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    /// Lines starting with this are skipped
+    pub comment: Option<u8>,
+    /// Without a header row, the columns are DEFAULT_COLUMNS in that order
+    pub has_headers: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            comment: None,
+            has_headers: true,
+        }
+    }
+}
+
+impl CsvDialect {
+    pub(crate) fn builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .comment(self.comment)
+            .flexible(true);
+        builder
+    }
+
+    /// Headers are set up front for headerless files, so csv doesn't
+    /// take the first row for them
+    pub(crate) fn open<R: Read>(&self, builder: &ReaderBuilder, reader: R) -> Reader<R> {
+        let mut reader = builder.from_reader(reader);
+        if !self.has_headers {
+            reader.set_headers(StringRecord::from(DEFAULT_COLUMNS.to_vec()));
+        }
+        reader
+    }
+}
+
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// Whichever of `,` `;` tab and `|` shows up most in the line. Ties
+/// (including none showing up at all) go to the one listed first.
+pub fn detect_delimiter(line: &[u8]) -> u8 {
+    DELIMITERS
+        .iter()
+        .rev()
+        .max_by_key(|delimiter| line.iter().filter(|byte| byte == delimiter).count())
+        .copied()
+        .unwrap_or(b',')
+}
+
+/// Detects the delimiter from the first line that isn't a comment, without
+/// consuming anything. Only looks at what's already buffered, which covers
+/// any sane header line.
+pub fn sniff_delimiter(input: &mut impl BufRead, comment: Option<u8>) -> io::Result<u8> {
+    let buffer = input.fill_buf()?;
+    let line = buffer
+        .split(|byte| *byte == b'\n')
+        .find(|line| comment.is_none_or(|comment| line.first() != Some(&comment)))
+        .unwrap_or_default();
+    Ok(detect_delimiter(line))
+}
+
+pub struct TransactionReader<R = File> {
+    reader: Reader<R>,
+    rounding: RoundingMode,
 }
 
 impl TransactionReader {
     pub fn from_path(path: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
-        let reader = ReaderBuilder::new()
-            .flexible(true)
-            .trim(csv::Trim::All)
-            .from_path(path)?;
+        Ok(Self::from_reader(File::open(path)?))
+    }
+}
+
+impl<R: Read> TransactionReader<R> {
+    pub fn from_reader(reader: R) -> Self {
+        Self::with_dialect(reader, &CsvDialect::default())
+    }
+
+    pub fn with_dialect(reader: R, dialect: &CsvDialect) -> Self {
+        let mut builder = dialect.builder();
+        builder.trim(csv::Trim::All);
+
+        Self {
+            reader: dialect.open(&builder, reader),
+            rounding: RoundingMode::default(),
+        }
+    }
 
-        Ok(Self { reader })
+    /// How amounts with more than 4 decimals get cut down, truncated by default
+    pub fn with_rounding(mut self, rounding: RoundingMode) -> Self {
+        self.rounding = rounding;
+        self
     }
 
     // Expose an iter() here so we can stream CSV records
-    pub fn iter(&mut self) -> DeserializeRecordsIter<'_, File, Transaction> {
+    pub fn iter(&mut self) -> impl Iterator<Item = csv::Result<Transaction>> + '_ {
+        let rounding = self.rounding;
+        self.iter_unrounded()
+            .map(move |result| result.map(|transaction| transaction.round(rounding)))
+    }
+
+    /// For rounding the same input more than one way, see Unrounded
+    pub fn iter_unrounded(&mut self) -> DeserializeRecordsIter<'_, R, Unrounded<Transaction>> {
         self.reader.deserialize()
     }
 }
+
+/// Errors from the readers that don't go through serde, with the position
+/// of the offending record whenever it's known
+#[derive(Debug)]
+pub enum ParseError {
+    Csv(csv::Error),
+    Record {
+        position: csv::Position,
+        message: String,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Csv(err) => write!(f, "{}", err),
+            ParseError::Record { position, message } => write!(
+                f,
+                "record {} (line: {}, byte: {}): {}",
+                position.record(),
+                position.line(),
+                position.byte(),
+                message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<csv::Error> for ParseError {
+    fn from(err: csv::Error) -> Self {
+        ParseError::Csv(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_delimiter() {
+        assert_eq!(detect_delimiter(b"type,client,tx,amount"), b',');
+        assert_eq!(detect_delimiter(b"type;client;tx;amount"), b';');
+        assert_eq!(detect_delimiter(b"type\tclient\ttx\tamount"), b'\t');
+        assert_eq!(detect_delimiter(b"deposit|1|1|\"1,5\""), b'|');
+        assert_eq!(detect_delimiter(b"type"), b',');
+
+        let mut input = "# exported 2024-01-01\ntype;client;tx;amount\n".as_bytes();
+        assert_eq!(sniff_delimiter(&mut input, Some(b'#')).unwrap(), b';');
+        assert_eq!(sniff_delimiter(&mut input, None).unwrap(), b',');
+    }
+
+    #[test]
+    fn test_dialect() {
+        let dialect = CsvDialect {
+            delimiter: b';',
+            quote: b'\'',
+            comment: Some(b'#'),
+            has_headers: false,
+        };
+        let input = "# no header\ndeposit;1;1;'2.5'\n#withdrawal;1;2;1.0\ndispute;1;1;\n";
+        let transactions: Vec<String> = TransactionReader::with_dialect(input.as_bytes(), &dialect)
+            .iter()
+            .map(|result| result.unwrap().to_string())
+            .collect();
+
+        assert_eq!(
+            transactions,
+            vec![
+                "type: deposit, client: 1, tx: 1, amount: 2.5000",
+                "type: dispute, client: 1, tx: 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_readers_round_the_same() {
+        use crate::toy_payments::{ChunkedTransactionReader, FastTransactionReader};
+
+        let input = "type,client,tx,amount,reference\n\
+                     deposit,1,1,1.00005,\n\
+                     adjustment_debit,1,2,0.00015,INC-1\n\
+                     withdrawal,1,3,,\n";
+        for (rounding, deposit, debit) in [
+            (RoundingMode::Truncate, "1.0000", "-0.0001"),
+            (RoundingMode::HalfUp, "1.0001", "-0.0002"),
+            (RoundingMode::HalfEven, "1.0000", "-0.0002"),
+        ] {
+            let expected = vec![
+                Ok(format!(
+                    "type: deposit, client: 1, tx: 1, amount: {}",
+                    deposit
+                )),
+                Ok(format!(
+                    "type: adjustment, client: 1, tx: 2, amount: {}, reference: INC-1",
+                    debit
+                )),
+                Err(()),
+            ];
+            let shown = |result: Result<Transaction, String>| {
+                result
+                    .map(|transaction| transaction.to_string())
+                    .map_err(|err| assert!(err.contains("missing amount"), "{}", err))
+            };
+
+            let serde: Vec<_> = TransactionReader::from_reader(input.as_bytes())
+                .with_rounding(rounding)
+                .iter()
+                .map(|result| shown(result.map_err(|err| err.to_string())))
+                .collect();
+            assert_eq!(serde, expected, "{:?}", rounding);
+            let fast: Vec<_> = FastTransactionReader::from_reader(input.as_bytes())
+                .unwrap()
+                .with_rounding(rounding)
+                .map(|result| shown(result.map_err(|err| err.to_string())))
+                .collect();
+            assert_eq!(fast, expected, "{:?} fast", rounding);
+            for fast_parse in [false, true] {
+                let chunked: Vec<_> = ChunkedTransactionReader::from_reader(input.as_bytes())
+                    .unwrap()
+                    .with_fast_parse(fast_parse)
+                    .unwrap()
+                    .with_rounding(rounding)
+                    .flatten()
+                    .map(|result| shown(result.map_err(|err| err.to_string())))
+                    .collect();
+                assert_eq!(chunked, expected, "{:?} chunked", rounding);
+            }
+        }
+    }
+}
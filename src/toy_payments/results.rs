@@ -0,0 +1,167 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use super::Transaction;
+use super::events::{EventListener, RejectionReason};
+
+/// One part of a multi-part transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Debit,
+    Credit,
+    Dispute,
+    Chargeback,
+}
+
+impl Step {
+    fn code(&self) -> &'static str {
+        match self {
+            Step::Debit => "DEBIT",
+            Step::Credit => "CREDIT",
+            Step::Dispute => "DISPUTE",
+            Step::Chargeback => "CHARGEBACK",
+        }
+    }
+}
+
+/// A step and how it went, `None` if an earlier step failed so it was
+/// never tried
+pub type StepResult = (Step, Option<Result<(), RejectionReason>>);
+
+/// What happened to a single row. Multi-part transactions are all or
+/// nothing: the first rejected step stops the rest and rolls back the ones
+/// before it, so a rejected step always means the whole row was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowResult {
+    Applied,
+    Rejected(RejectionReason),
+    Composite {
+        kind: &'static str,
+        steps: [StepResult; 2],
+    },
+}
+
+impl RowResult {
+    pub(crate) fn composite(
+        kind: &'static str,
+        first: (Step, Result<(), RejectionReason>),
+        second: (Step, Option<Result<(), RejectionReason>>),
+    ) -> Self {
+        RowResult::Composite {
+            kind,
+            steps: [(first.0, Some(first.1)), second],
+        }
+    }
+
+    pub fn is_applied(&self) -> bool {
+        match self {
+            RowResult::Applied => true,
+            RowResult::Rejected(_) => false,
+            RowResult::Composite { steps, .. } => {
+                steps.iter().all(|(_, result)| result == &Some(Ok(())))
+            }
+        }
+    }
+}
+
+/// The result code, e.g. `OK`, `REJECTED_LOCKED` or
+/// `TRANSFER_DEBIT_OK/CREDIT_REJECTED_LOCKED`
+impl fmt::Display for RowResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowResult::Applied => write!(f, "OK"),
+            RowResult::Rejected(reason) => write!(f, "REJECTED_{}", reason.code()),
+            RowResult::Composite { kind, steps } => {
+                write!(f, "{}_", kind)?;
+                let tried = steps
+                    .iter()
+                    .filter_map(|(step, result)| result.map(|result| (step, result)));
+                for (index, (step, result)) in tried.enumerate() {
+                    if index > 0 {
+                        write!(f, "/")?;
+                    }
+                    match result {
+                        Ok(()) => write!(f, "{}_OK", step.code())?,
+                        Err(reason) => write!(f, "{}_REJECTED_{}", step.code(), reason.code())?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Writes the result code of every processed row as CSV
+pub struct ResultsWriter<W: Write + Send> {
+    writer: W,
+}
+
+impl<W: Write + Send> ResultsWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "type,client,tx,result")?;
+        Ok(Self { writer })
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: Write + Send> EventListener for ResultsWriter<W> {
+    fn on_result(&mut self, transaction: &Transaction, result: &RowResult) {
+        let written = writeln!(
+            self.writer,
+            "{},{},{},{}",
+            transaction.type_name(),
+            transaction.client_id(),
+            transaction.transaction_id(),
+            result
+        );
+        if let Err(err) = written {
+            eprintln!("Error writing results: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(RowResult::Applied.to_string(), "OK");
+        assert_eq!(
+            RowResult::Rejected(RejectionReason::InsufficientFunds).to_string(),
+            "REJECTED_INSUFFICIENT_FUNDS"
+        );
+        let rolled_back = RowResult::composite(
+            "TRANSFER",
+            (Step::Debit, Ok(())),
+            (Step::Credit, Some(Err(RejectionReason::AccountLocked))),
+        );
+        assert_eq!(
+            rolled_back.to_string(),
+            "TRANSFER_DEBIT_OK/CREDIT_REJECTED_LOCKED"
+        );
+        assert!(!rolled_back.is_applied());
+        let stopped = RowResult::composite(
+            "TRANSFER",
+            (Step::Debit, Err(RejectionReason::InsufficientFunds)),
+            (Step::Credit, None),
+        );
+        assert_eq!(
+            stopped.to_string(),
+            "TRANSFER_DEBIT_REJECTED_INSUFFICIENT_FUNDS"
+        );
+        let applied = RowResult::composite(
+            "AUTO_CHARGEBACK",
+            (Step::Dispute, Ok(())),
+            (Step::Chargeback, Some(Ok(()))),
+        );
+        assert_eq!(
+            applied.to_string(),
+            "AUTO_CHARGEBACK_DISPUTE_OK/CHARGEBACK_OK"
+        );
+        assert!(applied.is_applied());
+    }
+}
@@ -0,0 +1,368 @@
+use std::error::Error;
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::amount::Amount;
+use super::events::{EventListener, RejectionReason};
+use super::{
+    Account, ClientId, DisputeState, OperatorAction, PaymentProcessor, Transaction, TransactionId,
+    deserialize_amount, serialize_amount,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// A locked account waiting for someone to look at it, with the balances
+/// as they were right after it locked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReviewItem {
+    pub id: u64,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    /// The transaction that locked it: a chargeback, or the dispute that
+    /// froze the account by going over the dispute cap
+    #[serde(rename = "tx")]
+    pub transaction_id: TransactionId,
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_required_amount"
+    )]
+    pub available: Amount,
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_required_amount"
+    )]
+    pub held: Amount,
+    pub status: ReviewStatus,
+    /// Ticket or note from whoever decided, empty while pending
+    #[serde(default)]
+    pub reference: String,
+}
+
+fn deserialize_required_amount<'de, D>(deserializer: D) -> Result<Amount, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_amount(deserializer)?.ok_or_else(|| serde::de::Error::custom("missing amount"))
+}
+
+/// Locked accounts waiting for review, kept as a CSV file between runs.
+/// As a listener it queues every account that locks. Decisions go through
+/// the processor as operator actions, so they end up in the audit log like
+/// any other correction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReviewQueue {
+    items: Vec<ReviewItem>,
+}
+
+impl ReviewQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(reader: impl Read) -> Result<Self, Box<dyn Error>> {
+        let items = csv::Reader::from_reader(reader)
+            .deserialize()
+            .collect::<Result<_, _>>()?;
+        Ok(Self { items })
+    }
+
+    pub fn write(&self, writer: impl Write) -> Result<(), Box<dyn Error>> {
+        write_items(writer, self.items.iter())
+    }
+
+    /// Like `write`, but only the items still waiting for a decision
+    pub fn write_pending(&self, writer: impl Write) -> Result<(), Box<dyn Error>> {
+        write_items(writer, self.pending())
+    }
+
+    pub fn items(&self) -> &[ReviewItem] {
+        &self.items
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &ReviewItem> {
+        self.items
+            .iter()
+            .filter(|item| item.status == ReviewStatus::Pending)
+    }
+
+    /// Moves a client's items over to the pseudonym it was erased to, so
+    /// the queue still matches the state. Returns how many there were.
+    pub fn erase_client(&mut self, client_id: ClientId, pseudonym: ClientId) -> usize {
+        let mut moved = 0;
+        for item in &mut self.items {
+            if item.client_id == client_id {
+                item.client_id = pseudonym;
+                moved += 1;
+            }
+        }
+        moved
+    }
+
+    /// Unlocks the account, after reversing the chargeback that locked it
+    /// if `reverse_chargeback` is set. Items for a freeze have no chargeback
+    /// to reverse, so that's an error for them. Nothing changes unless all
+    /// of it goes through.
+    pub fn approve(
+        &mut self,
+        id: u64,
+        processor: &mut PaymentProcessor,
+        reference: &str,
+        reverse_chargeback: bool,
+    ) -> Result<(), String> {
+        let item = self.pending_item(id)?;
+        let (client_id, transaction_id) = (item.client_id, item.transaction_id);
+
+        // Checked up front, so a failed unlock can't leave a reversal behind
+        match processor.accounts().get(&client_id) {
+            Some(account) if account.is_locked() => {}
+            Some(_) => return Err(rejected(RejectionReason::NotLocked)),
+            None => return Err(rejected(RejectionReason::UnknownClient)),
+        }
+        let charged_back = processor
+            .transactions()
+            .get(&transaction_id)
+            .is_some_and(|stored| {
+                stored.client_id == client_id && stored.state == DisputeState::ChargedBack
+            });
+        if reverse_chargeback && !charged_back {
+            return Err(format!(
+                "review {} wasn't locked by a chargeback (tx {} isn't charged back), \
+                 approve it without reversing",
+                id, transaction_id
+            ));
+        }
+        if reverse_chargeback {
+            processor
+                .apply_action(&OperatorAction::ReverseChargeback {
+                    client_id,
+                    transaction_id,
+                    reference: reference.to_string(),
+                })
+                .map_err(rejected)?;
+        }
+        processor
+            .apply_action(&OperatorAction::Unlock {
+                client_id,
+                reference: reference.to_string(),
+            })
+            .map_err(rejected)?;
+        self.decide(id, ReviewStatus::Approved, reference);
+        Ok(())
+    }
+
+    /// Leaves the account locked and takes it off the queue
+    pub fn reject(
+        &mut self,
+        id: u64,
+        processor: &mut PaymentProcessor,
+        reference: &str,
+    ) -> Result<(), String> {
+        let client_id = self.pending_item(id)?.client_id;
+        processor
+            .apply_action(&OperatorAction::KeepLocked {
+                client_id,
+                reference: reference.to_string(),
+            })
+            .map_err(rejected)?;
+        self.decide(id, ReviewStatus::Rejected, reference);
+        Ok(())
+    }
+
+    fn pending_item(&self, id: u64) -> Result<&ReviewItem, String> {
+        match self.items.iter().find(|item| item.id == id) {
+            Some(item) if item.status == ReviewStatus::Pending => Ok(item),
+            Some(item) => Err(format!("review {} was already {:?}", id, item.status)),
+            None => Err(format!("no review {}", id)),
+        }
+    }
+
+    fn decide(&mut self, id: u64, status: ReviewStatus, reference: &str) {
+        if let Some(item) = self.items.iter_mut().find(|item| item.id == id) {
+            item.status = status;
+            item.reference = reference.to_string();
+        }
+    }
+}
+
+fn write_items<'a>(
+    writer: impl Write,
+    items: impl Iterator<Item = &'a ReviewItem>,
+) -> Result<(), Box<dyn Error>> {
+    // Header by hand, so an empty queue still gets one
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    writer.write_record([
+        "id",
+        "client",
+        "tx",
+        "available",
+        "held",
+        "status",
+        "reference",
+    ])?;
+    for item in items {
+        writer.serialize(item)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn rejected(reason: RejectionReason) -> String {
+    format!("rejected: {}", reason)
+}
+
+impl EventListener for ReviewQueue {
+    fn on_account_locked(
+        &mut self,
+        client_id: ClientId,
+        transaction: &Transaction,
+        account: &Account,
+    ) {
+        let id = self.items.iter().map(|item| item.id).max().unwrap_or(0) + 1;
+        self.items.push(ReviewItem {
+            id,
+            client_id,
+            transaction_id: transaction.transaction_id(),
+            available: account.available(),
+            held: account.held(),
+            status: ReviewStatus::Pending,
+            reference: String::new(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{AuditLog, DisputeCap, OverCap, ProcessorConfig};
+    use std::sync::{Arc, Mutex};
+
+    fn locked(queue: &Arc<Mutex<ReviewQueue>>) -> PaymentProcessor {
+        let mut processor = PaymentProcessor::new();
+        processor.add_listener(queue.clone());
+        for transaction in [
+            Transaction::Deposit {
+                client_id: 1,
+                transaction_id: 1,
+                amount: Amount::from(10),
+            },
+            Transaction::Deposit {
+                client_id: 1,
+                transaction_id: 2,
+                amount: Amount::from(4),
+            },
+            Transaction::AutoChargeback {
+                client_id: 1,
+                transaction_id: 2,
+            },
+            Transaction::Deposit {
+                client_id: 2,
+                transaction_id: 3,
+                amount: Amount::from(1),
+            },
+            Transaction::AutoChargeback {
+                client_id: 2,
+                transaction_id: 3,
+            },
+        ] {
+            processor.process(&transaction);
+        }
+        processor
+    }
+
+    #[test]
+    fn test_queue_round_trip() {
+        let queue = Arc::new(Mutex::new(ReviewQueue::new()));
+        locked(&queue);
+        let queue = queue.lock().unwrap().clone();
+        assert_eq!(queue.pending().count(), 2);
+        assert_eq!(queue.items()[0].available, Amount::from(10));
+        assert_eq!(queue.items()[0].transaction_id, 2);
+
+        let mut csv = Vec::new();
+        queue.write(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv.clone()).unwrap(),
+            "id,client,tx,available,held,status,reference\n\
+             1,1,2,10.0,0.0,pending,\n\
+             2,2,3,0.0,0.0,pending,\n"
+        );
+        assert_eq!(ReviewQueue::read(csv.as_slice()).unwrap(), queue);
+    }
+
+    #[test]
+    fn test_decisions() {
+        let queue = Arc::new(Mutex::new(ReviewQueue::new()));
+        let mut processor = locked(&queue);
+        let mut queue = queue.lock().unwrap().clone();
+
+        let audit = Arc::new(Mutex::new(AuditLog::new(Vec::new())));
+        processor.add_listener(audit.clone());
+
+        queue.approve(1, &mut processor, "REP-1", true).unwrap();
+        let account = &processor.accounts()[&1];
+        assert!(!account.is_locked());
+        assert_eq!(account.available(), Amount::from(14));
+        assert!(queue.approve(1, &mut processor, "REP-1", true).is_err());
+
+        queue.reject(2, &mut processor, "FRAUD-2").unwrap();
+        assert!(processor.accounts()[&2].is_locked());
+        assert_eq!(queue.pending().count(), 0);
+        assert_eq!(queue.items()[1].status, ReviewStatus::Rejected);
+        assert_eq!(queue.items()[1].reference, "FRAUD-2");
+        assert!(queue.reject(3, &mut processor, "x").is_err());
+
+        drop(processor);
+        let log = Arc::into_inner(audit).unwrap().into_inner().unwrap();
+        let log = String::from_utf8(log.into_inner()).unwrap();
+        assert_eq!(
+            log,
+            "[OPERATOR] applied: type: reverse_chargeback, client: 1, tx: 2, reference: REP-1\n\
+             [OPERATOR] applied: type: unlock, client: 1, reference: REP-1\n\
+             [OPERATOR] applied: type: keep_locked, client: 2, reference: FRAUD-2\n"
+        );
+    }
+
+    #[test]
+    fn test_approve_freeze() {
+        let queue = Arc::new(Mutex::new(ReviewQueue::new()));
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            dispute_cap: Some(DisputeCap {
+                max_open: 1,
+                over_cap: OverCap::Reject,
+                freeze: true,
+            }),
+            ..ProcessorConfig::default()
+        });
+        processor.add_listener(queue.clone());
+        for transaction_id in 1..=2 {
+            processor.process(&Transaction::Deposit {
+                client_id: 1,
+                transaction_id,
+                amount: Amount::from(5),
+            });
+            processor.process(&Transaction::Dispute {
+                client_id: 1,
+                transaction_id,
+            });
+        }
+        let mut queue = queue.lock().unwrap().clone();
+        assert_eq!(queue.items()[0].transaction_id, 2);
+
+        let err = queue.approve(1, &mut processor, "REP-1", true).unwrap_err();
+        assert!(err.contains("wasn't locked by a chargeback"), "{}", err);
+        assert!(processor.accounts()[&1].is_locked());
+        assert_eq!(queue.pending().count(), 1);
+
+        queue.approve(1, &mut processor, "REP-1", false).unwrap();
+        assert!(!processor.accounts()[&1].is_locked());
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+
+use super::amount::Amount;
+use super::locale::{Locale, LocalizedDisplay};
+use super::{ClientId, PaymentProcessor};
+
+/// Picks a deterministic subset of clients, so a sample always contains all
+/// of a client's transactions and the same seed always picks the same clients.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientSampler {
+    rate: f64,
+    seed: u64,
+}
+
+impl ClientSampler {
+    /// `rate` is the fraction of clients to keep, in (0, 1]
+    pub fn new(rate: f64, seed: u64) -> Self {
+        Self { rate, seed }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    pub fn includes(&self, client_id: ClientId) -> bool {
+        if self.rate >= 1.0 {
+            return true;
+        }
+        // Top 53 bits as a uniform value in [0, 1)
+        let value = (splitmix64(self.seed ^ client_id as u64) >> 11) as f64 / (1u64 << 53) as f64;
+        value < self.rate
+    }
+}
+
+// SplitMix64's finalizer, mixes the client ID well enough for sampling
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Small seeded generator for anything that has to be reproducible
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        let value = splitmix64(self.0);
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        value
+    }
+
+    /// Uniform in [0, 1)
+    pub(crate) fn fraction(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// Aggregates of a sampled run scaled back up by the sample rate
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleEstimate {
+    pub rate: f64,
+    pub sampled_clients: u64,
+    pub clients: u64,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: u64,
+}
+
+impl PaymentProcessor {
+    /// Only meaningful if every processed transaction went through `sampler`
+    pub fn estimate_from_sample(&self, sampler: &ClientSampler) -> SampleEstimate {
+        let rate = sampler.rate();
+        let scale_count = |count: u64| (count as f64 / rate).round() as u64;
+        let scale_amount =
+            |amount: Amount| Amount::from_raw((amount.to_raw() as f64 / rate).round() as i64);
+
+        let (mut available, mut held) = (Amount::from(0), Amount::from(0));
+        let mut locked = 0;
+        for account in self.accounts.values() {
+            available += account.available();
+            held += account.held();
+            locked += account.is_locked() as u64;
+        }
+
+        let sampled_clients = self.accounts.len() as u64;
+        SampleEstimate {
+            rate,
+            sampled_clients,
+            clients: scale_count(sampled_clients),
+            available: scale_amount(available),
+            held: scale_amount(held),
+            total: scale_amount(available + held),
+            locked: scale_count(locked),
+        }
+    }
+}
+
+impl LocalizedDisplay for SampleEstimate {
+    fn fmt_localized(&self, f: &mut fmt::Formatter<'_>, locale: &Locale) -> fmt::Result {
+        writeln!(
+            f,
+            "sampled {} clients at rate {}, estimated totals:",
+            locale.count(self.sampled_clients),
+            locale.fraction(self.rate)
+        )?;
+        writeln!(f, "clients: ~{}", locale.count(self.clients))?;
+        writeln!(f, "available: ~{}", locale.amount(self.available))?;
+        writeln!(f, "held: ~{}", locale.amount(self.held))?;
+        writeln!(f, "total: ~{}", locale.amount(self.total))?;
+        write!(f, "locked accounts: ~{}", locale.count(self.locked))
+    }
+}
+
+impl fmt::Display for SampleEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_localized(f, &Locale::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::Transaction;
+
+    #[test]
+    fn test_sampling_is_deterministic() {
+        let sampler = ClientSampler::new(0.1, 42);
+        let picked: Vec<ClientId> = (0..=u16::MAX).filter(|id| sampler.includes(*id)).collect();
+        let again: Vec<ClientId> = (0..=u16::MAX).filter(|id| sampler.includes(*id)).collect();
+        assert_eq!(picked, again);
+
+        // Roughly 10% of all client IDs, and a different set for another seed
+        assert!((6000..7100).contains(&picked.len()), "{}", picked.len());
+        let other = ClientSampler::new(0.1, 43);
+        assert_ne!(
+            picked,
+            (0..=u16::MAX)
+                .filter(|id| other.includes(*id))
+                .collect::<Vec<_>>()
+        );
+
+        assert!((0..=u16::MAX).all(|id| ClientSampler::new(1.0, 7).includes(id)));
+    }
+
+    #[test]
+    fn test_estimate() {
+        let mut processor = PaymentProcessor::new();
+        for client_id in 1..=4 {
+            processor.process(&Transaction::Deposit {
+                client_id,
+                transaction_id: client_id as u32,
+                amount: Amount::from(1.5),
+            });
+        }
+
+        let estimate = processor.estimate_from_sample(&ClientSampler::new(0.25, 0));
+        assert_eq!(estimate.sampled_clients, 4);
+        assert_eq!(estimate.clients, 16);
+        assert_eq!(estimate.total, Amount::from(24));
+        assert_eq!(estimate.held, Amount::from(0));
+        assert_eq!(estimate.locked, 0);
+    }
+}
@@ -0,0 +1,275 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+
+use super::chargeback::civil_from_days;
+
+/// A cron-like schedule: `minute hour day-of-month month day-of-week`, in
+/// UTC. Each field is `*`, a number, a range (`1-5`), a step (`*/15`,
+/// `0-30/10`) or a comma separated list of those. Sunday is 0 (or 7).
+/// Like cron, when both day fields are restricted either one matching is
+/// enough.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Schedule {
+    // One bit per allowed value
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MINUTE: u64 = 60;
+const DAY: u64 = 24 * 60 * MINUTE;
+// Far enough to get past a run of years without a Feb 29
+const SEARCH_DAYS: u64 = 8 * 366;
+
+impl Schedule {
+    /// The first time strictly after `after` the schedule fires, at the
+    /// start of a minute. `None` if it never does (e.g. `0 0 31 2 *`).
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let secs = after
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut minute = secs / MINUTE + 1;
+        let last_day = minute * MINUTE / DAY + SEARCH_DAYS;
+        while minute * MINUTE / DAY <= last_day {
+            let day = minute * MINUTE / DAY;
+            if !self.day_matches(day) {
+                // Skip straight to the next midnight
+                minute = (day + 1) * DAY / MINUTE;
+                continue;
+            }
+            let of_day = minute * MINUTE % DAY / MINUTE;
+            if bit(self.hours, of_day / 60) && bit(self.minutes, of_day % 60) {
+                return Some(SystemTime::UNIX_EPOCH + Duration::from_secs(minute * MINUTE));
+            }
+            minute += 1;
+        }
+        None
+    }
+
+    fn day_matches(&self, day: u64) -> bool {
+        let (_, month, day_of_month) = civil_from_days(day);
+        if !bit(self.months, month) {
+            return false;
+        }
+        // 1970-01-01 was a Thursday
+        let weekday = (day + 4) % 7;
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (false, true) => bit(self.days, day_of_month),
+            (true, false) => bit(self.weekdays, weekday),
+            (false, false) => bit(self.days, day_of_month) || bit(self.weekdays, weekday),
+        }
+    }
+}
+
+fn bit(set: u64, value: u64) -> bool {
+    set & (1 << value) != 0
+}
+
+// One field into its bitset
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u64>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("bad step in {}", part))?,
+            ),
+            None => (part, 1),
+        };
+        let number = |value: &str| {
+            value
+                .parse::<u64>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .ok_or_else(|| format!("{} isn't a number from {} to {}", value, min, max))
+        };
+        let (from, to) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((from, to)) => (number(from)?, number(to)?),
+            // `5/10` means from 5 on, like cron
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if from > to {
+            return Err(format!("backwards range {}", range));
+        }
+        for value in (from..=to).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields.as_slice() else {
+            return Err(format!(
+                "expected 5 fields (minute hour day month weekday), got {}",
+                fields.len()
+            ));
+        };
+        let mut weekday_set = parse_field(weekdays, 0, 7)?;
+        // 7 is Sunday too
+        if bit(weekday_set, 7) {
+            weekday_set |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_set,
+            any_day: *days == "*",
+            any_weekday: *weekdays == "*",
+        })
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Names and prunes a rotating set of timestamped report files, e.g.
+/// `balances-20231114T221320Z.csv`. The names sort by time, so the oldest
+/// ones are the first ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportRotation {
+    pub prefix: String,
+    /// How many reports to hold on to
+    pub keep: usize,
+}
+
+impl ReportRotation {
+    pub fn file_name(&self, time: SystemTime) -> String {
+        let secs = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let (year, month, day) = civil_from_days(secs / DAY);
+        format!(
+            "{}-{:04}{:02}{:02}T{:02}{:02}{:02}Z.csv",
+            self.prefix,
+            year,
+            month,
+            day,
+            secs % DAY / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        )
+    }
+
+    /// Which of `existing` (file names, in any order) fall outside the
+    /// newest `keep` reports. Anything that isn't one of ours is left alone.
+    pub fn expired<'a>(&self, existing: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+        let start = format!("{}-", self.prefix);
+        let mut reports: Vec<&str> = existing
+            .into_iter()
+            .filter(|name| name.starts_with(&start) && name.ends_with("Z.csv"))
+            .collect();
+        reports.sort_unstable();
+        let expired = reports.len().saturating_sub(self.keep);
+        reports.truncate(expired);
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    // 2023-11-14T22:13:20Z, a Tuesday
+    const NOW: u64 = 1_700_000_000;
+    const MIDNIGHT: u64 = NOW - (22 * 3600 + 13 * 60 + 20);
+    const HOUR: u64 = 3600;
+
+    #[test]
+    fn test_next_after() {
+        let every_15: Schedule = "*/15 * * * *".parse().unwrap();
+        let quarter_past = MIDNIGHT + 22 * HOUR + 15 * MINUTE;
+        assert_eq!(every_15.next_after(at(NOW)), Some(at(quarter_past)));
+        // Strictly after, even when already on a matching minute
+        assert_eq!(
+            every_15.next_after(at(quarter_past)),
+            Some(at(quarter_past + 15 * MINUTE))
+        );
+
+        let nightly: Schedule = "30 2 * * *".parse().unwrap();
+        assert_eq!(
+            nightly.next_after(at(NOW)),
+            Some(at(MIDNIGHT + DAY + 2 * HOUR + 30 * MINUTE))
+        );
+
+        // Saturdays, or the 15th
+        let either: Schedule = "0 0 15 * 6".parse().unwrap();
+        assert_eq!(either.next_after(at(NOW)), Some(at(MIDNIGHT + DAY)));
+        let weekends: Schedule = "0 12 * * 0,6".parse().unwrap();
+        assert_eq!(
+            weekends.next_after(at(NOW)),
+            Some(at(MIDNIGHT + 4 * DAY + 12 * HOUR))
+        );
+
+        let leap_day: Schedule = "0 0 29 2 *".parse().unwrap();
+        assert!(leap_day.next_after(at(NOW)).is_some());
+        let never: Schedule = "0 0 31 2 *".parse().unwrap();
+        assert_eq!(never.next_after(at(NOW)), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("* * * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+        assert!("10-5 * * * *".parse::<Schedule>().is_err());
+        assert!("0 0 0 * *".parse::<Schedule>().is_err());
+        assert_eq!(
+            "0 0 * * 7".parse::<Schedule>(),
+            "0 0 * * 0,7".parse::<Schedule>()
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        let rotation = ReportRotation {
+            prefix: String::from("balances"),
+            keep: 2,
+        };
+        assert_eq!(rotation.file_name(at(NOW)), "balances-20231114T221320Z.csv");
+        let existing = [
+            "balances-20231114T221320Z.csv",
+            "balances-20231114T201320Z.csv",
+            "notes.txt",
+            "balances-20231114T211320Z.csv",
+            "balances-20231114T191320Z.csv",
+        ];
+        assert_eq!(
+            rotation.expired(existing),
+            vec![
+                "balances-20231114T191320Z.csv",
+                "balances-20231114T201320Z.csv"
+            ]
+        );
+    }
+}
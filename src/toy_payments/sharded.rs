@@ -0,0 +1,739 @@
+use std::collections::hash_map::Entry;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[cfg(debug_assertions)]
+use super::hashing::HashMap;
+use super::sync::{Arc, BoundedQueue, mpsc, thread};
+use super::{Account, ClientId, PaymentProcessor, Transaction, TransactionId, timed};
+
+// How many batches can queue up per shard before the reader has to wait
+const QUEUED_BATCHES: usize = 4;
+
+/// What a processor promises about the order transactions get applied (and
+/// reported to listeners) in, relative to the order they were submitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderingGuarantee {
+    /// Everything in submission order, a single PaymentProcessor
+    Total,
+    /// Each client's transactions in submission order, but nothing across
+    /// clients: a listener shared between shards sees different clients'
+    /// events interleaved. Transfers count as the sender's, one to a client
+    /// on another shard also waits for the recipient's earlier transactions.
+    PerClient,
+}
+
+impl OrderingGuarantee {
+    /// Whether `earlier` is guaranteed to be applied before `later`, when
+    /// it was submitted first
+    pub fn orders(&self, earlier: &Transaction, later: &Transaction) -> bool {
+        match self {
+            OrderingGuarantee::Total => true,
+            OrderingGuarantee::PerClient => earlier.client_id() == later.client_id(),
+        }
+    }
+}
+
+impl PaymentProcessor {
+    pub fn ordering(&self) -> OrderingGuarantee {
+        OrderingGuarantee::Total
+    }
+}
+
+/// Runs one PaymentProcessor per shard, each on its own thread.
+///
+/// Transactions are routed by client, so all of a client's transactions go
+/// through the same shard in the order they were submitted, while different
+/// clients are processed concurrently. Since clients can only dispute their
+/// own transactions, this gives the same final state as a single processor.
+/// See `ordering` for what that means for listeners. finish returns only
+/// once every submitted transaction has been applied.
+///
+/// A transfer to a client on another shard is applied by the sender's shard,
+/// with the recipient's shard lending it the recipient's account for that
+/// one transaction. Both shards wait on each other at that point, since
+/// they reach it in submission order that can't deadlock.
+///
+/// Every transaction gets a sequence number as it's submitted, and debug
+/// builds check each shard applies a client's transactions in increasing
+/// sequence order. The queues and listener locking are checked with loom,
+/// see the concurrency_model feature.
+pub struct ShardedProcessor {
+    queues: Vec<Arc<BoundedQueue<Vec<Work>>>>,
+    workers: Vec<thread::JoinHandle<(PaymentProcessor, Duration)>>,
+    next_sequence: AtomicU64,
+}
+
+// A recipient's account and tombstone, while another shard has them
+type Lent = (Option<Account>, Option<ClientId>);
+
+// What a shard's worker gets handed, with its sequence number
+enum Work {
+    Apply(u64, Transaction),
+    // A transfer to another shard's client, applied once that shard lends
+    // the recipient over
+    Borrow {
+        sequence: u64,
+        transaction: Transaction,
+        lent: mpsc::Receiver<Lent>,
+        give_back: mpsc::Sender<Lent>,
+    },
+    // The other end of a Borrow, waits until the recipient is given back
+    Lend {
+        sequence: u64,
+        client_id: ClientId,
+        lend: mpsc::Sender<Lent>,
+        given_back: mpsc::Receiver<Lent>,
+    },
+}
+
+// Closes the shard's queue if its worker dies, so process_batch fails
+// instead of blocking forever on a full queue
+struct CloseOnDrop(Arc<BoundedQueue<Vec<Work>>>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Shards stored a transaction under the same ID for different clients.
+/// One processor would have kept only the later of the two, and which one
+/// that is got lost in the split, so the shards can't be merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionIdCollision {
+    /// The lowest of the colliding IDs, and two of the clients using it
+    pub transaction_id: TransactionId,
+    pub client_ids: (ClientId, ClientId),
+    /// How many IDs collide altogether
+    pub count: usize,
+}
+
+impl fmt::Display for TransactionIdCollision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tx {} is used by clients {} and {} on different shards ({} colliding ID(s)), \
+             only a single thread gives a well-defined result",
+            self.transaction_id, self.client_ids.0, self.client_ids.1, self.count
+        )
+    }
+}
+
+impl Error for TransactionIdCollision {}
+
+pub fn shard_for(client_id: ClientId, shard_count: usize) -> usize {
+    client_id as usize % shard_count
+}
+
+impl ShardedProcessor {
+    pub fn new(shards: Vec<PaymentProcessor>) -> Self {
+        assert!(!shards.is_empty(), "need at least one shard");
+
+        let mut queues = Vec::with_capacity(shards.len());
+        let mut workers = Vec::with_capacity(shards.len());
+        for mut shard in shards {
+            let queue = Arc::new(BoundedQueue::new(QUEUED_BATCHES));
+            queues.push(queue.clone());
+            workers.push(thread::spawn(move || {
+                let queue = CloseOnDrop(queue);
+                let mut order = OrderCheck::default();
+                // Per batch, so it only covers processing and not waiting on the reader
+                let mut busy = Duration::ZERO;
+                while let Some(batch) = queue.0.pop() {
+                    timed(&mut busy, || {
+                        for work in batch {
+                            shard.work(work, &mut order);
+                        }
+                    });
+                }
+                (shard, busy)
+            }));
+        }
+
+        Self {
+            queues,
+            workers,
+            next_sequence: AtomicU64::new(0),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.queues.len()
+    }
+
+    pub fn ordering(&self) -> OrderingGuarantee {
+        match self.shard_count() {
+            1 => OrderingGuarantee::Total,
+            _ => OrderingGuarantee::PerClient,
+        }
+    }
+
+    /// Hands the transactions over to their shards, keeping their relative order
+    pub fn process_batch(&self, transactions: Vec<Transaction>) {
+        let shard_count = self.shard_count();
+        let first = self
+            .next_sequence
+            .fetch_add(transactions.len() as u64, Ordering::Relaxed);
+        let mut batches: Vec<Vec<Work>> = (0..shard_count).map(|_| Vec::new()).collect();
+        for (sequence, transaction) in (first..).zip(transactions) {
+            let shard = shard_for(transaction.client_id(), shard_count);
+            match transaction {
+                Transaction::Transfer { to_client_id, .. }
+                    if shard_for(to_client_id, shard_count) != shard =>
+                {
+                    let (lend, lent) = mpsc::channel();
+                    let (give_back, given_back) = mpsc::channel();
+                    batches[shard_for(to_client_id, shard_count)].push(Work::Lend {
+                        sequence,
+                        client_id: to_client_id,
+                        lend,
+                        given_back,
+                    });
+                    batches[shard].push(Work::Borrow {
+                        sequence,
+                        transaction,
+                        lent,
+                        give_back,
+                    });
+                }
+                transaction => batches[shard].push(Work::Apply(sequence, transaction)),
+            }
+        }
+
+        for (queue, batch) in self.queues.iter().zip(batches) {
+            if !batch.is_empty() && queue.push(batch).is_err() {
+                panic!("shard worker stopped");
+            }
+        }
+    }
+
+    /// Waits for every shard to drain and merges them back into one
+    /// processor, see merge for when that fails
+    pub fn finish(self) -> Result<PaymentProcessor, TransactionIdCollision> {
+        Ok(self.finish_timed()?.0)
+    }
+
+    /// Same as finish, plus how long each shard spent processing
+    pub fn finish_timed(self) -> Result<(PaymentProcessor, Vec<Duration>), TransactionIdCollision> {
+        for queue in &self.queues {
+            queue.close();
+        }
+        let (shards, busy) = self
+            .workers
+            .into_iter()
+            .map(|worker| worker.join().expect("shard worker panicked"))
+            .unzip();
+        Ok((PaymentProcessor::merge(shards)?, busy))
+    }
+}
+
+// Last sequence number applied per client. Only kept in debug builds, it's
+// a check on the routing and queues rather than something that can go wrong
+// with bad input.
+#[derive(Default)]
+struct OrderCheck {
+    #[cfg(debug_assertions)]
+    last: HashMap<ClientId, u64>,
+}
+
+impl OrderCheck {
+    #[cfg(debug_assertions)]
+    fn check(&mut self, client_id: ClientId, sequence: u64) {
+        if let Some(last) = self.last.insert(client_id, sequence) {
+            assert!(
+                last < sequence,
+                "client {} transactions applied out of order ({} after {})",
+                client_id,
+                sequence,
+                last
+            );
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn check(&mut self, _client_id: ClientId, _sequence: u64) {}
+}
+
+impl PaymentProcessor {
+    // A worker's side of Work. If the other shard's worker died the channel
+    // is closed, and this one panics too rather than carry on without it.
+    fn work(&mut self, work: Work, order: &mut OrderCheck) {
+        match work {
+            Work::Apply(sequence, transaction) => {
+                order.check(transaction.client_id(), sequence);
+                self.process(&transaction);
+            }
+            Work::Borrow {
+                sequence,
+                transaction,
+                lent,
+                give_back,
+            } => {
+                order.check(transaction.client_id(), sequence);
+                let lent = lent.recv().expect("shard worker stopped");
+                let lent = self.process_borrowed(&transaction, lent);
+                give_back.send(lent).expect("shard worker stopped");
+            }
+            Work::Lend {
+                sequence,
+                client_id,
+                lend,
+                given_back,
+            } => {
+                order.check(client_id, sequence);
+                lend.send(self.lend(client_id))
+                    .expect("shard worker stopped");
+                let lent = given_back.recv().expect("shard worker stopped");
+                self.take_back(client_id, lent);
+            }
+        }
+    }
+
+    fn lend(&mut self, client_id: ClientId) -> Lent {
+        (
+            self.accounts.remove(&client_id),
+            self.merged_clients.remove(&client_id),
+        )
+    }
+
+    fn take_back(&mut self, client_id: ClientId, (account, into): Lent) {
+        if let Some(account) = account {
+            self.accounts.insert(client_id, account);
+        }
+        if let Some(into) = into {
+            self.merged_clients.insert(client_id, into);
+        }
+    }
+
+    // Applies a transfer with the recipient lent over from its shard, and
+    // hands the recipient back the way the transfer left it
+    fn process_borrowed(&mut self, transaction: &Transaction, lent: Lent) -> Lent {
+        let Transaction::Transfer { to_client_id, .. } = *transaction else {
+            unreachable!("only transfers are borrowed for");
+        };
+        self.take_back(to_client_id, lent);
+        // Lets the credit through, the recipient is here for now
+        let shard = self.shard.take();
+        self.process(transaction);
+        self.shard = shard;
+        self.lend(to_client_id)
+    }
+
+    /// Splits the accounts and stored transactions by client into `count`
+    /// processors with the same config. Listeners aren't carried over, so
+    /// they need to be added to each shard. Each shard rejects transfers to
+    /// clients that belong to another one, ShardedProcessor lends the
+    /// recipient over for those.
+    pub fn into_shards(self, count: usize) -> Vec<PaymentProcessor> {
+        let mut shards: Vec<PaymentProcessor> = (0..count)
+            .map(|index| PaymentProcessor {
+                shard: Some((index, count)),
+                ..PaymentProcessor::with_config(self.config.clone())
+            })
+            .collect();
+        for shard in &mut shards {
+            shard.expired_transactions = self.expired_for_shard(shard.shard);
+        }
+        for (client_id, account) in self.accounts {
+            shards[shard_for(client_id, count)]
+                .accounts
+                .insert(client_id, account);
+        }
+        for (transaction_id, stored) in self.compressed_transactions {
+            shards[shard_for(stored.client_id, count)]
+                .compressed_transactions
+                .insert(transaction_id, stored);
+        }
+        for ((client_id, index), withdrawn) in self.category_withdrawn {
+            shards[shard_for(client_id, count)]
+                .category_withdrawn
+                .insert((client_id, index), withdrawn);
+        }
+        for (client_id, window) in self.dispute_windows {
+            shards[shard_for(client_id, count)]
+                .dispute_windows
+                .insert(client_id, window);
+        }
+        for (client_id, seen) in self.seen_transactions {
+            shards[shard_for(client_id, count)]
+                .seen_transactions
+                .insert(client_id, seen);
+        }
+        for (client_id, into) in self.merged_clients {
+            shards[shard_for(client_id, count)]
+                .merged_clients
+                .insert(client_id, into);
+        }
+        shards
+    }
+
+    /// Reverse of into_shards. The shards' listeners are dropped. Fails if
+    /// clients on different shards stored transactions under the same ID.
+    pub fn merge(
+        shards: Vec<PaymentProcessor>,
+    ) -> Result<PaymentProcessor, TransactionIdCollision> {
+        let mut shards = shards.into_iter();
+        let mut merged = shards.next().expect("need at least one shard");
+        merged.listeners.clear();
+        merged.shard = None;
+        let mut collisions = Vec::new();
+        for shard in shards {
+            merged.accounts.extend(shard.accounts);
+            for (transaction_id, stored) in shard.compressed_transactions {
+                match merged.compressed_transactions.entry(transaction_id) {
+                    Entry::Occupied(entry) => collisions.push((
+                        transaction_id,
+                        entry.get().client_id.min(stored.client_id),
+                        entry.get().client_id.max(stored.client_id),
+                    )),
+                    Entry::Vacant(entry) => {
+                        entry.insert(stored);
+                    }
+                }
+            }
+            merged.merged_clients.extend(shard.merged_clients);
+            merged.category_withdrawn.extend(shard.category_withdrawn);
+            merged.dispute_windows.extend(shard.dispute_windows);
+            merged.seen_transactions.extend(shard.seen_transactions);
+            merged.merge_expired(shard.expired_transactions);
+            merged.transaction_filter = None;
+        }
+        collisions.sort_unstable();
+        collisions.dedup_by_key(|(transaction_id, _, _)| *transaction_id);
+        match collisions.first() {
+            Some(&(transaction_id, first, second)) => Err(TransactionIdCollision {
+                transaction_id,
+                client_ids: (first, second),
+                count: collisions.len(),
+            }),
+            None => Ok(merged),
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "concurrency_model")))]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, TransactionReader};
+
+    fn transactions() -> Vec<Transaction> {
+        let mut transactions = Vec::new();
+        for transaction_id in 0..200u32 {
+            let client_id = (transaction_id % 7) as ClientId;
+            transactions.push(Transaction::Deposit {
+                client_id,
+                transaction_id,
+                amount: Amount::from(transaction_id as u64),
+            });
+            if transaction_id % 5 == 0 {
+                transactions.push(Transaction::Dispute {
+                    client_id,
+                    transaction_id,
+                });
+            }
+            if transaction_id % 10 == 0 {
+                transactions.push(Transaction::Chargeback {
+                    client_id,
+                    transaction_id,
+                });
+            }
+            transactions.push(Transaction::Withdrawal {
+                client_id,
+                transaction_id: transaction_id + 1000,
+                amount: Amount::from(3),
+            });
+        }
+        transactions
+    }
+
+    #[test]
+    fn test_sharded_matches_single() {
+        let mut single = PaymentProcessor::new();
+        for transaction in &transactions() {
+            single.process(transaction);
+        }
+
+        let sharded = ShardedProcessor::new(PaymentProcessor::new().into_shards(3));
+        sharded.process_batch(transactions());
+        let merged = sharded.finish().unwrap();
+
+        assert_eq!(merged.accounts, single.accounts);
+        assert_eq!(merged.state_hash(), single.state_hash());
+        assert_eq!(
+            merged.compressed_transactions,
+            single.compressed_transactions
+        );
+    }
+
+    #[test]
+    fn test_sharded_transfers_match_single() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/resources/transfers.csv");
+        // The last row is missing its recipient, skipped same as the CLI does
+        let transactions: Vec<Transaction> = TransactionReader::from_path(path.into())
+            .unwrap()
+            .iter()
+            .filter_map(Result::ok)
+            .collect();
+        let mut single = PaymentProcessor::new();
+        for transaction in &transactions {
+            single.process(transaction);
+        }
+
+        let sharded = ShardedProcessor::new(PaymentProcessor::new().into_shards(3));
+        sharded.process_batch(transactions);
+        let merged = sharded.finish().unwrap();
+
+        assert_eq!(merged.accounts, single.accounts);
+        assert_eq!(merged.state_hash(), single.state_hash());
+        assert_eq!(merged.accounts[&1].available(), Amount::from(75));
+        assert_eq!(merged.accounts[&3].available(), Amount::from(25));
+    }
+
+    #[test]
+    fn test_state_hash_independent_of_threads() {
+        // Deposits and withdrawals from transactions(), with transfers to
+        // clients on every other shard mixed in
+        let mut transactions = transactions();
+        for transaction_id in 0..200u32 {
+            let client_id = (transaction_id % 7) as ClientId;
+            transactions.insert(
+                (transaction_id * 2) as usize,
+                Transaction::Transfer {
+                    client_id,
+                    transaction_id: transaction_id + 2000,
+                    to_client_id: (client_id * 3 + 1) % 7,
+                    amount: Amount::from(u64::from(transaction_id % 40)),
+                },
+            );
+        }
+        let mut single = PaymentProcessor::new();
+        for transaction in &transactions {
+            single.process(transaction);
+        }
+
+        for threads in 1..=4 {
+            let sharded = ShardedProcessor::new(PaymentProcessor::new().into_shards(threads));
+            // In chunks, like the CLI hands them over
+            for chunk in transactions.chunks(64) {
+                sharded.process_batch(chunk.to_vec());
+            }
+            let merged = sharded.finish().unwrap();
+            assert_eq!(
+                merged.state_hash(),
+                single.state_hash(),
+                "{} threads",
+                threads
+            );
+        }
+    }
+
+    #[test]
+    fn test_per_client_order() {
+        use crate::toy_payments::{EventListener, RejectionReason, TransactionId};
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Recorder {
+            seen: Vec<(ClientId, TransactionId)>,
+        }
+
+        impl EventListener for Recorder {
+            fn on_applied(&mut self, transaction: &Transaction) {
+                self.seen
+                    .push((transaction.client_id(), transaction.transaction_id()));
+            }
+
+            fn on_rejected(&mut self, transaction: &Transaction, _: RejectionReason) {
+                self.on_applied(transaction);
+            }
+        }
+
+        let recorder = Arc::new(Mutex::new(Recorder::default()));
+        let mut shards = PaymentProcessor::new().into_shards(4);
+        for shard in &mut shards {
+            shard.add_listener(recorder.clone());
+        }
+        let sharded = ShardedProcessor::new(shards);
+        assert_eq!(sharded.ordering(), OrderingGuarantee::PerClient);
+        let transactions = transactions();
+        for batch in transactions.chunks(17) {
+            sharded.process_batch(batch.to_vec());
+        }
+        sharded.finish().unwrap();
+
+        let seen = &recorder.lock().unwrap().seen;
+        assert_eq!(seen.len(), transactions.len());
+        for client_id in 0..7 {
+            let submitted: Vec<_> = transactions
+                .iter()
+                .filter(|transaction| transaction.client_id() == client_id)
+                .map(Transaction::transaction_id)
+                .collect();
+            let applied: Vec<_> = seen
+                .iter()
+                .filter(|(client, _)| *client == client_id)
+                .map(|(_, transaction_id)| *transaction_id)
+                .collect();
+            assert_eq!(applied, submitted, "client {}", client_id);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "out of order")]
+    fn test_order_check() {
+        let mut order = OrderCheck::default();
+        order.check(1, 5);
+        order.check(2, 3);
+        order.check(1, 4);
+    }
+
+    #[test]
+    fn test_transaction_id_collision() {
+        let deposit = |client_id, transaction_id| Transaction::Deposit {
+            client_id,
+            transaction_id,
+            amount: Amount::from(1),
+        };
+        // 1 and 4 share shard 1 of 3, so that one's settled like a single
+        // processor would. 2 and 3 are on the other two.
+        let transactions = vec![
+            deposit(1, 7),
+            deposit(4, 7),
+            deposit(1, 9),
+            deposit(2, 9),
+            deposit(2, 8),
+            deposit(3, 8),
+        ];
+        let sharded = ShardedProcessor::new(PaymentProcessor::new().into_shards(3));
+        sharded.process_batch(transactions);
+        assert_eq!(
+            sharded.finish().err(),
+            Some(TransactionIdCollision {
+                transaction_id: 8,
+                client_ids: (2, 3),
+                count: 2,
+            })
+        );
+
+        let sharded = ShardedProcessor::new(PaymentProcessor::new().into_shards(3));
+        sharded.process_batch(vec![deposit(1, 7), deposit(4, 7)]);
+        assert_eq!(sharded.finish().unwrap().transactions()[&7].client_id, 4);
+    }
+
+    #[test]
+    fn test_shard_roundtrip() {
+        let mut processor = PaymentProcessor::new();
+        for transaction in &transactions() {
+            processor.process(transaction);
+        }
+        let accounts = processor.accounts.clone();
+
+        let shards = processor.into_shards(4);
+        for (index, shard) in shards.iter().enumerate() {
+            assert!(shard.accounts.keys().all(|id| shard_for(*id, 4) == index));
+        }
+        assert_eq!(PaymentProcessor::merge(shards).unwrap().accounts, accounts);
+    }
+}
+
+// Run with `cargo test --release --features concurrency_model loom`
+#[cfg(all(test, feature = "concurrency_model"))]
+mod loom_tests {
+    use super::*;
+    use crate::toy_payments::sync::Mutex;
+    use crate::toy_payments::{Amount, EventListener, TransactionId};
+
+    #[derive(Default)]
+    struct Recorder {
+        applied: Vec<(ClientId, TransactionId)>,
+    }
+
+    impl EventListener for Recorder {
+        fn on_applied(&mut self, transaction: &Transaction) {
+            self.applied
+                .push((transaction.client_id(), transaction.transaction_id()));
+        }
+    }
+
+    fn deposit(client_id: ClientId, transaction_id: TransactionId) -> Transaction {
+        Transaction::Deposit {
+            client_id,
+            transaction_id,
+            amount: Amount::from(1),
+        }
+    }
+
+    #[test]
+    fn loom_shared_listener_sees_every_transaction_in_client_order() {
+        let mut builder = loom::model::Builder::new();
+        // Keeps the state space small enough to run in seconds
+        builder.preemption_bound = Some(2);
+        builder.check(|| {
+            let recorder = Arc::new(Mutex::new(Recorder::default()));
+            let mut shards = PaymentProcessor::new().into_shards(2);
+            for shard in &mut shards {
+                shard.add_listener(recorder.clone());
+            }
+
+            let sharded = ShardedProcessor::new(shards);
+            // Two batches, so both shards get fed more than once
+            sharded.process_batch(vec![deposit(0, 1), deposit(1, 2), deposit(0, 3)]);
+            sharded.process_batch(vec![
+                deposit(1, 4),
+                Transaction::Dispute {
+                    client_id: 0,
+                    transaction_id: 1,
+                },
+            ]);
+            let merged = sharded.finish().unwrap();
+
+            // No lost updates, neither in the state nor in the shared listener
+            assert_eq!(merged.accounts[&0].total(), Amount::from(2));
+            assert_eq!(merged.accounts[&0].held(), Amount::from(1));
+            assert_eq!(merged.accounts[&1].total(), Amount::from(2));
+            let applied = recorder.lock().unwrap().applied.clone();
+            assert_eq!(applied.len(), 5);
+
+            // Per client, everything shows up in submission order
+            let for_client = |client_id| {
+                applied
+                    .iter()
+                    .filter(|(client, _)| *client == client_id)
+                    .map(|(_, tx)| *tx)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(for_client(0), vec![1, 3, 1]);
+            assert_eq!(for_client(1), vec![2, 4]);
+        });
+    }
+
+    #[test]
+    fn loom_cross_shard_transfers_dont_deadlock() {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(|| {
+            let sharded = ShardedProcessor::new(PaymentProcessor::new().into_shards(2));
+            let transfer = |client_id, transaction_id, to_client_id| Transaction::Transfer {
+                client_id,
+                transaction_id,
+                to_client_id,
+                amount: Amount::from(1),
+            };
+            // Both ways, so each shard lends and borrows
+            sharded.process_batch(vec![
+                deposit(0, 1),
+                transfer(0, 2, 1),
+                transfer(1, 3, 0),
+                deposit(1, 4),
+            ]);
+            let merged = sharded.finish().unwrap();
+
+            assert_eq!(merged.accounts[&0].total(), Amount::from(1));
+            assert_eq!(merged.accounts[&1].total(), Amount::from(1));
+        });
+    }
+}
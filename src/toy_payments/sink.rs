@@ -0,0 +1,70 @@
+use super::{Account, ClientId, PaymentProcessor};
+
+/// Somewhere other than stdout to push the final balances to. Sinks get the
+/// accounts in one go, sorted by client, so they can write them as a single
+/// batch (and all-or-nothing, if the backend allows for it).
+pub trait AccountSink {
+    fn write_accounts(
+        &mut self,
+        accounts: &[(ClientId, Account)],
+    ) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+impl PaymentProcessor {
+    /// Hands the accounts `filter` returns true for over to `sink`
+    pub fn write_to_sink(
+        &self,
+        sink: &mut impl AccountSink,
+        filter: impl Fn(ClientId, &Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut accounts: Vec<(ClientId, Account)> = self
+            .accounts
+            .iter()
+            .filter(|(client_id, account)| filter(**client_id, account))
+            .map(|(client_id, account)| (*client_id, account.clone()))
+            .collect();
+        accounts.sort_unstable_by_key(|(client_id, _)| *client_id);
+
+        sink.write_accounts(&accounts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, Transaction};
+
+    #[derive(Default)]
+    struct VecSink(Vec<(ClientId, Account)>);
+
+    impl AccountSink for VecSink {
+        fn write_accounts(
+            &mut self,
+            accounts: &[(ClientId, Account)],
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            self.0.extend_from_slice(accounts);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_to_sink() {
+        let mut processor = PaymentProcessor::new();
+        for (client_id, transaction_id) in [(3, 1), (1, 2), (2, 3)] {
+            processor.process(&Transaction::Deposit {
+                client_id,
+                transaction_id,
+                amount: Amount::from(1),
+            });
+        }
+
+        let mut sink = VecSink::default();
+        processor
+            .write_to_sink(&mut sink, |client_id, _| client_id != 2)
+            .unwrap();
+
+        let client_ids: Vec<ClientId> = sink.0.iter().map(|(client_id, _)| *client_id).collect();
+        assert_eq!(client_ids, vec![1, 3]);
+        assert_eq!(sink.0[0].1.total(), Amount::from(1));
+    }
+}
@@ -0,0 +1,471 @@
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use super::amount::Amount;
+use super::window::ExpiredTransactions;
+use super::{Account, ClientId, DisputeState, PaymentProcessor, StoredTransaction, TransactionId};
+
+// Bump the version whenever the layout below changes, and keep reading
+// the older ones
+const MAGIC: &[u8; 6] = b"TPSNAP";
+const VERSION: u8 = 8;
+const OLDEST_VERSION: u8 = 2;
+
+/// Binary snapshots of the processor state (accounts plus the stored
+/// transactions needed for future disputes), so a run can pick up where
+/// a previous one left off.
+///
+/// Older versions load with defaults for what they didn't have yet, except
+/// v1, which didn't record who each transaction was from.
+///
+/// Layout, all integers little-endian:
+/// - magic `TPSNAP`, version byte
+/// - u64 account count, then per account: client u16, available i64, held i64, locked u8
+///   (0 unlocked, 1 locked, 2 frozen over the dispute cap; since v8, 1 for both before),
+///   total disputes u32, chargebacks u32 (since v5, zero before)
+/// - u64 transaction count, then per transaction: tx u32, client u16, amount i64,
+///   dispute state u8 (0 undisputed, 1 disputed, 2 resolved, 3 charged back; since
+///   v3, undisputed before)
+/// - u64 merged client count, then per merged client: client u16, merged into u16
+///   (since v4, none before)
+/// - u64 count of clients with seen IDs (for the dedup window), then per client:
+///   client u16, u64 ID count, tx u32 per ID (oldest first). Since v6, none before.
+/// - the dispute window's expired IDs: u64 how many ever expired, u64 capacity per
+///   generation, u64 IDs in the newest generation, then per generation (newest first)
+///   u64 word count and the filter's u64 words. Since v7, none before.
+impl PaymentProcessor {
+    // Every stored withdrawal counted against its category, same as when it
+    // was processed
+    fn recount_category_withdrawals(&mut self) {
+        self.category_withdrawn.clear();
+        let Some(limits) = self.config.category_limits.clone() else {
+            return;
+        };
+        for (transaction_id, stored) in &self.compressed_transactions {
+            if stored.amount >= Amount::from(0) {
+                continue;
+            }
+            if let Some((index, _)) = limits.lookup(*transaction_id) {
+                *self
+                    .category_withdrawn
+                    .entry((stored.client_id, index))
+                    .or_default() -= stored.amount;
+            }
+        }
+    }
+
+    pub fn save_snapshot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+
+        // Sorted so the same state always produces the same bytes
+        let mut client_ids: Vec<&ClientId> = self.accounts.keys().collect();
+        client_ids.sort();
+        writer.write_all(&(client_ids.len() as u64).to_le_bytes())?;
+        for client_id in client_ids {
+            let account = &self.accounts[client_id];
+            writer.write_all(&client_id.to_le_bytes())?;
+            writer.write_all(&account.available_funds.to_raw().to_le_bytes())?;
+            writer.write_all(&account.held_funds.to_raw().to_le_bytes())?;
+            writer.write_all(&[encode_locked(account)])?;
+            writer.write_all(&account.total_disputes.to_le_bytes())?;
+            writer.write_all(&account.chargebacks.to_le_bytes())?;
+        }
+
+        let mut transaction_ids: Vec<&TransactionId> =
+            self.compressed_transactions.keys().collect();
+        transaction_ids.sort();
+        writer.write_all(&(transaction_ids.len() as u64).to_le_bytes())?;
+        for transaction_id in transaction_ids {
+            let stored = &self.compressed_transactions[transaction_id];
+            writer.write_all(&transaction_id.to_le_bytes())?;
+            writer.write_all(&stored.client_id.to_le_bytes())?;
+            writer.write_all(&stored.amount.to_raw().to_le_bytes())?;
+            writer.write_all(&[encode_state(stored.state)])?;
+        }
+
+        let mut merged: Vec<(&ClientId, &ClientId)> = self.merged_clients.iter().collect();
+        merged.sort();
+        writer.write_all(&(merged.len() as u64).to_le_bytes())?;
+        for (client_id, into) in merged {
+            writer.write_all(&client_id.to_le_bytes())?;
+            writer.write_all(&into.to_le_bytes())?;
+        }
+
+        let mut seen: Vec<_> = self.seen_transactions.iter().collect();
+        seen.sort_unstable_by_key(|(client_id, _)| **client_id);
+        writer.write_all(&(seen.len() as u64).to_le_bytes())?;
+        for (client_id, seen) in seen {
+            let transaction_ids: Vec<_> = seen.ids().collect();
+            writer.write_all(&client_id.to_le_bytes())?;
+            writer.write_all(&(transaction_ids.len() as u64).to_le_bytes())?;
+            for transaction_id in transaction_ids {
+                writer.write_all(&transaction_id.to_le_bytes())?;
+            }
+        }
+
+        let (capacity, len, count, generations) = self.expired_transactions.parts();
+        writer.write_all(&count.to_le_bytes())?;
+        writer.write_all(&(capacity as u64).to_le_bytes())?;
+        writer.write_all(&(len as u64).to_le_bytes())?;
+        for bits in generations {
+            writer.write_all(&(bits.len() as u64).to_le_bytes())?;
+            let bytes: Vec<u8> = bits.iter().flat_map(|word| word.to_le_bytes()).collect();
+            writer.write_all(&bytes)?;
+        }
+
+        writer.flush()
+    }
+
+    /// Replaces the current accounts, stored transactions and merged clients with the snapshot's
+    pub fn load_snapshot<R: Read>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut magic = [0u8; 6];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a snapshot file"));
+        }
+        let version = read_u8(&mut reader)?;
+        if version == 1 {
+            return Err(invalid_data(
+                "version 1 snapshots don't have the transactions' clients, can't load them",
+            ));
+        }
+        if !(OLDEST_VERSION..=VERSION).contains(&version) {
+            return Err(invalid_data(&format!(
+                "unsupported snapshot version: {}",
+                version
+            )));
+        }
+
+        self.accounts.clear();
+        let account_count = read_u64(&mut reader)?;
+        for _ in 0..account_count {
+            let client_id = read_u16(&mut reader)?;
+            let available_funds = Amount::from_raw(read_i64(&mut reader)?);
+            let held_funds = Amount::from_raw(read_i64(&mut reader)?);
+            let locked = read_u8(&mut reader)?;
+            let mut account = Account {
+                available_funds,
+                held_funds,
+                is_locked: locked != 0,
+                frozen: locked == 2,
+                open_disputes: 0,
+                total_disputes: 0,
+                chargebacks: 0,
+            };
+            if version >= 5 {
+                account.total_disputes = read_u32(&mut reader)?;
+                account.chargebacks = read_u32(&mut reader)?;
+            }
+            self.accounts.insert(client_id, account);
+        }
+
+        self.compressed_transactions.clear();
+        self.transaction_filter = None;
+        let transaction_count = read_u64(&mut reader)?;
+        for _ in 0..transaction_count {
+            let transaction_id = read_u32(&mut reader)?;
+            let stored = StoredTransaction {
+                client_id: read_u16(&mut reader)?,
+                amount: Amount::from_raw(read_i64(&mut reader)?),
+                state: if version >= 3 {
+                    decode_state(read_u8(&mut reader)?)?
+                } else {
+                    DisputeState::Undisputed
+                },
+            };
+            if stored.state == DisputeState::Disputed
+                && let Some(account) = self.accounts.get_mut(&stored.client_id)
+            {
+                account.open_disputes += 1;
+            }
+            self.compressed_transactions.insert(transaction_id, stored);
+        }
+        self.recount_category_withdrawals();
+        self.restart_dispute_windows();
+
+        self.merged_clients.clear();
+        let merged_count = if version >= 4 {
+            read_u64(&mut reader)?
+        } else {
+            0
+        };
+        for _ in 0..merged_count {
+            let client_id = read_u16(&mut reader)?;
+            self.merged_clients
+                .insert(client_id, read_u16(&mut reader)?);
+        }
+
+        // Kept even without a dedup window, see remember_seen
+        self.seen_transactions.clear();
+        let seen_count = if version >= 6 {
+            read_u64(&mut reader)?
+        } else {
+            0
+        };
+        for _ in 0..seen_count {
+            let client_id = read_u16(&mut reader)?;
+            let id_count = read_u64(&mut reader)?;
+            let mut transaction_ids = Vec::new();
+            for _ in 0..id_count {
+                transaction_ids.push(read_u32(&mut reader)?);
+            }
+            self.remember_seen(client_id, transaction_ids);
+        }
+
+        self.expired_transactions = Arc::new(if version >= 7 {
+            read_expired(&mut reader)?
+        } else {
+            ExpiredTransactions::default()
+        });
+
+        Ok(())
+    }
+}
+
+fn read_expired<R: Read>(reader: &mut R) -> io::Result<ExpiredTransactions> {
+    let count = read_u64(reader)?;
+    let capacity = read_u64(reader)? as usize;
+    let len = read_u64(reader)? as usize;
+    let mut generations = [Vec::new(), Vec::new()];
+    for bits in &mut generations {
+        let word_count = read_u64(reader)?;
+        for _ in 0..word_count {
+            bits.push(read_u64(reader)?);
+        }
+    }
+    ExpiredTransactions::from_parts(capacity, len, count, generations)
+        .ok_or_else(|| invalid_data("expired IDs don't match their capacity"))
+}
+
+fn encode_locked(account: &Account) -> u8 {
+    match (account.is_locked, account.frozen) {
+        (false, _) => 0,
+        (true, false) => 1,
+        (true, true) => 2,
+    }
+}
+
+fn encode_state(state: DisputeState) -> u8 {
+    match state {
+        DisputeState::Undisputed => 0,
+        DisputeState::Disputed => 1,
+        DisputeState::Resolved => 2,
+        DisputeState::ChargedBack => 3,
+    }
+}
+
+fn decode_state(byte: u8) -> io::Result<DisputeState> {
+    match byte {
+        0 => Ok(DisputeState::Undisputed),
+        1 => Ok(DisputeState::Disputed),
+        2 => Ok(DisputeState::Resolved),
+        3 => Ok(DisputeState::ChargedBack),
+        _ => Err(invalid_data(&format!("unknown dispute state: {}", byte))),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_i64<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(i64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{
+        DedupWindow, OperatorAction, ProcessorConfig, RejectionReason, Transaction,
+    };
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let mut processor = PaymentProcessor::new();
+        processor.process(&Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Amount::from(10.5),
+        });
+        processor.process(&Transaction::Deposit {
+            client_id: 2,
+            transaction_id: 2,
+            amount: Amount::from(3),
+        });
+        processor.process(&Transaction::Dispute {
+            client_id: 2,
+            transaction_id: 2,
+        });
+        processor.process(&Transaction::Chargeback {
+            client_id: 2,
+            transaction_id: 2,
+        });
+        processor.process(&Transaction::Deposit {
+            client_id: 3,
+            transaction_id: 3,
+            amount: Amount::from(1),
+        });
+        processor
+            .apply_action(&OperatorAction::MergeClient {
+                client_id: 3,
+                into: 1,
+                reference: String::from("CRM-1"),
+            })
+            .unwrap();
+
+        let mut bytes = Vec::new();
+        processor.save_snapshot(&mut bytes).unwrap();
+
+        let mut restored = PaymentProcessor::new();
+        restored.load_snapshot(bytes.as_slice()).unwrap();
+        assert_eq!(restored.accounts, processor.accounts);
+        assert_eq!(
+            restored.compressed_transactions,
+            processor.compressed_transactions
+        );
+        assert_eq!(restored.merged_clients, processor.merged_clients);
+
+        // Disputes against transactions from the previous run still work
+        restored.process(&Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 1,
+        });
+        assert_eq!(restored.accounts[&1].held_funds, Amount::from(10.5));
+    }
+
+    // Client 1 with 10.0 available and a stored deposit of it, in an older
+    // version's layout. From v5 on it has had 2 disputes and a chargeback.
+    fn old_snapshot(version: u8) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(version);
+        bytes.extend(1u64.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(Amount::from(10).to_raw().to_le_bytes());
+        bytes.extend(0i64.to_le_bytes());
+        bytes.push(0);
+        if version >= 5 {
+            bytes.extend(2u32.to_le_bytes());
+            bytes.extend(1u32.to_le_bytes());
+        }
+        bytes.extend(1u64.to_le_bytes());
+        bytes.extend(7u32.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(Amount::from(10).to_raw().to_le_bytes());
+        if version >= 3 {
+            bytes.push(0);
+        }
+        if version >= 4 {
+            bytes.extend(0u64.to_le_bytes());
+        }
+        if version >= 6 {
+            bytes.extend(0u64.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_snapshot_v4() {
+        let mut restored = PaymentProcessor::new();
+        restored.load_snapshot(old_snapshot(4).as_slice()).unwrap();
+        let account = &restored.accounts[&1];
+        assert_eq!(account.available(), Amount::from(10));
+        assert_eq!((account.total_disputes, account.chargebacks), (0, 0));
+
+        // And it counts from there
+        restored.process(&Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 7,
+        });
+        assert_eq!(restored.accounts[&1].total_disputes, 1);
+    }
+
+    #[test]
+    fn test_snapshot_v5() {
+        let mut restored = PaymentProcessor::with_config(ProcessorConfig {
+            dedup_window: Some(DedupWindow::Last(10)),
+            ..ProcessorConfig::default()
+        });
+        restored.load_snapshot(old_snapshot(5).as_slice()).unwrap();
+        let account = &restored.accounts[&1];
+        assert_eq!((account.total_disputes, account.chargebacks), (2, 1));
+        assert!(restored.seen_transactions.is_empty());
+
+        // The window starts out empty and fills up from there
+        let deposit = Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 8,
+            amount: Amount::from(1),
+        };
+        assert_eq!(restored.try_process(&deposit), Ok(()));
+        assert_eq!(
+            restored.try_process(&deposit),
+            Err(RejectionReason::DuplicateTransaction)
+        );
+    }
+
+    #[test]
+    fn test_older_snapshots() {
+        for version in 2..=6 {
+            let mut restored = PaymentProcessor::new();
+            restored
+                .load_snapshot(old_snapshot(version).as_slice())
+                .unwrap();
+            assert_eq!(
+                restored.accounts[&1].total(),
+                Amount::from(10),
+                "v{}",
+                version
+            );
+            assert_eq!(
+                restored.compressed_transactions[&7].state,
+                DisputeState::Undisputed
+            );
+            assert!(restored.merged_clients.is_empty());
+        }
+
+        let err = PaymentProcessor::new()
+            .load_snapshot(old_snapshot(1).as_slice())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_snapshot_truncated() {
+        let processor = PaymentProcessor::new();
+        let mut bytes = Vec::new();
+        processor.save_snapshot(&mut bytes).unwrap();
+        bytes.pop();
+
+        let err = PaymentProcessor::new()
+            .load_snapshot(bytes.as_slice())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,141 @@
+use std::io::{self, Write};
+
+use super::{Account, ClientId, DisputeState, PaymentProcessor};
+
+/// Table names used by the SQL export
+#[derive(Debug, Clone)]
+pub struct SqlTables {
+    pub accounts: String,
+    pub disputes: String,
+}
+
+impl Default for SqlTables {
+    fn default() -> Self {
+        Self {
+            accounts: String::from("accounts"),
+            disputes: String::from("disputes"),
+        }
+    }
+}
+
+/// Table names go into the statements as-is, so only plain identifiers
+/// are allowed (optionally schema-qualified, e.g. `reporting.accounts`)
+pub fn is_valid_table_name(name: &str) -> bool {
+    let is_identifier = |part: &str| {
+        let mut chars = part.chars();
+        matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    let parts: Vec<&str> = name.split('.').collect();
+    parts.len() <= 2 && parts.into_iter().all(is_identifier)
+}
+
+impl PaymentProcessor {
+    /// Same as dump_csv, but as SQL INSERT statements for the accounts and
+    /// the currently open disputes
+    pub fn dump_sql(&self, tables: &SqlTables) -> io::Result<()> {
+        self.dump_sql_filtered(tables, |_, _| true)
+    }
+
+    /// Same as dump_sql, but only for the accounts `filter` returns true for.
+    /// Open disputes are always written in full.
+    pub fn dump_sql_filtered(
+        &self,
+        tables: &SqlTables,
+        filter: impl Fn(ClientId, &Account) -> bool,
+    ) -> io::Result<()> {
+        let mut out = io::BufWriter::new(io::stdout().lock());
+        self.write_sql(&mut out, tables, filter)?;
+        out.flush()
+    }
+
+    /// Same as dump_sql_filtered, but into any writer
+    pub fn write_sql(
+        &self,
+        out: &mut impl Write,
+        tables: &SqlTables,
+        filter: impl Fn(ClientId, &Account) -> bool,
+    ) -> io::Result<()> {
+        // Sorted so that reruns give the same file
+        let mut client_ids: Vec<ClientId> = self.accounts.keys().copied().collect();
+        client_ids.sort_unstable();
+        for client_id in client_ids {
+            let account = &self.accounts[&client_id];
+            if !filter(client_id, account) {
+                continue;
+            }
+            writeln!(
+                out,
+                "INSERT INTO {} (client, available, held, total, locked) VALUES ({}, {}, {}, {}, {});",
+                tables.accounts,
+                client_id,
+                account.available(),
+                account.held(),
+                account.total(),
+                if account.is_locked() { "TRUE" } else { "FALSE" },
+            )?;
+        }
+
+        let mut disputes: Vec<_> = self
+            .compressed_transactions
+            .iter()
+            .filter(|(_, stored)| stored.state == DisputeState::Disputed)
+            .collect();
+        disputes.sort_unstable_by_key(|(transaction_id, _)| **transaction_id);
+        for (transaction_id, stored) in disputes {
+            writeln!(
+                out,
+                "INSERT INTO {} (client, tx, amount) VALUES ({}, {}, {});",
+                tables.disputes, stored.client_id, transaction_id, stored.amount,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, Transaction};
+
+    #[test]
+    fn test_write_sql() {
+        let mut processor = PaymentProcessor::new();
+        for (client_id, transaction_id, amount) in [(2, 1, 1.5), (1, 2, 2.0), (1, 3, 3.0)] {
+            processor.process(&Transaction::Deposit {
+                client_id,
+                transaction_id,
+                amount: Amount::from(amount),
+            });
+        }
+        processor.process(&Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 3,
+        });
+
+        let tables = SqlTables {
+            accounts: String::from("reporting.accounts"),
+            disputes: String::from("open_disputes"),
+        };
+        let mut out = Vec::new();
+        processor.write_sql(&mut out, &tables, |_, _| true).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "INSERT INTO reporting.accounts (client, available, held, total, locked) VALUES (1, 2.0000, 3.0000, 5.0000, FALSE);\n\
+             INSERT INTO reporting.accounts (client, available, held, total, locked) VALUES (2, 1.5000, 0.0000, 1.5000, FALSE);\n\
+             INSERT INTO open_disputes (client, tx, amount) VALUES (1, 3, 3.0000);\n"
+        );
+    }
+
+    #[test]
+    fn test_table_names() {
+        assert!(is_valid_table_name("accounts"));
+        assert!(is_valid_table_name("reporting.accounts_2024"));
+        assert!(!is_valid_table_name(""));
+        assert!(!is_valid_table_name("1accounts"));
+        assert!(!is_valid_table_name("a.b.c"));
+        assert!(!is_valid_table_name("accounts; DROP TABLE accounts"));
+    }
+}
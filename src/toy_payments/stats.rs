@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::amount::Amount;
+use super::events::{EventListener, RejectionReason};
+use super::locale::{Locale, LocalizedDisplay};
+use super::{Account, ClientId, Transaction, TransactionId};
+
+/// Running counters over everything the processor has seen
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub applied: u64,
+    pub rejected: BTreeMap<RejectionReason, u64>,
+    pub adjustments: u64,
+    pub disputes_opened: u64,
+    pub disputes_resolved: u64,
+    /// Disputes past the dispute cap, rejected or flagged
+    pub disputes_over_cap: u64,
+    pub chargebacks: u64,
+    pub accounts_locked: u64,
+    /// Locked for going over the dispute cap, also counted in accounts_locked
+    pub accounts_frozen: u64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn total_rejected(&self) -> u64 {
+        self.rejected.values().sum()
+    }
+}
+
+impl EventListener for Stats {
+    fn on_applied(&mut self, transaction: &Transaction) {
+        self.applied += 1;
+        if let Transaction::Adjustment { .. } = transaction {
+            self.adjustments += 1;
+        }
+    }
+
+    fn on_rejected(&mut self, _transaction: &Transaction, reason: RejectionReason) {
+        *self.rejected.entry(reason).or_default() += 1;
+    }
+
+    fn on_dispute_opened(&mut self, _: ClientId, _: TransactionId, _: Amount) {
+        self.disputes_opened += 1;
+    }
+
+    fn on_dispute_resolved(&mut self, _: ClientId, _: TransactionId, _: Amount) {
+        self.disputes_resolved += 1;
+    }
+
+    fn on_charged_back(&mut self, _: ClientId, _: TransactionId, _: Amount) {
+        self.chargebacks += 1;
+    }
+
+    fn on_dispute_over_cap(&mut self, _: ClientId, _: TransactionId, _: u32, _: bool) {
+        self.disputes_over_cap += 1;
+    }
+
+    fn on_account_locked(&mut self, _: ClientId, transaction: &Transaction, _: &Account) {
+        self.accounts_locked += 1;
+        if let Transaction::Dispute { .. } = transaction {
+            self.accounts_frozen += 1;
+        }
+    }
+}
+
+impl LocalizedDisplay for Stats {
+    fn fmt_localized(&self, f: &mut fmt::Formatter<'_>, locale: &Locale) -> fmt::Result {
+        writeln!(f, "applied: {}", locale.count(self.applied))?;
+        writeln!(f, "rejected: {}", locale.count(self.total_rejected()))?;
+        for (reason, count) in &self.rejected {
+            writeln!(f, "  {}: {}", reason, locale.count(*count))?;
+        }
+        writeln!(f, "adjustments: {}", locale.count(self.adjustments))?;
+        writeln!(f, "disputes opened: {}", locale.count(self.disputes_opened))?;
+        writeln!(
+            f,
+            "disputes resolved: {}",
+            locale.count(self.disputes_resolved)
+        )?;
+        writeln!(
+            f,
+            "disputes over cap: {}",
+            locale.count(self.disputes_over_cap)
+        )?;
+        writeln!(f, "chargebacks: {}", locale.count(self.chargebacks))?;
+        writeln!(f, "accounts locked: {}", locale.count(self.accounts_locked))?;
+        write!(f, "accounts frozen: {}", locale.count(self.accounts_frozen))
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_localized(f, &Locale::default())
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+
+// Everything the sharded processor synchronizes on goes through here, so
+// the concurrency_model feature can swap in loom's versions and have loom
+// check every interleaving (see the loom tests in sharded.rs).
+#[cfg(feature = "concurrency_model")]
+pub(crate) use loom::{
+    sync::{Arc, Condvar, Mutex, mpsc},
+    thread,
+};
+#[cfg(not(feature = "concurrency_model"))]
+pub(crate) use std::{
+    sync::{Arc, Condvar, Mutex, mpsc},
+    thread,
+};
+
+/// FIFO with a fixed capacity: push waits while it's full and pop waits
+/// while it's empty. Once closed, pushes fail and pop drains what's left
+/// before returning None.
+pub(crate) struct BoundedQueue<T> {
+    state: Mutex<QueueState<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+impl<T> BoundedQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                items: VecDeque::with_capacity(capacity),
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Hands the item back if the queue got closed
+    pub fn push(&self, item: T) -> Result<(), T> {
+        let mut state = self.state.lock().unwrap();
+        while state.items.len() >= self.capacity && !state.closed {
+            state = self.not_full.wait(state).unwrap();
+        }
+        if state.closed {
+            return Err(item);
+        }
+        state.items.push_back(item);
+        drop(state);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                drop(state);
+                self.not_full.notify_one();
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.not_empty.wait(state).unwrap();
+        }
+    }
+
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+}
+
+#[cfg(all(test, not(feature = "concurrency_model")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_queue() {
+        let queue = Arc::new(BoundedQueue::new(2));
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut items = Vec::new();
+                while let Some(item) = queue.pop() {
+                    items.push(item);
+                }
+                items
+            })
+        };
+        for item in 0..100 {
+            queue.push(item).unwrap();
+        }
+        queue.close();
+
+        assert_eq!(consumer.join().unwrap(), (0..100).collect::<Vec<_>>());
+        assert_eq!(queue.push(100), Err(100));
+    }
+}
+
+#[cfg(all(test, feature = "concurrency_model"))]
+mod loom_tests {
+    use super::*;
+
+    #[test]
+    fn loom_queue_delivers_everything_in_order() {
+        loom::model(|| {
+            let queue = Arc::new(BoundedQueue::new(1));
+            let consumer = {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    let mut items = Vec::new();
+                    while let Some(item) = queue.pop() {
+                        items.push(item);
+                    }
+                    items
+                })
+            };
+            for item in 0..3 {
+                queue.push(item).unwrap();
+            }
+            queue.close();
+
+            assert_eq!(consumer.join().unwrap(), vec![0, 1, 2]);
+        });
+    }
+}
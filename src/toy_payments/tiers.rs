@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Read;
+
+use csv::{ReaderBuilder, Trim};
+use serde::Deserialize;
+
+use super::amount::Amount;
+use super::hashing::HashMap;
+use super::{ClientId, deserialize_amount};
+
+/// What a tier changes about withdrawals (`[tier.<name>]` in the config).
+/// Clients without a tier get the defaults, which is how withdrawals
+/// worked before tiers: no fee, no limit, no overdraft.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TierRules {
+    /// Flat fee taken from the available funds on top of every withdrawal
+    #[serde(default, deserialize_with = "deserialize_amount_or_zero")]
+    pub withdrawal_fee: Amount,
+    /// Largest single withdrawal
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub withdrawal_limit: Option<Amount>,
+    /// How far below zero a withdrawal can take the available funds
+    #[serde(default, deserialize_with = "deserialize_amount_or_zero")]
+    pub overdraft: Amount,
+}
+
+fn deserialize_amount_or_zero<'de, D>(deserializer: D) -> Result<Amount, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(deserialize_amount(deserializer)?.unwrap_or_default())
+}
+
+/// The tier rules from the config and which clients are on which tier.
+/// Rules are looked up per withdrawal, by client.
+#[derive(Debug, Clone, Default)]
+pub struct Tiers {
+    names: Vec<String>,
+    rules: Vec<TierRules>,
+    clients: HashMap<ClientId, usize>,
+    default: TierRules,
+}
+
+// One row of the client metadata file, anything past these columns is ignored
+#[derive(Deserialize)]
+struct MetadataRow {
+    client: ClientId,
+    tier: String,
+}
+
+impl Tiers {
+    pub fn new(tiers: BTreeMap<String, TierRules>) -> Self {
+        let (names, rules) = tiers.into_iter().unzip();
+        Self {
+            names,
+            rules,
+            ..Self::default()
+        }
+    }
+
+    pub fn assign(&mut self, client_id: ClientId, tier: &str) -> Result<(), String> {
+        let index = self
+            .names
+            .iter()
+            .position(|name| name == tier)
+            .ok_or_else(|| format!("unknown tier {} for client {}", tier, client_id))?;
+        self.clients.insert(client_id, index);
+        Ok(())
+    }
+
+    /// Assigns tiers from a client metadata CSV with `client` and `tier`
+    /// columns. Returns how many clients it assigned.
+    pub fn read_metadata(&mut self, reader: impl Read) -> Result<usize, Box<dyn Error>> {
+        let mut reader = ReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(reader);
+        let mut assigned = 0;
+        for row in reader.deserialize() {
+            let row: MetadataRow = row?;
+            self.assign(row.client, &row.tier)?;
+            assigned += 1;
+        }
+        Ok(assigned)
+    }
+
+    pub fn tier(&self, client_id: ClientId) -> Option<&str> {
+        self.clients
+            .get(&client_id)
+            .map(|index| self.names[*index].as_str())
+    }
+
+    pub fn rules(&self, client_id: ClientId) -> &TierRules {
+        match self.clients.get(&client_id) {
+            Some(index) => &self.rules[*index],
+            None => &self.default,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{
+        PaymentProcessor, ProcessorConfig, RejectionReason, Transaction, TransactionId,
+    };
+    use std::sync::Arc;
+
+    fn withdrawal(client_id: ClientId, transaction_id: TransactionId, amount: u64) -> Transaction {
+        Transaction::Withdrawal {
+            client_id,
+            transaction_id,
+            amount: Amount::from(amount),
+        }
+    }
+
+    #[test]
+    fn test_tier_rules() {
+        let config: BTreeMap<String, TierRules> = toml::from_str(
+            "[basic]\n\
+             withdrawal_fee = 1.5\n\
+             withdrawal_limit = 100\n\
+             [premium]\n\
+             overdraft = 50\n",
+        )
+        .unwrap();
+        let mut tiers = Tiers::new(config);
+        let assigned = tiers
+            .read_metadata("client,tier,segment\n1,basic,retail\n2,premium,business\n".as_bytes())
+            .unwrap();
+        assert_eq!(assigned, 2);
+        assert_eq!(tiers.tier(2), Some("premium"));
+        assert_eq!(tiers.tier(3), None);
+        assert!(tiers.assign(3, "gold").is_err());
+
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            tiers: Arc::new(tiers),
+            ..ProcessorConfig::default()
+        });
+        for client_id in 1..=3 {
+            processor.process(&Transaction::Deposit {
+                client_id,
+                transaction_id: client_id as TransactionId,
+                amount: Amount::from(200),
+            });
+        }
+
+        // Basic: fee on top, capped at 100 per withdrawal, no overdraft
+        assert_eq!(
+            processor.try_process(&withdrawal(1, 10, 150)),
+            Err(RejectionReason::OverWithdrawalLimit)
+        );
+        assert_eq!(processor.try_process(&withdrawal(1, 11, 100)), Ok(()));
+        assert_eq!(processor.accounts()[&1].available(), Amount::from(98.5));
+        assert_eq!(
+            processor.try_process(&withdrawal(1, 12, 98)),
+            Err(RejectionReason::InsufficientFunds)
+        );
+        // Premium: can go 50 below zero
+        assert_eq!(processor.try_process(&withdrawal(2, 13, 250)), Ok(()));
+        assert_eq!(
+            processor.try_process(&withdrawal(2, 14, 1)),
+            Err(RejectionReason::InsufficientFunds)
+        );
+        assert_eq!(processor.accounts()[&2].available(), -Amount::from(50));
+        // No tier, same as before
+        assert_eq!(
+            processor.try_process(&withdrawal(3, 15, 201)),
+            Err(RejectionReason::InsufficientFunds)
+        );
+    }
+}
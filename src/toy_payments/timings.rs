@@ -0,0 +1,91 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Time spent in each stage of a run, for --timings. In parallel mode the
+/// stage totals are summed over the threads doing them, and `threads` has
+/// the breakdown per thread.
+#[derive(Debug, Default, Clone)]
+pub struct Timings {
+    /// Wall clock for the whole run
+    pub total: Duration,
+    pub parse: Duration,
+    /// None when records get validated in the same step they're parsed in
+    /// (serde), so the two can't be told apart
+    pub validate: Option<Duration>,
+    pub process: Duration,
+    pub output: Duration,
+    pub threads: Vec<ThreadTimings>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ThreadTimings {
+    pub name: String,
+    pub parse: Duration,
+    pub validate: Option<Duration>,
+    pub process: Duration,
+}
+
+/// What one parsing thread spent, see Timings for `validate`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ParseTime {
+    pub parse: Duration,
+    pub validate: Option<Duration>,
+}
+
+impl ParseTime {
+    pub fn add(&mut self, other: ParseTime) {
+        self.parse += other.parse;
+        if let Some(validate) = other.validate {
+            *self.validate.get_or_insert_default() += validate;
+        }
+    }
+}
+
+impl Timings {
+    pub fn add_parse(&mut self, time: ParseTime) {
+        let mut total = ParseTime {
+            parse: self.parse,
+            validate: self.validate,
+        };
+        total.add(time);
+        self.parse = total.parse;
+        self.validate = total.validate;
+    }
+}
+
+/// Runs `f`, adding how long it took to `total`
+pub fn timed<T>(total: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    *total += start.elapsed();
+    result
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "total: {:.2?}", self.total)?;
+        writeln!(f, "parse: {:.2?}", self.parse)?;
+        match self.validate {
+            Some(validate) => writeln!(f, "validate: {:.2?}", validate)?,
+            None => writeln!(
+                f,
+                "validate: included in parse (serde), use --fast-parse to split it out"
+            )?,
+        }
+        writeln!(f, "process: {:.2?}", self.process)?;
+        write!(f, "output: {:.2?}", self.output)?;
+        // Threads only ever do one kind of work, parsing or processing
+        for thread in &self.threads {
+            write!(f, "\n  {}:", thread.name)?;
+            if thread.process.is_zero() {
+                write!(f, " parse {:.2?}", thread.parse)?;
+                if let Some(validate) = thread.validate {
+                    write!(f, ", validate {:.2?}", validate)?;
+                }
+            } else {
+                write!(f, " process {:.2?}", thread.process)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use super::bloom::{BITS_PER_ITEM, bit_indexes};
+use super::{ClientId, DisputeState, PaymentProcessor, TransactionId};
+
+// ~1.25MB of bits per generation
+const EXPIRED_PER_GENERATION: usize = 1 << 20;
+
+/// How long deposits/withdrawals stay disputable (`[dispute_window]` in the
+/// config). Counted in the client's own transactions rather than all of
+/// them, so it comes out the same however the clients are sharded. The
+/// input has no timestamps, so there's no time-based window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DisputeWindow {
+    /// A transaction can still be disputed by the client's next `max_age`
+    /// transactions, after that it's dropped from the store and disputes
+    /// for it are rejected as `DISPUTE_WINDOW_EXPIRED`
+    pub max_age: u64,
+}
+
+/// Where a client is in its window: how many transactions it's had, and
+/// its stored ones (with the count they came in at) oldest first
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClientWindow {
+    seen: u64,
+    stored: VecDeque<(u64, TransactionId)>,
+}
+
+/// What the dispute window dropped from the store, so disputing it is
+/// rejected as expired rather than unknown. A bloom filter over (client, tx)
+/// in two generations: once the newer one is full the older one is cleared
+/// and takes over, so it stays the same size and only the last 1-2M expired
+/// IDs are remembered. Older ones come back as unknown, and ~1% of IDs that
+/// never expired come back as expired.
+#[derive(Debug, Clone)]
+pub(crate) struct ExpiredTransactions {
+    // Newest first, each one empty until something goes in
+    generations: [Vec<u64>; 2],
+    capacity: usize,
+    // In the newest generation
+    len: usize,
+    // Since this shard was split off, so merging can add them up
+    added: usize,
+    // Ever, for expired_transactions()
+    count: u64,
+}
+
+impl Default for ExpiredTransactions {
+    fn default() -> Self {
+        Self::with_capacity(EXPIRED_PER_GENERATION)
+    }
+}
+
+impl ExpiredTransactions {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            generations: [Vec::new(), Vec::new()],
+            capacity,
+            len: 0,
+            added: 0,
+            count: 0,
+        }
+    }
+
+    fn insert(&mut self, client_id: ClientId, transaction_id: TransactionId) {
+        if self.len >= self.capacity {
+            self.generations.swap(0, 1);
+            self.generations[0].fill(0);
+            self.len = 0;
+        }
+        let words = words(self.capacity);
+        let bits = &mut self.generations[0];
+        if bits.is_empty() {
+            *bits = vec![0; words];
+        }
+        for bit in bit_indexes(words, key(client_id, transaction_id)) {
+            bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+        self.added += 1;
+        self.count += 1;
+    }
+
+    /// False means it never expired (or too long ago), true means probably
+    pub(crate) fn contains(&self, client_id: ClientId, transaction_id: TransactionId) -> bool {
+        let key = key(client_id, transaction_id);
+        self.generations
+            .iter()
+            .filter(|bits| !bits.is_empty())
+            .any(|bits| {
+                bit_indexes(bits.len(), key).all(|bit| bits[bit / 64] & (1 << (bit % 64)) != 0)
+            })
+    }
+
+    fn union(&mut self, other: ExpiredTransactions) {
+        for (bits, other) in self.generations.iter_mut().zip(other.generations) {
+            if bits.is_empty() {
+                *bits = other;
+            } else if !other.is_empty() {
+                for (word, other) in bits.iter_mut().zip(other) {
+                    *word |= other;
+                }
+            }
+        }
+        // Each shard's newest generation started with the same entries, so
+        // only what it added since counts again. Can come out over capacity
+        // if a shard moved on a generation, the next insert moves this one on.
+        self.len += other.added;
+        self.added += other.added;
+        self.count += other.count;
+    }
+
+    // For snapshots: (capacity, newest generation's length, how many ever,
+    // generations newest first)
+    pub(crate) fn parts(&self) -> (usize, usize, u64, &[Vec<u64>; 2]) {
+        (self.capacity, self.len, self.count, &self.generations)
+    }
+
+    /// None if the generations aren't the size the capacity makes them
+    pub(crate) fn from_parts(
+        capacity: usize,
+        len: usize,
+        count: u64,
+        generations: [Vec<u64>; 2],
+    ) -> Option<Self> {
+        let words = words(capacity);
+        if generations
+            .iter()
+            .any(|bits| !bits.is_empty() && bits.len() != words)
+        {
+            return None;
+        }
+        Some(Self {
+            generations,
+            capacity,
+            len,
+            added: 0,
+            count,
+        })
+    }
+}
+
+fn words(capacity: usize) -> usize {
+    (capacity.max(1) * BITS_PER_ITEM).div_ceil(64)
+}
+
+fn key(client_id: ClientId, transaction_id: TransactionId) -> u64 {
+    (u64::from(client_id) << 32) | u64::from(transaction_id)
+}
+
+impl PaymentProcessor {
+    // Called for every transaction from the client, before it's applied
+    pub(crate) fn advance_dispute_window(&mut self, client_id: ClientId) {
+        let Some(window) = self.config.dispute_window else {
+            return;
+        };
+        let client = self.dispute_windows.entry(client_id).or_default();
+        client.seen += 1;
+        while let Some(&(stored_at, transaction_id)) = client.stored.front() {
+            if client.seen - stored_at <= window.max_age {
+                break;
+            }
+            client.stored.pop_front();
+            // Open disputes still need resolving, and chargebacks are kept
+            // for the representment export
+            let expires = self
+                .compressed_transactions
+                .get(&transaction_id)
+                .is_some_and(|stored| {
+                    stored.client_id == client_id
+                        && matches!(
+                            stored.state,
+                            DisputeState::Undisputed | DisputeState::Resolved
+                        )
+                });
+            if expires {
+                self.compressed_transactions.remove(&transaction_id);
+                Arc::make_mut(&mut self.expired_transactions).insert(client_id, transaction_id);
+                if let Some(filter) = &mut self.transaction_filter {
+                    filter.remove();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn track_for_expiry(&mut self, client_id: ClientId, transaction_id: TransactionId) {
+        if self.config.dispute_window.is_none() {
+            return;
+        }
+        let client = self.dispute_windows.entry(client_id).or_default();
+        client.stored.push_back((client.seen, transaction_id));
+    }
+
+    // The merged client's transactions go on the end of the other client's
+    // window, as if they'd just come in. Its expired IDs can't be moved
+    // over (the filter can't list them), so those turn unknown.
+    pub(crate) fn merge_dispute_window(&mut self, client_id: ClientId, into: ClientId) {
+        if let Some(merged) = self.dispute_windows.remove(&client_id) {
+            for (_, transaction_id) in merged.stored {
+                self.track_for_expiry(into, transaction_id);
+            }
+        }
+    }
+
+    // After a snapshot load nothing says how old the stored transactions
+    // are, so they all start a fresh window
+    pub(crate) fn restart_dispute_windows(&mut self) {
+        self.dispute_windows.clear();
+        if self.config.dispute_window.is_none() {
+            return;
+        }
+        let mut transaction_ids: Vec<_> = self.compressed_transactions.keys().copied().collect();
+        transaction_ids.sort_unstable();
+        for transaction_id in transaction_ids {
+            let client_id = self.compressed_transactions[&transaction_id].client_id;
+            self.track_for_expiry(client_id, transaction_id);
+        }
+    }
+
+    // Can't be split by client, so every shard gets all of it. The count
+    // only goes to the first one, so merging adds up.
+    pub(crate) fn expired_for_shard(
+        &self,
+        shard: Option<(usize, usize)>,
+    ) -> Arc<ExpiredTransactions> {
+        match shard {
+            None => self.expired_transactions.clone(),
+            Some((index, _)) => Arc::new(ExpiredTransactions {
+                added: 0,
+                count: if index == 0 {
+                    self.expired_transactions.count
+                } else {
+                    0
+                },
+                ..(*self.expired_transactions).clone()
+            }),
+        }
+    }
+
+    // For merging shards back: anything either of them remembers. The
+    // shards all started from the same copy, so the sizes match.
+    pub(crate) fn merge_expired(&mut self, other: Arc<ExpiredTransactions>) {
+        Arc::make_mut(&mut self.expired_transactions).union(Arc::unwrap_or_clone(other));
+    }
+
+    /// How many transactions have been dropped from the store by the
+    /// dispute window
+    pub fn expired_transactions(&self) -> u64 {
+        self.expired_transactions.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy_payments::{Amount, ProcessorConfig, RejectionReason, Transaction};
+
+    fn deposit(client_id: ClientId, transaction_id: TransactionId) -> Transaction {
+        Transaction::Deposit {
+            client_id,
+            transaction_id,
+            amount: Amount::from(1),
+        }
+    }
+
+    fn dispute(client_id: ClientId, transaction_id: TransactionId) -> Transaction {
+        Transaction::Dispute {
+            client_id,
+            transaction_id,
+        }
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        let mut processor = PaymentProcessor::with_config(ProcessorConfig {
+            dispute_window: Some(DisputeWindow { max_age: 2 }),
+            transaction_filter: true,
+            ..ProcessorConfig::default()
+        });
+        processor.process(&deposit(1, 1));
+        processor.process(&deposit(1, 2));
+        processor.process(&deposit(1, 3));
+        // Other clients don't age client 1's transactions
+        for transaction_id in 10..20 {
+            processor.process(&deposit(2, transaction_id));
+        }
+        // Tx 1 is 3 transactions back now
+        assert_eq!(
+            processor.try_process(&dispute(1, 1)),
+            Err(RejectionReason::DisputeWindowExpired)
+        );
+        assert!(!processor.transactions().contains_key(&1));
+        // Tx 3 is only 2 back (the rejected dispute counts too)
+        assert_eq!(processor.try_process(&dispute(1, 3)), Ok(()));
+        // Expired IDs are only remembered with their client
+        assert_eq!(
+            processor.try_process(&dispute(2, 1)),
+            Err(RejectionReason::UnknownTransaction)
+        );
+        assert_eq!(
+            processor.try_process(&dispute(1, 99)),
+            Err(RejectionReason::UnknownTransaction)
+        );
+
+        // Open disputes outlive the window, they still need resolving
+        for transaction_id in 4..10 {
+            processor.process(&deposit(1, transaction_id));
+        }
+        assert_eq!(
+            processor.try_process(&Transaction::Resolve {
+                client_id: 1,
+                transaction_id: 3,
+            }),
+            Ok(())
+        );
+        // Txs 1, 2 and 4-7 for client 1, 10-17 for client 2
+        assert_eq!(processor.expired_transactions(), 14);
+        assert!(processor.check_invariants().is_empty());
+
+        // Still expired rather than unknown after a snapshot, or sharding
+        let mut bytes = Vec::new();
+        processor.save_snapshot(&mut bytes).unwrap();
+        let mut restored = PaymentProcessor::with_config(processor.config.clone());
+        restored.load_snapshot(bytes.as_slice()).unwrap();
+        let merged = PaymentProcessor::merge(restored.into_shards(3)).unwrap();
+        assert_eq!(merged.expired_transactions(), 14);
+        assert_eq!(
+            merged.copy_state().try_process(&dispute(1, 2)),
+            Err(RejectionReason::DisputeWindowExpired)
+        );
+        assert_eq!(
+            merged.copy_state().try_process(&dispute(2, 17)),
+            Err(RejectionReason::DisputeWindowExpired)
+        );
+    }
+
+    #[test]
+    fn test_expired_generations() {
+        let mut expired = ExpiredTransactions::with_capacity(4);
+        for transaction_id in 1..=9 {
+            expired.insert(1, transaction_id);
+        }
+        // 1-4 went with the generation that got reused for 9
+        assert!((5..=9).all(|transaction_id| expired.contains(1, transaction_id)));
+        assert!(!(1..=4).any(|transaction_id| expired.contains(1, transaction_id)));
+        assert!(!expired.contains(2, 9));
+        assert_eq!(expired.count, 9);
+        assert!(
+            expired
+                .generations
+                .iter()
+                .all(|bits| bits.len() == words(4))
+        );
+    }
+
+    #[test]
+    fn test_merge_expired_generations() {
+        let mut processor = PaymentProcessor::new();
+        let mut expired = ExpiredTransactions::with_capacity(4);
+        expired.insert(1, 1);
+        processor.expired_transactions = Arc::new(expired);
+        let mut shards: Vec<_> = (0..2)
+            .map(|index| processor.expired_for_shard(Some((index, 2))))
+            .collect();
+        Arc::make_mut(&mut shards[0]).insert(1, 2);
+        Arc::make_mut(&mut shards[1]).insert(2, 3);
+        Arc::make_mut(&mut shards[1]).insert(2, 4);
+        let mut merged = PaymentProcessor::new();
+        merged.expired_transactions = shards.remove(0);
+        merged.merge_expired(shards.remove(0));
+        // The one from before the split, plus what each shard added
+        assert_eq!(merged.expired_transactions.parts().1, 4);
+        assert_eq!(merged.expired_transactions(), 4);
+
+        // So the newest generation is full, and 5 starts a new one that
+        // pushes 1-4 out once it's full too
+        let expired = Arc::make_mut(&mut merged.expired_transactions);
+        for transaction_id in 5..=8 {
+            expired.insert(1, transaction_id);
+        }
+        assert!(expired.contains(1, 1) && expired.contains(2, 4));
+        expired.insert(1, 9);
+        assert!(!expired.contains(1, 1) && !expired.contains(2, 3) && !expired.contains(2, 4));
+        assert!((5..=9).all(|transaction_id| expired.contains(1, transaction_id)));
+    }
+}